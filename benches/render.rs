@@ -0,0 +1,110 @@
+//! Benchmarks of the parts of rendering whose performance matters most, so that regressions
+//! (e.g. in the intersection code) are caught. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rustracer::partition::Partition;
+use rustracer::raytracer::RayTracer;
+use rustracer::scene::{Scene, SceneBuilder};
+use rustracer::texture::{ColorSpace, ImageTexture, TextureSource};
+use rustracer::{build_scene, Config};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Size of the images rendered by the benchmarks, which is small so that each iteration
+/// is quick.
+const IMAGE_WIDTH: u32 = 64;
+const IMAGE_HEIGHT: u32 = 48;
+
+/// Number of texture lookups measured per iteration.
+const TEXTURE_LOOKUPS: u32 = 4096;
+
+fn tests_directory() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests"))
+}
+
+/// Builds the given test scenefile, with the given render options.
+fn test_scene(scene: &str, options: &[&str]) -> (Scene, Config, Partition) {
+    let mut args: Vec<OsString> = vec![
+        "rustracer".into(),
+        "--width".into(),
+        IMAGE_WIDTH.to_string().into(),
+        "--height".into(),
+        IMAGE_HEIGHT.to_string().into(),
+        "--scene".into(),
+        tests_directory().join("scenefiles").join(scene).into(),
+        "--textures".into(),
+        tests_directory().join("textures").into(),
+        "--output".into(),
+        "".into(),
+    ];
+    args.extend(options.iter().map(OsString::from));
+
+    let config = Config::from_iter(args);
+    let partition = config.partition().unwrap();
+    let scene = build_scene(&config, &SceneBuilder::new()).unwrap();
+
+    (scene, config, partition)
+}
+
+/// Camera rays only, which measures intersecting rays with the scene's geometry.
+fn primary_rays(c: &mut Criterion) {
+    let (scene, config, partition) = test_scene("test_efficiency/recursiveSpheres4.xml", &[]);
+    let ray_tracer = RayTracer::new(&scene, config).unwrap();
+
+    c.bench_function("primary rays", |b| {
+        b.iter(|| ray_tracer.render(&partition, |_| {}).unwrap())
+    });
+}
+
+/// Camera rays along with a shadow ray toward every light from each point they hit.
+fn shadow_rays(c: &mut Criterion) {
+    let (scene, config, partition) = test_scene(
+        "test_efficiency/recursiveSpheres4.xml",
+        &["--enable-shadows"],
+    );
+    let ray_tracer = RayTracer::new(&scene, config).unwrap();
+
+    c.bench_function("shadow rays", |b| {
+        b.iter(|| ray_tracer.render(&partition, |_| {}).unwrap())
+    });
+}
+
+/// Looking up the values of an image texture at a spread of UV coordinates.
+fn texture_lookup(c: &mut Criterion) {
+    let texture = ImageTexture::open(
+        &tests_directory().join("textures/board.png"),
+        ColorSpace::Linear,
+    )
+    .unwrap();
+
+    c.bench_function("texture lookup", |b| {
+        b.iter(|| {
+            for lookup in 0..TEXTURE_LOOKUPS {
+                let u = lookup as f32 / TEXTURE_LOOKUPS as f32;
+                let v = (lookup * 7 % TEXTURE_LOOKUPS) as f32 / TEXTURE_LOOKUPS as f32;
+                black_box(texture.value(black_box((u, v))));
+            }
+        })
+    });
+}
+
+/// Rendering a textured scene, which includes texture lookups as part of shading.
+fn textured_render(c: &mut Criterion) {
+    let (scene, config, partition) =
+        test_scene("test_feature/texture_cube.xml", &["--enable-texture"]);
+    let ray_tracer = RayTracer::new(&scene, config).unwrap();
+
+    c.bench_function("textured render", |b| {
+        b.iter(|| ray_tracer.render(&partition, |_| {}).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    primary_rays,
+    shadow_rays,
+    texture_lookup,
+    textured_render
+);
+criterion_main!(benches);
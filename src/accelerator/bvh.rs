@@ -0,0 +1,249 @@
+//! Bounding volume hierarchies, which recursively split the items into two groups and
+//! bound each group with a box.
+
+use super::Accelerator;
+use crate::bounding_box::BoundingBox;
+use crate::intersection::Intersection;
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::raytracer::Ray;
+use std::cmp::Ordering;
+
+/// Maximum number of items stored in a single leaf of the BVH.
+const MAX_ITEMS_PER_LEAF: usize = 2;
+
+/// A node of the BVH. Leaves refer to a contiguous range of the BVH's item indices, which
+/// are ordered during construction so that each leaf's items are adjacent.
+#[derive(Debug)]
+enum BvhNode {
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &BoundingBox {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a list of items (such as shapes), which refers to
+/// them by their index in the list.
+#[derive(Debug, Default)]
+pub struct Bvh {
+    /// Nodes of the hierarchy, where the first node is the root.
+    nodes: Vec<BvhNode>,
+    /// Indices of the items, in the order in which the leaves refer to them.
+    items: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds a BVH over items with the given bounding boxes. Items with empty bounds
+    /// (which can't be hit) are left out.
+    pub fn new(item_bounds: &[BoundingBox]) -> Self {
+        let mut items: Vec<usize> = (0..item_bounds.len())
+            .filter(|&item| !item_bounds[item].is_empty())
+            .collect();
+        let mut nodes = Vec::new();
+
+        if !items.is_empty() {
+            Bvh::build_node(&mut nodes, &mut items, 0, item_bounds);
+        }
+
+        Self { nodes, items }
+    }
+
+    /// Recursively builds the node over the given items (which start at index `first` in
+    /// the BVH's item list), splitting at the median centroid along the longest axis.
+    /// Returns the index of the constructed node.
+    fn build_node(
+        nodes: &mut Vec<BvhNode>,
+        items: &mut [usize],
+        first: usize,
+        item_bounds: &[BoundingBox],
+    ) -> usize {
+        let bounds = items.iter().fold(BoundingBox::empty(), |bounds, &item| {
+            bounds.union(&item_bounds[item])
+        });
+
+        let index = nodes.len();
+        nodes.push(BvhNode::Leaf {
+            bounds,
+            first,
+            count: items.len(),
+        });
+
+        if items.len() <= MAX_ITEMS_PER_LEAF {
+            return index;
+        }
+
+        let centroid_bounds = items.iter().fold(BoundingBox::empty(), |bounds, &item| {
+            bounds.including(&item_bounds[item].centroid())
+        });
+        let axis = centroid_bounds.longest_axis();
+
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |&a, &b| {
+            let a_centroid = item_bounds[a].centroid()[axis];
+            let b_centroid = item_bounds[b].centroid()[axis];
+            a_centroid
+                .partial_cmp(&b_centroid)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let (left_items, right_items) = items.split_at_mut(middle);
+        let left = Bvh::build_node(nodes, left_items, first, item_bounds);
+        let right = Bvh::build_node(nodes, right_items, first + middle, item_bounds);
+
+        nodes[index] = BvhNode::Interior {
+            bounds,
+            left,
+            right,
+        };
+
+        index
+    }
+}
+
+impl Accelerator for Bvh {
+    /// Only the items whose bounds the ray passes through (in front of the closest
+    /// intersection found so far) are tested.
+    fn closest_intersection<'a>(
+        &self,
+        ray: &Ray,
+        intersect_item: &dyn Fn(usize) -> Option<Intersection<'a>>,
+    ) -> Option<Intersection<'a>> {
+        let mut closest: Option<Intersection> = None;
+
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+
+            // Skip nodes that the ray misses, or which are entirely behind the closest hit so far
+            match node.bounds().intersect(ray) {
+                Some((t_enter, _))
+                    if closest
+                        .as_ref()
+                        .map_or(true, |c| t_enter <= c.component_intersection.t) => {}
+                _ => continue,
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &item in &self.items[first..first + count] {
+                        if let Some(intersection) = intersect_item(item) {
+                            if closest.as_ref().map_or(true, |c| intersection < *c) {
+                                closest = Some(intersection);
+                            }
+                        }
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+
+        closest
+    }
+
+    /// The rays of the packet traverse the BVH together, visiting every node that any of
+    /// them passes through (in front of its closest intersection so far), so that each
+    /// node's bounds are loaded once and tested against all of the rays at once.
+    fn closest_intersections<'a>(
+        &self,
+        packet: &RayPacket,
+        intersect_item: &dyn Fn(usize, usize) -> Option<Intersection<'a>>,
+    ) -> [Option<Intersection<'a>>; PACKET_SIZE] {
+        let mut closest: [Option<Intersection>; PACKET_SIZE] = Default::default();
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+
+            let t_max = std::array::from_fn(|lane| {
+                closest[lane]
+                    .as_ref()
+                    .map_or(f32::INFINITY, |c| c.component_intersection.t)
+            });
+            let hits = node.bounds().intersect_packet(packet, &t_max);
+            if !hits.contains(&true) {
+                continue;
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for lane in packet.active_lanes().filter(|&lane| hits[lane]) {
+                        for &item in &self.items[first..first + count] {
+                            if let Some(intersection) = intersect_item(lane, item) {
+                                if closest[lane].as_ref().map_or(true, |c| intersection < *c) {
+                                    closest[lane] = Some(intersection);
+                                }
+                            }
+                        }
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+
+        closest
+    }
+
+    /// Recomputes the bounds of every node from the bottom up. Items that were left out for
+    /// having empty bounds can't be added this way, so the BVH can't be refit once any of
+    /// them has non-empty bounds.
+    fn refit(&mut self, item_bounds: &[BoundingBox]) -> bool {
+        let mut included = vec![false; item_bounds.len()];
+        for &item in &self.items {
+            included[item] = true;
+        }
+        if (0..item_bounds.len()).any(|item| !included[item] && !item_bounds[item].is_empty()) {
+            return false;
+        }
+
+        // Children are always built after their parents, so they come later in the list
+        for index in (0..self.nodes.len()).rev() {
+            let refit_bounds = match self.nodes[index] {
+                BvhNode::Leaf { first, count, .. } => self.items[first..first + count]
+                    .iter()
+                    .fold(BoundingBox::empty(), |bounds, &item| {
+                        bounds.union(&item_bounds[item])
+                    }),
+                BvhNode::Interior { left, right, .. } => {
+                    self.nodes[left].bounds().union(self.nodes[right].bounds())
+                }
+            };
+
+            match self.nodes[index] {
+                BvhNode::Leaf { ref mut bounds, .. } | BvhNode::Interior { ref mut bounds, .. } => {
+                    *bounds = refit_bounds;
+                }
+            }
+        }
+
+        true
+    }
+}
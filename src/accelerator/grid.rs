@@ -0,0 +1,191 @@
+//! Uniform grids, which divide the region containing all of the items into equally sized
+//! cells (voxels), each listing the items whose bounds overlap it.
+
+use super::Accelerator;
+use crate::bounding_box::BoundingBox;
+use crate::intersection::Intersection;
+use crate::math::{vec3, Vec3};
+use crate::raytracer::Ray;
+
+/// Number of cells along the longest axis of the grid per cube root of the number of items,
+/// which makes for a few items per cell when the items are evenly distributed.
+const CELLS_PER_CUBE_ROOT_ITEM: f32 = 3.0;
+
+/// Maximum number of cells along any axis of the grid.
+const MAX_RESOLUTION: usize = 64;
+
+/// A uniform grid over a list of items (such as shapes), which refers to them by their index
+/// in the list.
+#[derive(Debug)]
+pub struct Grid {
+    /// Bounds of all of the items, which is the region of space that the grid divides.
+    bounds: BoundingBox,
+    /// Number of cells along each axis.
+    resolution: [usize; 3],
+    /// Size of each cell along each axis.
+    cell_size: Vec3,
+    /// For each cell (ordered by x, then y, then z), the range of item indices that overlap it.
+    cells: Vec<(usize, usize)>,
+    /// Indices of the items, in the order in which the cells refer to them (an item may be
+    /// listed for more than one cell, if its bounds overlap several).
+    items: Vec<usize>,
+}
+
+impl Grid {
+    /// Builds a grid over items with the given bounding boxes. Items with empty bounds
+    /// (which can't be hit) are left out.
+    pub fn new(item_bounds: &[BoundingBox]) -> Self {
+        let items: Vec<usize> = (0..item_bounds.len())
+            .filter(|&item| !item_bounds[item].is_empty())
+            .collect();
+        let bounds = items.iter().fold(BoundingBox::empty(), |bounds, &item| {
+            bounds.union(&item_bounds[item])
+        });
+
+        let mut grid = Self {
+            bounds,
+            resolution: [1, 1, 1],
+            cell_size: vec3(0.0, 0.0, 0.0),
+            cells: Vec::new(),
+            items: Vec::new(),
+        };
+
+        if items.is_empty() {
+            return grid;
+        }
+
+        // Give the cells roughly equal extents along each axis (so flat scenes get a flat grid)
+        let extent = bounds.max - bounds.min;
+        let longest_extent = extent[bounds.longest_axis()];
+        let cells_per_unit =
+            CELLS_PER_CUBE_ROOT_ITEM * (items.len() as f32).cbrt() / longest_extent;
+
+        for axis in 0..3 {
+            let cells = (extent[axis] * cells_per_unit).round() as usize;
+            grid.resolution[axis] = cells.clamp(1, MAX_RESOLUTION);
+            grid.cell_size[axis] = extent[axis] / grid.resolution[axis] as f32;
+        }
+
+        // List the items overlapping each cell
+        let mut cell_items = vec![Vec::new(); grid.resolution.iter().product()];
+        for &item in &items {
+            let low = grid.cell_containing(&item_bounds[item].min);
+            let high = grid.cell_containing(&item_bounds[item].max);
+
+            for z in low[2]..=high[2] {
+                for y in low[1]..=high[1] {
+                    for x in low[0]..=high[0] {
+                        cell_items[grid.cell_index(&[x, y, z])].push(item);
+                    }
+                }
+            }
+        }
+
+        for items in cell_items {
+            grid.cells.push((grid.items.len(), items.len()));
+            grid.items.extend(items);
+        }
+
+        grid
+    }
+
+    /// Finds the coordinates of the cell containing the given point, clamped to the grid.
+    fn cell_containing(&self, point: &Vec3) -> [usize; 3] {
+        std::array::from_fn(|axis| {
+            if self.resolution[axis] == 1 {
+                return 0;
+            }
+
+            let cell = ((point[axis] - self.bounds.min[axis]) / self.cell_size[axis]).floor();
+            (cell.max(0.0) as usize).min(self.resolution[axis] - 1)
+        })
+    }
+
+    /// Converts the coordinates of a cell into its index in the list of cells.
+    fn cell_index(&self, cell: &[usize; 3]) -> usize {
+        cell[0] + self.resolution[0] * (cell[1] + self.resolution[1] * cell[2])
+    }
+}
+
+impl Accelerator for Grid {
+    /// Steps through the cells that the ray passes through from nearest to farthest (using
+    /// the 3D-DDA algorithm of Amanatides and Woo), stopping at the first cell within which
+    /// an intersection has been found.
+    fn closest_intersection<'a>(
+        &self,
+        ray: &Ray,
+        intersect_item: &dyn Fn(usize) -> Option<Intersection<'a>>,
+    ) -> Option<Intersection<'a>> {
+        if self.cells.is_empty() {
+            return None;
+        }
+
+        let (t_enter, t_exit) = self.bounds.intersect(ray)?;
+        let mut cell = self.cell_containing(&ray.at(t_enter).truncate());
+
+        // For each axis, the t-value at which the ray crosses into the next cell along that
+        // axis, the change in t between such crossings, and the direction of the step
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        let mut step = [0isize; 3];
+
+        for axis in 0..3 {
+            let direction = ray.direction[axis];
+            if self.resolution[axis] == 1 || direction == 0.0 {
+                continue;
+            }
+
+            let position = ray.position[axis];
+            let cell_min = self.bounds.min[axis] + cell[axis] as f32 * self.cell_size[axis];
+
+            if direction > 0.0 {
+                t_next[axis] = (cell_min + self.cell_size[axis] - position) / direction;
+                step[axis] = 1;
+            } else {
+                t_next[axis] = (cell_min - position) / direction;
+                step[axis] = -1;
+            }
+            t_delta[axis] = self.cell_size[axis] / direction.abs();
+        }
+
+        let mut closest: Option<Intersection> = None;
+
+        loop {
+            let (first, count) = self.cells[self.cell_index(&cell)];
+            for &item in &self.items[first..first + count] {
+                if let Some(intersection) = intersect_item(item) {
+                    if closest.as_ref().map_or(true, |c| intersection < *c) {
+                        closest = Some(intersection);
+                    }
+                }
+            }
+
+            let axis = if t_next[0] < t_next[1] && t_next[0] < t_next[2] {
+                0
+            } else if t_next[1] < t_next[2] {
+                1
+            } else {
+                2
+            };
+
+            // Cells are visited in order along the ray, so no later cell can have a closer
+            // intersection than one within this cell
+            let t_cell_exit = t_next[axis].min(t_exit);
+            if closest
+                .as_ref()
+                .map_or(false, |c| c.component_intersection.t <= t_cell_exit)
+                || t_next[axis] > t_exit
+            {
+                break;
+            }
+
+            match cell[axis].checked_add_signed(step[axis]) {
+                Some(next) if next < self.resolution[axis] => cell[axis] = next,
+                _ => break,
+            }
+            t_next[axis] += t_delta[axis];
+        }
+
+        closest
+    }
+}
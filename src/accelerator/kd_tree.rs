@@ -0,0 +1,216 @@
+//! Kd-trees, which recursively split space in two with axis-aligned planes, placing each item
+//! on whichever sides of the plane its bounds reach.
+
+use super::Accelerator;
+use crate::bounding_box::BoundingBox;
+use crate::intersection::Intersection;
+use crate::raytracer::Ray;
+use std::cmp::Ordering;
+
+/// Maximum number of items stored in a single leaf of the tree, unless splitting it further
+/// wouldn't separate its items.
+const MAX_ITEMS_PER_LEAF: usize = 2;
+
+/// A node of the kd-tree. Leaves refer to a range of the tree's item indices (an item may
+/// be listed in more than one leaf, if its bounds straddle a splitting plane).
+#[derive(Debug)]
+enum KdNode {
+    Leaf {
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        /// Index (0 = x, 1 = y, 2 = z) of the axis that the splitting plane is normal to.
+        axis: usize,
+        /// Position of the splitting plane along its axis.
+        split: f32,
+        below: usize,
+        above: usize,
+    },
+}
+
+/// A kd-tree over a list of items (such as shapes), which refers to them by their index in
+/// the list.
+#[derive(Debug)]
+pub struct KdTree {
+    /// Nodes of the tree, where the first node is the root.
+    nodes: Vec<KdNode>,
+    /// Indices of the items, in the order in which the leaves refer to them.
+    items: Vec<usize>,
+    /// Bounds of all of the items, which is the region of space that the root node splits.
+    bounds: BoundingBox,
+}
+
+impl KdTree {
+    /// Builds a kd-tree over items with the given bounding boxes. Items with empty bounds
+    /// (which can't be hit) are left out.
+    pub fn new(item_bounds: &[BoundingBox]) -> Self {
+        let items: Vec<usize> = (0..item_bounds.len())
+            .filter(|&item| !item_bounds[item].is_empty())
+            .collect();
+        let bounds = items.iter().fold(BoundingBox::empty(), |bounds, &item| {
+            bounds.union(&item_bounds[item])
+        });
+
+        let mut tree = Self {
+            nodes: Vec::new(),
+            items: Vec::new(),
+            bounds,
+        };
+
+        if !items.is_empty() {
+            // The usual rule of thumb for the depth of a kd-tree, which keeps items that
+            // straddle many splitting planes from being duplicated without limit
+            let max_depth = 8 + (1.3 * (items.len() as f32).log2()) as usize;
+            tree.build_node(items, bounds, item_bounds, max_depth);
+        }
+
+        tree
+    }
+
+    /// Recursively builds the node over the given items within the given region, splitting
+    /// it at the median item centroid along its longest axis. Returns the index of the
+    /// constructed node.
+    fn build_node(
+        &mut self,
+        mut items: Vec<usize>,
+        bounds: BoundingBox,
+        item_bounds: &[BoundingBox],
+        remaining_depth: usize,
+    ) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(KdNode::Leaf {
+            first: self.items.len(),
+            count: items.len(),
+        });
+
+        if items.len() <= MAX_ITEMS_PER_LEAF || remaining_depth == 0 {
+            self.items.extend(items);
+            return index;
+        }
+
+        let axis = bounds.longest_axis();
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |&a, &b| {
+            let a_centroid = item_bounds[a].centroid()[axis];
+            let b_centroid = item_bounds[b].centroid()[axis];
+            a_centroid
+                .partial_cmp(&b_centroid)
+                .unwrap_or(Ordering::Equal)
+        });
+        let split = item_bounds[items[middle]].centroid()[axis];
+
+        let below_items: Vec<usize> = items
+            .iter()
+            .copied()
+            .filter(|&item| item_bounds[item].min[axis] <= split)
+            .collect();
+        let above_items: Vec<usize> = items
+            .iter()
+            .copied()
+            .filter(|&item| item_bounds[item].max[axis] >= split)
+            .collect();
+
+        // Stop if the plane doesn't separate any of the items, since splitting would only
+        // duplicate them
+        if below_items.len() == items.len() && above_items.len() == items.len() {
+            self.items.extend(items);
+            return index;
+        }
+
+        let mut below_bounds = bounds;
+        below_bounds.max[axis] = split;
+        let mut above_bounds = bounds;
+        above_bounds.min[axis] = split;
+
+        let below = self.build_node(below_items, below_bounds, item_bounds, remaining_depth - 1);
+        let above = self.build_node(above_items, above_bounds, item_bounds, remaining_depth - 1);
+
+        self.nodes[index] = KdNode::Interior {
+            axis,
+            split,
+            below,
+            above,
+        };
+
+        index
+    }
+}
+
+impl Accelerator for KdTree {
+    /// Visits the leaves that the ray passes through from nearest to farthest, stopping at
+    /// the first leaf within which an intersection has been found.
+    fn closest_intersection<'a>(
+        &self,
+        ray: &Ray,
+        intersect_item: &dyn Fn(usize) -> Option<Intersection<'a>>,
+    ) -> Option<Intersection<'a>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let (t_enter, t_exit) = self.bounds.intersect(ray)?;
+        let mut closest: Option<Intersection> = None;
+
+        // Nodes to visit, with the range of t-values over which the ray is within each of them
+        let mut stack = vec![(0, t_enter, t_exit)];
+
+        while let Some((node_index, t_min, t_max)) = stack.pop() {
+            match self.nodes[node_index] {
+                KdNode::Leaf { first, count } => {
+                    for &item in &self.items[first..first + count] {
+                        if let Some(intersection) = intersect_item(item) {
+                            if closest.as_ref().map_or(true, |c| intersection < *c) {
+                                closest = Some(intersection);
+                            }
+                        }
+                    }
+
+                    // Leaves are visited in order along the ray, so no later leaf can have
+                    // a closer intersection than one within this leaf
+                    if closest
+                        .as_ref()
+                        .map_or(false, |c| c.component_intersection.t <= t_max)
+                    {
+                        break;
+                    }
+                }
+                KdNode::Interior {
+                    axis,
+                    split,
+                    below,
+                    above,
+                } => {
+                    let origin = ray.position[axis];
+                    let direction = ray.direction[axis];
+
+                    // The child containing the start of the ray's range is visited first
+                    let below_first = origin < split || (origin == split && direction <= 0.0);
+                    let (near, far) = if below_first {
+                        (below, above)
+                    } else {
+                        (above, below)
+                    };
+
+                    if direction == 0.0 {
+                        stack.push((near, t_min, t_max));
+                        continue;
+                    }
+
+                    let t_split = (split - origin) / direction;
+
+                    if t_split > t_max || t_split <= 0.0 {
+                        stack.push((near, t_min, t_max));
+                    } else if t_split < t_min {
+                        stack.push((far, t_min, t_max));
+                    } else {
+                        stack.push((far, t_split, t_max));
+                        stack.push((near, t_min, t_split));
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
@@ -0,0 +1,102 @@
+//! Acceleration structures over the shapes of a scene, so that rays are only tested against
+//! the shapes near their path rather than every shape in the scene.
+
+use crate::bounding_box::BoundingBox;
+use crate::intersection::Intersection;
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::raytracer::Ray;
+use anyhow::{bail, Error, Result};
+use std::str::FromStr;
+
+mod bvh;
+mod grid;
+mod kd_tree;
+mod octree;
+
+pub use bvh::Bvh;
+pub use grid::Grid;
+pub use kd_tree::KdTree;
+pub use octree::Octree;
+
+/// A spatial structure over a list of items (such as shapes), which refers to them by their
+/// index in the list.
+pub trait Accelerator: std::fmt::Debug + Send + Sync {
+    /// Finds the closest intersection between the ray and any item, using the given function
+    /// to intersect the ray with the item at an index.
+    fn closest_intersection<'a>(
+        &self,
+        ray: &Ray,
+        intersect_item: &dyn Fn(usize) -> Option<Intersection<'a>>,
+    ) -> Option<Intersection<'a>>;
+
+    /// Finds the closest intersection of each active ray of the packet with any item, using
+    /// the given function to intersect the ray in a lane with the item at an index. By
+    /// default, each ray is traced separately.
+    fn closest_intersections<'a>(
+        &self,
+        packet: &RayPacket,
+        intersect_item: &dyn Fn(usize, usize) -> Option<Intersection<'a>>,
+    ) -> [Option<Intersection<'a>>; PACKET_SIZE] {
+        std::array::from_fn(|lane| {
+            if packet.is_active(lane) {
+                self.closest_intersection(&packet.rays[lane], &|item| intersect_item(lane, item))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Updates the structure in place for items whose bounds have changed (given the new
+    /// bounds of every item), keeping how it divides the items, which is much quicker than
+    /// rebuilding it after small edits (though traversal slows as the items drift from where
+    /// they were when it was built). Returns whether the structure could be refit: if not
+    /// (which is the default), it must be rebuilt instead.
+    fn refit(&mut self, _item_bounds: &[BoundingBox]) -> bool {
+        false
+    }
+}
+
+/// The kinds of acceleration structure that can be used for a scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceleratorKind {
+    /// A bounding volume hierarchy, which is quick to build.
+    Bvh,
+    /// A kd-tree, which splits space (rather than the items) and so may be traversed more
+    /// quickly in dense scenes, but takes longer to build.
+    KdTree,
+    /// A uniform grid of cells, which suits scenes whose items are evenly distributed.
+    Grid,
+    /// A loose octree, which stores each item once at the level matching its size, and so
+    /// suits clusters of heavily overlapping items at many scales (e.g. instanced fractals).
+    Octree,
+}
+
+impl AcceleratorKind {
+    /// Names of all the acceleration structures, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["bvh", "kd-tree", "grid", "octree"];
+
+    /// Builds an acceleration structure of this kind over items with the given bounding
+    /// boxes. Items with empty bounds (which can't be hit) are left out.
+    pub fn build(&self, item_bounds: &[BoundingBox]) -> Box<dyn Accelerator> {
+        match self {
+            AcceleratorKind::Bvh => Box::new(Bvh::new(item_bounds)),
+            AcceleratorKind::KdTree => Box::new(KdTree::new(item_bounds)),
+            AcceleratorKind::Grid => Box::new(Grid::new(item_bounds)),
+            AcceleratorKind::Octree => Box::new(Octree::new(item_bounds)),
+        }
+    }
+}
+
+impl FromStr for AcceleratorKind {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "bvh" => Ok(AcceleratorKind::Bvh),
+            "kd-tree" => Ok(AcceleratorKind::KdTree),
+            "grid" => Ok(AcceleratorKind::Grid),
+            "octree" => Ok(AcceleratorKind::Octree),
+            other_name => bail!("Unknown acceleration structure {}", other_name),
+        }
+    }
+}
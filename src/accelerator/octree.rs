@@ -0,0 +1,197 @@
+//! Loose octrees, which recursively split a cube into eight octants, storing each item in the
+//! smallest node that can hold it. The octants of a loose octree overlap (each reaches half
+//! its size beyond its cell), so that an item straddling a boundary still fits in a small
+//! node instead of being duplicated or kept at the top of the tree. This suits scenes of
+//! heavily overlapping items at many scales, such as recursively instanced clusters.
+
+use super::Accelerator;
+use crate::bounding_box::BoundingBox;
+use crate::intersection::Intersection;
+use crate::math::Vec3;
+use crate::raytracer::Ray;
+
+/// Maximum number of items in a node before its items are pushed down into its octants.
+const MAX_ITEMS_PER_NODE: usize = 4;
+
+/// Maximum depth of the tree, which bounds the work done for many coincident items.
+const MAX_DEPTH: usize = 16;
+
+/// A node of the octree, which holds the items that don't fit in any of its octants.
+#[derive(Debug)]
+struct OctreeNode {
+    /// Bounds of the items within the node and all of its descendants (which may be much
+    /// smaller than the node's cell).
+    bounds: BoundingBox,
+    /// Range of the octree's item indices held by this node.
+    first: usize,
+    count: usize,
+    /// Indices of the nodes of the octants that contain any items.
+    children: Vec<usize>,
+}
+
+/// A loose octree over a list of items (such as shapes), which refers to them by their
+/// index in the list.
+#[derive(Debug)]
+pub struct Octree {
+    /// Nodes of the tree, where the first node is the root.
+    nodes: Vec<OctreeNode>,
+    /// Indices of the items, in the order in which the nodes refer to them.
+    items: Vec<usize>,
+}
+
+impl Octree {
+    /// Builds an octree over items with the given bounding boxes. Items with empty bounds
+    /// (which can't be hit) are left out.
+    pub fn new(item_bounds: &[BoundingBox]) -> Self {
+        let items: Vec<usize> = (0..item_bounds.len())
+            .filter(|&item| !item_bounds[item].is_empty())
+            .collect();
+        let bounds = items.iter().fold(BoundingBox::empty(), |bounds, &item| {
+            bounds.union(&item_bounds[item])
+        });
+
+        let mut tree = Self {
+            nodes: Vec::new(),
+            items: Vec::new(),
+        };
+
+        if !items.is_empty() {
+            // The root's cell is the cube around all of the items
+            let extent = bounds.max - bounds.min;
+            let half_size = extent.max_element() / 2.0;
+            tree.build_node(items, bounds.centroid(), half_size, item_bounds, 0);
+        }
+
+        tree
+    }
+
+    /// Recursively builds the node over the given items, whose cell is the cube with the
+    /// given center and half size. Returns the index of the constructed node.
+    fn build_node(
+        &mut self,
+        items: Vec<usize>,
+        center: Vec3,
+        half_size: f32,
+        item_bounds: &[BoundingBox],
+        depth: usize,
+    ) -> usize {
+        let bounds = items.iter().fold(BoundingBox::empty(), |bounds, &item| {
+            bounds.union(&item_bounds[item])
+        });
+
+        let index = self.nodes.len();
+        self.nodes.push(OctreeNode {
+            bounds,
+            first: 0,
+            count: 0,
+            children: Vec::new(),
+        });
+
+        // An item fits in the octant containing its centroid if it reaches no further beyond
+        // the octant's cell than the octant's looseness allows (half the parent's half size)
+        let octant_half_size = half_size / 2.0;
+        let splits = items.len() > MAX_ITEMS_PER_NODE && depth < MAX_DEPTH;
+        let mut held = Vec::new();
+        let mut octants = vec![Vec::new(); 8];
+        for item in items {
+            let item_half_extent = (item_bounds[item].max - item_bounds[item].min) / 2.0;
+
+            if splits && item_half_extent.max_element() <= octant_half_size {
+                octants[Self::octant(&center, &item_bounds[item].centroid())].push(item);
+            } else {
+                held.push(item);
+            }
+        }
+
+        self.nodes[index].first = self.items.len();
+        self.nodes[index].count = held.len();
+        self.items.extend(held);
+
+        for (octant, octant_items) in octants.into_iter().enumerate() {
+            if octant_items.is_empty() {
+                continue;
+            }
+
+            let offset = Vec3::from_array(std::array::from_fn(|axis| {
+                if octant & (1 << axis) == 0 {
+                    -octant_half_size
+                } else {
+                    octant_half_size
+                }
+            }));
+            let child = self.build_node(
+                octant_items,
+                center + offset,
+                octant_half_size,
+                item_bounds,
+                depth + 1,
+            );
+            self.nodes[index].children.push(child);
+        }
+
+        index
+    }
+
+    /// The index of the octant (with bit 0 set for the upper half along x, bit 1 for y, and
+    /// bit 2 for z) of the cell with the given center that contains the given point.
+    fn octant(center: &Vec3, point: &Vec3) -> usize {
+        (0..3)
+            .filter(|&axis| point[axis] >= center[axis])
+            .fold(0, |octant, axis| octant | (1 << axis))
+    }
+}
+
+impl Accelerator for Octree {
+    /// Visits the nodes whose bounds the ray passes through (in front of the closest
+    /// intersection found so far), visiting the octants of each node from nearest to
+    /// farthest, so that close hits are found early and prune the farther octants.
+    fn closest_intersection<'a>(
+        &self,
+        ray: &Ray,
+        intersect_item: &dyn Fn(usize) -> Option<Intersection<'a>>,
+    ) -> Option<Intersection<'a>> {
+        let mut closest: Option<Intersection> = None;
+
+        let Some((t_enter, _)) = self
+            .nodes
+            .first()
+            .and_then(|root| root.bounds.intersect(ray))
+        else {
+            return None;
+        };
+
+        // Nodes to visit, along with the t-value at which the ray enters their bounds
+        let mut stack = vec![(0, t_enter)];
+        let mut children = Vec::with_capacity(8);
+
+        while let Some((node_index, t_enter)) = stack.pop() {
+            if closest
+                .as_ref()
+                .map_or(false, |c| t_enter > c.component_intersection.t)
+            {
+                continue;
+            }
+
+            let node = &self.nodes[node_index];
+            for &item in &self.items[node.first..node.first + node.count] {
+                if let Some(intersection) = intersect_item(item) {
+                    if closest.as_ref().map_or(true, |c| intersection < *c) {
+                        closest = Some(intersection);
+                    }
+                }
+            }
+
+            children.clear();
+            children.extend(node.children.iter().filter_map(|&child| {
+                let (t_enter, _) = self.nodes[child].bounds.intersect(ray)?;
+                Some((child, t_enter))
+            }));
+
+            // The nearest octant is pushed last, so that it is visited first
+            children.sort_unstable_by(|(_, a), (_, b)| b.total_cmp(a));
+            stack.extend_from_slice(&children);
+        }
+
+        closest
+    }
+}
@@ -0,0 +1,227 @@
+//! Accumulation buffers for progressive rendering, which record the sum of every sample
+//! taken for each pixel so that more samples can be added to an image at any time.
+//!
+//! On disk, an accumulation is stored as the magic bytes `RTACCUM2`, followed by its width,
+//! height, and row offset (as little-endian `u32`s), followed by each pixel in row-major
+//! order as its sample count (`u32`), the red, green, and blue sums of its samples, and the
+//! sums of their squares (`f32`s), also little-endian.
+
+use crate::lights;
+use crate::math::{vec4, Vec4};
+use crate::partition::Partition;
+use anyhow::{bail, Context, Result};
+use image::RgbImage;
+use std::fs;
+use std::path::Path;
+
+/// Magic bytes identifying an accumulation file (and the version of its format).
+const MAGIC: &[u8; 8] = b"RTACCUM2";
+
+/// Number of bytes used to store each pixel in an accumulation file.
+const BYTES_PER_PIXEL: usize = 28;
+
+/// Running sums of the samples taken for a single pixel, from which their mean and variance
+/// can be found.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelSamples {
+    pub count: u32,
+    sum: [f32; 3],
+    sum_of_squares: [f32; 3],
+}
+
+impl PixelSamples {
+    /// Adds a sample of the given intensity.
+    pub fn add(&mut self, intensity: &Vec4) {
+        self.count += 1;
+        for channel in 0..3 {
+            self.sum[channel] += intensity[channel];
+            self.sum_of_squares[channel] += intensity[channel] * intensity[channel];
+        }
+    }
+
+    /// The average intensity of the samples.
+    pub fn mean(&self) -> Vec4 {
+        let count = self.count.max(1) as f32;
+        vec4(self.sum[0], self.sum[1], self.sum[2], 1.0) / count
+    }
+
+    /// Estimates the variance of the mean of the samples (i.e. of the pixel's value), for
+    /// each of the red, green, and blue channels. This is 0 for pixels with fewer than 2 samples.
+    pub fn variance(&self) -> [f32; 3] {
+        if self.count < 2 {
+            return [0.0; 3];
+        }
+
+        let count = self.count as f32;
+        [0, 1, 2].map(|channel| {
+            let sample_variance = (self.sum_of_squares[channel]
+                - self.sum[channel] * self.sum[channel] / count)
+                / (count - 1.0);
+            sample_variance.max(0.0) / count
+        })
+    }
+}
+
+/// The samples taken for each pixel of (part of) an image.
+#[derive(Debug)]
+pub struct Accumulation {
+    width: u32,
+    height: u32,
+    /// Row of the full image that the first row of the accumulation corresponds to.
+    row_offset: u32,
+    pixels: Vec<PixelSamples>,
+}
+
+impl Accumulation {
+    /// Constructs an empty accumulation for the rows rendered for the given partition.
+    pub fn new(partition: &Partition) -> Self {
+        let pixel_count = partition.image_width as usize * partition.rendered_height() as usize;
+
+        Self {
+            width: partition.image_width,
+            height: partition.rendered_height(),
+            row_offset: partition.rendered_row_start(),
+            pixels: vec![PixelSamples::default(); pixel_count],
+        }
+    }
+
+    /// Loads the accumulation at the given path if it exists (validating that it covers the
+    /// rows rendered for the partition), otherwise constructing an empty one.
+    pub fn load_or_new(path: &Path, partition: &Partition) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Accumulation::new(partition));
+        }
+
+        let accumulation = Accumulation::load(path)?;
+
+        if (
+            accumulation.width,
+            accumulation.height,
+            accumulation.row_offset,
+        ) != (
+            partition.image_width,
+            partition.rendered_height(),
+            partition.rendered_row_start(),
+        ) {
+            bail!(
+                "Accumulation file {} ({}x{} from row {}) does not match the image being rendered ({}x{} from row {})",
+                path.display(),
+                accumulation.width,
+                accumulation.height,
+                accumulation.row_offset,
+                partition.image_width,
+                partition.rendered_height(),
+                partition.rendered_row_start()
+            );
+        }
+
+        Ok(accumulation)
+    }
+
+    /// Loads an accumulation from the file at the given path.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read accumulation file: {}", path.display()))?;
+
+        let header_length = MAGIC.len() + 12;
+        if bytes.len() < header_length || &bytes[..MAGIC.len()] != MAGIC {
+            bail!("{} is not an accumulation file", path.display());
+        }
+
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let read_f32 =
+            |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let read_rgb = |offset: usize| [0, 4, 8].map(|channel| read_f32(offset + channel));
+
+        let width = read_u32(MAGIC.len());
+        let height = read_u32(MAGIC.len() + 4);
+        let row_offset = read_u32(MAGIC.len() + 8);
+        let pixel_count = width as usize * height as usize;
+
+        if bytes.len() != header_length + pixel_count * BYTES_PER_PIXEL {
+            bail!(
+                "Accumulation file {} is truncated or corrupt",
+                path.display()
+            );
+        }
+
+        let pixels = (0..pixel_count)
+            .map(|pixel_index| {
+                let offset = header_length + pixel_index * BYTES_PER_PIXEL;
+                PixelSamples {
+                    count: read_u32(offset),
+                    sum: read_rgb(offset + 4),
+                    sum_of_squares: read_rgb(offset + 16),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            row_offset,
+            pixels,
+        })
+    }
+
+    /// Saves the accumulation to the file at the given path. The file is replaced atomically,
+    /// so that an interruption while saving never leaves a partially written accumulation.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 12 + self.pixels.len() * BYTES_PER_PIXEL);
+        bytes.extend_from_slice(MAGIC);
+
+        for value in [self.width, self.height, self.row_offset] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for pixel in &self.pixels {
+            bytes.extend_from_slice(&pixel.count.to_le_bytes());
+            for channel in pixel.sum.iter().chain(&pixel.sum_of_squares) {
+                bytes.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+
+        fs::write(&temporary_path, bytes)
+            .and_then(|_| fs::rename(&temporary_path, path))
+            .with_context(|| format!("Failed to write accumulation file: {}", path.display()))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Row of the full image that the first row of the accumulation corresponds to.
+    pub fn row_offset(&self) -> u32 {
+        self.row_offset
+    }
+
+    /// The samples that have been taken for the pixel at the given column/row.
+    pub fn pixel(&self, col: u32, row: u32) -> &PixelSamples {
+        &self.pixels[self.pixel_index(col, row)]
+    }
+
+    /// Mutable access to the samples that have been taken for the pixel at the given column/row.
+    pub fn pixel_mut(&mut self, col: u32, row: u32) -> &mut PixelSamples {
+        let index = self.pixel_index(col, row);
+        &mut self.pixels[index]
+    }
+
+    /// Produces an image in which each pixel is the average of the samples taken for it.
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |col, row| {
+            lights::to_rgb(&self.pixel(col, row).mean())
+        })
+    }
+
+    fn pixel_index(&self, col: u32, row: u32) -> usize {
+        row as usize * self.width as usize + col as usize
+    }
+}
@@ -0,0 +1,195 @@
+//! Arbitrary output variables (AOVs): images of per-pixel data other than the final color,
+//! which are written alongside the rendered image.
+
+use crate::accumulation::Accumulation;
+use crate::math::{Mat4, Vec4};
+use anyhow::{bail, Error, Result};
+use image::{DynamicImage, Rgb, Rgb32FImage};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A kind of per-pixel data that can be output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// The number of samples taken for each pixel.
+    SampleCount,
+    /// The estimated variance of each pixel's value, per color channel.
+    Variance,
+    /// The ID of the shape seen through the center of each pixel (see [`object_id_value`]).
+    ObjectId,
+    /// The unit normal of the surface seen through the center of each pixel, in the space
+    /// given by `--normal-space`.
+    Normal,
+}
+
+/// What is seen through the center of a pixel, from which the AOVs describing the scene's
+/// geometry are made.
+#[derive(Debug, Clone, Copy)]
+pub struct CenterHit {
+    /// Position of the shape in the scenefile.
+    pub shape_id: usize,
+    /// Unit normal of the surface, in the space of the normal AOV.
+    pub normal: Vec4,
+}
+
+/// The space that the normals of the normal AOV are given in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalSpace {
+    World,
+    /// Relative to the camera, which looks down its -z axis with +y up.
+    Camera,
+}
+
+impl NormalSpace {
+    /// Names of all the spaces, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["world", "camera"];
+
+    /// The matrix transforming world space directions into this space, for the camera with
+    /// the given inverse view matrix.
+    pub fn from_world(&self, inverse_view_matrix: &Mat4) -> Mat4 {
+        match self {
+            NormalSpace::World => Mat4::IDENTITY,
+            NormalSpace::Camera => inverse_view_matrix.inverse(),
+        }
+    }
+}
+
+impl FromStr for NormalSpace {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "world" => Ok(NormalSpace::World),
+            "camera" => Ok(NormalSpace::Camera),
+            other_name => bail!("Unknown normal space {}", other_name),
+        }
+    }
+}
+
+/// How the values of an AOV are stored in its image file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AovEncoding {
+    /// Floating point values in an EXR image, as they are.
+    Exr,
+    /// 8-bit values in a PNG image, remapped from -1-1 to 0-1 (as normal maps are stored).
+    Png,
+}
+
+impl AovEncoding {
+    /// Names of all the encodings, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["exr", "png"];
+
+    fn extension(&self) -> &'static str {
+        match self {
+            AovEncoding::Exr => "exr",
+            AovEncoding::Png => "png",
+        }
+    }
+
+    /// Saves an AOV image, which has already been remapped if this is a remapping encoding.
+    pub fn save(&self, image: &Rgb32FImage, path: &Path) -> Result<()> {
+        match self {
+            AovEncoding::Exr => image.save(path)?,
+            AovEncoding::Png => DynamicImage::ImageRgb32F(image.clone())
+                .into_rgb8()
+                .save(path)?,
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for AovEncoding {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "exr" => Ok(AovEncoding::Exr),
+            "png" => Ok(AovEncoding::Png),
+            other_name => bail!("Unknown AOV encoding {}", other_name),
+        }
+    }
+}
+
+/// The value of the object ID AOV for a pixel through which the shape with the given
+/// position in the scenefile (if any) is seen. Shapes are numbered from 1, so that pixels
+/// which show no shape are 0.
+pub fn object_id_value(shape_id: Option<usize>) -> f32 {
+    shape_id.map_or(0.0, |shape_id| (shape_id + 1) as f32)
+}
+
+impl Aov {
+    /// Names of all the AOVs, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["sample-count", "variance", "object-id", "normal"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::SampleCount => "sample-count",
+            Aov::Variance => "variance",
+            Aov::ObjectId => "object-id",
+            Aov::Normal => "normal",
+        }
+    }
+
+    /// How the AOV is stored, given the encoding chosen for normals (`--normal-encoding`).
+    /// Every other AOV is a floating point EXR image.
+    pub fn encoding(&self, normal_encoding: AovEncoding) -> AovEncoding {
+        match self {
+            Aov::Normal => normal_encoding,
+            _ => AovEncoding::Exr,
+        }
+    }
+
+    /// Path where the AOV is saved with the given encoding, given the path of the rendered
+    /// image, e.g. `image.png` has the variance AOV `image.variance.exr`.
+    pub fn output_path(&self, output: &Path, encoding: AovEncoding) -> PathBuf {
+        output.with_extension(format!("{}.{}", self.name(), encoding.extension()))
+    }
+
+    /// Produces the image of this AOV (with the given encoding) from the samples taken for
+    /// each pixel, and the function giving what (if anything) is seen through the center of
+    /// the pixel at each column/row of the accumulation.
+    pub fn image<F>(
+        &self,
+        accumulation: &Accumulation,
+        encoding: AovEncoding,
+        center_hit: F,
+    ) -> Rgb32FImage
+    where
+        F: Fn(u32, u32) -> Option<CenterHit>,
+    {
+        Rgb32FImage::from_fn(accumulation.width(), accumulation.height(), |col, row| {
+            let pixel = accumulation.pixel(col, row);
+
+            match self {
+                Aov::SampleCount => Rgb([pixel.count as f32; 3]),
+                Aov::Variance => Rgb(pixel.variance()),
+                Aov::ObjectId => {
+                    Rgb([object_id_value(center_hit(col, row).map(|hit| hit.shape_id)); 3])
+                }
+                Aov::Normal => {
+                    let normal = center_hit(col, row).map_or(Vec4::ZERO, |hit| hit.normal);
+                    let [x, y, z] = normal.truncate().to_array();
+                    match encoding {
+                        AovEncoding::Exr => Rgb([x, y, z]),
+                        AovEncoding::Png => Rgb([x, y, z].map(|value| value * 0.5 + 0.5)),
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl FromStr for Aov {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "sample-count" => Ok(Aov::SampleCount),
+            "variance" => Ok(Aov::Variance),
+            "object-id" => Ok(Aov::ObjectId),
+            "normal" => Ok(Aov::Normal),
+            other_name => bail!("Unknown AOV {}", other_name),
+        }
+    }
+}
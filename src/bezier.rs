@@ -0,0 +1,297 @@
+//! Bicubic Bezier patches, which are intersected by refining an initial guess (from a grid
+//! of bounding boxes over the patch) using Newton's method.
+
+use crate::bounding_box::BoundingBox;
+use crate::intersection::ComponentIntersection;
+use crate::math::{vec3, Vec3};
+use crate::primitive::PrimitiveComponent;
+use crate::raytracer::Ray;
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+/// Number of sub-patches along each parametric direction of a patch, each of which is
+/// bounded separately to find starting points for Newton's method.
+const SUBDIVISIONS: usize = 8;
+
+/// Maximum number of Newton iterations made from each starting point.
+const MAX_NEWTON_ITERATIONS: usize = 12;
+
+/// Distance between the ray and the surface below which Newton's method has converged.
+const CONVERGENCE_THRESHOLD: f32 = 1e-4;
+
+/// Amount by which the parametric coordinates of a solution may lie outside the patch.
+const PARAMETER_TOLERANCE: f32 = 1e-3;
+
+/// Control points of a bicubic patch, indexed as `[v][u]`.
+pub type ControlPoints = [[Vec3; 4]; 4];
+
+/// A region of a patch that has its own bounding box.
+#[derive(Debug)]
+struct SubPatch {
+    bounds: BoundingBox,
+    /// Parametric coordinates at the center of the region.
+    center: (f32, f32),
+}
+
+/// A bicubic Bezier patch, parameterized over u and v in [0, 1], in object space. Patches
+/// are two-sided: their normals always face the ray that intersects them.
+#[derive(Debug)]
+pub struct BezierPatch {
+    control_points: ControlPoints,
+    sub_patches: Vec<SubPatch>,
+}
+
+impl BezierPatch {
+    /// Constructs a patch from its control points, bounding each of its sub-patches.
+    pub fn new(control_points: ControlPoints) -> Self {
+        let size = 1.0 / SUBDIVISIONS as f32;
+        let mut sub_patches = Vec::new();
+
+        for i in 0..SUBDIVISIONS {
+            // Control points of the strip of the patch within this range of u
+            let u_range = (i as f32 * size, (i + 1) as f32 * size);
+            let strip = control_points.map(|row| sub_curve(&row, u_range));
+
+            for j in 0..SUBDIVISIONS {
+                let v_range = (j as f32 * size, (j + 1) as f32 * size);
+                let columns = (0..4).map(|k| sub_curve(&strip.map(|row| row[k]), v_range));
+
+                // By the convex hull property, the sub-patch lies within the bounds of its
+                // control points
+                let bounds = columns.fold(BoundingBox::empty(), |bounds, column| {
+                    bounds.union(&BoundingBox::from_points(&column))
+                });
+
+                sub_patches.push(SubPatch {
+                    bounds,
+                    center: ((u_range.0 + u_range.1) / 2.0, (v_range.0 + v_range.1) / 2.0),
+                });
+            }
+        }
+
+        Self {
+            control_points,
+            sub_patches,
+        }
+    }
+
+    /// Evaluates the patch's position, and its partial derivatives with respect to u and v,
+    /// at the given parametric coordinates.
+    fn evaluate(&self, u: f32, v: f32) -> (Vec3, Vec3, Vec3) {
+        let (basis_u, derivative_u) = bernstein(u);
+        let (basis_v, derivative_v) = bernstein(v);
+
+        let zero = vec3(0.0, 0.0, 0.0);
+        let (mut position, mut tangent_u, mut tangent_v) = (zero, zero, zero);
+
+        for (row, points) in self.control_points.iter().enumerate() {
+            for (col, &point) in points.iter().enumerate() {
+                position += point * (basis_v[row] * basis_u[col]);
+                tangent_u += point * (basis_v[row] * derivative_u[col]);
+                tangent_v += point * (derivative_v[row] * basis_u[col]);
+            }
+        }
+
+        (position, tangent_u, tangent_v)
+    }
+
+    /// Determines the surface normal at the given parametric coordinates (not normalized).
+    fn normal(&self, u: f32, v: f32) -> Vec3 {
+        let (_, tangent_u, tangent_v) = self.evaluate(u, v);
+        let normal = tangent_u.cross(tangent_v);
+
+        // At degenerate points (like where a row of control points coincide) one of the
+        // tangents vanishes, so use the normal at a nearby point instead
+        if normal.length() > 1e-6 {
+            normal
+        } else {
+            let nudge = |t: f32| if t < 0.5 { t + 1e-3 } else { t - 1e-3 };
+            let (_, tangent_u, tangent_v) = self.evaluate(nudge(u), nudge(v));
+            tangent_u.cross(tangent_v)
+        }
+    }
+
+    /// Uses Newton's method to solve for the (u, v, t) at which the ray meets the patch,
+    /// starting from the given guess.
+    fn newton(&self, ray: &Ray, (mut u, mut v, mut t): (f32, f32, f32)) -> Option<(f32, f32, f32)> {
+        let origin = ray.position.truncate();
+        let direction = ray.direction.truncate();
+
+        for _ in 0..MAX_NEWTON_ITERATIONS {
+            let (position, tangent_u, tangent_v) = self.evaluate(u, v);
+
+            // The difference between the surface point and the ray point, which should be zero
+            let error = position - (origin + direction * t);
+            if error.length() < CONVERGENCE_THRESHOLD {
+                let in_patch =
+                    |t: f32| (-PARAMETER_TOLERANCE..=1.0 + PARAMETER_TOLERANCE).contains(&t);
+                return (in_patch(u) && in_patch(v) && ray.contains(t))
+                    .then(|| (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0), t));
+            }
+
+            // Solve J * delta = error, where the columns of the Jacobian J are the
+            // derivatives of the error with respect to u, v, and t (using Cramer's rule)
+            let negative_direction = -direction;
+            let determinant = tangent_u.dot(tangent_v.cross(negative_direction));
+            if determinant.abs() < 1e-12 {
+                return None;
+            }
+
+            u -= error.dot(tangent_v.cross(negative_direction)) / determinant;
+            v -= tangent_u.dot(error.cross(negative_direction)) / determinant;
+            t -= tangent_u.dot(tangent_v.cross(error)) / determinant;
+        }
+
+        None
+    }
+}
+
+impl PrimitiveComponent for BezierPatch {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        // Visit the sub-patches that the ray passes through, from nearest to farthest
+        let mut candidates: Vec<(f32, f32, &SubPatch)> = self
+            .sub_patches
+            .iter()
+            .filter_map(|sub_patch| {
+                let (t_enter, t_exit) = sub_patch.bounds.intersect(ray)?;
+                Some((t_enter, t_exit, sub_patch))
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        let mut closest: Option<(f32, f32, f32)> = None;
+
+        for (t_enter, t_exit, sub_patch) in candidates {
+            if closest.map_or(false, |(_, _, t)| t < t_enter) {
+                break;
+            }
+
+            let guess = (
+                sub_patch.center.0,
+                sub_patch.center.1,
+                (t_enter + t_exit) / 2.0,
+            );
+            if let Some(solution) = self.newton(ray, guess) {
+                if closest.map_or(true, |(_, _, t)| solution.2 < t) {
+                    closest = Some(solution);
+                }
+            }
+        }
+
+        let (u, v, t) = closest?;
+
+        let mut normal = self.normal(u, v).normalize();
+        if normal.dot(ray.direction.truncate()) > 0.0 {
+            normal = -normal;
+        }
+
+        let (_, dp_du, dp_dv) = self.evaluate(u, v);
+
+        Some(ComponentIntersection::new(
+            t,
+            normal.extend(0.0),
+            (u, v),
+            dp_du,
+            dp_dv,
+        ))
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.sub_patches
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, sub_patch| {
+                bounds.union(&sub_patch.bounds)
+            })
+    }
+}
+
+/// Evaluates the cubic Bernstein polynomials, and their derivatives, at t.
+fn bernstein(t: f32) -> ([f32; 4], [f32; 4]) {
+    let s = 1.0 - t;
+
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * s - 6.0 * t * s,
+            6.0 * t * s - 3.0 * t * t,
+            3.0 * t * t,
+        ],
+    )
+}
+
+/// Evaluates the blossom of the cubic Bezier curve with the given control points at the
+/// parameters (a, b, c), using de Casteljau's algorithm with a different parameter at each level.
+fn blossom(points: &[Vec3; 4], parameters: [f32; 3]) -> Vec3 {
+    let mut points = points.to_vec();
+
+    for t in parameters {
+        points = points
+            .windows(2)
+            .map(|pair| pair[0] * (1.0 - t) + pair[1] * t)
+            .collect();
+    }
+
+    points[0]
+}
+
+/// Finds the control points of the part of a cubic Bezier curve between two parameters.
+fn sub_curve(points: &[Vec3; 4], (start, end): (f32, f32)) -> [Vec3; 4] {
+    [
+        blossom(points, [start, start, start]),
+        blossom(points, [start, start, end]),
+        blossom(points, [start, end, end]),
+        blossom(points, [end, end, end]),
+    ]
+}
+
+/// Loads the patches in a file of the Bezier patch format used to distribute the Utah
+/// teapot: the number of patches, followed by each patch as a line with its degree in
+/// u and v (which must be "3 3") and 16 lines of "x y z" control points.
+pub fn load_patches(path: &Path) -> Result<Vec<ControlPoints>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read patch file: {}", path.display()))?;
+
+    parse_patches(&contents)
+        .with_context(|| format!("Failed to parse patch file: {}", path.display()))
+}
+
+fn parse_patches(contents: &str) -> Result<Vec<ControlPoints>> {
+    let mut numbers = contents.split_whitespace().map(|token| {
+        token
+            .parse::<f32>()
+            .map_err(|_| anyhow!("Invalid number \"{}\"", token))
+    });
+    let mut next_number = || {
+        numbers
+            .next()
+            .unwrap_or_else(|| bail!("Unexpected end of file"))
+    };
+
+    let patch_count = next_number()?;
+    let mut patches = Vec::new();
+
+    for patch_index in 0..patch_count as usize {
+        let degrees = (next_number()?, next_number()?);
+        if degrees != (3.0, 3.0) {
+            bail!(
+                "Patch {} has degree {:?}, but only bicubic patches are supported",
+                patch_index,
+                degrees
+            );
+        }
+
+        let mut control_points = [[vec3(0.0, 0.0, 0.0); 4]; 4];
+        for row in control_points.iter_mut() {
+            for point in row.iter_mut() {
+                *point = vec3(next_number()?, next_number()?, next_number()?);
+            }
+        }
+
+        patches.push(control_points);
+    }
+
+    Ok(patches)
+}
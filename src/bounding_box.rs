@@ -0,0 +1,144 @@
+//! Axis-aligned bounding boxes, used to quickly rule out intersections with groups of geometry.
+
+use crate::math::{vec3, vec4, Mat4, Vec3};
+use crate::raytracer::Ray;
+
+/// An axis-aligned box, described by its minimum and maximum corners.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox {
+    /// Constructs a bounding box that contains nothing, and which acts as the identity for `union`.
+    pub fn empty() -> Self {
+        Self {
+            min: vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    /// Constructs the smallest bounding box containing all of the given points.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(BoundingBox::empty(), |bounds, point| {
+                bounds.including(point)
+            })
+    }
+
+    /// Grows this bounding box so that it includes the given point.
+    pub fn including(&self, point: &Vec3) -> Self {
+        Self {
+            min: self.min.min(*point),
+            max: self.max.max(*point),
+        }
+    }
+
+    /// Finds the smallest bounding box that contains both this box and the other.
+    pub fn union(&self, other: &BoundingBox) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Grows this bounding box by the given distance in every direction.
+    pub fn expanded(&self, distance: f32) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+
+        Self {
+            min: self.min - Vec3::splat(distance),
+            max: self.max + Vec3::splat(distance),
+        }
+    }
+
+    /// Whether the box contains nothing (i.e. it is the empty box).
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Finds the bounding box of this box after it is transformed by the given matrix.
+    pub fn transformed(&self, transformation: &Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let corners: Vec<Vec3> = (0..8)
+            .map(|corner| {
+                let pick = |axis: usize| {
+                    if corner & (1 << axis) == 0 {
+                        self.min[axis]
+                    } else {
+                        self.max[axis]
+                    }
+                };
+                (*transformation * vec4(pick(0), pick(1), pick(2), 1.0)).truncate()
+            })
+            .collect();
+
+        BoundingBox::from_points(&corners)
+    }
+
+    /// The point at the center of the box.
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The length of the box's diagonal, or zero if the box is empty.
+    pub fn diagonal_length(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        (self.max - self.min).length()
+    }
+
+    /// Determines the index (0 = x, 1 = y, 2 = z) of the axis along which the box is widest.
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.max - self.min;
+
+        if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Determines the range of t-values over which the ray lies within the box (using the
+    /// slab method), if the ray hits the box at all within its valid interval of t-values.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let mut t_enter = ray.t_min;
+        let mut t_exit = ray.t_max;
+
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t_near = (self.min[axis] - ray.position[axis]) * inverse_direction;
+            let mut t_far = (self.max[axis] - ray.position[axis]) * inverse_direction;
+
+            if t_near > t_far {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+
+            // NaN comparisons (from a zero direction component on a slab boundary) are false,
+            // so they leave the interval unchanged rather than rejecting the ray
+            if t_near > t_enter {
+                t_enter = t_near;
+            }
+            if t_far < t_exit {
+                t_exit = t_far;
+            }
+
+            if t_enter > t_exit {
+                return None;
+            }
+        }
+
+        Some((t_enter, t_exit))
+    }
+}
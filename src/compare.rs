@@ -0,0 +1,148 @@
+//! Side-by-side comparisons of two renders (of two scenes, or of one scene under two sets of
+//! options), for judging the effect of a change to a scene or to the raytracer's settings.
+
+use crate::{render_config, Config};
+use anyhow::{anyhow, Result};
+use image::{GenericImage, Rgb, RgbImage};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Instant;
+use structopt::StructOpt;
+
+/// Factor by which differences between the two renders are amplified in the diff strip, so
+/// that subtle differences (e.g. in noise) are still visible.
+const DIFF_SCALE: f32 = 4.0;
+
+/// Difference in any channel of a pixel (out of 255) at which the renders are counted as
+/// differing at that pixel.
+const SIGNIFICANT_PIXEL_DIFF: u8 = 2;
+
+/// Command-line options for comparing two renders.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rustracer compare",
+    about = "Render two scenes (or one scene under two sets of options) side by side"
+)]
+pub struct CompareConfig {
+    /// Path to the .xml scenefile shown on the left
+    #[structopt(parse(from_os_str))]
+    pub scene_a: PathBuf,
+    /// Path to the .xml scenefile shown on the right (the same as the left, if omitted)
+    #[structopt(parse(from_os_str))]
+    pub scene_b: Option<PathBuf>,
+    /// Path where the comparison image should be saved
+    #[structopt(long, parse(from_os_str))]
+    pub out: PathBuf,
+    /// Path of directory that texture images in the scenefiles are relative to
+    #[structopt(short, long, parse(from_os_str))]
+    pub textures: PathBuf,
+    /// Width (pixels) of each render
+    #[structopt(default_value = "512", short, long)]
+    pub width: u32,
+    /// Height (pixels) of each render
+    #[structopt(default_value = "384", short, long)]
+    pub height: u32,
+    /// Render options (as would be passed to rustracer) used for both renders, e.g.
+    /// "--enable-shadows --samples 4"
+    #[structopt(default_value = "", long, allow_hyphen_values = true)]
+    pub options: String,
+    /// Additional render options used only for the render on the left
+    #[structopt(default_value = "", long, allow_hyphen_values = true)]
+    pub options_a: String,
+    /// Additional render options used only for the render on the right
+    #[structopt(default_value = "", long, allow_hyphen_values = true)]
+    pub options_b: String,
+}
+
+/// The result of comparing two renders.
+#[derive(Debug)]
+pub struct Comparison {
+    /// The two renders side by side, followed by a strip showing (amplified) how they differ.
+    pub image: RgbImage,
+    /// Time taken by each of the two renders.
+    pub render_seconds: [f64; 2],
+    /// Number of pixels at which the renders differ noticeably.
+    pub differing_pixels: u32,
+}
+
+impl CompareConfig {
+    /// The configuration of the render on the given side (0 for the left, 1 for the right).
+    fn render_config(&self, side: usize) -> Result<Config> {
+        let (scene, options) = match side {
+            0 => (&self.scene_a, &self.options_a),
+            _ => (
+                self.scene_b.as_ref().unwrap_or(&self.scene_a),
+                &self.options_b,
+            ),
+        };
+
+        let mut args: Vec<OsString> = vec![
+            "rustracer".into(),
+            "--width".into(),
+            self.width.to_string().into(),
+            "--height".into(),
+            self.height.to_string().into(),
+            "--scene".into(),
+            scene.into(),
+            "--textures".into(),
+            (&self.textures).into(),
+            // Each render is only kept in memory, as part of the comparison
+            "--output".into(),
+            (&self.out).into(),
+        ];
+        args.extend(
+            self.options
+                .split_whitespace()
+                .chain(options.split_whitespace())
+                .map(OsString::from),
+        );
+
+        let config = Config::from_iter_safe(args)
+            .map_err(|error| anyhow!("Invalid render options: {}", error.message))?;
+        if config.tile_count > 1 || config.accumulation.is_some() {
+            return Err(anyhow!(
+                "Renders to compare can't be split into tiles or rendered progressively"
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Renders both sides of the comparison (one after the other), and combines them into a
+/// single image.
+pub fn compare(config: &CompareConfig) -> Result<Comparison> {
+    let mut renders = Vec::new();
+    let mut render_seconds = [0.0; 2];
+
+    for (side, seconds) in render_seconds.iter_mut().enumerate() {
+        let render_start = Instant::now();
+        renders.push(render_config(config.render_config(side)?, |_| {})?.image);
+        *seconds = render_start.elapsed().as_secs_f64();
+    }
+
+    let (a, b) = (&renders[0], &renders[1]);
+    let (width, height) = a.dimensions();
+
+    let mut diff = RgbImage::new(width, height);
+    let mut differing_pixels = 0;
+    for ((a_pixel, b_pixel), diff_pixel) in a.pixels().zip(b.pixels()).zip(diff.pixels_mut()) {
+        let channel_diffs: [u8; 3] =
+            std::array::from_fn(|channel| a_pixel[channel].abs_diff(b_pixel[channel]));
+        if channel_diffs.iter().any(|&d| d >= SIGNIFICANT_PIXEL_DIFF) {
+            differing_pixels += 1;
+        }
+        *diff_pixel = Rgb(channel_diffs.map(|d| (d as f32 * DIFF_SCALE).min(255.0) as u8));
+    }
+
+    let mut image = RgbImage::new(width * 3, height);
+    for (index, panel) in [a, b, &diff].into_iter().enumerate() {
+        image.copy_from(panel, width * index as u32, 0)?;
+    }
+
+    Ok(Comparison {
+        image,
+        render_seconds,
+        differing_pixels,
+    })
+}
@@ -0,0 +1,150 @@
+//! Attribution of rendering cost to the shapes of a scene, so that the shapes which make a
+//! render slow can be found.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Number of shapes listed in a cost report.
+const REPORTED_SHAPE_COUNT: usize = 10;
+
+/// The purpose for which a ray is traced, by which the cost of intersecting it is attributed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    /// A ray from the camera (or continuing past a transparent surface that it hit).
+    Primary,
+    /// A ray toward a light, testing whether the light is obstructed.
+    Shadow,
+    /// A ray reflected off a surface (or continuing past a transparent surface that it hit).
+    Reflection,
+}
+
+impl RayKind {
+    pub(crate) const ALL: [RayKind; 3] = [RayKind::Primary, RayKind::Shadow, RayKind::Reflection];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            RayKind::Primary => "primary",
+            RayKind::Shadow => "shadow",
+            RayKind::Reflection => "reflection",
+        }
+    }
+}
+
+/// Counters of the work done intersecting rays with a single shape, which may be updated
+/// concurrently by the threads rendering different pixels.
+#[derive(Debug, Default)]
+pub struct ShapeCost {
+    /// Number of rays of each kind that were tested against the shape.
+    tests: [AtomicU64; 3],
+    /// Number of rays of each kind that intersected the shape (not necessarily as their
+    /// closest hit).
+    hits: [AtomicU64; 3],
+    /// Total time spent testing rays against the shape.
+    nanoseconds: AtomicU64,
+}
+
+impl ShapeCost {
+    /// Runs the given intersection test of a ray of the given kind, recording its cost.
+    pub fn measure<T>(&self, kind: RayKind, test: impl FnOnce() -> Option<T>) -> Option<T> {
+        let start = Instant::now();
+        let result = test();
+        let elapsed = start.elapsed().as_nanos() as u64;
+
+        self.nanoseconds.fetch_add(elapsed, Ordering::Relaxed);
+        self.tests[kind.index()].fetch_add(1, Ordering::Relaxed);
+        if result.is_some() {
+            self.hits[kind.index()].fetch_add(1, Ordering::Relaxed);
+        }
+
+        result
+    }
+}
+
+/// The cost attributed to a single shape over a render.
+#[derive(Debug)]
+pub struct ShapeCostSummary {
+    /// Description of the shape, including the object it was defined in.
+    pub label: String,
+    pub tests: [u64; 3],
+    pub hits: [u64; 3],
+    pub seconds: f64,
+}
+
+/// The costs of all shapes over a render, from most to least expensive.
+#[derive(Debug)]
+pub struct CostReport {
+    pub shapes: Vec<ShapeCostSummary>,
+}
+
+impl CostReport {
+    /// Summarizes the costs recorded for the given shapes, given as their labels and costs.
+    pub fn new<'a>(shapes: impl IntoIterator<Item = (&'a str, &'a ShapeCost)>) -> Self {
+        let load = |counters: &[AtomicU64; 3]| {
+            std::array::from_fn(|index| counters[index].load(Ordering::Relaxed))
+        };
+
+        let mut shapes: Vec<ShapeCostSummary> = shapes
+            .into_iter()
+            .map(|(label, cost)| ShapeCostSummary {
+                label: label.to_string(),
+                tests: load(&cost.tests),
+                hits: load(&cost.hits),
+                seconds: cost.nanoseconds.load(Ordering::Relaxed) as f64 * 1e-9,
+            })
+            .collect();
+        shapes.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+
+        Self { shapes }
+    }
+}
+
+impl fmt::Display for CostReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total_seconds: f64 = self.shapes.iter().map(|shape| shape.seconds).sum();
+
+        writeln!(
+            f,
+            "Intersection time by shape ({:.2}s total across all threads):",
+            total_seconds
+        )?;
+
+        for shape in self.shapes.iter().take(REPORTED_SHAPE_COUNT) {
+            let share = if total_seconds > 0.0 {
+                100.0 * shape.seconds / total_seconds
+            } else {
+                0.0
+            };
+            write!(
+                f,
+                "  {:5.1}% {:8.3}s  {}",
+                share, shape.seconds, shape.label
+            )?;
+
+            for kind in RayKind::ALL {
+                write!(
+                    f,
+                    ", {} {}/{}",
+                    kind.name(),
+                    shape.hits[kind.index()],
+                    shape.tests[kind.index()]
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        if self.shapes.len() > REPORTED_SHAPE_COUNT {
+            writeln!(
+                f,
+                "  ... and {} more shapes",
+                self.shapes.len() - REPORTED_SHAPE_COUNT
+            )?;
+        }
+
+        write!(f, "  (ray counts are hits/tests)")
+    }
+}
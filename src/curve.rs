@@ -0,0 +1,230 @@
+//! Thin tubes swept along curves (e.g. for hair, wires, and ropes), which are intersected
+//! as a chain of capsules.
+
+use crate::bounding_box::BoundingBox;
+use crate::intersection::ComponentIntersection;
+use crate::math::{vec3, Vec3};
+use crate::primitive::PrimitiveComponent;
+use crate::raytracer::Ray;
+use std::f32::consts::PI;
+
+/// Number of straight segments that each cubic Bezier span of a curve is divided into.
+const SEGMENTS_PER_BEZIER_SPAN: usize = 16;
+
+/// A straight section of a curve: the set of points within the curve's radius of the line
+/// segment between two points (a cylinder with hemispherical ends).
+#[derive(Debug)]
+struct Capsule {
+    start: Vec3,
+    end: Vec3,
+    bounds: BoundingBox,
+    /// Range of the u texture coordinate over the length of the capsule.
+    u_range: (f32, f32),
+}
+
+/// A tube of constant radius around a polyline, in object space.
+#[derive(Debug)]
+pub struct Curve {
+    radius: f32,
+    capsules: Vec<Capsule>,
+    bounds: BoundingBox,
+}
+
+impl Curve {
+    /// Constructs a tube of the given radius around the polyline through the given points.
+    pub fn polyline(points: &[Vec3], radius: f32) -> Self {
+        let padding = vec3(radius, radius, radius);
+
+        let lengths: Vec<f32> = points
+            .windows(2)
+            .map(|segment| (segment[1] - segment[0]).length())
+            .collect();
+        let total_length: f32 = lengths.iter().sum();
+
+        let mut capsules = Vec::new();
+        let mut length_so_far = 0.0;
+
+        for (segment, length) in points.windows(2).zip(lengths) {
+            let (start, end) = (segment[0], segment[1]);
+            if length == 0.0 {
+                continue;
+            }
+
+            let bounds = BoundingBox::from_points(&[
+                start - padding,
+                start + padding,
+                end - padding,
+                end + padding,
+            ]);
+
+            capsules.push(Capsule {
+                start,
+                end,
+                bounds,
+                u_range: (
+                    length_so_far / total_length,
+                    (length_so_far + length) / total_length,
+                ),
+            });
+            length_so_far += length;
+        }
+
+        let bounds = capsules
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, capsule| {
+                bounds.union(&capsule.bounds)
+            });
+
+        Self {
+            radius,
+            capsules,
+            bounds,
+        }
+    }
+
+    /// Constructs a tube of the given radius around a piecewise cubic Bezier curve, whose
+    /// control points are given as the start of the curve followed by 3 points for each span.
+    pub fn bezier(control_points: &[Vec3], radius: f32) -> Self {
+        let mut points = vec![control_points[0]];
+
+        for span in control_points.windows(4).step_by(3) {
+            for i in 1..=SEGMENTS_PER_BEZIER_SPAN {
+                let t = i as f32 / SEGMENTS_PER_BEZIER_SPAN as f32;
+                let s = 1.0 - t;
+
+                points.push(
+                    span[0] * (s * s * s)
+                        + span[1] * (3.0 * s * s * t)
+                        + span[2] * (3.0 * s * t * t)
+                        + span[3] * (t * t * t),
+                );
+            }
+        }
+
+        Curve::polyline(&points, radius)
+    }
+
+    /// Intersects the ray (whose direction must be a unit vector) with a single capsule,
+    /// returning the t-value at which it enters the capsule, if it is no less than `t_min`.
+    fn intersect_capsule(
+        &self,
+        capsule: &Capsule,
+        origin: Vec3,
+        direction: Vec3,
+        t_min: f32,
+    ) -> Option<f32> {
+        let axis = capsule.end - capsule.start;
+        let start_to_origin = origin - capsule.start;
+
+        let axis_length_squared = axis.dot(axis);
+        let axis_dot_direction = axis.dot(direction);
+        let axis_dot_offset = axis.dot(start_to_origin);
+
+        // Intersect with the infinite cylinder around the axis
+        let a = axis_length_squared - axis_dot_direction * axis_dot_direction;
+        let b = axis_length_squared * direction.dot(start_to_origin)
+            - axis_dot_offset * axis_dot_direction;
+        let c = axis_length_squared * start_to_origin.dot(start_to_origin)
+            - axis_dot_offset * axis_dot_offset
+            - self.radius * self.radius * axis_length_squared;
+        let discriminant = b * b - a * c;
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // Position along the axis (scaled by its squared length) at which the ray enters the
+        // cylinder. Rays parallel to the axis can only enter through the end they approach first
+        let along_axis = if a > 0.0 {
+            let t = (-b - discriminant.sqrt()) / a;
+            let along_axis = axis_dot_offset + t * axis_dot_direction;
+
+            if along_axis > 0.0 && along_axis < axis_length_squared {
+                return (t >= t_min).then_some(t);
+            }
+            along_axis
+        } else {
+            -axis_dot_direction
+        };
+
+        // The cylinder was entered beyond one of its ends, so intersect with the sphere at that end
+        let center = if along_axis <= 0.0 {
+            capsule.start
+        } else {
+            capsule.end
+        };
+        let center_to_origin = origin - center;
+        let b = direction.dot(center_to_origin);
+        let c = center_to_origin.dot(center_to_origin) - self.radius * self.radius;
+        let discriminant = b * b - c;
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let t = -b - discriminant.sqrt();
+        (t >= t_min).then_some(t)
+    }
+}
+
+impl PrimitiveComponent for Curve {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        self.bounds.intersect(ray)?;
+
+        // Capsules are intersected using a unit direction, so t-values must be scaled back
+        let direction_length = ray.direction.truncate().length();
+        let origin = ray.position.truncate();
+        let direction = ray.direction.truncate() / direction_length;
+        let t_min = ray.t_min * direction_length;
+
+        let (t, capsule) = self
+            .capsules
+            .iter()
+            .filter(|capsule| capsule.bounds.intersect(ray).is_some())
+            .filter_map(|capsule| {
+                Some((
+                    self.intersect_capsule(capsule, origin, direction, t_min)?,
+                    capsule,
+                ))
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))?;
+
+        if !ray.contains(t / direction_length) {
+            return None;
+        }
+
+        // The normal points away from the closest point on the capsule's axis
+        let point = origin + direction * t;
+        let axis = capsule.end - capsule.start;
+        let along_axis = ((point - capsule.start).dot(axis) / axis.dot(axis)).clamp(0.0, 1.0);
+        let normal = (point - (capsule.start + axis * along_axis)).normalize();
+
+        // Measure the angle around the axis from an arbitrary direction perpendicular to it
+        let reference = if axis.x.abs() < 0.9 * axis.length() {
+            vec3(1.0, 0.0, 0.0)
+        } else {
+            vec3(0.0, 1.0, 0.0)
+        };
+        let side = axis.cross(reference).normalize();
+        let up = side.cross(axis).normalize();
+        let angle = normal.dot(up).atan2(normal.dot(side));
+
+        // u runs along the curve, and v increases with the angle around its axis
+        let du_dalong = capsule.u_range.1 - capsule.u_range.0;
+
+        Some(ComponentIntersection::new(
+            t / direction_length,
+            normal.extend(0.0),
+            (
+                capsule.u_range.0 + du_dalong * along_axis,
+                (angle + PI) / (2.0 * PI),
+            ),
+            axis * du_dalong,
+            axis.cross(normal),
+        ))
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.bounds
+    }
+}
@@ -0,0 +1,231 @@
+//! Image-based lighting: an environment map (an equirectangular image of the light arriving
+//! from every direction, e.g. an HDR photo of the sky) which is seen by the rays that miss the
+//! scene, which lights the scene's surfaces diffusely in place of the constant ambient term,
+//! and which rough surfaces reflect blurred.
+
+use crate::math::{vec3, Quat, Vec3, Vec4};
+use crate::texture::{ImageTexture, TextureSource};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Size of the environment map after it is shrunk to compute the diffuse light it gives.
+const SOURCE_WIDTH: u32 = 64;
+const SOURCE_HEIGHT: u32 = 32;
+
+/// Size of the precomputed map of the diffuse light arriving at surfaces facing each direction,
+/// which varies slowly enough with the direction to be stored at a low resolution.
+const IRRADIANCE_WIDTH: usize = 32;
+const IRRADIANCE_HEIGHT: usize = 16;
+
+/// Size of the environment map after it is shrunk to compute the blurred reflections of rough
+/// surfaces, which keep more of its detail than the diffuse light.
+const GLOSSY_SOURCE_WIDTH: u32 = 128;
+const GLOSSY_SOURCE_HEIGHT: u32 = 64;
+
+/// Size of each precomputed map of the environment blurred for a rough surface.
+const GLOSSY_WIDTH: usize = 64;
+const GLOSSY_HEIGHT: usize = 32;
+
+/// Number of precomputed blurred maps, for roughnesses evenly spaced above 0 (for which the
+/// sharp image itself is reflected) up to 1.
+const GLOSSY_LEVELS: usize = 4;
+
+/// The light arriving at the scene from far away in every direction.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// Equirectangular image of the light arriving from each direction, with -z at its center
+    /// and +y at its top.
+    image: ImageTexture,
+    /// Factor that the light of the image is multiplied by.
+    intensity: f32,
+    /// Rotation of the environment around the y axis.
+    rotation: Quat,
+    /// Cosine-weighted average of the light arriving at a surface facing the direction of each
+    /// pixel of an equirectangular map, row by row from the top.
+    irradiance: Vec<Vec4>,
+    /// Maps (laid out like the irradiance map) of the average light arriving over a lobe
+    /// around the direction of each pixel, which widens with the roughness of each level.
+    glossy: Vec<Vec<Vec4>>,
+}
+
+impl Environment {
+    /// Constructs an environment from the given equirectangular image, whose light is scaled
+    /// by the given intensity, rotated by the given angle (in radians) around the y axis.
+    pub fn new(image: ImageTexture, intensity: f32, rotation: f32) -> Self {
+        let (width, height) = image.dimensions();
+
+        let (source_width, source_height) = (width.min(SOURCE_WIDTH), height.min(SOURCE_HEIGHT));
+        let irradiance = convolve(
+            &image.downsampled(source_width, source_height),
+            (source_width, source_height),
+            (IRRADIANCE_WIDTH, IRRADIANCE_HEIGHT),
+            |cos_theta| cos_theta,
+        );
+
+        let (source_width, source_height) = (
+            width.min(GLOSSY_SOURCE_WIDTH),
+            height.min(GLOSSY_SOURCE_HEIGHT),
+        );
+        let source = image.downsampled(source_width, source_height);
+        let glossy = (1..=GLOSSY_LEVELS)
+            .map(|level| {
+                // The lobe of a Phong reflection whose exponent gives the level's roughness,
+                // which is uniform over the hemisphere at a roughness of 1
+                let roughness = level as f32 / GLOSSY_LEVELS as f32;
+                let exponent = 2.0 / (roughness * roughness) - 2.0;
+                convolve(
+                    &source,
+                    (source_width, source_height),
+                    (GLOSSY_WIDTH, GLOSSY_HEIGHT),
+                    |cos_theta| cos_theta.powf(exponent),
+                )
+            })
+            .collect();
+
+        Self {
+            image,
+            intensity,
+            rotation: Quat::from_rotation_y(-rotation),
+            irradiance,
+            glossy,
+        }
+    }
+
+    /// Scales the light of the environment by the given factor, and turns it further by the
+    /// given angle (in radians) around the y axis.
+    pub fn adjust(&mut self, intensity: f32, rotation: f32) {
+        self.intensity *= intensity;
+        self.rotation *= Quat::from_rotation_y(-rotation);
+    }
+
+    /// The light arriving from the given (world space) direction.
+    pub fn radiance(&self, direction: Vec4) -> Vec4 {
+        let uv = equirectangular_uv(self.rotation * direction.truncate().normalize());
+
+        (self.image.value(uv) * self.intensity)
+            .truncate()
+            .extend(1.0)
+    }
+
+    /// The (cosine-weighted) average light arriving at a surface with the given (world space)
+    /// normal from the environment, ignoring anything in the way. A white diffuse surface
+    /// lit by it reflects this light.
+    pub fn irradiance(&self, normal: Vec4) -> Vec4 {
+        let uv = equirectangular_uv(self.rotation * normal.truncate().normalize());
+
+        (sample(&self.irradiance, (IRRADIANCE_WIDTH, IRRADIANCE_HEIGHT), uv) * self.intensity)
+            .truncate()
+            .extend(1.0)
+    }
+
+    /// The light reflected along the given (world space) direction by a surface of the given
+    /// roughness, from 0 (a mirror, which reflects the environment sharply) to 1: the light
+    /// arriving over a lobe around the direction, which widens with the roughness, ignoring
+    /// anything in the way. It is interpolated between the precomputed blurred maps.
+    pub fn glossy_radiance(&self, direction: Vec4, roughness: f32) -> Vec4 {
+        let uv = equirectangular_uv(self.rotation * direction.truncate().normalize());
+        let level = roughness.clamp(0.0, 1.0) * GLOSSY_LEVELS as f32;
+        let lower = (level as usize).min(GLOSSY_LEVELS - 1);
+
+        let light = |level: usize| match level {
+            0 => self.image.value(uv),
+            _ => sample(&self.glossy[level - 1], (GLOSSY_WIDTH, GLOSSY_HEIGHT), uv),
+        };
+
+        (light(lower).lerp(light(lower + 1), level - lower as f32) * self.intensity)
+            .truncate()
+            .extend(1.0)
+    }
+}
+
+/// Computes an equirectangular map of the given size from the light of the pixels of a shrunk
+/// environment map (row by row from the top, with the given size): each of its pixels is the
+/// average of the light arriving from the hemisphere around its direction, weighted by the
+/// given function of the cosine of the angle away from that direction.
+fn convolve(
+    source: &[Vec4],
+    (source_width, source_height): (u32, u32),
+    (width, height): (usize, usize),
+    weight: impl Fn(f32) -> f32 + Sync,
+) -> Vec<Vec4> {
+    // Direction and solid angle of each pixel of the shrunk image, whose pixels get smaller
+    // toward the poles
+    let texels: Vec<(Vec3, f32)> = (0..source_height)
+        .flat_map(|row| {
+            (0..source_width).map(move |column| {
+                let uv = (
+                    (column as f32 + 0.5) / source_width as f32,
+                    1.0 - (row as f32 + 0.5) / source_height as f32,
+                );
+                let latitude = (uv.1 - 0.5) * PI;
+                let solid_angle =
+                    (2.0 * PI / source_width as f32) * (PI / source_height as f32) * latitude.cos();
+                (direction(uv), solid_angle)
+            })
+        })
+        .collect();
+
+    (0..width * height)
+        .into_par_iter()
+        .map(|index| {
+            let (row, column) = (index / width, index % width);
+            let center = direction((
+                (column as f32 + 0.5) / width as f32,
+                1.0 - (row as f32 + 0.5) / height as f32,
+            ));
+
+            let (mut sum, mut total_weight) = (Vec4::ZERO, 0.0);
+            for (&(texel_direction, solid_angle), &light) in texels.iter().zip(source) {
+                let cos_theta = center.dot(texel_direction);
+                if cos_theta > 0.0 {
+                    let weight = weight(cos_theta) * solid_angle;
+                    sum += light * weight;
+                    total_weight += weight;
+                }
+            }
+
+            sum / total_weight
+        })
+        .collect()
+}
+
+/// Bilinearly interpolates an equirectangular map of the given size (row by row from the top)
+/// at the given UV coordinate, between the centers of its pixels, wrapping around
+/// horizontally.
+fn sample(map: &[Vec4], (width, height): (usize, usize), (u, v): (f32, f32)) -> Vec4 {
+    let x = u * width as f32 - 0.5;
+    let y = ((1.0 - v) * height as f32 - 0.5).clamp(0.0, height as f32 - 1.0);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let pixel = |column: f32, row: f32| {
+        let column = (column as i32).rem_euclid(width as i32) as usize;
+        let row = (row as usize).min(height - 1);
+        map[row * width + column]
+    };
+    let top = pixel(x0, y0).lerp(pixel(x0 + 1.0, y0), fx);
+    let bottom = pixel(x0, y0 + 1.0).lerp(pixel(x0 + 1.0, y0 + 1.0), fx);
+
+    top.lerp(bottom, fy)
+}
+
+/// The unit direction at the given UV coordinate of an equirectangular map.
+pub(crate) fn direction((u, v): (f32, f32)) -> Vec3 {
+    let longitude = (u - 0.5) * 2.0 * PI;
+    let latitude = (v - 0.5) * PI;
+
+    vec3(
+        latitude.cos() * longitude.sin(),
+        latitude.sin(),
+        -latitude.cos() * longitude.cos(),
+    )
+}
+
+/// The UV coordinate of the given unit direction in an equirectangular map (the inverse of
+/// [`direction`]).
+fn equirectangular_uv(direction: Vec3) -> (f32, f32) {
+    (
+        0.5 + direction.x.atan2(-direction.z) / (2.0 * PI),
+        0.5 + direction.y.clamp(-1.0, 1.0).asin() / PI,
+    )
+}
@@ -0,0 +1,430 @@
+//! An interactive scene inspector (built with the `gui` feature), which shows the tree of
+//! named objects in a scene and lets their materials and the scene's lights be tweaked,
+//! re-rendering a low-resolution preview after every change.
+
+use crate::accelerator::AcceleratorKind;
+use crate::aov::{AovEncoding, NormalSpace};
+use crate::integrator::ReflectionModel;
+use crate::lights::Light;
+use crate::raytracer::RayTracer;
+use crate::sampling::SamplePattern;
+use crate::scene::{MaterialOverrides, ObjectOutline, Scene, TreeScene};
+use crate::Config;
+use anyhow::{anyhow, Result};
+use eframe::egui;
+use image::RgbImage;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use structopt::StructOpt;
+
+/// Command-line options for the scene inspector.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rustracer inspect",
+    about = "Interactively tweak the materials and lights of a scene"
+)]
+pub struct InspectConfig {
+    /// Path to the .xml scenefile to inspect
+    #[structopt(short, long, parse(from_os_str))]
+    pub scene: PathBuf,
+    /// Path of directory that texture images in the scenefile are relative to
+    #[structopt(short, long, parse(from_os_str))]
+    pub textures: PathBuf,
+    /// Width (pixels) of the preview image
+    #[structopt(default_value = "320", short, long)]
+    pub width: u32,
+    /// Height (pixels) of the preview image
+    #[structopt(default_value = "240", short, long)]
+    pub height: u32,
+}
+
+impl InspectConfig {
+    /// The configuration that previews are rendered with, which enables every effect but
+    /// only takes one sample per pixel.
+    fn preview_config(&self) -> Config {
+        Config {
+            width: self.width,
+            height: self.height,
+            max_megapixels: 100.0,
+            allow_huge: false,
+            scene: self.scene.clone(),
+            output: PathBuf::new(),
+            textures: self.textures.clone(),
+            enable_shadows: true,
+            enable_reflections: true,
+            enable_texture: true,
+            emissive_lights: None,
+            environment_intensity: None,
+            environment_rotation: None,
+            environment_shadow_samples: 16,
+            texture_cache: None,
+            clay: false,
+            override_material: None,
+            srgb_textures: false,
+            enable_parallelism: true,
+            threads: None,
+            reflection_model: ReflectionModel::Legacy,
+            reflection_threshold: None,
+            flat: false,
+            samples: 1,
+            preserve_hierarchy: false,
+            lod: None,
+            lod_cull: None,
+            tile_index: 0,
+            tile_count: 1,
+            tile_overscan: 0,
+            frame_start: None,
+            frame_end: None,
+            frame_step: 1,
+            accumulation: None,
+            aovs: Vec::new(),
+            normal_space: NormalSpace::World,
+            normal_encoding: AovEncoding::Exr,
+            sample_pattern: SamplePattern::Random,
+            stochastic_transparency: false,
+            analytic: false,
+            min_t: None,
+            self_intersect_offset: None,
+            parallel_threshold: None,
+            material_overrides: None,
+            patch: None,
+            cost_report: false,
+            max_pixel_time: None,
+            accelerator: AcceleratorKind::Bvh,
+            preview_term: false,
+            debug_pixel: None,
+            ray_export: None,
+            ray_export_stride: 16,
+            packets: false,
+            rerender: None,
+            rerender_objects: Vec::new(),
+            report: None,
+            palette: None,
+            ascii: None,
+            ascii_columns: 80,
+            ascii_color: false,
+            save_partial: false,
+        }
+    }
+}
+
+/// Opens the inspector on the configured scene, returning once its window is closed.
+pub fn run(config: InspectConfig) -> Result<()> {
+    let tree_scene = TreeScene::parse(&config.scene, &config.textures)?;
+
+    eframe::run_native(
+        "rustracer inspector",
+        eframe::NativeOptions::default(),
+        Box::new(move |creation_context| {
+            Box::new(Inspector::new(
+                config,
+                tree_scene,
+                creation_context.egui_ctx.clone(),
+            ))
+        }),
+    )
+    .map_err(|error| anyhow!("Failed to run the inspector: {}", error))
+}
+
+/// A rendered preview (or the reason it failed), along with the number of the request for it.
+type FinishedPreview = (u64, Result<RgbImage, String>);
+
+/// State of the inspector's window.
+struct Inspector {
+    config: InspectConfig,
+    /// The scene as it was parsed, which every preview is rendered from.
+    tree_scene: TreeScene,
+    outline: ObjectOutline,
+    /// Name of the object whose material is being edited, if any.
+    selected: Option<String>,
+    /// Changes made to the materials of objects so far.
+    overrides: MaterialOverrides,
+    /// The scene's lights, as they have been changed so far.
+    lights: Vec<Light>,
+    preview: Option<egui::TextureHandle>,
+    /// Number of the latest preview to have been requested, so that previews which were
+    /// requested before later changes can be ignored.
+    generation: u64,
+    finished_previews: (Sender<FinishedPreview>, Receiver<FinishedPreview>),
+    /// Message describing why the latest preview failed to render, if it did.
+    error: Option<String>,
+    context: egui::Context,
+}
+
+impl Inspector {
+    fn new(config: InspectConfig, tree_scene: TreeScene, context: egui::Context) -> Self {
+        let mut inspector = Self {
+            config,
+            outline: tree_scene.outline(),
+            lights: tree_scene.lights().to_vec(),
+            tree_scene,
+            selected: None,
+            overrides: MaterialOverrides::default(),
+            preview: None,
+            generation: 0,
+            finished_previews: channel(),
+            error: None,
+            context,
+        };
+        inspector.request_preview();
+
+        inspector
+    }
+
+    /// Starts rendering a preview of the scene with the changes made so far, on a separate
+    /// thread so that the window stays responsive.
+    fn request_preview(&mut self) {
+        self.generation += 1;
+        let generation = self.generation;
+
+        let mut tree_scene = self.tree_scene.clone();
+        tree_scene.lights_mut().clone_from_slice(&self.lights);
+        let overrides = self.overrides.clone();
+        let config = self.config.preview_config();
+        let sender = self.finished_previews.0.clone();
+        let context = self.context.clone();
+
+        thread::spawn(move || {
+            let render = || -> Result<RgbImage> {
+                tree_scene.apply_material_overrides(&overrides)?;
+                let scene = Scene::with_accelerator(tree_scene, config.accelerator)?;
+                let partition = config.partition()?;
+
+                Ok(RayTracer::new(&scene, config)?
+                    .render(&partition, |_| {})?
+                    .image)
+            };
+
+            let image = render().map_err(|error| format!("Failed to render preview: {:#}", error));
+            let _ = sender.send((generation, image));
+            context.request_repaint();
+        });
+    }
+
+    /// Shows the latest preview to have finished rendering.
+    fn receive_previews(&mut self) {
+        while let Ok((generation, image)) = self.finished_previews.1.try_recv() {
+            if generation != self.generation {
+                continue;
+            }
+
+            let image = match image {
+                Ok(image) => image,
+                Err(error) => {
+                    self.error = Some(error);
+                    continue;
+                }
+            };
+
+            let size = [image.width() as usize, image.height() as usize];
+            let image = egui::ColorImage::from_rgb(size, image.as_raw());
+
+            match self.preview {
+                Some(ref mut preview) => preview.set(image, egui::TextureOptions::default()),
+                None => {
+                    self.preview = Some(self.context.load_texture(
+                        "preview",
+                        image,
+                        egui::TextureOptions::default(),
+                    ))
+                }
+            }
+            self.error = None;
+        }
+    }
+
+    /// Shows the given object and the objects within it, indented under it.
+    fn show_outline(ui: &mut egui::Ui, outline: &ObjectOutline, selected: &mut Option<String>) {
+        let is_selected = selected.as_deref() == Some(outline.name.as_str());
+        let label = format!("{} ({} shapes)", outline.name, outline.shape_count);
+
+        if ui.selectable_label(is_selected, label).clicked() {
+            *selected = Some(outline.name.clone());
+        }
+
+        if !outline.children.is_empty() {
+            ui.indent(&outline.name, |ui| {
+                for child in &outline.children {
+                    Self::show_outline(ui, child, selected);
+                }
+            });
+        }
+    }
+
+    /// Shows the controls for the material of the selected object, returning whether any
+    /// of its parameters were changed.
+    fn show_material_editor(&mut self, ui: &mut egui::Ui) -> bool {
+        let Some(ref name) = self.selected else {
+            ui.label("Select an object to edit its material");
+            return false;
+        };
+        let Some(material) = find_object(&self.outline, name).and_then(|o| o.material.clone())
+        else {
+            ui.label(format!("{} has no shapes of its own", name));
+            return false;
+        };
+
+        let material_override = self.overrides.object_mut(name);
+        let mut changed = false;
+
+        ui.heading(name);
+        egui::Grid::new("material").show(ui, |ui| {
+            let colors: [(&str, &mut Option<[f32; 3]>, _); 5] = [
+                ("Ambient", &mut material_override.ambient, material.ambient),
+                ("Diffuse", &mut material_override.diffuse, material.diffuse),
+                (
+                    "Specular",
+                    &mut material_override.specular,
+                    material.specular,
+                ),
+                (
+                    "Reflective",
+                    &mut material_override.reflective,
+                    material.reflective,
+                ),
+                (
+                    "Emissive",
+                    &mut material_override.emissive,
+                    material.emissive,
+                ),
+            ];
+
+            for (label, color, original) in colors {
+                let mut rgb = color.unwrap_or([original.x, original.y, original.z]);
+                ui.label(label);
+                if ui.color_edit_button_rgb(&mut rgb).changed() {
+                    *color = Some(rgb);
+                    changed = true;
+                }
+                ui.end_row();
+            }
+
+            changed |= edit_parameter(
+                ui,
+                "Shininess",
+                &mut material_override.shininess,
+                material.shininess,
+                0.0..=100.0,
+            );
+            changed |= edit_parameter(
+                ui,
+                "Opacity",
+                &mut material_override.opacity,
+                material.opacity,
+                0.0..=1.0,
+            );
+            changed |= edit_parameter(
+                ui,
+                "Roughness",
+                &mut material_override.roughness,
+                material.roughness,
+                0.0..=1.0,
+            );
+            if let Some(ref texture) = material.texture {
+                changed |= edit_parameter(
+                    ui,
+                    "Texture blend",
+                    &mut material_override.blend,
+                    texture.blend,
+                    0.0..=1.0,
+                );
+            }
+        });
+
+        changed
+    }
+
+    /// Shows the controls for the colors of the lights, returning whether any were changed.
+    fn show_light_editor(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+
+        egui::Grid::new("lights").show(ui, |ui| {
+            for (index, light) in self.lights.iter_mut().enumerate() {
+                ui.label(format!("{} light {}", light.kind.name(), index + 1));
+
+                let color = light.kind.color_mut();
+                let mut rgb = [color.x, color.y, color.z];
+                if ui.color_edit_button_rgb(&mut rgb).changed() {
+                    [color.x, color.y, color.z] = rgb;
+                    changed = true;
+                }
+                ui.end_row();
+            }
+        });
+
+        changed
+    }
+}
+
+impl eframe::App for Inspector {
+    fn update(&mut self, context: &egui::Context, _frame: &mut eframe::Frame) {
+        self.receive_previews();
+
+        egui::SidePanel::left("objects").show(context, |ui| {
+            ui.heading("Objects");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                Self::show_outline(ui, &self.outline, &mut self.selected);
+            });
+        });
+
+        let mut changed = false;
+        egui::SidePanel::right("parameters").show(context, |ui| {
+            changed |= self.show_material_editor(ui);
+            ui.separator();
+            ui.heading("Lights");
+            changed |= self.show_light_editor(ui);
+        });
+
+        egui::CentralPanel::default().show(context, |ui| {
+            if let Some(ref error) = self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            match self.preview {
+                Some(ref preview) => {
+                    ui.image(preview);
+                }
+                None if self.error.is_none() => {
+                    ui.label("Rendering preview...");
+                }
+                None => {}
+            }
+        });
+
+        if changed {
+            self.request_preview();
+        }
+    }
+}
+
+/// Shows a slider for a numeric material parameter, which starts at the material's original
+/// value until it is overridden. Returns whether the parameter was changed.
+fn edit_parameter(
+    ui: &mut egui::Ui,
+    label: &str,
+    parameter: &mut Option<f32>,
+    original: f32,
+    range: std::ops::RangeInclusive<f32>,
+) -> bool {
+    let mut value = parameter.unwrap_or(original);
+    ui.label(label);
+    let changed = ui.add(egui::Slider::new(&mut value, range)).changed();
+    ui.end_row();
+
+    if changed {
+        *parameter = Some(value);
+    }
+    changed
+}
+
+/// Finds the outline of the object with the given name.
+fn find_object<'a>(outline: &'a ObjectOutline, name: &str) -> Option<&'a ObjectOutline> {
+    if outline.name == name {
+        return Some(outline);
+    }
+
+    outline
+        .children
+        .iter()
+        .find_map(|child| find_object(child, name))
+}
@@ -0,0 +1,593 @@
+//! Integrators, which compute how much light arrives along a ray traced into the scene (and
+//! so determine the shading model of the render).
+
+use crate::cost::RayKind;
+use crate::intersection::Intersection;
+use crate::lights;
+use crate::math::Vec4;
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::pixel_trace::PixelTrace;
+use crate::ray_export::{self, RayPath};
+use crate::raytracer::Ray;
+use crate::sampling::Sampler;
+use crate::scene::{Material, Scene};
+use crate::Config;
+use anyhow::{bail, Error, Result};
+use std::str::FromStr;
+use std::time::Instant;
+
+/// Total number of rays that will be traced (including camera ray) when
+/// computing illumination for reflective materials.
+const MAX_REFLECTION_DEPTH: u8 = 4;
+
+/// Maximum reflection depth when reflections are instead cut off by their contribution (with
+/// `--reflection-threshold`), so that rays between perfect mirrors still terminate.
+const MAX_THRESHOLD_REFLECTION_DEPTH: u8 = 32;
+
+/// How much of the light arriving along a mirror-reflected ray is reflected by a surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReflectionModel {
+    /// The material's reflective color, scaled by the scene's global specular coefficient
+    /// (ks), as the benchmark images were rendered.
+    Legacy,
+    /// The material's reflective color alone.
+    Material,
+    /// The material's reflective color as the reflectance at normal incidence, increasing
+    /// toward white at grazing angles (by Schlick's approximation of the Fresnel equations).
+    Fresnel,
+    /// For materials with an index of refraction, the reflectance given by Schlick's
+    /// approximation for that index, with the light that isn't reflected left for the
+    /// surface's own (Phong) color and for transmission through it. Other materials reflect
+    /// their reflective color alone.
+    Dielectric,
+}
+
+impl ReflectionModel {
+    /// Names of all the reflection models, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] =
+        &["legacy", "material-only", "fresnel", "dielectric"];
+
+    /// Whether surfaces of the given material reflect any light under this model.
+    pub fn reflects(&self, material: &Material) -> bool {
+        material.reflective != Vec4::ZERO
+            || (*self == ReflectionModel::Dielectric && material.ior.is_some())
+    }
+
+    /// The fraction of the reflected light (per channel) that a surface of the given
+    /// material reflects, when the incoming ray hits it at an angle whose cosine (with the
+    /// surface normal) is `cos_theta`.
+    pub fn weight(&self, material: &Material, global_ks: f32, cos_theta: f32) -> Vec4 {
+        let reflective = material.reflective;
+        match (self, material.ior) {
+            (ReflectionModel::Legacy, _) => reflective * global_ks,
+            (ReflectionModel::Material, _) | (ReflectionModel::Dielectric, None) => reflective,
+            (ReflectionModel::Fresnel, _) => {
+                reflective + (Vec4::ONE - reflective) * grazing(cos_theta)
+            }
+            (ReflectionModel::Dielectric, Some(ior)) => Vec4::splat(schlick(ior, cos_theta)),
+        }
+    }
+
+    /// The fraction of the light that isn't reflected by a surface of the given material
+    /// (see [`Self::weight`]), which scales the light from its own color and transmission.
+    pub fn transmittance(&self, material: &Material, cos_theta: f32) -> f32 {
+        match (self, material.ior) {
+            (ReflectionModel::Dielectric, Some(ior)) => 1.0 - schlick(ior, cos_theta),
+            _ => 1.0,
+        }
+    }
+}
+
+/// The factor by which Schlick's approximation increases reflectance toward grazing angles,
+/// for an incoming ray at an angle whose cosine (with the surface normal) is `cos_theta`.
+fn grazing(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta.min(1.0)).powi(5)
+}
+
+/// The reflectance of a surface between air and a material with the given index of
+/// refraction, by Schlick's approximation.
+fn schlick(ior: f32, cos_theta: f32) -> f32 {
+    let normal_reflectance = ((ior - 1.0) / (ior + 1.0)).powi(2);
+    normal_reflectance + (1.0 - normal_reflectance) * grazing(cos_theta)
+}
+
+impl FromStr for ReflectionModel {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "legacy" => Ok(ReflectionModel::Legacy),
+            "material-only" => Ok(ReflectionModel::Material),
+            "fresnel" => Ok(ReflectionModel::Fresnel),
+            "dielectric" => Ok(ReflectionModel::Dielectric),
+            other_name => bail!("Unknown reflection model {}", other_name),
+        }
+    }
+}
+
+/// The settings and time limit under which a camera ray is traced.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceContext<'a> {
+    pub config: &'a Config,
+    /// Time by which the pixel that the ray belongs to must be finished, if any.
+    deadline: Option<Instant>,
+    /// Where the events of tracing the ray are recorded, if it is being debugged.
+    trace: Option<&'a PixelTrace>,
+    /// Where the segments of the ray's path are recorded, if it is being exported.
+    path: Option<&'a RayPath>,
+}
+
+impl<'a> TraceContext<'a> {
+    pub fn new(config: &'a Config, deadline: Option<Instant>) -> Self {
+        Self {
+            config,
+            deadline,
+            trace: None,
+            path: None,
+        }
+    }
+
+    /// Records the events of tracing the ray in the given trace.
+    pub fn with_trace(mut self, trace: &'a PixelTrace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Records the segments of the ray's path (and of the rays it spawns) in the given path.
+    pub fn with_path(mut self, path: &'a RayPath) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// Records a segment of a ray's path, if the path is being recorded.
+    pub fn record_segment(&self, start: &Vec4, end: &Vec4, kind: RayKind) {
+        if let Some(path) = self.path {
+            path.add(start, end, kind);
+        }
+    }
+
+    /// Records an event in the trace, if there is one. The message is only formatted when it
+    /// is recorded, so that tracing costs nothing when it is off.
+    pub fn record(&self, message: impl FnOnce() -> String) {
+        if let Some(trace) = self.trace {
+            trace.record(message());
+        }
+    }
+
+    /// Records the start of a ray in the trace, if there is one (see [`PixelTrace::begin`]).
+    pub fn begin(&self, message: impl FnOnce() -> String) {
+        if let Some(trace) = self.trace {
+            trace.begin(message());
+        }
+    }
+
+    /// Records the end of a ray in the trace, if there is one (see [`PixelTrace::end`]).
+    pub fn end(&self, message: impl FnOnce() -> String) {
+        if let Some(trace) = self.trace {
+            trace.end(message());
+        }
+    }
+
+    /// Whether the deadline (if any) has passed, after which tracing should be abandoned.
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() > deadline)
+    }
+}
+
+/// A way of computing the light arriving along rays, which the raytracer uses for every
+/// camera ray. Implementing this allows other shading models to be used, while reusing
+/// the parsing of scenes, intersection of geometry, and output of images.
+pub trait Integrator: Send + Sync {
+    /// Computes the intensity (radiance) of the light arriving at the ray's origin from
+    /// along the ray, which may involve tracing further rays into the scene.
+    ///
+    /// Returns `None` if the context's deadline passes before the ray has been traced.
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4>;
+
+    /// Computes the radiance along each active ray of a packet of (coherent) camera rays,
+    /// e.g. by intersecting them with the scene together. By default, each ray is traced
+    /// separately with [`Self::radiance`].
+    fn radiance_packet(
+        &self,
+        packet: &RayPacket,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> [Option<Vec4>; PACKET_SIZE] {
+        std::array::from_fn(|lane| {
+            if packet.is_active(lane) {
+                self.radiance(&packet.rays[lane], scene, sampler, context)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Whitted-style raytracing: Phong illumination from the scene's lights (optionally with
+/// shadows), plus mirror reflections (which are only blurred where rough materials reflect
+/// the environment) and transmission through partially transparent surfaces. This is the
+/// integrator used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Whitted;
+
+impl Whitted {
+    /// Traces a ray which has been reflected `depth` times, and whose radiance is scaled by
+    /// `throughput` (the product of the reflectances along its path) in the final image.
+    fn trace(
+        ray: &Ray,
+        depth: u8,
+        throughput: Vec4,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        // Look for the shape intersection with the minimum t-value (indicates closeness to the ray origin)
+        let closest_intersection =
+            (!context.deadline_passed()).then(|| scene.intersect(ray, Self::ray_kind(depth)));
+
+        Self::trace_intersection(
+            ray,
+            depth,
+            throughput,
+            closest_intersection,
+            scene,
+            sampler,
+            context,
+        )
+    }
+
+    /// Finishes tracing a ray (see [`Self::trace`]) whose closest intersection with the
+    /// scene has been found, which is `None` if the deadline passed before it was found.
+    fn trace_intersection(
+        ray: &Ray,
+        depth: u8,
+        throughput: Vec4,
+        closest_intersection: Option<Option<Intersection>>,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        context.begin(|| Self::describe_ray(ray, depth));
+        let radiance = closest_intersection.and_then(|closest_intersection| {
+            Self::shade(
+                ray,
+                depth,
+                throughput,
+                closest_intersection.as_ref(),
+                scene,
+                sampler,
+                context,
+            )
+        });
+        context.end(|| match radiance {
+            Some(radiance) => format!("radiance {:.4}", radiance),
+            None => String::from("aborted (exceeded the maximum time per pixel)"),
+        });
+
+        radiance
+    }
+
+    /// Traces a ray reflected off a surface of the given roughness (see [`Self::trace`]). If
+    /// the ray misses the scene, a rough surface reflects the environment blurred by its
+    /// roughness rather than the light along the ray itself, while the shapes it reflects stay
+    /// sharp.
+    fn trace_reflection(
+        ray: &Ray,
+        depth: u8,
+        throughput: Vec4,
+        roughness: f32,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        if roughness == 0.0 {
+            return Self::trace(ray, depth, throughput, scene, sampler, context);
+        }
+
+        let closest_intersection =
+            (!context.deadline_passed()).then(|| scene.intersect(ray, RayKind::Reflection));
+        if let Some(None) = closest_intersection {
+            context.begin(|| Self::describe_ray(ray, depth));
+            Self::record_miss(ray, RayKind::Reflection, scene, context);
+            context.record(|| {
+                format!(
+                    "reflecting the environment blurred by roughness {:.4}",
+                    roughness
+                )
+            });
+            let radiance = scene.glossy_background(ray.direction, roughness);
+            context.end(|| format!("radiance {:.4}", radiance));
+
+            return Some(radiance);
+        }
+
+        Self::trace_intersection(
+            ray,
+            depth,
+            throughput,
+            closest_intersection,
+            scene,
+            sampler,
+            context,
+        )
+    }
+
+    /// Describes a ray which has been reflected `depth` times, for the trace of a pixel.
+    fn describe_ray(ray: &Ray, depth: u8) -> String {
+        format!(
+            "ray (depth {}) from {:.4} toward {:.4}",
+            depth, ray.position, ray.direction
+        )
+    }
+
+    /// Records a ray that misses the scene in the trace of a pixel, as a segment leaving it.
+    fn record_miss(ray: &Ray, kind: RayKind, scene: &Scene, context: &TraceContext) {
+        let escape_point =
+            ray.position + ray.direction.normalize() * ray_export::escape_length(scene);
+        context.record_segment(&ray.position, &escape_point, kind);
+        context.record(|| String::from("missed the scene"));
+    }
+
+    /// The purpose for which a ray that has been reflected `depth` times is traced.
+    fn ray_kind(depth: u8) -> RayKind {
+        if depth == 0 {
+            RayKind::Primary
+        } else {
+            RayKind::Reflection
+        }
+    }
+
+    /// Computes the light arriving along a ray from its closest intersection with the scene.
+    fn shade(
+        ray: &Ray,
+        depth: u8,
+        throughput: Vec4,
+        closest_intersection: Option<&Intersection>,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        let config = context.config;
+        let kind = Self::ray_kind(depth);
+
+        match closest_intersection {
+            Some(intersection) => {
+                let t = intersection.component_intersection.t;
+                context.record_segment(&ray.position, &ray.at(t), kind);
+                context.record(|| {
+                    let hit = &intersection.component_intersection;
+                    format!(
+                        "hit {} at t = {:.4} (point {:.4}, normal {:.4}, uv ({:.4}, {:.4}))",
+                        intersection.label,
+                        hit.t,
+                        ray.at(hit.t),
+                        hit.normal,
+                        hit.uv.0,
+                        hit.uv.1
+                    )
+                });
+
+                let color = lights::phong(scene, context, intersection, ray);
+                context.record(|| format!("phong illumination {:.4}", color));
+
+                let max_depth = match config.reflection_threshold {
+                    Some(_) => MAX_THRESHOLD_REFLECTION_DEPTH,
+                    None => MAX_REFLECTION_DEPTH,
+                };
+                // The side of the surface that the ray hit doesn't matter
+                let cos_theta = ray
+                    .direction
+                    .normalize()
+                    .dot(intersection.component_intersection.normal)
+                    .abs();
+                // The reflectance of each of the material's layers is mixed
+                let mut reflectance = Vec4::ZERO;
+                let mut transmittance = 0.0;
+                let mut reflects = false;
+                let mut roughness = 0.0;
+                lights::for_each_layer(config, intersection, |material, weight| {
+                    reflectance += config.reflection_model.weight(
+                        material,
+                        scene.global_lighting_coefficients.ks,
+                        cos_theta,
+                    ) * weight;
+                    transmittance +=
+                        config.reflection_model.transmittance(material, cos_theta) * weight;
+                    reflects |= config.reflection_model.reflects(material);
+                    roughness += material.roughness * weight;
+                });
+                if transmittance != 1.0 {
+                    context.record(|| format!("fresnel transmittance {:.4}", transmittance));
+                }
+                let color = color * transmittance;
+                let reflected_throughput = throughput * reflectance;
+
+                let surface_color = if !config.enable_reflections || !reflects || depth == max_depth
+                {
+                    // If there are no reflections enabled, the material isn't at all reflective,
+                    // or we are at the maximum depth for recursively tracing rays, stop recurring.
+                    color
+                } else if config.reflection_threshold.map_or(false, |threshold| {
+                    reflected_throughput.truncate().max_element() < threshold
+                }) {
+                    // The reflection would contribute too little to the image to be worth tracing
+                    context.record(|| {
+                        format!(
+                            "reflection throughput {:.4} below the threshold",
+                            reflected_throughput
+                        )
+                    });
+                    color
+                } else {
+                    let reflected_direction = lights::reflect_around(
+                        &ray.direction,
+                        &intersection.component_intersection.normal,
+                    );
+                    // Skip the surface that the reflected ray leaves
+                    let reflected_ray = scene.ray(
+                        ray.at(intersection.component_intersection.t),
+                        reflected_direction,
+                        scene.self_intersect_offset,
+                        f32::INFINITY,
+                    );
+                    let reflected_light = reflectance
+                        * Self::trace_reflection(
+                            &reflected_ray,
+                            depth + 1,
+                            reflected_throughput,
+                            roughness,
+                            scene,
+                            sampler,
+                            context,
+                        )?;
+
+                    // Use the color from the original ray, but add the contribution of a
+                    // ray that has been reflected off the intersected surface
+                    color + reflected_light
+                };
+
+                let opacity = lights::opacity(config, intersection);
+                if opacity == 1.0 {
+                    return Some(surface_color);
+                }
+                context.record(|| format!("opacity {:.4}, continuing past the surface", opacity));
+
+                // Some light passes through the surface (less any that it reflects), so continue
+                // the ray past it
+                let t = intersection.component_intersection.t;
+                let transmitted_ray = scene.ray(
+                    ray.at(t),
+                    ray.direction,
+                    scene.self_intersect_offset / ray.direction.length(),
+                    ray.t_max - t,
+                );
+
+                if config.uses_stochastic_transparency() {
+                    if sampler.next_1d() < opacity {
+                        Some(surface_color)
+                    } else {
+                        Self::trace(
+                            &transmitted_ray,
+                            depth,
+                            throughput * transmittance,
+                            scene,
+                            sampler,
+                            context,
+                        )
+                        .map(|light| light * transmittance)
+                    }
+                } else {
+                    Some(
+                        surface_color * opacity
+                            + Self::trace(
+                                &transmitted_ray,
+                                depth,
+                                throughput * (1.0 - opacity) * transmittance,
+                                scene,
+                                sampler,
+                                context,
+                            )? * (1.0 - opacity)
+                                * transmittance,
+                    )
+                }
+            }
+            // There is no intersection, so there is no illumination from this ray
+            None => {
+                Self::record_miss(ray, kind, scene, context);
+                Some(scene.background(ray.direction))
+            }
+        }
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        Self::trace(ray, 0, Vec4::ONE, scene, sampler, context)
+    }
+
+    /// The camera rays are intersected with the scene together, after which the rest of
+    /// each ray's path is traced separately.
+    fn radiance_packet(
+        &self,
+        packet: &RayPacket,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> [Option<Vec4>; PACKET_SIZE] {
+        let mut radiances = [None; PACKET_SIZE];
+        if context.deadline_passed() {
+            return radiances;
+        }
+
+        let closest_intersections = scene.intersect_packet(packet, RayKind::Primary);
+        for (lane, closest_intersection) in closest_intersections.into_iter().enumerate() {
+            if packet.is_active(lane) {
+                radiances[lane] = Self::trace_intersection(
+                    &packet.rays[lane],
+                    0,
+                    Vec4::ONE,
+                    Some(closest_intersection),
+                    scene,
+                    sampler,
+                    context,
+                );
+            }
+        }
+
+        radiances
+    }
+}
+
+/// Flat shading: each camera ray takes the unlit color of the surface it hits (see
+/// [`lights::flat`]), without any lights, shadows, reflections, or transparency. This renders
+/// in a fraction of the time of a full render, for checking the framing of the camera and
+/// the placement of textures.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Flat;
+
+impl Integrator for Flat {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _sampler: &mut Sampler,
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        if context.deadline_passed() {
+            return None;
+        }
+
+        match scene.intersect(ray, RayKind::Primary) {
+            Some(intersection) => {
+                let t = intersection.component_intersection.t;
+                context.record_segment(&ray.position, &ray.at(t), RayKind::Primary);
+                context.record(|| format!("hit {} at t = {:.4}", intersection.label, t));
+
+                let color = lights::flat(scene, context.config, &intersection);
+                context.record(|| format!("flat color {:.4}", color));
+                Some(color)
+            }
+            None => {
+                let escape_point =
+                    ray.position + ray.direction.normalize() * ray_export::escape_length(scene);
+                context.record_segment(&ray.position, &escape_point, RayKind::Primary);
+                context.record(|| String::from("missed the scene"));
+                Some(scene.background(ray.direction))
+            }
+        }
+    }
+}
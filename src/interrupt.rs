@@ -0,0 +1,113 @@
+//! Renders which stop partway through (because they were interrupted, e.g. with Ctrl-C, or
+//! because rendering part of the image failed), whose completed tiles can still be saved
+//! (with `--save-partial`) along with a sidecar file listing which tiles are valid.
+
+use anyhow::{Context, Result};
+use image::RgbImage;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the render has been asked to stop. This is global, as it is set from a signal handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks any render in progress (or started later) to stop as soon as the tiles that are
+/// currently being rendered finish.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Why a render stopped before all of its tiles were rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Interrupted,
+    /// Rendering a tile failed (panicked) with the given message.
+    Failed(String),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Interrupted => write!(f, "interrupted"),
+            StopReason::Failed(message) => write!(f, "failed: {}", message),
+        }
+    }
+}
+
+/// A rectangular tile of a partial render, in pixels of its image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The part of an image that was rendered before the render stopped, which is returned as
+/// the error of the render. Pixels outside of the valid tiles are black.
+#[derive(Debug)]
+pub struct PartialRender {
+    pub image: RgbImage,
+    /// Tiles of the image whose pixels were all rendered.
+    pub valid_tiles: Vec<Tile>,
+    /// Total number of tiles that the image is divided into.
+    pub tile_count: usize,
+    pub reason: StopReason,
+}
+
+/// Contents of the sidecar file saved with a partial render.
+#[derive(Serialize)]
+struct PartialSidecar<'a> {
+    width: u32,
+    height: u32,
+    reason: String,
+    tile_count: usize,
+    valid_tiles: &'a [Tile],
+}
+
+impl PartialRender {
+    /// Path of the sidecar file describing a partial render saved at the given image path.
+    pub fn sidecar_path(image_path: &Path) -> PathBuf {
+        image_path.with_extension("partial.json")
+    }
+
+    /// Saves the partial image at the given path, along with its sidecar file.
+    pub fn save(&self, image_path: &Path) -> Result<()> {
+        self.image.save(image_path)?;
+
+        let sidecar = PartialSidecar {
+            width: self.image.width(),
+            height: self.image.height(),
+            reason: self.reason.to_string(),
+            tile_count: self.tile_count,
+            valid_tiles: &self.valid_tiles,
+        };
+        let sidecar_path = Self::sidecar_path(image_path);
+        fs::write(&sidecar_path, serde_json::to_string_pretty(&sidecar)?).with_context(|| {
+            format!(
+                "Failed to write partial render sidecar: {}",
+                sidecar_path.display()
+            )
+        })
+    }
+}
+
+impl fmt::Display for PartialRender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Render {} after {} of {} tiles",
+            self.reason,
+            self.valid_tiles.len(),
+            self.tile_count
+        )
+    }
+}
+
+impl std::error::Error for PartialRender {}
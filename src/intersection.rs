@@ -1,5 +1,6 @@
 //! Types for representing an intersection between a ray and part of the scene.
 
+use crate::math::{self, onb, Mat4, Vec3, Vec4};
 use crate::scene::Material;
 use std::cmp::Ordering;
 
@@ -8,13 +9,63 @@ use std::cmp::Ordering;
 #[derive(Debug)]
 pub struct ComponentIntersection {
     pub t: f32,
-    pub normal: glm::Vec4,
+    pub normal: Vec4,
     pub uv: (f32, f32),
+    /// Unit vector perpendicular to the normal, in the direction of increasing u.
+    pub tangent: Vec4,
+    /// Unit vector perpendicular to both the normal and tangent, in the direction of increasing v.
+    pub bitangent: Vec4,
+    /// Derivatives of the surface's position with respect to its u and v texture coordinates,
+    /// whose lengths (unlike the tangent frame's) give how far the surface stretches per unit
+    /// of u and v, e.g. for the footprint of a texture lookup.
+    pub dp_du: Vec4,
+    pub dp_dv: Vec4,
+}
+
+impl ComponentIntersection {
+    /// Constructs an intersection, deriving its tangent frame from the derivatives of the
+    /// surface's position with respect to its u and v texture coordinates.
+    pub fn new(t: f32, normal: Vec4, uv: (f32, f32), dp_du: Vec3, dp_dv: Vec3) -> Self {
+        let (tangent, bitangent) =
+            onb::tangent_frame(&normal.truncate().normalize(), &dp_du, &dp_dv);
+
+        Self {
+            t,
+            normal,
+            uv,
+            tangent: tangent.extend(0.0),
+            bitangent: bitangent.extend(0.0),
+            dp_du: dp_du.extend(0.0),
+            dp_dv: dp_dv.extend(0.0),
+        }
+    }
+
+    /// Transforms the normal, tangent frame, and derivatives of the intersection by the given
+    /// matrix (e.g. from object space into world space).
+    pub fn transform(&mut self, transformation: &Mat4) {
+        self.normal = math::transform_normal(transformation, &self.normal);
+        // Derivatives lie along the surface, so (unlike the normal) they transform directly
+        self.dp_du = *transformation * self.dp_du;
+        self.dp_dv = *transformation * self.dp_dv;
+        (self.tangent, self.bitangent) = onb::transform_tangent_frame(
+            transformation,
+            &self.normal,
+            &self.tangent,
+            &self.bitangent,
+        );
+    }
+}
+
+/// Orders t-values so that NaN (e.g. from a degenerate transformation) is never the
+/// closest, and so that the order is the same however the values are compared.
+fn compare_t(a: f32, b: f32) -> Ordering {
+    let key = |t: f32| if t.is_nan() { f32::INFINITY } else { t };
+    key(a).total_cmp(&key(b))
 }
 
 impl Ord for ComponentIntersection {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.t.partial_cmp(&other.t).unwrap_or(Ordering::Equal)
+        compare_t(self.t, other.t)
     }
 }
 
@@ -26,24 +77,40 @@ impl PartialOrd for ComponentIntersection {
 
 impl PartialEq for ComponentIntersection {
     fn eq(&self, other: &Self) -> bool {
-        self.t == other.t
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for ComponentIntersection {}
 
 /// Wrapper for [`ComponentIntersection`] that also has information about the
-/// material that is being intersected.
+/// material (and shape) that is being intersected.
 #[derive(Debug)]
 pub struct Intersection<'a> {
     pub component_intersection: ComponentIntersection,
     pub material: &'a Material,
+    /// Position of the hit in the object space of the intersected shape, at which solid
+    /// textures are evaluated.
+    pub object_point: Vec3,
+    /// Description of the shape that was intersected.
+    pub label: &'a str,
+    /// Position of the intersected shape in the scenefile.
+    pub shape_id: usize,
+    /// Index of the intersected shape in the scene's shapes, which (unlike `shape_id`) is
+    /// different for each instance of the same shape. In scenes whose transform hierarchy is
+    /// preserved, it is the index within the shapes of the node that the shape is in.
+    pub shape_index: usize,
 }
 
+/// Intersections are ordered by their t-values, and intersections at exactly the same t-value
+/// (e.g. on coplanar surfaces) by the order of their shapes in the scenefile, so that the
+/// same one is always the closest, whatever order the shapes are tested in. (Within a shape,
+/// ties go to the first of the primitive's components.)
 impl Ord for Intersection<'_> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.component_intersection
             .cmp(&other.component_intersection)
+            .then(self.shape_id.cmp(&other.shape_id))
     }
 }
 
@@ -55,7 +122,7 @@ impl PartialOrd for Intersection<'_> {
 
 impl PartialEq for Intersection<'_> {
     fn eq(&self, other: &Self) -> bool {
-        self.component_intersection == other.component_intersection
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -1,19 +1,60 @@
-use anyhow::Result;
-use image::RgbImage;
-use raytracer::RayTracer;
-use scene::{Scene, TreeScene};
+use accelerator::AcceleratorKind;
+use accumulation::Accumulation;
+use anyhow::{bail, Result};
+use aov::{Aov, AovEncoding, NormalSpace};
+use integrator::ReflectionModel;
+use partition::Partition;
+use pixel_trace::{PixelPosition, PixelTrace};
+use raytracer::{RayTracer, Render};
+use rerender::Rerender;
+use sampling::SamplePattern;
+use scene::{LevelOfDetail, MaterialOverride, MaterialOverrides, Scene, SceneBuilder, ScenePatch};
+use std::fmt;
 use std::path::PathBuf;
+use std::time::Instant;
 use structopt::StructOpt;
+use stylize::Palette;
+use texture::ColorSpace;
 
-mod intersection;
-mod lights;
+pub mod accelerator;
+pub mod accumulation;
+pub mod aov;
+mod bezier;
+pub mod bounding_box;
+pub mod compare;
+pub mod cost;
+mod curve;
+pub mod environment;
+#[cfg(feature = "gui")]
+pub mod inspector;
+pub mod integrator;
+pub mod interrupt;
+pub mod intersection;
+pub mod lights;
+pub mod math;
+mod mesh;
+mod noise;
+pub mod package;
+pub mod packet;
+pub mod partition;
+pub mod pixel_trace;
+mod point_cloud;
 mod primitive;
+pub mod ray_export;
 pub mod raytracer;
+pub mod report;
+pub mod rerender;
+pub mod sampling;
 pub mod scene;
 mod shape;
+pub mod sky;
+pub mod stylize;
+mod terminal_preview;
+pub mod texture;
+pub mod user_primitive;
 
 /// Command-line options for the raytracer.
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 #[structopt(name = "rustracer", about = "A Rust Raytracer")]
 pub struct Config {
     /// Sets the width (pixels) of the output image
@@ -22,6 +63,13 @@ pub struct Config {
     /// Sets the height (pixels) of the output image
     #[structopt(short, long)]
     pub height: u32,
+    /// Largest image (in millions of pixels) that may be rendered, to catch mistyped
+    /// resolutions before they allocate gigabytes of memory
+    #[structopt(default_value = "100", long)]
+    pub max_megapixels: f64,
+    /// Render images larger than --max-megapixels
+    #[structopt(long)]
+    pub allow_huge: bool,
     /// Path to the .xml scenefile to render
     #[structopt(short, long, parse(from_os_str))]
     pub scene: PathBuf,
@@ -40,17 +88,506 @@ pub struct Config {
     /// Enable texture mapping
     #[structopt(long)]
     pub enable_texture: bool,
+    /// Decode image textures from sRGB into linear intensities before shading (except those
+    /// given colorspace="linear" in the scenefile, e.g. data textures)
+    #[structopt(long)]
+    pub srgb_textures: bool,
+    /// Load image textures when they are first sampled, into a cache of at most this many
+    /// megabytes that evicts the least recently used images, instead of loading them all
+    /// before rendering (for scenes with more large textures than fit in memory)
+    #[structopt(long)]
+    pub texture_cache: Option<usize>,
     /// Enable parallel processing of pixels
     #[structopt(long)]
     pub enable_parallelism: bool,
+    /// Number of threads to render with when parallelism is enabled (by default, one for each
+    /// core of the machine)
+    #[structopt(long)]
+    pub threads: Option<usize>,
+    /// How much light reflective surfaces reflect: "legacy" scales the material's
+    /// reflective color by the global ks (matching the benchmark images), "material-only"
+    /// uses the reflective color alone, "fresnel" increases it at grazing angles, and
+    /// "dielectric" finds it from the index of refraction of materials with an <ior> (by
+    /// Schlick's approximation), dimming their own color and transmission by as much, while
+    /// other materials reflect their reflective color alone
+    #[structopt(default_value = "legacy", long, possible_values = ReflectionModel::NAMES)]
+    pub reflection_model: ReflectionModel,
+    /// Stop tracing reflections once the product of the reflectances along a ray's path falls
+    /// below this fraction (instead of after a fixed 4 rays), so that chains of mirrors are
+    /// followed as far as they visibly contribute to the image
+    #[structopt(long)]
+    pub reflection_threshold: Option<f32>,
+    /// Shade surfaces with only their ambient and diffuse (or texture) colors, without any
+    /// lights, shadows, reflections, or transparency, for quickly checking the composition
+    /// of a scene
+    #[structopt(long)]
+    pub flat: bool,
     /// Number of samples per pixel
     #[structopt(default_value = "1", long)]
     pub samples: u8,
+    /// Keep the scene's transform hierarchy at render time (transforming rays down the
+    /// tree) instead of flattening it into a transformation per shape
+    #[structopt(long)]
+    pub preserve_hierarchy: bool,
+    /// Replace shapes smaller than this many pixels in the image (by the diameter of their
+    /// bounding spheres) with sphere proxies covering the same area on average
+    #[structopt(long)]
+    pub lod: Option<f32>,
+    /// Leave out shapes smaller than this many pixels in the image entirely
+    #[structopt(long)]
+    pub lod_cull: Option<f32>,
+    /// Index of the horizontal band (tile) of the image to render, when the image is
+    /// split across multiple invocations with --tile-count
+    #[structopt(default_value = "0", long)]
+    pub tile_index: u32,
+    /// Number of horizontal bands (tiles) that the image is split into, of which only the
+    /// one given by --tile-index is rendered
+    #[structopt(default_value = "1", long)]
+    pub tile_count: u32,
+    /// Number of extra rows to render above and below the tile, overlapping with the
+    /// neighbouring tiles, which are cropped off when the tiles are assembled
+    #[structopt(default_value = "0", long)]
+    pub tile_overscan: u32,
+    /// First frame of an animation to render, whose number replaces each run of '#' in the
+    /// scene and output paths (padded with zeros to the length of the run)
+    #[structopt(long)]
+    pub frame_start: Option<u32>,
+    /// Last frame of the animation to render (inclusive), which is --frame-start by default
+    #[structopt(long)]
+    pub frame_end: Option<u32>,
+    /// Number of frames to advance by between the frames that are rendered
+    #[structopt(default_value = "1", long)]
+    pub frame_step: u32,
+    /// Path of a progressive accumulation file, which is resumed from if it exists. Each of
+    /// the --samples passes adds one more sample to every pixel, after which the file is updated
+    #[structopt(long, parse(from_os_str))]
+    pub accumulation: Option<PathBuf>,
+    /// Additional per-pixel data to output alongside the image, each saved as an EXR image
+    /// next to the output (e.g. image.variance.exr). May be given multiple times
+    #[structopt(long = "aov", possible_values = Aov::NAMES)]
+    pub aovs: Vec<Aov>,
+    /// Space that the normals of the normal AOV are given in
+    #[structopt(default_value = "world", long, possible_values = NormalSpace::NAMES)]
+    pub normal_space: NormalSpace,
+    /// How the normal AOV is saved: as signed floats in an EXR image, or remapped to 0-1 in
+    /// an 8-bit PNG image (e.g. image.normal.png)
+    #[structopt(default_value = "exr", long, possible_values = AovEncoding::NAMES)]
+    pub normal_encoding: AovEncoding,
+    /// How the positions of samples within each pixel are chosen. Blue noise makes the noise
+    /// of renders with few samples less structured and more pleasant to look at
+    #[structopt(default_value = "random", long, possible_values = SamplePattern::NAMES)]
+    pub sample_pattern: SamplePattern,
+    /// Pass rays through partially transparent surfaces at random (with a probability of the
+    /// surface's transparency) instead of tracing what is behind every such surface, which is
+    /// faster when many cutouts overlap, at the cost of noise
+    #[structopt(long)]
+    pub stochastic_transparency: bool,
+    /// Replace every random choice with a fixed one, so that renders are identical on every
+    /// platform: samples lie on a regular grid within each pixel, shadow rays are cast to
+    /// the centers of lights, and --stochastic-transparency is ignored
+    #[structopt(long)]
+    pub analytic: bool,
+    /// Smallest t-value at which camera rays can hit a surface (overriding the scenefile's
+    /// <mint>), e.g. to cut away geometry right in front of the camera
+    #[structopt(long)]
+    pub min_t: Option<f32>,
+    /// Distance along secondary rays from the surface they leave within which they can't
+    /// hit anything (overriding the scenefile's <selfintersectoffset>), to fix shadow acne
+    /// or light leaking. By default this is derived from the size of the scene
+    #[structopt(long)]
+    pub self_intersect_offset: Option<f32>,
+    /// Largest component of a unit ray direction along a plane's normal at which the ray is
+    /// considered parallel to the plane (overriding the scenefile's <parallelthreshold>)
+    #[structopt(long)]
+    pub parallel_threshold: Option<f32>,
+    /// Path of a TOML file of replacement material parameters for named objects and
+    /// materials, which are applied after the scenefile is parsed
+    #[structopt(long, parse(from_os_str))]
+    pub material_overrides: Option<PathBuf>,
+    /// Render with every material replaced by a neutral matte gray one (without textures), to
+    /// see the scene's lighting independently of its materials
+    #[structopt(long)]
+    pub clay: bool,
+    /// Render with every material replaced by the neutral one of --clay, with the given
+    /// parameters as name:value pairs separated by semicolons (colors as r,g,b), e.g.
+    /// "diffuse:0.5,0.6,0.7;specular:0.2,0.2,0.2;shininess:20"
+    #[structopt(long)]
+    pub override_material: Option<MaterialOverride>,
+    /// Light the scene with every shape whose material is emissive (as a light about the
+    /// size of the shape), casting this many shadow rays toward each from every shaded point
+    #[structopt(long)]
+    pub emissive_lights: Option<u32>,
+    /// Factor that the light of the scene's environment map or sky is multiplied by (on top
+    /// of its intensity in the scenefile)
+    #[structopt(long)]
+    pub environment_intensity: Option<f32>,
+    /// Angle (in degrees) to turn the scene's environment map or sky by around the y axis (on
+    /// top of its rotation in the scenefile)
+    #[structopt(long)]
+    pub environment_rotation: Option<f32>,
+    /// Number of shadow rays cast over the hemisphere around each shaded point to find how much
+    /// of the environment map or sky is blocked from it, when shadows are enabled
+    #[structopt(long, default_value = "16")]
+    pub environment_shadow_samples: u32,
+    /// Path of an XML file of lights (which replace the scene's lights with the same <id>, or
+    /// are added) and material parameters (by material name), applied before rendering
+    #[structopt(long, parse(from_os_str))]
+    pub patch: Option<PathBuf>,
+    /// Measure the time spent intersecting rays with each shape, and report the most
+    /// expensive shapes after rendering
+    #[structopt(long)]
+    pub cost_report: bool,
+    /// Maximum time (in seconds) to spend rendering each pixel, after which the pixel is
+    /// aborted and filled with magenta (so that pathological pixels can't hang the render)
+    #[structopt(long)]
+    pub max_pixel_time: Option<f64>,
+    /// Acceleration structure built over the shapes of the scene (unless the hierarchy is
+    /// preserved), which may be faster to build or to traverse depending on the scene
+    #[structopt(default_value = "bvh", long, possible_values = AcceleratorKind::NAMES)]
+    pub accelerator: AcceleratorKind,
+    /// Draw a downscaled preview of the render in the terminal (using truecolor ANSI escape
+    /// codes), which is updated as parts of the image finish, in place of the progress bar
+    #[structopt(long)]
+    pub preview_term: bool,
+    /// Instead of rendering the image, trace a single ray through the center of the pixel at
+    /// the given column/row (as x,y), printing every intersection, light contribution, and
+    /// bounce along the way
+    #[structopt(long)]
+    pub debug_pixel: Option<PixelPosition>,
+    /// Path of an OBJ file to export the paths of a subset of the rays to (as lines grouped
+    /// by the kind of ray), for inspecting the behavior of rays in a 3D viewer
+    #[structopt(long, parse(from_os_str))]
+    pub ray_export: Option<PathBuf>,
+    /// Spacing (in pixels, along each axis) of the pixels whose rays are exported with
+    /// --ray-export. Only the first sample of each such pixel is exported
+    #[structopt(default_value = "16", long)]
+    pub ray_export_stride: u32,
+    /// Trace the samples of each pixel in packets of rays, which are intersected with the
+    /// scene's acceleration structure together (using SIMD instructions)
+    #[structopt(long)]
+    pub packets: bool,
+    /// Path of a previous render (rendered with `--aov object-id`) of which to only re-render
+    /// the pixels showing the objects given by --rerender-objects, copying the rest from it
+    #[structopt(long, parse(from_os_str))]
+    pub rerender: Option<PathBuf>,
+    /// IDs of the objects (as given by the object ID AOV) to re-render with --rerender,
+    /// separated by commas
+    #[structopt(long, use_delimiter = true)]
+    pub rerender_objects: Vec<u32>,
+    /// Path of an HTML file to save a report of the render to, which embeds the image and
+    /// AOVs along with the settings, scene statistics, timings, and (with --cost-report) the
+    /// most expensive shapes
+    #[structopt(long, parse(from_os_str))]
+    pub report: Option<PathBuf>,
+    /// Quantize the output image to a fixed palette of colors, with dithering
+    #[structopt(long, possible_values = Palette::NAMES)]
+    pub palette: Option<Palette>,
+    /// Path of a text file to save the output image to as ASCII art
+    #[structopt(long, parse(from_os_str))]
+    pub ascii: Option<PathBuf>,
+    /// Width (in characters) of the ASCII art saved with --ascii
+    #[structopt(default_value = "80", long)]
+    pub ascii_columns: u32,
+    /// Color the characters of the ASCII art saved with --ascii with truecolor ANSI escape
+    /// codes (for viewing it with e.g. `cat` in a terminal)
+    #[structopt(long)]
+    pub ascii_color: bool,
+    /// If the render is interrupted (with Ctrl-C) or fails partway, save the tiles that were
+    /// finished to the output path, along with a .partial.json file listing them
+    #[structopt(long)]
+    pub save_partial: bool,
+}
+
+/// Command-line options for assembling the tiles of a split render into the full image.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rustracer assemble",
+    about = "Stitch together the tiles of a render split with --tile-count"
+)]
+pub struct AssembleConfig {
+    /// Path of directory containing the rendered tiles and their .tile sidecar files
+    #[structopt(long, parse(from_os_str))]
+    pub tiles: PathBuf,
+    /// Path where the assembled image should be saved (the format is determined by its extension)
+    #[structopt(long, parse(from_os_str))]
+    pub out: PathBuf,
+    /// Largest image (in millions of pixels) that may be assembled, to catch corrupted tile
+    /// sidecars before they allocate gigabytes of memory
+    #[structopt(default_value = "100", long)]
+    pub max_megapixels: f64,
+    /// Assemble images larger than --max-megapixels
+    #[structopt(long)]
+    pub allow_huge: bool,
+}
+
+/// A reason that the size of the image in a configuration can't be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageSizeError {
+    /// The image has no pixels.
+    Empty { width: u32, height: u32 },
+    /// The image has more pixels than the configured maximum.
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_megapixels: f64,
+    },
+}
+
+impl fmt::Display for ImageSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ImageSizeError::Empty { width, height } => write!(
+                f,
+                "Cannot render a {}x{} image, which has no pixels",
+                width, height
+            ),
+            ImageSizeError::TooLarge {
+                width,
+                height,
+                max_megapixels,
+            } => write!(
+                f,
+                "A {}x{} image ({:.1} megapixels) is larger than the maximum of {} megapixels \
+                 (pass --allow-huge to proceed anyway)",
+                width,
+                height,
+                width as f64 * height as f64 / 1e6,
+                max_megapixels
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageSizeError {}
+
+/// Checks that an image of the given size has pixels, and (unless huge images are allowed)
+/// no more than the given maximum number of them, in millions.
+pub fn validate_image_size(
+    width: u32,
+    height: u32,
+    max_megapixels: f64,
+    allow_huge: bool,
+) -> Result<(), ImageSizeError> {
+    if width == 0 || height == 0 {
+        return Err(ImageSizeError::Empty { width, height });
+    }
+    if !allow_huge && width as f64 * height as f64 > max_megapixels * 1e6 {
+        return Err(ImageSizeError::TooLarge {
+            width,
+            height,
+            max_megapixels,
+        });
+    }
+
+    Ok(())
+}
+
+impl Config {
+    /// Checks that the image has a size that can be rendered: it must have pixels, and
+    /// (unless huge images are allowed) no more than the maximum number of them.
+    pub fn validate_size(&self) -> Result<(), ImageSizeError> {
+        validate_image_size(
+            self.width,
+            self.height,
+            self.max_megapixels,
+            self.allow_huge,
+        )
+    }
+
+    /// Whether partially transparent surfaces are passed through at random (which is never
+    /// the case in analytic mode).
+    pub fn uses_stochastic_transparency(&self) -> bool {
+        self.stochastic_transparency && !self.analytic
+    }
+
+    /// The frames of the animation that this configuration renders, or `None` if it renders
+    /// a single image (without a frame range).
+    pub fn frames(&self) -> Result<Option<Vec<u32>>> {
+        let start = match (self.frame_start, self.frame_end) {
+            (None, None) => return Ok(None),
+            (None, Some(_)) => bail!("--frame-end requires --frame-start"),
+            (Some(start), _) => start,
+        };
+        let end = self.frame_end.unwrap_or(start);
+        if end < start {
+            bail!("Frame range {}-{} ends before it starts", start, end);
+        }
+        if self.frame_step == 0 {
+            bail!("Frame step must be at least 1");
+        }
+        if !partition::has_frame_placeholder(&self.output) {
+            bail!(
+                "The output path must contain '#' to be replaced by the frame number, so that \
+                 frames don't overwrite each other"
+            );
+        }
+
+        Ok(Some(
+            (start..=end).step_by(self.frame_step as usize).collect(),
+        ))
+    }
+
+    /// The configuration that renders the given frame of the animation, whose number replaces
+    /// the runs of '#' in the paths of the scene and of the files that are written.
+    pub fn for_frame(&self, frame: u32) -> Config {
+        let frame_path = |path: &PathBuf| partition::frame_path(path, frame);
+
+        Config {
+            scene: frame_path(&self.scene),
+            output: frame_path(&self.output),
+            accumulation: self.accumulation.as_ref().map(frame_path),
+            ray_export: self.ray_export.as_ref().map(frame_path),
+            report: self.report.as_ref().map(frame_path),
+            ascii: self.ascii.as_ref().map(frame_path),
+            frame_start: None,
+            frame_end: None,
+            ..self.clone()
+        }
+    }
+
+    /// The part of the image that this configuration renders, which fails if the size of
+    /// the image is invalid (see [`Config::validate_size`]).
+    pub fn partition(&self) -> Result<Partition> {
+        self.validate_size()?;
+
+        Partition::new(
+            self.tile_index,
+            self.tile_count,
+            self.width,
+            self.height,
+            self.tile_overscan,
+        )
+    }
 }
 
 /// Use the given configuration to produce a render of the indicated scenefile with the given parameters.
-pub fn render_config<F: Fn() + Sync>(config: Config, pixel_finished: F) -> Result<RgbImage> {
-    let tree_scene = TreeScene::parse(&config.scene, &config.textures)?;
-    let scene = Scene::try_from(tree_scene)?;
-    Ok(RayTracer::new(scene, config).render(pixel_finished))
+pub fn render_config<F: Fn(u64) + Sync>(config: Config, pixels_finished: F) -> Result<Render> {
+    render_config_with_builder(config, &SceneBuilder::new(), pixels_finished)
+}
+
+/// Like [`render_config`], but parses the scenefile with the given builder, so that it may
+/// use the user-defined primitives registered with it.
+pub fn render_config_with_builder<F: Fn(u64) + Sync>(
+    config: Config,
+    builder: &SceneBuilder,
+    pixels_finished: F,
+) -> Result<Render> {
+    let partition = config.partition()?;
+    let rerender = match config.rerender {
+        Some(_) if config.accumulation.is_some() => {
+            bail!("Progressive renders can't re-render only some objects")
+        }
+        Some(ref previous) => Some(Rerender::load(
+            previous,
+            &config.rerender_objects,
+            &partition,
+        )?),
+        None => None,
+    };
+    let build_start = Instant::now();
+    let scene = build_scene(&config, builder)?;
+    let scene_build_time = build_start.elapsed();
+
+    let render = match config.accumulation.clone() {
+        Some(accumulation_path) => {
+            let mut accumulation = Accumulation::load_or_new(&accumulation_path, &partition)?;
+            RayTracer::new(&scene, config)?.render_progressive(
+                &mut accumulation,
+                pixels_finished,
+                |accumulation| accumulation.save(&accumulation_path),
+            )
+        }
+        None => {
+            let ray_tracer = RayTracer::new(&scene, config)?;
+            let ray_tracer = match rerender {
+                Some(rerender) => ray_tracer.with_rerender(rerender),
+                None => ray_tracer,
+            };
+            ray_tracer.render(&partition, pixels_finished)
+        }
+    };
+
+    render.map(|render| Render {
+        scene_build_time,
+        ..render
+    })
+}
+
+/// Traces the pixel given by the configuration's `debug_pixel` (see
+/// [`RayTracer::trace_pixel`]), instead of rendering the whole image.
+pub fn trace_config_pixel(config: Config) -> Result<PixelTrace> {
+    config.validate_size()?;
+    let position = config
+        .debug_pixel
+        .ok_or_else(|| anyhow::anyhow!("No pixel to debug was given"))?;
+    let scene = build_scene(&config, &SceneBuilder::new())?;
+
+    RayTracer::new(&scene, config)?.trace_pixel(position)
+}
+
+/// Parses the configured scenefile with the given builder (applying any material overrides),
+/// and prepares it for rendering (e.g. with a [`RayTracer`] constructed directly, to render
+/// the scene repeatedly).
+pub fn build_scene(config: &Config, builder: &SceneBuilder) -> Result<Scene> {
+    let mut tree_scene = builder.parse(&config.scene, &config.textures)?;
+    if let Some(ref patch_path) = config.patch {
+        tree_scene.apply_patch(&ScenePatch::load(patch_path)?)?;
+    }
+    if let Some(ref overrides_path) = config.material_overrides {
+        tree_scene.apply_material_overrides(&MaterialOverrides::load(overrides_path)?)?;
+    }
+
+    if config.clay || config.override_material.is_some() {
+        let material_override = config.override_material.clone().unwrap_or_default();
+        tree_scene.replace_all_materials(material_override.clay_material());
+    }
+
+    if config.srgb_textures {
+        tree_scene.set_texture_color_space(ColorSpace::Srgb);
+    }
+    if let Some(megabytes) = config.texture_cache {
+        tree_scene.set_texture_cache_capacity(megabytes * 1024 * 1024);
+    }
+
+    let environment_intensity = config.environment_intensity.unwrap_or(1.0);
+    if environment_intensity.is_nan() || environment_intensity < 0.0 {
+        bail!("Environment intensity must not be negative");
+    }
+    let environment_rotation = config.environment_rotation.unwrap_or(0.0).to_radians();
+    tree_scene.adjust_environment(environment_intensity, environment_rotation);
+    if config.environment_shadow_samples == 0 {
+        bail!("Environment shadow samples must be at least 1");
+    }
+
+    let tolerances = tree_scene.tolerances_mut();
+    if let Some(min_t) = config.min_t {
+        tolerances.min_t = min_t;
+    }
+    if let Some(self_intersect_offset) = config.self_intersect_offset {
+        tolerances.self_intersect_offset = Some(self_intersect_offset);
+    }
+    if let Some(parallel_threshold) = config.parallel_threshold {
+        tolerances.parallel_threshold = parallel_threshold;
+    }
+
+    let lod = (config.lod.is_some() || config.lod_cull.is_some()).then(|| LevelOfDetail {
+        proxy_size: config.lod.unwrap_or(0.0),
+        cull_size: config.lod_cull,
+        image_height: config.height,
+    });
+
+    let mut scene = match lod {
+        Some(_) if config.preserve_hierarchy => {
+            bail!("Level of detail can't be used when the transform hierarchy is preserved")
+        }
+        Some(ref lod) => Scene::with_level_of_detail(tree_scene, config.accelerator, lod)?,
+        None if config.preserve_hierarchy => Scene::with_hierarchy(tree_scene)?,
+        None => Scene::with_accelerator(tree_scene, config.accelerator)?,
+    };
+    scene.track_costs = config.cost_report;
+    if let Some(shadow_samples) = config.emissive_lights {
+        scene.add_emissive_lights(shadow_samples)?;
+    }
+
+    Ok(scene)
 }
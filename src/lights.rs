@@ -2,82 +2,265 @@
 //! spot lights), and also includes texture mapping.
 
 use crate::{
+    bounding_box::BoundingBox,
+    cost::RayKind,
+    integrator::TraceContext,
     intersection::Intersection,
+    math::{vec3, vec4, Vec3, Vec4},
+    ray_export,
     raytracer::Ray,
-    scene::{Scene, Texture},
-    shape::Shape,
+    scene::{Material, Scene, Texture},
     Config,
 };
 use image::Rgb;
+use std::sync::Arc;
 
 /// Offset from a point of intersecting that a recursive ray must be fired from
-/// in order to avoid unwanted intersections with the intersected object itself.
-pub const SELF_INTERSECT_OFFSET: f32 = 0.001;
-
-/// Calculates the Phong illumination as a vector of intensity values for a given point of intersection.
-pub fn phong(scene: &Scene, config: &Config, intersection: &Intersection, ray: &Ray) -> glm::Vec4 {
-    let mut illumination = glm::vec4(0.0, 0.0, 0.0, 1.0);
+/// in order to avoid unwanted intersections with the intersected object itself, as a
+/// fraction of the scene's size (the length of the diagonal of its bounding box). This keeps
+/// the offset meaningful whatever units the scene is authored in.
+pub const RELATIVE_SELF_INTERSECT_OFFSET: f32 = 1e-4;
+
+/// Offset used in place of the relative one for scenes without a meaningful size (e.g. a
+/// scene with no shapes, or with only a single point).
+pub const DEFAULT_SELF_INTERSECT_OFFSET: f32 = 0.001;
+
+/// Fractional part of the golden ratio, by which successive shadow rays toward the environment
+/// are turned around the normal, to spread them evenly over the hemisphere.
+const GOLDEN_RATIO_FRACTION: f32 = 0.618_034;
+
+/// Derives the self-intersection offset for a scene with the given bounds.
+pub fn self_intersect_offset(scene_bounds: &BoundingBox) -> f32 {
+    let offset = RELATIVE_SELF_INTERSECT_OFFSET * scene_bounds.diagonal_length();
+
+    if offset.is_normal() {
+        offset
+    } else {
+        DEFAULT_SELF_INTERSECT_OFFSET
+    }
+}
 
-    // First, add the ambient color of the material
-    illumination =
-        illumination + intersection.material.ambient * scene.global_lighting_coefficients.ka;
+/// Calculates the Phong illumination as a vector of intensity values for a given point of
+/// intersection, recording the contribution of each light in the context's trace (if any).
+pub fn phong(
+    scene: &Scene,
+    context: &TraceContext,
+    intersection: &Intersection,
+    ray: &Ray,
+) -> Vec4 {
+    let config = context.config;
+    let mut illumination = vec4(0.0, 0.0, 0.0, 1.0);
 
     let intersection_point = ray.at(intersection.component_intersection.t);
     let normal = intersection.component_intersection.normal;
-    let intersection_to_camera = glm::normalize(-ray.direction);
+
+    // First, add the ambient color of the material (or, in an environment, the diffuse light
+    // that the surface reflects from it, less what is blocked if shadows are enabled), and
+    // the color it glows with
+    let environment_light = scene.environment.as_ref().map(|environment| {
+        let light = environment.irradiance(normal);
+        if !config.enable_shadows {
+            return light;
+        }
+
+        let visibility = environment_visibility(scene, context, &intersection_point, normal);
+        context.record(|| format!("environment: visibility {:.4}", visibility));
+        light * visibility
+    });
+    for_each_layer(config, intersection, |material, weight| {
+        let ambient = match environment_light {
+            Some(light) => diffuse_color(scene, config, intersection, material) * light,
+            None => material.ambient * scene.global_lighting_coefficients.ka,
+        };
+        illumination += (ambient + material.emissive) * weight;
+    });
+    let intersection_to_camera = (-ray.direction).normalize();
 
     scene
         .lights
         .iter()
-        .flat_map(|light| {
-            if config.enable_shadows && !light.is_visible(&intersection_point, &scene.shapes) {
+        .enumerate()
+        .flat_map(|(index, light)| {
+            let light_name = || format!("{} light {}", light.kind.name(), index);
+
+            if !light.is_in_range(&intersection_point) {
+                context.record(|| format!("{}: out of range", light_name()));
+                return None;
+            }
+            if light.emitter == Some(intersection.shape_index) {
+                context.record(|| format!("{}: emitted by the surface", light_name()));
+                return None;
+            }
+
+            let visibility = if config.enable_shadows {
+                light.visibility(&intersection_point, scene, context)
+            } else {
+                1.0
+            };
+
+            // Fully occluded points receive nothing from an ordinary light, and
+            // fully visible points are not darkened by a shadow-only light
+            if (visibility == 0.0 && !light.shadow_only) || (visibility == 1.0 && light.shadow_only)
+            {
+                context.record(|| {
+                    format!(
+                        "{}: visibility {:.4}, no contribution",
+                        light_name(),
+                        visibility
+                    )
+                });
                 return None;
             }
 
             let light_to_intersection = light.direction_to_point(&intersection_point);
             let intersection_to_light = -light_to_intersection;
-            let mut diffuse_angle = glm::dot(normal, intersection_to_light);
+            let mut diffuse_angle = normal.dot(intersection_to_light);
             if diffuse_angle < 0.0 {
                 diffuse_angle = 0.0;
             }
 
-            let mut diffuse = glm::vec4(1.0, 1.0, 1.0, 1.0) * diffuse_angle;
-
-            if config.enable_texture && intersection.material.texture.is_some() {
-                let texture = intersection.material.texture.as_ref().unwrap();
-                let texture_color =
-                    uv_lookup(intersection.component_intersection.uv, texture, scene);
-
-                diffuse = diffuse
-                    * ((intersection.material.diffuse
-                        * (1.0 - texture.blend)
-                        * scene.global_lighting_coefficients.kd)
-                        + (texture_color * texture.blend));
-            } else {
-                diffuse =
-                    diffuse * scene.global_lighting_coefficients.kd * intersection.material.diffuse;
-            }
-
             let mirror_direction = reflect_around(&light_to_intersection, &normal);
-            let mut specular_angle = glm::dot(mirror_direction, intersection_to_camera);
-
-            if specular_angle < 0.0 {
-                specular_angle = 0.0;
-            } else {
-                specular_angle = specular_angle.powf(intersection.material.shininess);
+            let mirror_angle = mirror_direction.dot(intersection_to_camera);
+
+            // The diffuse and specular light of each of the material's layers is mixed
+            let mut diffuse = Vec4::ZERO;
+            let mut specular = Vec4::ZERO;
+            for_each_layer(config, intersection, |material, weight| {
+                diffuse +=
+                    diffuse_color(scene, config, intersection, material) * diffuse_angle * weight;
+
+                let specular_angle = if mirror_angle < 0.0 {
+                    0.0
+                } else {
+                    mirror_angle.powf(shininess(config, intersection, material))
+                };
+                specular += specular_color(config, intersection, material)
+                    * scene.global_lighting_coefficients.ks
+                    * specular_angle
+                    * weight;
+            });
+
+            let mut contribution = light.intensity_at(&intersection_point) * (diffuse + specular);
+            if config.enable_texture {
+                contribution *= light.gobo_at(&intersection_point);
             }
-
-            let specular = intersection.material.specular
-                * scene.global_lighting_coefficients.ks
-                * specular_angle;
-
-            Some(light.intensity_at(&intersection_point) * (diffuse + specular))
+            let contribution = if light.shadow_only {
+                contribution * -(1.0 - visibility)
+            } else {
+                contribution * visibility
+            };
+
+            context.record(|| {
+                format!(
+                    "{}: visibility {:.4}, diffuse {:.4}, specular {:.4}, contribution {:.4}",
+                    light_name(),
+                    visibility,
+                    diffuse,
+                    specular,
+                    contribution
+                )
+            });
+
+            Some(contribution)
         })
         .fold(illumination, |acc, individual_light_illumination| {
             acc + individual_light_illumination
         })
 }
 
+/// Calls the given function with each flat material (ignoring its layer) that the material at
+/// a point of intersection mixes, along with its weight in the mix (the weights sum to 1).
+/// Layers' masks are only used if texture mapping is enabled.
+pub fn for_each_layer(
+    config: &Config,
+    intersection: &Intersection,
+    mut f: impl FnMut(&Material, f32),
+) {
+    let mut material = intersection.material;
+    let mut weight = 1.0;
+
+    while let Some(ref layer) = material.layer {
+        let coverage = match layer.mask {
+            Some(ref mask) if config.enable_texture => {
+                layer.factor
+                    * texture_value(intersection, mask)
+                        .truncate()
+                        .dot(Vec3::splat(1.0 / 3.0))
+            }
+            _ => layer.factor,
+        }
+        .clamp(0.0, 1.0);
+
+        if coverage < 1.0 {
+            f(material, weight * (1.0 - coverage));
+        }
+        material = &layer.material;
+        weight *= coverage;
+        if weight == 0.0 {
+            return;
+        }
+    }
+
+    f(material, weight);
+}
+
+/// The diffuse color of the surface of the given material at a point of intersection (scaled
+/// by the global kd), blended with its texture if texture mapping is enabled.
+fn diffuse_color(
+    scene: &Scene,
+    config: &Config,
+    intersection: &Intersection,
+    material: &Material,
+) -> Vec4 {
+    let diffuse = material.diffuse * scene.global_lighting_coefficients.kd;
+
+    match material.texture {
+        Some(ref texture) if config.enable_texture => {
+            let texture_color = texture_lookup(intersection, texture);
+            diffuse * (1.0 - texture.blend) + texture_color * texture.blend
+        }
+        _ => diffuse,
+    }
+}
+
+/// The specular color of the surface of the given material at a point of intersection,
+/// multiplied by its specular map if it has one and texture mapping is enabled.
+fn specular_color(config: &Config, intersection: &Intersection, material: &Material) -> Vec4 {
+    match material.specular_map {
+        Some(ref map) if config.enable_texture => {
+            material.specular * texture_lookup(intersection, map)
+        }
+        _ => material.specular,
+    }
+}
+
+/// The shininess of the surface of the given material at a point of intersection, multiplied
+/// by the brightness of its shininess map if it has one and texture mapping is enabled.
+fn shininess(config: &Config, intersection: &Intersection, material: &Material) -> f32 {
+    match material.shininess_map {
+        Some(ref map) if config.enable_texture => {
+            let brightness = texture_value(intersection, map)
+                .truncate()
+                .dot(Vec3::splat(1.0 / 3.0));
+            material.shininess * brightness
+        }
+        _ => material.shininess,
+    }
+}
+
+/// Calculates the unlit color of a point of intersection, for quickly checking the
+/// composition of a scene: its ambient color plus its diffuse (or texture) color, as if it
+/// faced a white light head-on, without considering the scene's lights or shadows.
+pub fn flat(scene: &Scene, config: &Config, intersection: &Intersection) -> Vec4 {
+    let mut color = Vec4::ZERO;
+    for_each_layer(config, intersection, |material, weight| {
+        let ambient = material.ambient * scene.global_lighting_coefficients.ka;
+        color += (ambient + diffuse_color(scene, config, intersection, material)) * weight;
+    });
+
+    color.truncate().extend(1.0)
+}
+
 /// Scales an intensity value in the range 0.0-1.0 onto integers 0-255, and
 /// clamps any values outside that range to the min/max accordingly.
 fn clamp_intensity(intensity: f32) -> u8 {
@@ -85,7 +268,7 @@ fn clamp_intensity(intensity: f32) -> u8 {
 }
 
 /// Converts a vector of intensity values to an RGB triple, clamping as needed.
-pub fn to_rgb(intensity: &glm::Vec4) -> Rgb<u8> {
+pub fn to_rgb(intensity: &Vec4) -> Rgb<u8> {
     Rgb([
         clamp_intensity(intensity.x),
         clamp_intensity(intensity.y),
@@ -93,131 +276,393 @@ pub fn to_rgb(intensity: &glm::Vec4) -> Rgb<u8> {
     ])
 }
 
-/// Converts an RGB value (0-255) to an intensity between 0.0-1.0
-fn int_to_intensity(rgb_value: u8) -> f32 {
-    rgb_value as f32 / 255.0
+/// Calculates the attenuation of a light with the given attenuation function coefficients over the given distance
+fn attenuation_over_distance(coefficients: &Vec3, distance: f32) -> f32 {
+    1f32.min(1.0 / (coefficients.z * distance.powi(2) + coefficients.y * distance + coefficients.x))
 }
 
-/// Converts an RGB triple to a vector of intensity.
-fn to_intensity(rgb: &Rgb<u8>) -> glm::Vec4 {
-    glm::vec4(
-        int_to_intensity(rgb[0]),
-        int_to_intensity(rgb[1]),
-        int_to_intensity(rgb[2]),
-        1.0,
-    )
+/// Calculates a vector reflected about an axis.
+pub fn reflect_around(in_direction: &Vec4, reflection_axis: &Vec4) -> Vec4 {
+    (*in_direction - *reflection_axis * 2.0 * in_direction.dot(*reflection_axis)).normalize()
 }
 
-/// Calculates the attenuation of a light with the given attenuation function coefficients over the given distance
-fn attenuation_over_distance(coefficients: &glm::Vec3, distance: f32) -> f32 {
-    1f32.min(1.0 / (coefficients.z * distance.powi(2) + coefficients.y * distance + coefficients.x))
+/// The color of a texture at a point of intersection.
+fn texture_lookup(intersection: &Intersection, texture: &Texture) -> Vec4 {
+    texture_value(intersection, texture).truncate().extend(1.0)
 }
 
-/// Calculates a vector reflected about an axis.
-pub fn reflect_around(in_direction: &glm::Vec4, reflection_axis: &glm::Vec4) -> glm::Vec4 {
-    glm::normalize(
-        *in_direction - *reflection_axis * 2.0 * glm::dot(*in_direction, *reflection_axis),
+/// The value (including alpha) of a texture at a point of intersection.
+fn texture_value(intersection: &Intersection, texture: &Texture) -> Vec4 {
+    texture.value(
+        intersection.component_intersection.uv,
+        intersection.object_point,
     )
 }
 
-/// Converts a UV coordinate to the value of a texture at that coordinate.
-fn uv_lookup(uv: (f32, f32), texture: &Texture, scene: &Scene) -> glm::Vec4 {
-    let texture_image = scene
-        .textures
-        .get(&texture.filename)
-        .expect("Tried to access unloaded texture");
+/// Determines how opaque the surface is at a point of intersection, between 0 (fully
+/// transparent) and 1 (fully opaque), from its material's opacity and (if texture mapping
+/// is enabled) the alpha channel of its texture, which can be used to cut out shapes.
+pub fn opacity(config: &Config, intersection: &Intersection) -> f32 {
+    let mut opacity = 0.0;
+    for_each_layer(config, intersection, |material, weight| {
+        let texture_alpha = match material.texture {
+            Some(ref texture) if config.enable_texture => texture_value(intersection, texture).w,
+            _ => 1.0,
+        };
+        opacity += material.opacity * texture_alpha * weight;
+    });
+
+    opacity
+}
 
-    let (u, v) = uv;
-    let column = (u * texture_image.width() as f32 * texture.repeat_u).floor() as u32
-        % texture_image.width();
-    let row = ((1.0 - v) * texture_image.height() as f32 * texture.repeat_v).floor() as u32
-        % texture_image.height();
+/// Determines how much of the environment is visible from the given point, as the average
+/// fraction of light transmitted along shadow rays cast over the hemisphere around the given
+/// normal, whose directions are distributed by the cosine of their angle with it (as the light
+/// averaged by [`Environment::irradiance`](crate::environment::Environment::irradiance) is
+/// weighted). This is between 0 (fully enclosed) and 1 (nothing in the way).
+fn environment_visibility(
+    scene: &Scene,
+    context: &TraceContext,
+    point: &Vec4,
+    normal: Vec4,
+) -> f32 {
+    let config = context.config;
+    let samples = config.environment_shadow_samples;
+    let axis = normal.truncate().normalize();
+    let (tangent, bitangent) = axis.any_orthonormal_pair();
+
+    // The rays form an even spiral over the hemisphere, which is turned at random (except in
+    // analytic mode) so that the pattern doesn't show
+    let (offset, turn) = if config.analytic {
+        (0.0, 0.0)
+    } else {
+        (rand::random::<f32>(), rand::random::<f32>())
+    };
+
+    let transmitted: f32 = (0..samples)
+        .map(|index| {
+            let u = ((index as f32 + 0.5) / samples as f32 + offset).fract();
+            let phi =
+                2.0 * std::f32::consts::PI * (index as f32 * GOLDEN_RATIO_FRACTION + turn).fract();
+            let (sin_theta, cos_theta) = (u.sqrt(), (1.0 - u).sqrt());
+            let direction = (axis * cos_theta
+                + (tangent * phi.cos() + bitangent * phi.sin()) * sin_theta)
+                .extend(0.0);
+
+            let escape_point = *point + direction * ray_export::escape_length(scene);
+            context.record_segment(point, &escape_point, RayKind::Shadow);
+
+            let ray = scene.ray(
+                *point,
+                direction,
+                scene.self_intersect_offset,
+                f32::INFINITY,
+            );
+            transmittance(scene, config, ray, None)
+        })
+        .sum();
 
-    to_intensity(texture_image.get_pixel(column, row))
+    transmitted / samples as f32
 }
 
-/// A light source.
-#[derive(Debug)]
-pub enum Light {
+/// The fraction of light transmitted along a shadow ray, following it through any partially
+/// transparent surfaces in the way (each of which blocks some of the light) up to its end, or
+/// up to the surface of the given shape that the light stands for (see [`Light::emitter`]).
+fn transmittance(scene: &Scene, config: &Config, mut ray: Ray, emitter: Option<usize>) -> f32 {
+    let mut transmittance = 1.0;
+
+    while let Some(intersection) = scene.intersect(&ray, RayKind::Shadow) {
+        // The point on the light is within the glowing shape that it stands for, so the
+        // light is reached at the shape's surface
+        if emitter == Some(intersection.shape_index) {
+            break;
+        }
+
+        let opacity = opacity(config, &intersection);
+        if config.uses_stochastic_transparency() {
+            // Pass through the surface with a probability of its transparency
+            if rand::random::<f32>() < opacity {
+                return 0.0;
+            }
+        } else {
+            transmittance *= 1.0 - opacity;
+        }
+
+        if transmittance == 0.0 {
+            break;
+        }
+
+        // Continue past the surface, up to the end of the ray
+        ray.t_min = intersection.component_intersection.t + scene.self_intersect_offset;
+    }
+
+    transmittance
+}
+
+/// The kind of a light source, which determines how light is emitted from it.
+#[derive(Debug, Clone)]
+pub enum LightKind {
     /// A light that emanates from a single point in space in all directions.
     Point {
-        color: glm::Vector4<f32>,
-        position: glm::Vector4<f32>,
-        attenuation: glm::Vector3<f32>,
+        color: Vec4,
+        position: Vec4,
+        attenuation: Vec3,
+        /// Radius of the sphere that the light emanates from, which softens its shadows.
+        radius: f32,
     },
     /// A light that emanates in a given direction (from infinitely far away).
     Directional {
-        color: glm::Vector4<f32>,
-        direction: glm::Vector4<f32>,
-        attenuation: glm::Vector3<f32>,
+        color: Vec4,
+        direction: Vec4,
+        attenuation: Vec3,
+        /// Angle (in radians) between the direction toward the center of the light's disk
+        /// in the sky (e.g. the sun's) and its edge, which softens its shadows.
+        angular_radius: f32,
     },
     /// A light that emanates in the shape of a cone from a point.
     Spot {
-        color: glm::Vector4<f32>,
-        position: glm::Vector4<f32>,
-        direction: glm::Vector4<f32>,
-        attenuation: glm::Vector3<f32>,
+        color: Vec4,
+        position: Vec4,
+        direction: Vec4,
+        attenuation: Vec3,
         penumbra: f32,
         angle: f32,
+        /// Radius of the sphere that the light emanates from, which softens its shadows.
+        radius: f32,
+        /// Texture projected through the cone (a gobo), which tints the light like a slide in
+        /// a projector, e.g. for stained glass. The texture spans the width of the cone, with
+        /// its top toward +y (or -z if the light points straight up or down).
+        gobo: Option<Arc<Texture>>,
     },
 }
 
+/// A light source.
+#[derive(Debug, Clone)]
+pub struct Light {
+    /// Identifier given by the light's `<id>` tag, by which patches replace it.
+    pub id: Option<String>,
+    pub kind: LightKind,
+    /// Number of shadow rays cast toward the light from each shaded point.
+    pub shadow_samples: u32,
+    /// If set, the light does not illuminate anything, and instead only darkens the points
+    /// that are occluded from it (by the amount that it would have illuminated them).
+    pub shadow_only: bool,
+    /// Distance from the light beyond which it has no effect.
+    pub max_distance: Option<f32>,
+    /// Index (in the scene's shapes) of the glowing shape that the light stands for, if any
+    /// (see [`Light::emissive`]). The light doesn't illuminate the shape itself, and shadow rays
+    /// toward it reach it as soon as they hit the shape.
+    pub emitter: Option<usize>,
+}
+
+impl LightKind {
+    /// Name of the kind of light, as used for its type in scenefiles.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightKind::Point { .. } => "point",
+            LightKind::Directional { .. } => "directional",
+            LightKind::Spot { .. } => "spot",
+        }
+    }
+
+    /// The texture projected by the light, if any.
+    pub fn gobo(&self) -> Option<&Texture> {
+        match self {
+            LightKind::Spot { gobo, .. } => gobo.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Mutable access to the color (and so the intensity) of the light.
+    pub fn color_mut(&mut self) -> &mut Vec4 {
+        match self {
+            LightKind::Point { color, .. }
+            | LightKind::Directional { color, .. }
+            | LightKind::Spot { color, .. } => color,
+        }
+    }
+}
+
 impl Light {
+    /// A light for the shape at the given index (in the scene's shapes), with the given (world
+    /// space) bounds, whose material has the given emissive color, so that the shape lights
+    /// its surroundings. The light is a sphere at the center of the shape's bounds, about as
+    /// big as the shape, whose light falls off from the emissive color at its surface with
+    /// the square of the distance from its center.
+    pub fn emissive(
+        shape_index: usize,
+        color: Vec4,
+        bounds: &BoundingBox,
+        shadow_samples: u32,
+    ) -> Self {
+        let size = bounds.max - bounds.min;
+        let radius = (size.x + size.y + size.z) / 6.0;
+
+        Light {
+            id: None,
+            kind: LightKind::Point {
+                color,
+                position: bounds.centroid().extend(1.0),
+                attenuation: vec3(0.0, 0.0, 1.0 / (radius * radius).max(f32::MIN_POSITIVE)),
+                radius,
+            },
+            shadow_samples,
+            shadow_only: false,
+            max_distance: None,
+            emitter: Some(shape_index),
+        }
+    }
+
     /// Finds the distance from the light source to the given point. Directional
     /// lights do not have a position, so this returns an `Option`.
-    fn distance_to_point(&self, point: &glm::Vec4) -> Option<f32> {
-        match self {
-            Light::Directional { .. } => None,
-            Light::Point { position, .. } | Light::Spot { position, .. } => {
-                Some(glm::length(*position - *point))
+    fn distance_to_point(&self, point: &Vec4) -> Option<f32> {
+        match self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => {
+                Some((position - *point).length())
             }
         }
     }
 
     /// Computes a vector from the light to the given point.
-    fn direction_to_point(&self, point: &glm::Vec4) -> glm::Vec4 {
-        glm::normalize(match self {
-            Light::Directional { direction, .. } => *direction,
-            Light::Point { position, .. } | Light::Spot { position, .. } => *point - *position,
+    fn direction_to_point(&self, point: &Vec4) -> Vec4 {
+        (match self.kind {
+            LightKind::Directional { direction, .. } => direction,
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => {
+                *point - position
+            }
         })
+        .normalize()
     }
 
-    /// Determine if a given point is "visible" to the light source - i.e. if a ray
-    /// can be cast from the light to the point without intersecting any objects.
-    fn is_visible(&self, point: &glm::Vec4, shapes: &[Shape]) -> bool {
-        let to_point = self.direction_to_point(point);
-        let point_to_light_ray = Ray::new(
-            *point + (glm::normalize(-to_point) * SELF_INTERSECT_OFFSET),
-            glm::normalize(-to_point),
-        );
-        let distance = self.distance_to_point(point);
+    /// Determines whether the given point is close enough to the light to be affected by it.
+    fn is_in_range(&self, point: &Vec4) -> bool {
+        match (self.max_distance, self.distance_to_point(point)) {
+            (Some(max_distance), Some(distance)) => distance <= max_distance,
+            _ => true,
+        }
+    }
+
+    /// The center of the light, or `None` for directional lights (which have no position).
+    fn center(&self) -> Option<Vec4> {
+        match self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => Some(position),
+        }
+    }
+
+    /// Chooses a point on the light to cast a shadow ray toward. Lights with a radius are
+    /// sampled uniformly within their sphere, and otherwise the light's position is used.
+    fn sample_position(&self) -> Option<Vec4> {
+        match self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point {
+                position, radius, ..
+            }
+            | LightKind::Spot {
+                position, radius, ..
+            } => {
+                if radius == 0.0 {
+                    return Some(position);
+                }
+
+                // Rejection sample a point within the unit sphere
+                loop {
+                    let offset = vec3(
+                        rand::random::<f32>() * 2.0 - 1.0,
+                        rand::random::<f32>() * 2.0 - 1.0,
+                        rand::random::<f32>() * 2.0 - 1.0,
+                    );
+
+                    if offset.dot(offset) <= 1.0 {
+                        return Some(position + (offset * radius).extend(0.0));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Chooses a direction along which to cast a shadow ray toward a directional light, given
+    /// the direction toward its center. Lights with an angular radius are sampled uniformly
+    /// within the cone of directions toward their disk, and otherwise the direction toward
+    /// the center is used.
+    fn sample_direction(&self, to_center: Vec4) -> Vec4 {
+        let LightKind::Directional { angular_radius, .. } = self.kind else {
+            return to_center;
+        };
+        if angular_radius == 0.0 {
+            return to_center;
+        }
 
-        // The point is visible to the light if a ray from the point to the light
-        // does not intersect with any other objects before hitting the light
-        shapes
-            .iter()
-            .flat_map(|shape| shape.intersect(&point_to_light_ray))
-            .filter(|intersection| match distance {
-                // The light is infinitely far away, any intersection obstructs it
-                None => true,
-                // The light is some fixed distance away, look for intersections *closer* than it
-                Some(distance) => intersection.component_intersection.t < distance,
+        let axis = to_center.truncate();
+        let (tangent, bitangent) = axis.any_orthonormal_pair();
+        let cos_theta = 1.0 - rand::random::<f32>() * (1.0 - angular_radius.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rand::random::<f32>() * 2.0 * std::f32::consts::PI;
+
+        (axis * cos_theta + (tangent * phi.cos() + bitangent * phi.sin()) * sin_theta)
+            .normalize()
+            .extend(0.0)
+    }
+
+    /// Determine how "visible" a given point is to the light source, as the average fraction
+    /// of light transmitted along shadow rays cast from the point toward the light. This is
+    /// between 0 (fully in shadow) and 1 (fully lit).
+    fn visibility(&self, point: &Vec4, scene: &Scene, context: &TraceContext) -> f32 {
+        let config = context.config;
+        // In analytic mode, a single shadow ray is cast toward the center of the light
+        let shadow_samples = if config.analytic {
+            1
+        } else {
+            self.shadow_samples
+        };
+        let transmitted: f32 = (0..shadow_samples)
+            .map(|_| {
+                let sampled_position = if config.analytic {
+                    self.center()
+                } else {
+                    self.sample_position()
+                };
+                let (to_light, distance) = match sampled_position {
+                    Some(position) => (
+                        (position - *point).normalize(),
+                        Some((position - *point).length()),
+                    ),
+                    None if config.analytic => (-self.direction_to_point(point), None),
+                    None => (self.sample_direction(-self.direction_to_point(point)), None),
+                };
+
+                let light_point = *point
+                    + to_light * distance.unwrap_or_else(|| ray_export::escape_length(scene));
+                context.record_segment(point, &light_point, RayKind::Shadow);
+
+                // If the light is some fixed distance away, only intersections *closer* than
+                // it obstruct it (if it is infinitely far away, any intersection does)
+                let point_to_light_ray = scene.ray(
+                    *point,
+                    to_light,
+                    scene.self_intersect_offset,
+                    distance.unwrap_or(f32::INFINITY),
+                );
+
+                transmittance(scene, config, point_to_light_ray, self.emitter)
             })
-            .count()
-            == 0
+            .sum();
+
+        transmitted / shadow_samples as f32
     }
 
     /// Determines the intensity of the light source at a given point. This can be affected
     /// by attenuation over distance, or in the case of a spotlight, where the point is
     /// in the light's cone of illumination.
-    fn intensity_at(&self, point: &glm::Vec4) -> glm::Vec4 {
+    fn intensity_at(&self, point: &Vec4) -> Vec4 {
         let distance = self.distance_to_point(point);
-        match self {
-            Light::Directional { color, .. } => *color,
-            Light::Point {
+        match self.kind {
+            LightKind::Directional { color, .. } => color,
+            LightKind::Point {
                 color, attenuation, ..
-            } => *color * attenuation_over_distance(attenuation, distance.unwrap()),
-            Light::Spot {
+            } => color * attenuation_over_distance(&attenuation, distance.unwrap()),
+            LightKind::Spot {
                 color,
                 direction,
                 attenuation,
@@ -226,21 +671,19 @@ impl Light {
                 ..
             } => {
                 let inner_angle = angle - penumbra;
-                let attenuation = attenuation_over_distance(attenuation, distance.unwrap());
+                let attenuation = attenuation_over_distance(&attenuation, distance.unwrap());
 
-                let angle_between_spot_and_point = glm::acos(glm::dot(
-                    glm::normalize(*direction),
-                    self.direction_to_point(point),
-                ));
+                let angle_between_spot_and_point =
+                    (direction.normalize().dot(self.direction_to_point(point))).acos();
 
                 // If the angle to intersection is within the strongest part of the spot
                 if angle_between_spot_and_point <= inner_angle {
-                    return *color * attenuation;
+                    return color * attenuation;
                 }
 
                 // If the angle to intersection is fully outside the outermost angle, spot has no effect
-                if angle_between_spot_and_point > *angle {
-                    return glm::vec4(0.0, 0.0, 0.0, 1.0);
+                if angle_between_spot_and_point > angle {
+                    return vec4(0.0, 0.0, 0.0, 1.0);
                 }
 
                 // Otherwise, the angle is between the inner angle and the outer, there is a falloff applied
@@ -250,8 +693,45 @@ impl Light {
                     -2.0 * fraction_into_penumbra.powi(3) + 3.0 * fraction_into_penumbra.powi(2)
                 };
 
-                *color * attenuation * (1.0 - falloff)
+                color * attenuation * (1.0 - falloff)
             }
         }
     }
+
+    /// The color of the light's gobo (see [`LightKind::Spot`]) projected onto the given point,
+    /// or white if the light has none.
+    fn gobo_at(&self, point: &Vec4) -> Vec4 {
+        let LightKind::Spot {
+            direction,
+            angle,
+            gobo: Some(ref gobo),
+            ..
+        } = self.kind
+        else {
+            return vec4(1.0, 1.0, 1.0, 1.0);
+        };
+
+        // Axes of the plane that the gobo is projected from, facing along the light
+        let forward = direction.truncate().normalize();
+        let up = if forward.cross(Vec3::Y).length_squared() > 1e-6 {
+            Vec3::Y
+        } else {
+            Vec3::NEG_Z
+        };
+        let right = forward.cross(up).normalize();
+        let up = right.cross(forward);
+
+        // The gobo spans the cone's width where it is projected at unit distance
+        let to_point = self.direction_to_point(point).truncate();
+        let extent = to_point.dot(forward) * angle.tan();
+        if extent <= 0.0 {
+            return vec4(1.0, 1.0, 1.0, 1.0);
+        }
+        let uv = (
+            0.5 + to_point.dot(right) / extent * 0.5,
+            0.5 + to_point.dot(up) / extent * 0.5,
+        );
+
+        gobo.value(uv, vec3(uv.0, uv.1, 0.0)).truncate().extend(1.0)
+    }
 }
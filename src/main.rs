@@ -2,22 +2,115 @@
 //! and produces images that portray a 3D view of the scenes.
 
 use anyhow::Result;
+use image::buffer::ConvertBuffer;
+use image::{ColorType, Rgb32FImage};
 use indicatif::{ProgressBar, ProgressStyle};
-use rustracer::Config;
+use rustracer::compare::{self, CompareConfig};
+use rustracer::interrupt::{self, PartialRender};
+use rustracer::package::{self, PackConfig, UnpackConfig};
+use rustracer::report::RenderReport;
+use rustracer::{partition, ray_export, stylize, AssembleConfig, Config};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
 use structopt::StructOpt;
 
+/// Maximum number of aborted pixels whose positions are listed after rendering.
+const MAX_LISTED_ABORTED_PIXELS: usize = 10;
+
 /// Parses the CLI arguments, invokes the raytracer, and saves the output image, propagating errors.
 fn run() -> Result<()> {
+    // Assembling tiles is a separate subcommand, so that the options for rendering are unchanged
+    if std::env::args().nth(1).as_deref() == Some("assemble") {
+        return assemble(AssembleConfig::from_iter(std::env::args().skip(1)));
+    }
+    if std::env::args().nth(1).as_deref() == Some("compare") {
+        return compare(CompareConfig::from_iter(std::env::args().skip(1)));
+    }
+    if std::env::args().nth(1).as_deref() == Some("pack") {
+        return pack(PackConfig::from_iter(std::env::args().skip(1)));
+    }
+    if std::env::args().nth(1).as_deref() == Some("unpack") {
+        return unpack(UnpackConfig::from_iter(std::env::args().skip(1)));
+    }
+    #[cfg(feature = "gui")]
+    if std::env::args().nth(1).as_deref() == Some("inspect") {
+        return rustracer::inspector::run(rustracer::inspector::InspectConfig::from_iter(
+            std::env::args().skip(1),
+        ));
+    }
+
     let config = Config::from_args();
+    let frames = config.frames()?;
+    if config.debug_pixel.is_some() {
+        // Only the first frame of an animation is traced
+        let config = match frames {
+            Some(ref frames) => config.for_frame(frames[0]),
+            None => config,
+        };
+        print!("{}", rustracer::trace_config_pixel(config)?);
+        return Ok(());
+    }
 
-    println!(
-        "Rendering {} as {}x{} image",
-        config.scene.display(),
-        config.width,
-        config.height
-    );
+    // The first Ctrl-C stops the render once its current tiles finish, so that they can be
+    // saved, and a second one exits immediately
+    if config.save_partial {
+        ctrlc::set_handler(|| {
+            if interrupt::is_interrupted() {
+                std::process::exit(130);
+            }
+            interrupt::interrupt();
+        })?;
+    }
+
+    match frames {
+        Some(frames) => {
+            for frame in frames {
+                println!("Frame {}", frame);
+                render(config.for_frame(frame))?;
+            }
+            Ok(())
+        }
+        None => render(config),
+    }
+}
+
+/// Renders the image of the given configuration, and saves it along with the other outputs
+/// that the configuration asks for.
+fn render(config: Config) -> Result<()> {
+    let partition = config.partition()?;
+
+    if partition.count > 1 {
+        println!(
+            "Rendering {} as tile {} of {} (rows {}-{}) of {}x{} image",
+            config.scene.display(),
+            partition.index + 1,
+            partition.count,
+            partition.row_start(),
+            partition.row_end() - 1,
+            config.width,
+            config.height
+        );
+    } else {
+        println!(
+            "Rendering {} as {}x{} image",
+            config.scene.display(),
+            config.width,
+            config.height
+        );
+    }
 
-    let progress_bar = ProgressBar::new((config.width * config.height) as u64);
+    // Progressive renders make a pass over every pixel for each sample
+    let passes = match config.accumulation {
+        Some(_) => config.samples as u64,
+        None => 1,
+    };
+    // The terminal preview redraws itself in place, which the progress bar would interfere with
+    let progress_bar = if config.preview_term {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(config.width as u64 * partition.rendered_height() as u64 * passes)
+    };
 
     progress_bar.set_style(
         ProgressStyle::with_template(
@@ -27,16 +120,200 @@ fn run() -> Result<()> {
     );
 
     let output_image_path = config.output.clone();
-    let output_image = rustracer::render_config(config, || {
-        progress_bar.inc(1);
-    })?;
+    let ray_export_path = config.ray_export.clone();
+    let report_path = config.report.clone();
+    let ascii_path = config.ascii.clone();
+    let (ascii_columns, ascii_color) = (config.ascii_columns, config.ascii_color);
+    let normal_encoding = config.normal_encoding;
+    let scene_path = config.scene.clone();
+    let settings = format!("{:#?}", config);
+    let render_start = Instant::now();
+    let save_partial = config.save_partial;
+
+    let render = rustracer::render_config(config, |pixels| {
+        progress_bar.inc(pixels);
+    });
+    let render = match render {
+        Ok(render) => render,
+        Err(error) => {
+            progress_bar.abandon();
+            if let (true, Some(partial)) = (save_partial, error.downcast_ref::<PartialRender>()) {
+                partial.save(&output_image_path)?;
+                println!(
+                    "Saved the {} finished tiles as {} (listed in {})",
+                    partial.valid_tiles.len(),
+                    output_image_path.display(),
+                    PartialRender::sidecar_path(&output_image_path).display()
+                );
+            }
+            return Err(error);
+        }
+    };
+    let render_time = render_start.elapsed();
+    let render_seconds = render_time.as_secs_f64();
 
     progress_bar.finish();
 
-    output_image.save(&output_image_path)?;
+    if is_exr(&output_image_path) {
+        let image: Rgb32FImage = render.image.convert();
+        image.save(&output_image_path)?;
+    } else {
+        render.image.save(&output_image_path)?;
+    }
+
+    for (aov, aov_image) in &render.aovs {
+        let encoding = aov.encoding(normal_encoding);
+        let aov_path = aov.output_path(&output_image_path, encoding);
+        encoding.save(aov_image, &aov_path)?;
+        println!("AOV {} saved as {}", aov.name(), aov_path.display());
+    }
+
+    // Record which part of the image this is, so that the tiles can be assembled later
+    if partition.count > 1 {
+        partition.write_sidecar(&output_image_path, render_seconds)?;
+    }
+
+    if !render.aborted_pixels.is_empty() {
+        let listed: Vec<String> = render
+            .aborted_pixels
+            .iter()
+            .take(MAX_LISTED_ABORTED_PIXELS)
+            .map(|(col, row)| format!("({}, {})", col, row))
+            .collect();
+        println!(
+            "Aborted {} pixels for exceeding the maximum time per pixel (filled with magenta): {}{}",
+            render.aborted_pixels.len(),
+            listed.join(", "),
+            if render.aborted_pixels.len() > MAX_LISTED_ABORTED_PIXELS {
+                ", ..."
+            } else {
+                ""
+            }
+        );
+    }
 
     println!("Output saved as {}", output_image_path.display());
 
+    if let Some(ray_export_path) = ray_export_path {
+        ray_export::write_obj(&ray_export_path, &render.ray_segments)?;
+        println!(
+            "Exported {} ray segments to {}",
+            render.ray_segments.len(),
+            ray_export_path.display()
+        );
+    }
+
+    if let Some(ascii_path) = ascii_path {
+        fs::write(
+            &ascii_path,
+            stylize::ascii_art(&render.image, ascii_columns, ascii_color),
+        )?;
+        println!("ASCII art saved as {}", ascii_path.display());
+    }
+
+    if let Some(ref cost_report) = render.cost_report {
+        println!("{}", cost_report);
+    }
+
+    if let Some(report_path) = report_path {
+        let report = RenderReport {
+            scene: &scene_path,
+            settings,
+            render: &render,
+            total_time: render_time,
+        };
+        report.save(&report_path)?;
+        println!("Report saved as {}", report_path.display());
+    }
+
+    Ok(())
+}
+
+/// Whether the given path is of an EXR image, which only supports floating point pixels.
+fn is_exr(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("exr"))
+}
+
+/// Stitches together the tiles of a split render, saving the full image along with the
+/// combined statistics of rendering its tiles.
+fn assemble(config: AssembleConfig) -> Result<()> {
+    let (image, stats) = partition::assemble(&config)?;
+    // Floating point tiles stay floating point in an EXR image, and are quantized to 8 bits
+    // for the other formats (which mostly can't store them)
+    if is_exr(&config.out) {
+        image.into_rgb32f().save(&config.out)?;
+    } else if matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F) {
+        image.into_rgb8().save(&config.out)?;
+    } else {
+        image.save(&config.out)?;
+    }
+
+    let stats_summary = format!(
+        "tile_count {}\ntotal_render_seconds {}\nmax_render_seconds {}\n",
+        stats.tile_count, stats.total_render_seconds, stats.max_render_seconds
+    );
+    let mut stats_path = config.out.as_os_str().to_owned();
+    stats_path.push(".stats");
+    fs::write(&stats_path, stats_summary)?;
+
+    println!(
+        "Assembled {} tiles (total render time {:.2}s, slowest tile {:.2}s)",
+        stats.tile_count, stats.total_render_seconds, stats.max_render_seconds
+    );
+    println!("Output saved as {}", config.out.display());
+
+    Ok(())
+}
+
+/// Renders the two sides of a comparison, saving them side by side along with their difference.
+fn compare(config: CompareConfig) -> Result<()> {
+    println!(
+        "Rendering comparison as {}x{} images",
+        config.width, config.height
+    );
+
+    let comparison = compare::compare(&config)?;
+    comparison.image.save(&config.out)?;
+
+    let pixel_count = config.width as u64 * config.height as u64;
+    println!(
+        "Rendered in {:.2}s (left) and {:.2}s (right), differing at {} of {} pixels ({:.2}%)",
+        comparison.render_seconds[0],
+        comparison.render_seconds[1],
+        comparison.differing_pixels,
+        pixel_count,
+        comparison.differing_pixels as f64 / pixel_count as f64 * 100.0
+    );
+    println!("Output saved as {}", config.out.display());
+
+    Ok(())
+}
+
+/// Packages a scene into a single file.
+fn pack(config: PackConfig) -> Result<()> {
+    let file_count = package::pack(&config)?;
+    println!(
+        "Packaged {} with {} file(s) as {}",
+        config.scene.display(),
+        file_count,
+        config.out.display()
+    );
+
+    Ok(())
+}
+
+/// Extracts a packaged scene, and prints the options to render it with.
+fn unpack(config: UnpackConfig) -> Result<()> {
+    let scene = package::unpack(&config)?;
+    println!(
+        "Unpacked {} into {}; render it with --scene {} --textures {}",
+        config.package.display(),
+        config.out.display(),
+        scene.display(),
+        package::textures_directory(&config.out).display()
+    );
+
     Ok(())
 }
 
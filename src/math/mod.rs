@@ -0,0 +1,19 @@
+//! Geometric types and utilities shared by the primitives and shading.
+//!
+//! The vector, matrix, and quaternion types are re-exported from [`glam`] (whose `Vec4` and
+//! `Mat4` use SIMD where available), and the rest of the crate refers to them only through
+//! this module, so that the library backing them can be changed in one place.
+
+pub mod onb;
+
+pub use glam::{vec3, vec4, Mat3, Mat4, Quat, Vec3, Vec4};
+
+/// Transforms a normal vector by the given transformation matrix, using the inverse
+/// transpose of its upper 3x3 so that the result remains perpendicular to the surface.
+pub fn transform_normal(transformation: &Mat4, normal: &Vec4) -> Vec4 {
+    let normal_transform = Mat3::from_mat4(*transformation).inverse().transpose();
+
+    (normal_transform * normal.truncate())
+        .normalize()
+        .extend(0.0)
+}
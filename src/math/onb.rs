@@ -0,0 +1,105 @@
+//! Orthonormal bases around surface normals, and the tangent frames of surfaces (the
+//! directions in which their texture coordinates increase), which shading techniques like
+//! normal mapping and sampling directions around a normal need to agree on.
+
+use super::{vec3, Mat4, Vec3, Vec4};
+
+/// Vectors shorter than this are too short to take a meaningful direction from.
+const DEGENERATE_LENGTH: f32 = 1e-8;
+
+/// Builds two unit vectors which, together with the given unit normal, form an orthonormal
+/// basis, returned as `(tangent, bitangent)` where `cross(normal, tangent) == bitangent`.
+/// The basis varies continuously with the normal, except across the plane `normal.z == 0`.
+///
+/// This is the branchless construction from Duff et al., "Building an Orthonormal Basis,
+/// Revisited" (2017).
+pub fn build_orthonormal_basis(normal: &Vec3) -> (Vec3, Vec3) {
+    let sign = 1f32.copysign(normal.z);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+
+    let tangent = vec3(
+        1.0 + sign * normal.x * normal.x * a,
+        sign * b,
+        -sign * normal.x,
+    );
+    let bitangent = vec3(b, sign + normal.y * normal.y * a, -normal.y);
+
+    (tangent, bitangent)
+}
+
+/// Builds the tangent frame of a surface with the given unit normal from the derivatives of
+/// the surface's position with respect to its u and v texture coordinates, returned as
+/// `(tangent, bitangent)`. The tangent is the direction of increasing u, made perpendicular
+/// to the normal, and the bitangent is perpendicular to both and points toward increasing v
+/// (so the frame is left-handed where the texture is mirrored).
+///
+/// Where the derivatives are degenerate (e.g. at the poles of a sphere), the frame falls back
+/// on whichever derivative is usable, or else on [`build_orthonormal_basis`].
+pub fn tangent_frame(normal: &Vec3, dp_du: &Vec3, dp_dv: &Vec3) -> (Vec3, Vec3) {
+    let along_surface = |direction: &Vec3| {
+        let projected = *direction - *normal * normal.dot(*direction);
+        let length = projected.length();
+        (length > DEGENERATE_LENGTH).then(|| projected / length)
+    };
+
+    let (tangent, bitangent) = match (along_surface(dp_du), along_surface(dp_dv)) {
+        (Some(tangent), _) => (tangent, normal.cross(tangent)),
+        (None, Some(bitangent)) => (bitangent.cross(*normal), bitangent),
+        (None, None) => return build_orthonormal_basis(normal),
+    };
+
+    if bitangent.dot(*dp_dv) < 0.0 {
+        (tangent, -bitangent)
+    } else {
+        (tangent, bitangent)
+    }
+}
+
+/// Finds the derivatives of position with respect to the u and v texture coordinates over a
+/// triangle with the given corner positions and texture coordinates, returned as
+/// `(dp_du, dp_dv)`. Returns `None` if the texture coordinates of the triangle are degenerate
+/// (e.g. all the same).
+pub fn triangle_uv_derivatives(
+    positions: &[Vec3; 3],
+    uvs: &[(f32, f32); 3],
+) -> Option<(Vec3, Vec3)> {
+    let edge_ab = positions[1] - positions[0];
+    let edge_ac = positions[2] - positions[0];
+    let (du_ab, dv_ab) = (uvs[1].0 - uvs[0].0, uvs[1].1 - uvs[0].1);
+    let (du_ac, dv_ac) = (uvs[2].0 - uvs[0].0, uvs[2].1 - uvs[0].1);
+
+    let determinant = du_ab * dv_ac - du_ac * dv_ab;
+    if determinant.abs() < DEGENERATE_LENGTH {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let dp_du = (edge_ab * dv_ac - edge_ac * dv_ab) * inverse_determinant;
+    let dp_dv = (edge_ac * du_ab - edge_ab * du_ac) * inverse_determinant;
+
+    Some((dp_du, dp_dv))
+}
+
+/// The direction of increasing u at the given point on a surface of revolution around the
+/// y axis, on which u decreases as the angle `atan2(z, x)` increases.
+pub fn revolution_dp_du(point: &Vec4) -> Vec3 {
+    vec3(point.z, 0.0, -point.x)
+}
+
+/// Transforms a tangent frame by the given transformation matrix, returning the frame around
+/// the given (already transformed) normal.
+pub fn transform_tangent_frame(
+    transformation: &Mat4,
+    normal: &Vec4,
+    tangent: &Vec4,
+    bitangent: &Vec4,
+) -> (Vec4, Vec4) {
+    let (tangent, bitangent) = tangent_frame(
+        &normal.truncate(),
+        &(*transformation * *tangent).truncate(),
+        &(*transformation * *bitangent).truncate(),
+    );
+
+    (tangent.extend(0.0), bitangent.extend(0.0))
+}
@@ -0,0 +1,786 @@
+//! Triangle meshes loaded from Wavefront OBJ files, which are intersected
+//! using a bounding volume hierarchy built over their triangles.
+
+use crate::bounding_box::BoundingBox;
+use crate::intersection::ComponentIntersection;
+use crate::math::{onb, vec3, Vec3};
+use crate::primitive::PrimitiveComponent;
+use crate::raytracer::Ray;
+use crate::texture::{ImageTexture, TextureSource};
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Maximum number of triangles stored in a single leaf of a mesh's BVH.
+const MAX_TRIANGLES_PER_LEAF: usize = 4;
+
+/// Most segments that each edge of a triangle is split into when a mesh is displaced with an
+/// automatic subdivision level.
+const MAX_AUTOMATIC_SEGMENTS: u32 = 64;
+
+/// Most triangles that a mesh is tessellated into when it is displaced with an automatic
+/// subdivision level.
+const MAX_AUTOMATIC_TRIANGLES: usize = 4_000_000;
+
+/// Displacement of the surface of a mesh along its normals by a height texture.
+#[derive(Debug)]
+pub struct Displacement {
+    /// Texture whose brightness (the average of its color channels) is the height of the
+    /// surface at each UV coordinate.
+    pub height: ImageTexture,
+    /// Distance (in object space) that a height of 1 moves the surface.
+    pub scale: f32,
+    /// Number of segments each edge of the mesh's triangles is split into, or `None` to
+    /// split them finely enough to show each texel of the height texture.
+    pub segments: Option<u32>,
+}
+
+/// A vertex of a tessellated triangle.
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    position: Vec3,
+    normal: Vec3,
+    uv: (f32, f32),
+}
+
+impl Vertex {
+    /// The vertex at the given fraction of the way from this vertex to another.
+    fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        Vertex {
+            position: self.position.lerp(other.position, t),
+            normal: self.normal.lerp(other.normal, t),
+            uv: (
+                self.uv.0 + (other.uv.0 - self.uv.0) * t,
+                self.uv.1 + (other.uv.1 - self.uv.1) * t,
+            ),
+        }
+    }
+}
+
+/// A triangle of a mesh, which refers to its vertex data by index into the mesh's buffers.
+#[derive(Debug, Clone)]
+struct Triangle {
+    positions: [usize; 3],
+    normals: Option<[usize; 3]>,
+    uvs: Option<[usize; 3]>,
+}
+
+/// A node of a mesh's bounding volume hierarchy. Leaves refer to a contiguous
+/// range of the mesh's triangles, which are ordered during construction so that
+/// each leaf's triangles are adjacent.
+#[derive(Debug)]
+enum BvhNode {
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &BoundingBox {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// The contents of a Wavefront OBJ file, before its triangles are built into meshes.
+struct ObjContents {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    triangles: Vec<Triangle>,
+    /// Index (in `material_names`) of the material assigned to each triangle, if any.
+    triangle_materials: Vec<Option<usize>>,
+    /// Names of the materials assigned to faces (by `usemtl`), in the order they first appear.
+    material_names: Vec<String>,
+    /// Paths of the material libraries (given by `mtllib`), relative to the OBJ file.
+    material_libraries: Vec<String>,
+}
+
+impl ObjContents {
+    /// The contents with their triangles tessellated and their vertices moved along their
+    /// normals by the given displacement, so that the detail of the height texture is part of
+    /// the mesh's geometry (and silhouette). The normals of the displaced mesh are recomputed
+    /// from its triangles, and each new triangle keeps the material of the one it is part of.
+    ///
+    /// Vertices on edges shared by triangles are computed identically for each triangle, so
+    /// that the displaced surface has no cracks wherever its triangles share texture
+    /// coordinates and normals. As this relies on the indices of the vertices (and on the
+    /// smooth normals computed from all of the triangles), the whole mesh is displaced at
+    /// once, before it is split by material.
+    fn displaced(self, displacement: &Displacement) -> Result<ObjContents> {
+        if self.triangles.iter().any(|triangle| triangle.uvs.is_none()) {
+            bail!("Displaced meshes must have texture coordinates");
+        }
+
+        let corner_normals = self.corner_normals();
+        let segments = displacement
+            .segments
+            .unwrap_or_else(|| self.automatic_segments(&displacement.height))
+            .max(1);
+
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut position_indices = HashMap::new();
+        let mut triangles = Vec::new();
+        let mut triangle_materials = Vec::new();
+
+        for ((triangle, normals), &material) in self
+            .triangles
+            .iter()
+            .zip(&corner_normals)
+            .zip(&self.triangle_materials)
+        {
+            let uv_indices = triangle
+                .uvs
+                .expect("Triangles should have texture coordinates");
+            let corners: [(usize, Vertex); 3] = std::array::from_fn(|corner| {
+                (
+                    triangle.positions[corner],
+                    Vertex {
+                        position: self.positions[triangle.positions[corner]],
+                        normal: normals[corner],
+                        uv: self.uvs[uv_indices[corner]],
+                    },
+                )
+            });
+
+            // Indices of the (displaced) grid of vertices over the triangle, by the number
+            // of segments toward its second and third corners
+            let mut grid = HashMap::new();
+            for i in 0..=segments {
+                for j in 0..=segments - i {
+                    let vertex =
+                        displace(&tessellated_vertex(&corners, segments, i, j), displacement);
+
+                    // Vertices are shared by position (e.g. with the neighbouring triangles),
+                    // so that the normals computed for them are smooth across edges
+                    let position_index = *position_indices
+                        .entry(vertex.position.to_array().map(f32::to_bits))
+                        .or_insert_with(|| {
+                            positions.push(vertex.position);
+                            positions.len() - 1
+                        });
+                    uvs.push(vertex.uv);
+                    grid.insert((i, j), (position_index, uvs.len() - 1));
+                }
+            }
+
+            let mut push_triangle = |corners: [(u32, u32); 3]| {
+                let [a, b, c] = corners.map(|corner| grid[&corner]);
+                triangles.push(Triangle {
+                    positions: [a.0, b.0, c.0],
+                    normals: Some([a.0, b.0, c.0]),
+                    uvs: Some([a.1, b.1, c.1]),
+                });
+                triangle_materials.push(material);
+            };
+            for i in 0..segments {
+                for j in 0..segments - i {
+                    push_triangle([(i, j), (i + 1, j), (i, j + 1)]);
+                    if i + j + 1 < segments {
+                        push_triangle([(i + 1, j), (i + 1, j + 1), (i, j + 1)]);
+                    }
+                }
+            }
+        }
+
+        // The normal of each vertex is the area-weighted average of its triangles' normals
+        let mut normals = vec![Vec3::ZERO; positions.len()];
+        for triangle in &triangles {
+            let [a, b, c] = triangle.positions.map(|i| positions[i]);
+            let face_normal = (b - a).cross(c - a);
+            for &i in &triangle.positions {
+                normals[i] += face_normal;
+            }
+        }
+        let normals = normals
+            .into_iter()
+            .map(|normal| normal.normalize_or_zero())
+            .collect();
+
+        Ok(ObjContents {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            triangle_materials,
+            material_names: self.material_names,
+            material_libraries: self.material_libraries,
+        })
+    }
+
+    /// The normal at each corner of each triangle: those given by the mesh, or (for triangles
+    /// without normals) the average of the normals of the triangles around the vertex.
+    fn corner_normals(&self) -> Vec<[Vec3; 3]> {
+        let mut smooth_normals = vec![Vec3::ZERO; self.positions.len()];
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.positions.map(|i| self.positions[i]);
+            let face_normal = (b - a).cross(c - a);
+            for &i in &triangle.positions {
+                smooth_normals[i] += face_normal;
+            }
+        }
+
+        self.triangles
+            .iter()
+            .map(|triangle| match triangle.normals {
+                Some(normals) => normals.map(|i| self.normals[i].normalize_or_zero()),
+                None => triangle
+                    .positions
+                    .map(|i| smooth_normals[i].normalize_or_zero()),
+            })
+            .collect()
+    }
+
+    /// The number of segments to split each edge of the triangles into so that each
+    /// segment spans about one texel of the given height texture, within the limits on the
+    /// tessellation.
+    fn automatic_segments(&self, height: &ImageTexture) -> u32 {
+        let (width, height) = height.dimensions();
+        let texels = |(u, v): (f32, f32)| vec3(u * width as f32, v * height as f32, 0.0);
+
+        let longest_edge = self
+            .triangles
+            .iter()
+            .filter_map(|triangle| triangle.uvs)
+            .flat_map(|uvs| {
+                let [a, b, c] = uvs.map(|i| texels(self.uvs[i]));
+                [a.distance(b), b.distance(c), c.distance(a)]
+            })
+            .fold(0.0, f32::max);
+
+        let max_segments = ((MAX_AUTOMATIC_TRIANGLES / self.triangles.len().max(1)) as f32)
+            .sqrt()
+            .floor() as u32;
+
+        (longest_edge.ceil() as u32).clamp(1, MAX_AUTOMATIC_SEGMENTS.min(max_segments.max(1)))
+    }
+}
+
+/// A mesh loaded from a Wavefront OBJ file, split into parts by the materials assigned to its
+/// faces.
+#[derive(Debug)]
+pub struct ObjParts {
+    /// Paths of the material libraries (given by `mtllib`) that define the materials, relative
+    /// to the OBJ file.
+    pub material_libraries: Vec<String>,
+    /// Name of the material of each part (or `None` for the faces without one), along with a
+    /// mesh of the part's faces. There is always at least one part, which is empty if the
+    /// mesh has no faces.
+    pub parts: Vec<(Option<String>, Mesh)>,
+}
+
+/// A primitive component made up of triangles, in object space.
+#[derive(Debug)]
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    triangles: Vec<Triangle>,
+    /// Nodes of the BVH over the triangles, where the first node is the root.
+    bvh: Vec<BvhNode>,
+}
+
+impl Mesh {
+    /// Constructs a mesh from its vertex data and triangles, building its BVH.
+    fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f32, f32)>,
+        mut triangles: Vec<Triangle>,
+    ) -> Self {
+        let mut bvh = Vec::new();
+
+        if !triangles.is_empty() {
+            Mesh::build_bvh_node(&mut bvh, &mut triangles, 0, &positions);
+        }
+
+        Self {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            bvh,
+        }
+    }
+
+    /// Loads a mesh from a Wavefront OBJ file, displacing it by the given displacement if
+    /// any. Only vertex positions, normals, texture coordinates, and faces are used;
+    /// polygonal faces are split into triangle fans.
+    pub fn load_obj(path: &Path, displacement: Option<&Displacement>) -> Result<Self> {
+        let contents = Mesh::read_obj(path, displacement)?;
+
+        Ok(Mesh::new(
+            contents.positions,
+            contents.normals,
+            contents.uvs,
+            contents.triangles,
+        ))
+    }
+
+    /// Loads a mesh from a Wavefront OBJ file (like [`Mesh::load_obj`]), split into a mesh
+    /// for each of the materials that its faces are assigned (by `usemtl`). Each part only
+    /// keeps the vertex data that its own faces use.
+    pub fn load_obj_parts(path: &Path, displacement: Option<&Displacement>) -> Result<ObjParts> {
+        let contents = Mesh::read_obj(path, displacement)?;
+
+        let mut part_triangles: Vec<Vec<Triangle>> =
+            vec![Vec::new(); contents.material_names.len() + 1];
+        for (triangle, material) in contents
+            .triangles
+            .into_iter()
+            .zip(contents.triangle_materials)
+        {
+            // Faces without a material are in the last part
+            part_triangles[material.unwrap_or(contents.material_names.len())].push(triangle);
+        }
+
+        let names = contents.material_names.into_iter().map(Some).chain([None]);
+        let mut parts: Vec<(Option<String>, Mesh)> = names
+            .zip(part_triangles)
+            .filter(|(_, triangles)| !triangles.is_empty())
+            .map(|(name, mut triangles)| {
+                let positions = compact(&contents.positions, &mut triangles, |triangle| {
+                    Some(&mut triangle.positions)
+                });
+                let normals = compact(&contents.normals, &mut triangles, |triangle| {
+                    triangle.normals.as_mut()
+                });
+                let uvs = compact(&contents.uvs, &mut triangles, |triangle| {
+                    triangle.uvs.as_mut()
+                });
+
+                (name, Mesh::new(positions, normals, uvs, triangles))
+            })
+            .collect();
+
+        // A mesh without any faces is a single empty part (as it is when loaded whole)
+        if parts.is_empty() {
+            parts.push((
+                None,
+                Mesh::new(Vec::new(), Vec::new(), Vec::new(), Vec::new()),
+            ));
+        }
+
+        Ok(ObjParts {
+            material_libraries: contents.material_libraries,
+            parts,
+        })
+    }
+
+    /// Reads the contents of an OBJ file, displaced by the given displacement if any.
+    fn read_obj(path: &Path, displacement: Option<&Displacement>) -> Result<ObjContents> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mesh file: {}", path.display()))?;
+
+        let contents = Mesh::parse_obj(&contents)
+            .with_context(|| format!("Failed to parse mesh file: {}", path.display()))?;
+        match displacement {
+            Some(displacement) => contents
+                .displaced(displacement)
+                .with_context(|| format!("Failed to displace mesh: {}", path.display())),
+            None => Ok(contents),
+        }
+    }
+
+    fn parse_obj(contents: &str) -> Result<ObjContents> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut triangles = Vec::new();
+        let mut triangle_materials = Vec::new();
+        let mut material_names: Vec<String> = Vec::new();
+        let mut material_libraries = Vec::new();
+        let mut material = None;
+
+        for (line_index, line) in contents.lines().enumerate() {
+            let line_number = line_index + 1;
+            let mut tokens = line.split_whitespace();
+
+            let parse_floats = |tokens: std::str::SplitWhitespace| -> Result<Vec<f32>> {
+                tokens
+                    .map(|token| {
+                        token
+                            .parse::<f32>()
+                            .map_err(|_| anyhow!("Invalid number on line {}", line_number))
+                    })
+                    .collect()
+            };
+
+            match tokens.next() {
+                Some("v") => match parse_floats(tokens)?[..] {
+                    [x, y, z, ..] => positions.push(vec3(x, y, z)),
+                    _ => bail!("Vertex on line {} must have 3 coordinates", line_number),
+                },
+                Some("vn") => match parse_floats(tokens)?[..] {
+                    [x, y, z] => normals.push(vec3(x, y, z)),
+                    _ => bail!("Normal on line {} must have 3 coordinates", line_number),
+                },
+                Some("vt") => match parse_floats(tokens)?[..] {
+                    [u, v, ..] => uvs.push((u, v)),
+                    [u] => uvs.push((u, 0.0)),
+                    _ => bail!(
+                        "Texture coordinate on line {} must have a coordinate",
+                        line_number
+                    ),
+                },
+                Some("f") => {
+                    let corners = tokens
+                        .map(|corner| {
+                            parse_face_corner(corner, (positions.len(), uvs.len(), normals.len()))
+                                .with_context(|| format!("Invalid face on line {}", line_number))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+
+                    if corners.len() < 3 {
+                        bail!("Face on line {} must have at least 3 vertices", line_number);
+                    }
+
+                    // Split the polygon into a fan of triangles around its first corner
+                    for i in 1..corners.len() - 1 {
+                        let [a, b, c] = [corners[0], corners[i], corners[i + 1]];
+
+                        triangles.push(Triangle {
+                            positions: [a.0, b.0, c.0],
+                            uvs: a.1.zip(b.1).zip(c.1).map(|((a, b), c)| [a, b, c]),
+                            normals: a.2.zip(b.2).zip(c.2).map(|((a, b), c)| [a, b, c]),
+                        });
+                        triangle_materials.push(material);
+                    }
+                }
+                Some("usemtl") => {
+                    let name = tokens.collect::<Vec<_>>().join(" ");
+
+                    // A `usemtl` without a name returns to faces without a material
+                    material = match material_names.iter().position(|existing| *existing == name) {
+                        _ if name.is_empty() => None,
+                        Some(index) => Some(index),
+                        None => {
+                            material_names.push(name);
+                            Some(material_names.len() - 1)
+                        }
+                    };
+                }
+                Some("mtllib") => material_libraries.extend(tokens.map(str::to_string)),
+                // Ignore comments, blank lines, and unsupported statements (groups, etc.)
+                _ => {}
+            }
+        }
+
+        Ok(ObjContents {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            triangle_materials,
+            material_names,
+            material_libraries,
+        })
+    }
+
+    /// Recursively builds the BVH node over the given triangles (which start at index `first`
+    /// in the mesh), splitting at the median centroid along the longest axis. Returns the
+    /// index of the constructed node.
+    fn build_bvh_node(
+        nodes: &mut Vec<BvhNode>,
+        triangles: &mut [Triangle],
+        first: usize,
+        positions: &[Vec3],
+    ) -> usize {
+        let triangle_bounds = |triangle: &Triangle| {
+            BoundingBox::from_points(triangle.positions.iter().map(|&i| &positions[i]))
+        };
+
+        let bounds = triangles
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, triangle| {
+                bounds.union(&triangle_bounds(triangle))
+            });
+
+        let index = nodes.len();
+        nodes.push(BvhNode::Leaf {
+            bounds,
+            first,
+            count: triangles.len(),
+        });
+
+        if triangles.len() <= MAX_TRIANGLES_PER_LEAF {
+            return index;
+        }
+
+        let centroid_bounds = triangles
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, triangle| {
+                bounds.including(&triangle_bounds(triangle).centroid())
+            });
+        let axis = centroid_bounds.longest_axis();
+
+        let middle = triangles.len() / 2;
+        triangles.select_nth_unstable_by(middle, |a, b| {
+            let a_centroid = triangle_bounds(a).centroid()[axis];
+            let b_centroid = triangle_bounds(b).centroid()[axis];
+            a_centroid
+                .partial_cmp(&b_centroid)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let (left_triangles, right_triangles) = triangles.split_at_mut(middle);
+        let left = Mesh::build_bvh_node(nodes, left_triangles, first, positions);
+        let right = Mesh::build_bvh_node(nodes, right_triangles, first + middle, positions);
+
+        nodes[index] = BvhNode::Interior {
+            bounds,
+            left,
+            right,
+        };
+
+        index
+    }
+
+    /// Intersects the ray with a single triangle, using the Möller–Trumbore algorithm.
+    fn intersect_triangle(&self, triangle: &Triangle, ray: &Ray) -> Option<ComponentIntersection> {
+        let [a, b, c] = triangle.positions.map(|i| self.positions[i]);
+        let origin = ray.position.truncate();
+        let direction = ray.direction.truncate();
+
+        let edge_ab = b - a;
+        let edge_ac = c - a;
+        let p = direction.cross(edge_ac);
+        let determinant = edge_ab.dot(p);
+
+        // The ray is parallel to the plane of the triangle
+        if determinant == 0.0 {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let a_to_origin = origin - a;
+
+        // Barycentric coordinates of the intersection point, weighting vertices b and c
+        let u = a_to_origin.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = a_to_origin.cross(edge_ab);
+        let v = direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge_ac.dot(q) * inverse_determinant;
+        if !ray.contains(t) {
+            return None;
+        }
+
+        let w = 1.0 - u - v;
+
+        let normal = match triangle.normals {
+            Some(normals) => {
+                let [na, nb, nc] = normals.map(|i| self.normals[i]);
+                na * w + nb * u + nc * v
+            }
+            None => edge_ab.cross(edge_ac),
+        };
+
+        // Without texture coordinates, the barycentric coordinates are used in their place
+        let (uv, (dp_du, dp_dv)) = match triangle.uvs {
+            Some(uvs) => {
+                let [ta, tb, tc] = uvs.map(|i| self.uvs[i]);
+                (
+                    (
+                        ta.0 * w + tb.0 * u + tc.0 * v,
+                        ta.1 * w + tb.1 * u + tc.1 * v,
+                    ),
+                    onb::triangle_uv_derivatives(&[a, b, c], &[ta, tb, tc])
+                        .unwrap_or((edge_ab, edge_ac)),
+                )
+            }
+            None => ((u, v), (edge_ab, edge_ac)),
+        };
+
+        Some(ComponentIntersection::new(
+            t,
+            normal.normalize().extend(0.0),
+            uv,
+            dp_du,
+            dp_dv,
+        ))
+    }
+}
+
+impl PrimitiveComponent for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        let mut closest: Option<ComponentIntersection> = None;
+
+        if self.bvh.is_empty() {
+            return None;
+        }
+
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.bvh[node_index];
+
+            // Skip nodes that the ray misses, or which are entirely behind the closest hit so far
+            match node.bounds().intersect(ray) {
+                Some((t_enter, _)) if closest.as_ref().map_or(true, |c| t_enter <= c.t) => {}
+                _ => continue,
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for triangle in &self.triangles[first..first + count] {
+                        if let Some(intersection) = self.intersect_triangle(triangle, ray) {
+                            if closest.as_ref().map_or(true, |c| intersection.t < c.t) {
+                                closest = Some(intersection);
+                            }
+                        }
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+
+        closest
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.bvh
+            .first()
+            .map_or(BoundingBox::empty(), |root| *root.bounds())
+    }
+}
+
+/// The vertex of a triangle's tessellation into the given number of segments per edge that is
+/// the given numbers of segments toward its second and third corners. Vertices on an edge of
+/// the triangle are interpolated along the edge in the same order (by the corners' position
+/// indices) whichever triangle they are computed for.
+fn tessellated_vertex(corners: &[(usize, Vertex); 3], segments: u32, i: u32, j: u32) -> Vertex {
+    let fraction = |steps: u32| steps as f32 / segments as f32;
+    let on_edge = |(a_index, a): &(usize, Vertex), (b_index, b): &(usize, Vertex), steps: u32| {
+        // `steps` is the number of segments from a toward b
+        if steps == 0 {
+            *a
+        } else if steps == segments {
+            *b
+        } else if a_index <= b_index {
+            a.lerp(b, fraction(steps))
+        } else {
+            b.lerp(a, fraction(segments - steps))
+        }
+    };
+
+    let [a, b, c] = corners;
+    if j == 0 {
+        on_edge(a, b, i)
+    } else if i == 0 {
+        on_edge(a, c, j)
+    } else if i + j == segments {
+        on_edge(b, c, j)
+    } else {
+        let (u, v) = (fraction(i), fraction(j));
+        let w = 1.0 - u - v;
+        let [a, b, c] = [a.1, b.1, c.1];
+        Vertex {
+            position: a.position * w + b.position * u + c.position * v,
+            normal: a.normal * w + b.normal * u + c.normal * v,
+            uv: (
+                a.uv.0 * w + b.uv.0 * u + c.uv.0 * v,
+                a.uv.1 * w + b.uv.1 * u + c.uv.1 * v,
+            ),
+        }
+    }
+}
+
+/// Moves a vertex along its normal by the height of the displacement's texture at its
+/// texture coordinate (which is tiled).
+fn displace(vertex: &Vertex, displacement: &Displacement) -> Vertex {
+    let (u, v) = vertex.uv;
+    let height = displacement
+        .height
+        .value((u.rem_euclid(1.0), v.rem_euclid(1.0)))
+        .truncate()
+        .dot(Vec3::splat(1.0 / 3.0));
+
+    Vertex {
+        position: vertex.position + vertex.normal.normalize_or_zero() * height * displacement.scale,
+        ..*vertex
+    }
+}
+
+/// The elements of the given buffer that the given triangles use (through the indices that
+/// the given function picks out of each triangle, if it has them), whose indices are rewritten
+/// to refer to the returned buffer.
+fn compact<T: Copy>(
+    buffer: &[T],
+    triangles: &mut [Triangle],
+    indices: impl Fn(&mut Triangle) -> Option<&mut [usize; 3]>,
+) -> Vec<T> {
+    let mut compacted = Vec::new();
+    let mut new_indices = HashMap::new();
+
+    for triangle in triangles {
+        for index in indices(triangle).into_iter().flatten() {
+            *index = *new_indices.entry(*index).or_insert_with(|| {
+                compacted.push(buffer[*index]);
+                compacted.len() - 1
+            });
+        }
+    }
+
+    compacted
+}
+
+/// Parses a single corner of an OBJ face (of the form `v`, `v/vt`, `v//vn`, or `v/vt/vn`),
+/// resolving 1-based and negative (relative) indices to indices into the mesh's buffers,
+/// given the number of (positions, uvs, normals) defined so far.
+fn parse_face_corner(
+    corner: &str,
+    (position_count, uv_count, normal_count): (usize, usize, usize),
+) -> Result<(usize, Option<usize>, Option<usize>)> {
+    fn resolve_index(index: &str, count: usize) -> Result<usize> {
+        let index: isize = index
+            .parse()
+            .map_err(|_| anyhow!("Invalid index \"{}\"", index))?;
+
+        let resolved = match index.cmp(&0) {
+            Ordering::Greater => index - 1,
+            Ordering::Less => count as isize + index,
+            Ordering::Equal => bail!("Indices must not be 0"),
+        };
+
+        if resolved < 0 || resolved as usize >= count {
+            bail!("Index {} is out of bounds", index);
+        }
+
+        Ok(resolved as usize)
+    }
+
+    let mut indices = corner.split('/');
+
+    let position = resolve_index(indices.next().unwrap_or_default(), position_count)?;
+    let uv = match indices.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, uv_count)?),
+    };
+    let normal = match indices.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve_index(index, normal_count)?),
+    };
+
+    Ok((position, uv, normal))
+}
@@ -0,0 +1,129 @@
+//! Deterministic 3D noise functions (Perlin gradient noise and Worley cellular noise), from
+//! which solid procedural textures such as marble and wood are built.
+
+use crate::math::{vec3, Vec3};
+
+/// Directions of the gradients at the lattice points of Perlin noise: the midpoints of the
+/// edges of a cube, as in Perlin's improved noise.
+const GRADIENTS: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// A well-mixed hash of the integer coordinates of a lattice cell, which stands in for the
+/// random values of the noise (so that it is the same on every run).
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut hash = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1_e995);
+    hash ^ (hash >> 15)
+}
+
+/// The smootherstep curve, which blends between lattice points without visible creases.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Perlin gradient noise at the given point, roughly between -1 and 1 (and 0 at every
+/// lattice point), varying smoothly over about one unit.
+pub fn perlin(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let offset = point - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let gradient = Vec3::from_array(GRADIENTS[hash(x + dx, y + dy, z + dz) as usize % 12]);
+        gradient.dot(offset - vec3(dx as f32, dy as f32, dz as f32))
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let (u, v, w) = (fade(offset.x), fade(offset.y), fade(offset.z));
+
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+/// Fractal noise: the sum of the given number of octaves of Perlin noise, each with twice the
+/// frequency and half the amplitude of the last, normalized to roughly between -1 and 1.
+pub fn fractal(point: Vec3, octaves: u32) -> f32 {
+    let (mut sum, mut total_amplitude) = (0.0, 0.0);
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+
+    for _ in 0..octaves {
+        sum += amplitude * perlin(point * frequency);
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    sum / total_amplitude
+}
+
+/// Turbulence: like [`fractal`] noise, but summing the absolute values of the octaves, which
+/// gives sharp creases (e.g. for the veins of marble). It is roughly between 0 and 1.
+pub fn turbulence(point: Vec3, octaves: u32) -> f32 {
+    let (mut sum, mut total_amplitude) = (0.0, 0.0);
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+
+    for _ in 0..octaves {
+        sum += amplitude * perlin(point * frequency).abs();
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    sum / total_amplitude
+}
+
+/// Worley (cellular) noise: the distance from the given point to the closest of a set of
+/// feature points scattered one per unit cell, which is 0 at the feature points and grows
+/// toward the borders between them.
+pub fn worley(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let mut closest = f32::INFINITY;
+
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                let (cx, cy, cz) = (x + dx, y + dy, z + dz);
+                // The feature point of each cell is at a position within it given by hashes
+                // of the cell's coordinates
+                let jitter = vec3(
+                    hash(cx, cy, cz) as f32,
+                    hash(cy, cz, cx) as f32,
+                    hash(cz, cx, cy) as f32,
+                ) / u32::MAX as f32;
+                let feature = vec3(cx as f32, cy as f32, cz as f32) + jitter;
+
+                closest = closest.min(feature.distance(point));
+            }
+        }
+    }
+
+    closest
+}
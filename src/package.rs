@@ -0,0 +1,311 @@
+//! Scene packages (`.rtscene` files): zip archives of a scenefile along with every texture,
+//! mesh, and other file that it refers to, so that a scene can be shared (or sent to be
+//! rendered elsewhere) as a single self-contained file.
+//!
+//! Within a package, the scenefile is `scene.xml`, its textures are in `textures/` (which is
+//! the directory to render the unpacked scene with `--textures`), and the files that it
+//! loads relative to itself (meshes, patches, and point clouds) are in `files/`. The paths in
+//! the scenefile are rewritten to match.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use structopt::StructOpt;
+use xmltree::{Element, EmitterConfig, XMLNode};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Name of the scenefile within a package.
+const SCENEFILE_NAME: &str = "scene.xml";
+
+/// Directory within a package of the scene's textures.
+const TEXTURES_DIRECTORY: &str = "textures";
+
+/// Directory within a package of the files that the scenefile loads relative to itself.
+const FILES_DIRECTORY: &str = "files";
+
+/// Tags whose `file` attribute is a path relative to the textures directory.
+const TEXTURE_TAGS: &[&str] = &[
+    "texture",
+    "specularmap",
+    "shininessmap",
+    "displacement",
+    "mask",
+    "environment",
+];
+
+/// Attributes of `<object type="primitive">` tags whose values are paths relative to the
+/// scenefile.
+const SCENE_RELATIVE_ATTRIBUTES: &[&str] = &["meshfile", "patchfile", "pointfile"];
+
+/// Statements of MTL material libraries whose last argument is a texture file, relative to
+/// the library.
+const MTL_TEXTURE_STATEMENTS: &[&str] = &["map_Kd", "map_Ks", "map_Ns"];
+
+/// Command-line options for packaging a scene.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rustracer pack",
+    about = "Package a scenefile and every file it uses into a single .rtscene file"
+)]
+pub struct PackConfig {
+    /// Path to the .xml scenefile to package
+    #[structopt(long, parse(from_os_str))]
+    pub scene: PathBuf,
+    /// Path to the directory that the scene's texture images are relative to
+    #[structopt(long, parse(from_os_str))]
+    pub textures: PathBuf,
+    /// Path where the package should be saved (e.g. scene.rtscene)
+    #[structopt(long, parse(from_os_str))]
+    pub out: PathBuf,
+}
+
+/// Command-line options for unpacking a packaged scene.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rustracer unpack",
+    about = "Extract a .rtscene package into a directory, ready to render"
+)]
+pub struct UnpackConfig {
+    /// Path to the .rtscene package
+    #[structopt(long, parse(from_os_str))]
+    pub package: PathBuf,
+    /// Path of the directory to extract the package into (which is created if needed)
+    #[structopt(long, parse(from_os_str))]
+    pub out: PathBuf,
+}
+
+/// The files to be added to a package, by their paths within it.
+#[derive(Default)]
+struct PackageFiles {
+    /// Path within the package of each file added so far, by the path it is read from.
+    names: HashMap<PathBuf, String>,
+    /// Every path within the package used so far.
+    used_names: HashSet<String>,
+}
+
+impl PackageFiles {
+    /// The path within the package (in the given directory) of the file read from the given
+    /// path, which keeps the file's name unless another file already has it.
+    fn add(&mut self, path: PathBuf, directory: &str) -> Result<String> {
+        if let Some(name) = self.names.get(&path) {
+            return Ok(name.clone());
+        }
+
+        let file_name = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .ok_or_else(|| anyhow!("Can't package file: {}", path.display()))?;
+
+        let mut name = format!("{}/{}", directory, file_name);
+        let mut copy = 1;
+        while self.used_names.contains(&name) {
+            copy += 1;
+            name = format!("{}/{}-{}", directory, copy, file_name);
+        }
+
+        self.used_names.insert(name.clone());
+        self.names.insert(path, name.clone());
+        Ok(name)
+    }
+
+    /// Adds the file read from the given path at exactly the given path within the files
+    /// directory, for files whose paths can't be rewritten (such as those that meshes refer
+    /// to), failing if another file is already there.
+    fn add_exact(&mut self, path: PathBuf, relative_name: &Path) -> Result<()> {
+        if !relative_name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!(
+                "Can't package file outside of the directory of the file that refers to it: {}",
+                path.display()
+            );
+        }
+
+        let name = format!(
+            "{}/{}",
+            FILES_DIRECTORY,
+            relative_name.to_string_lossy().replace('\\', "/")
+        );
+        match self.names.get(&path) {
+            Some(existing) if *existing == name => return Ok(()),
+            Some(existing) => bail!(
+                "Can't package {} as both {} and {}",
+                path.display(),
+                existing,
+                name
+            ),
+            None if self.used_names.contains(&name) => {
+                bail!("Can't package two different files as {}", name)
+            }
+            None => {}
+        }
+
+        self.used_names.insert(name.clone());
+        self.names.insert(path, name);
+        Ok(())
+    }
+}
+
+/// Packages the configured scene, returning the number of files (besides the scenefile) that
+/// were packaged along with it.
+pub fn pack(config: &PackConfig) -> Result<usize> {
+    let mut root = Element::parse(
+        File::open(&config.scene)
+            .with_context(|| format!("Failed to open scenefile: {}", config.scene.display()))?,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to parse scenefile as XML: {}",
+            config.scene.display()
+        )
+    })?;
+
+    let scene_directory = config.scene.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = PackageFiles::default();
+    rewrite_paths(&mut root, scene_directory, &config.textures, &mut files)?;
+
+    let mut package = ZipWriter::new(
+        File::create(&config.out)
+            .with_context(|| format!("Failed to create package: {}", config.out.display()))?,
+    );
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    package.start_file(SCENEFILE_NAME, options)?;
+    root.write_with_config(&mut package, EmitterConfig::new().perform_indent(true))?;
+
+    let mut names: Vec<(&PathBuf, &String)> = files.names.iter().collect();
+    names.sort_by_key(|&(_, name)| name);
+    for (path, name) in &names {
+        package.start_file(name.as_str(), options)?;
+        io::copy(
+            &mut File::open(path)
+                .with_context(|| format!("Failed to open file to package: {}", path.display()))?,
+            &mut package,
+        )?;
+    }
+    package.finish()?;
+
+    Ok(names.len())
+}
+
+/// Rewrites the paths of the files that the given element (or those within it) refers to, to
+/// be those of the files within the package, which are added to the files to package.
+fn rewrite_paths(
+    element: &mut Element,
+    scene_directory: &Path,
+    textures: &Path,
+    files: &mut PackageFiles,
+) -> Result<()> {
+    // Textures given by a source (rather than a file) are registered by the application
+    if TEXTURE_TAGS.contains(&element.name.as_str()) && !element.attributes.contains_key("source") {
+        if let Some(file) = element.attributes.get_mut("file") {
+            let name = files.add(textures.join(&*file), TEXTURES_DIRECTORY)?;
+            *file = name[TEXTURES_DIRECTORY.len() + 1..].to_string();
+        }
+    }
+
+    if element.name == "object" {
+        for &attribute in SCENE_RELATIVE_ATTRIBUTES {
+            if let Some(file) = element.attributes.get_mut(attribute) {
+                let path = scene_directory.join(&*file);
+                if attribute == "meshfile" {
+                    add_material_libraries(&path, files)?;
+                }
+                *file = files.add(path, FILES_DIRECTORY)?;
+            }
+        }
+    }
+
+    for child in &mut element.children {
+        if let XMLNode::Element(child) = child {
+            rewrite_paths(child, scene_directory, textures, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the MTL material libraries that the given OBJ mesh refers to, and the textures that
+/// they refer to, to the files to package. Their paths are relative to the mesh (which is
+/// packaged unchanged), so they keep the same paths relative to the files directory.
+fn add_material_libraries(mesh: &Path, files: &mut PackageFiles) -> Result<()> {
+    let directory = mesh.parent().unwrap_or_else(|| Path::new(""));
+    let contents = fs::read_to_string(mesh)
+        .with_context(|| format!("Failed to read mesh file: {}", mesh.display()))?;
+
+    let libraries = contents.lines().flat_map(|line| {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("mtllib") => tokens.collect(),
+            _ => Vec::new(),
+        }
+    });
+    for library in libraries {
+        let path = directory.join(library);
+        let library_contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read material library: {}", path.display()))?;
+        files.add_exact(path, Path::new(library))?;
+
+        let library_directory = Path::new(library).parent().unwrap_or_else(|| Path::new(""));
+        for line in library_contents.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if let [statement, .., texture] = tokens[..] {
+                if MTL_TEXTURE_STATEMENTS.contains(&statement) {
+                    let relative_name = library_directory.join(texture);
+                    files.add_exact(directory.join(&relative_name), &relative_name)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Extracts the configured package, returning the path of its scenefile.
+pub fn unpack(config: &UnpackConfig) -> Result<PathBuf> {
+    let mut package = ZipArchive::new(
+        File::open(&config.package)
+            .with_context(|| format!("Failed to open package: {}", config.package.display()))?,
+    )
+    .with_context(|| format!("Failed to read package: {}", config.package.display()))?;
+
+    if package.by_name(SCENEFILE_NAME).is_err() {
+        bail!("Package has no {}", SCENEFILE_NAME);
+    }
+
+    for index in 0..package.len() {
+        let mut file = package.by_index(index)?;
+        // Paths that would escape the output directory (e.g. through "..") are refused
+        let name = file
+            .enclosed_name()
+            .ok_or_else(|| anyhow!("Package contains an unsafe path: {}", file.name()))?
+            .to_path_buf();
+        if file.is_dir() {
+            continue;
+        }
+
+        let path = config.out.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(
+            &mut file,
+            &mut File::create(&path)
+                .with_context(|| format!("Failed to create file: {}", path.display()))?,
+        )?;
+    }
+
+    // The textures directory is given to the renderer even if the scene has no textures
+    fs::create_dir_all(config.out.join(TEXTURES_DIRECTORY))?;
+
+    Ok(config.out.join(SCENEFILE_NAME))
+}
+
+/// The directory of the textures of a package that has been extracted into the given directory.
+pub fn textures_directory(unpacked: &Path) -> PathBuf {
+    unpacked.join(TEXTURES_DIRECTORY)
+}
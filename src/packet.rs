@@ -0,0 +1,101 @@
+//! Packets of rays which are traced together, so that the coherent rays of a pixel's samples
+//! can share the work of traversing an acceleration structure, testing the bounds of each
+//! node against every ray of the packet at once with SIMD instructions.
+
+use crate::bounding_box::BoundingBox;
+use crate::raytracer::Ray;
+use wide::{f32x4, CmpGt, CmpLe, CmpLt};
+
+/// Number of rays in a packet (the number of lanes of the SIMD vectors they are tested with).
+pub const PACKET_SIZE: usize = 4;
+
+/// Up to [`PACKET_SIZE`] rays, along with their positions and inverse directions laid out
+/// with one ray per lane (so that each axis can be tested for all rays at once).
+#[derive(Debug, Clone)]
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_SIZE],
+    /// Which lanes hold rays that are being traced (a partial packet's unused lanes hold
+    /// copies of its first ray, which are ignored).
+    active: [bool; PACKET_SIZE],
+    position: [f32x4; 3],
+    inverse_direction: [f32x4; 3],
+    /// The valid intervals of t-values of the rays.
+    t_min: f32x4,
+    t_max: f32x4,
+}
+
+impl RayPacket {
+    /// Constructs a packet of the given rays, of which there must be between 1 and
+    /// [`PACKET_SIZE`].
+    pub fn new(rays: &[Ray]) -> Self {
+        assert!(
+            (1..=PACKET_SIZE).contains(&rays.len()),
+            "A packet must have between 1 and {} rays",
+            PACKET_SIZE
+        );
+
+        let active = std::array::from_fn(|lane| lane < rays.len());
+        let rays: [Ray; PACKET_SIZE] =
+            std::array::from_fn(|lane| rays.get(lane).unwrap_or(&rays[0]).clone());
+
+        // Gathers a value of each ray into the lanes of a vector
+        let lanes = |value: &dyn Fn(&Ray) -> f32| {
+            f32x4::new(std::array::from_fn(|lane| value(&rays[lane])))
+        };
+        let position = std::array::from_fn(|axis| lanes(&|ray| ray.position[axis]));
+        let inverse_direction = std::array::from_fn(|axis| lanes(&|ray| 1.0 / ray.direction[axis]));
+        let t_min = lanes(&|ray| ray.t_min);
+        let t_max = lanes(&|ray| ray.t_max);
+
+        Self {
+            rays,
+            active,
+            position,
+            inverse_direction,
+            t_min,
+            t_max,
+        }
+    }
+
+    /// The lanes of the packet that hold rays which are being traced.
+    pub fn active_lanes(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..PACKET_SIZE).filter(|&lane| self.active[lane])
+    }
+
+    pub fn is_active(&self, lane: usize) -> bool {
+        self.active[lane]
+    }
+}
+
+impl BoundingBox {
+    /// Tests every ray of the packet against the box at once (like [`BoundingBox::intersect`]),
+    /// returning for each lane whether its ray is active and enters the box at a t-value no
+    /// greater than the given maximum for the lane.
+    pub fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        t_max: &[f32; PACKET_SIZE],
+    ) -> [bool; PACKET_SIZE] {
+        let mut t_enter = packet.t_min;
+        let mut t_exit = packet.t_max;
+
+        for axis in 0..3 {
+            let t_min_slab = (f32x4::splat(self.min[axis]) - packet.position[axis])
+                * packet.inverse_direction[axis];
+            let t_max_slab = (f32x4::splat(self.max[axis]) - packet.position[axis])
+                * packet.inverse_direction[axis];
+
+            let swap = t_min_slab.cmp_gt(t_max_slab);
+            let t_near = swap.blend(t_max_slab, t_min_slab);
+            let t_far = swap.blend(t_min_slab, t_max_slab);
+
+            // As in the scalar test, NaN comparisons are false and leave the interval unchanged
+            t_enter = t_near.cmp_gt(t_enter).blend(t_near, t_enter);
+            t_exit = t_far.cmp_lt(t_exit).blend(t_far, t_exit);
+        }
+
+        let hits = (t_enter.cmp_le(t_exit) & t_enter.cmp_le(f32x4::new(*t_max))).move_mask();
+
+        std::array::from_fn(|lane| packet.active[lane] && hits & (1 << lane) != 0)
+    }
+}
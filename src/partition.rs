@@ -0,0 +1,368 @@
+//! Partitioning of renders among independent invocations (e.g. on a render farm): of an
+//! animation into frames, and of a single image into horizontal bands, along with assembly
+//! of the rendered bands.
+
+use crate::{validate_image_size, AssembleConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use image::{ColorType, DynamicImage, GenericImage, GenericImageView, ImageBuffer, Pixel};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File extension of the sidecar files that describe rendered partitions.
+const SIDECAR_EXTENSION: &str = "tile";
+
+/// Character of the paths of an animation's files that stands for a digit of the frame number.
+const FRAME_PLACEHOLDER: char = '#';
+
+/// Whether the given path has a placeholder for the frame number.
+pub fn has_frame_placeholder(path: &Path) -> bool {
+    path.to_string_lossy().contains(FRAME_PLACEHOLDER)
+}
+
+/// The given path with each run of '#' replaced by the given frame number, padded with zeros
+/// to the length of the run (e.g. `render.####.png` is `render.0012.png` for frame 12).
+pub fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let path = path.to_string_lossy();
+    let mut result = String::with_capacity(path.len());
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != FRAME_PLACEHOLDER {
+            result.push(c);
+            continue;
+        }
+
+        let mut width = 1;
+        while chars.next_if_eq(&FRAME_PLACEHOLDER).is_some() {
+            width += 1;
+        }
+        result.push_str(&format!("{:0width$}", frame, width = width));
+    }
+
+    PathBuf::from(result)
+}
+
+/// One of `count` horizontal bands of rows that an image is divided into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Partition {
+    /// Index of this partition, from the top of the image.
+    pub index: u32,
+    /// Total number of partitions that the image is divided into.
+    pub count: u32,
+    /// Width of the full image.
+    pub image_width: u32,
+    /// Height of the full image.
+    pub image_height: u32,
+    /// Number of extra rows rendered above and below the partition's own rows, which
+    /// overlap with the neighbouring partitions and are discarded during assembly.
+    pub overscan: u32,
+}
+
+impl Partition {
+    /// Constructs the given partition of an image, validating that it is one of the image's
+    /// partitions.
+    pub fn new(
+        index: u32,
+        count: u32,
+        image_width: u32,
+        image_height: u32,
+        overscan: u32,
+    ) -> Result<Self> {
+        if count == 0 {
+            bail!("Tile count must be at least 1");
+        }
+        if index >= count {
+            bail!("Tile index {} is out of range for {} tiles", index, count);
+        }
+        if count > image_height {
+            bail!(
+                "Cannot divide an image of height {} into {} tiles",
+                image_height,
+                count
+            );
+        }
+
+        Ok(Self {
+            index,
+            count,
+            image_width,
+            image_height,
+            overscan,
+        })
+    }
+
+    /// The first row of the image (inclusive) that is part of this partition.
+    pub fn row_start(&self) -> u32 {
+        (self.index as u64 * self.image_height as u64 / self.count as u64) as u32
+    }
+
+    /// The last row of the image (exclusive) that is part of this partition.
+    pub fn row_end(&self) -> u32 {
+        ((self.index as u64 + 1) * self.image_height as u64 / self.count as u64) as u32
+    }
+
+    /// The first row of the image (inclusive) that is rendered for this partition, including
+    /// overscan.
+    pub fn rendered_row_start(&self) -> u32 {
+        self.row_start().saturating_sub(self.overscan)
+    }
+
+    /// The last row of the image (exclusive) that is rendered for this partition, including
+    /// overscan.
+    pub fn rendered_row_end(&self) -> u32 {
+        (self.row_end() + self.overscan).min(self.image_height)
+    }
+
+    /// Number of rows that are rendered for this partition, including overscan.
+    pub fn rendered_height(&self) -> u32 {
+        self.rendered_row_end() - self.rendered_row_start()
+    }
+
+    /// Path of the sidecar file that describes the partition rendered to the given output image.
+    pub fn sidecar_path(output: &Path) -> PathBuf {
+        let mut sidecar = output.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(SIDECAR_EXTENSION);
+        PathBuf::from(sidecar)
+    }
+
+    /// Writes a sidecar file next to the given output image, recording which part of the
+    /// full image it contains along with statistics about rendering it.
+    pub fn write_sidecar(&self, output: &Path, render_seconds: f64) -> Result<()> {
+        let sidecar = TileSidecar {
+            index: self.index,
+            count: self.count,
+            image_width: self.image_width,
+            image_height: self.image_height,
+            overscan: self.overscan,
+            row_start: self.row_start(),
+            row_end: self.row_end(),
+            render_seconds,
+        };
+        let sidecar_path = Partition::sidecar_path(output);
+        fs::write(&sidecar_path, serde_json::to_string_pretty(&sidecar)?)
+            .with_context(|| format!("Failed to write tile sidecar: {}", sidecar_path.display()))
+    }
+
+    /// Reads a sidecar file, returning the partition it describes and the number of seconds
+    /// spent rendering it.
+    pub fn read_sidecar(sidecar_path: &Path) -> Result<(Self, f64)> {
+        let contents = fs::read_to_string(sidecar_path)
+            .with_context(|| format!("Failed to read tile sidecar: {}", sidecar_path.display()))?;
+        let sidecar: TileSidecar = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid tile sidecar: {}", sidecar_path.display()))?;
+
+        let partition = Partition::new(
+            sidecar.index,
+            sidecar.count,
+            sidecar.image_width,
+            sidecar.image_height,
+            sidecar.overscan,
+        )?;
+
+        Ok((partition, sidecar.render_seconds))
+    }
+}
+
+/// Contents of the sidecar file written next to each rendered partition. The rows it spans
+/// are derived from the other fields, and are only recorded for other tools to read.
+#[derive(Serialize, Deserialize)]
+struct TileSidecar {
+    index: u32,
+    count: u32,
+    image_width: u32,
+    image_height: u32,
+    overscan: u32,
+    row_start: u32,
+    row_end: u32,
+    render_seconds: f64,
+}
+
+/// Statistics about the rendering of all the tiles of an assembled image.
+#[derive(Debug)]
+pub struct AssemblyStats {
+    pub tile_count: u32,
+    /// Sum of the time spent rendering each tile.
+    pub total_render_seconds: f64,
+    /// Time spent rendering the slowest tile.
+    pub max_render_seconds: f64,
+}
+
+/// Stitches together the tiles in the given directory (each of which is an image with a
+/// sidecar file describing its partition) into the full image, discarding overscan rows.
+/// All tiles of the image must be present, and must agree on the dimensions of the image
+/// and on the format of its pixels, which the full image keeps (so that floating point
+/// tiles aren't quantized).
+pub fn assemble(config: &AssembleConfig) -> Result<(DynamicImage, AssemblyStats)> {
+    let tiles_directory = &config.tiles;
+    let mut tiles = Vec::new();
+
+    let entries = fs::read_dir(tiles_directory).with_context(|| {
+        format!(
+            "Failed to read tiles directory: {}",
+            tiles_directory.display()
+        )
+    })?;
+
+    for entry in entries {
+        let sidecar_path = entry?.path();
+        if sidecar_path.extension().and_then(|e| e.to_str()) != Some(SIDECAR_EXTENSION) {
+            continue;
+        }
+
+        let (partition, render_seconds) = Partition::read_sidecar(&sidecar_path)?;
+        tiles.push((sidecar_path.with_extension(""), partition, render_seconds));
+    }
+
+    let (_, first_partition, _) = tiles
+        .first()
+        .ok_or_else(|| anyhow!("No tiles found in {}", tiles_directory.display()))?;
+    let (count, image_width, image_height) = (
+        first_partition.count,
+        first_partition.image_width,
+        first_partition.image_height,
+    );
+    validate_image_size(
+        image_width,
+        image_height,
+        config.max_megapixels,
+        config.allow_huge,
+    )?;
+
+    let mut found = vec![false; count as usize];
+    let mut stats = AssemblyStats {
+        tile_count: count,
+        total_render_seconds: 0.0,
+        max_render_seconds: 0.0,
+    };
+
+    for (tile_path, partition, render_seconds) in &tiles {
+        if (
+            partition.count,
+            partition.image_width,
+            partition.image_height,
+        ) != (count, image_width, image_height)
+        {
+            bail!(
+                "Tile {} does not belong to the same image as the other tiles",
+                tile_path.display()
+            );
+        }
+        if std::mem::replace(&mut found[partition.index as usize], true) {
+            bail!("Found more than one tile with index {}", partition.index);
+        }
+
+        stats.total_render_seconds += render_seconds;
+        stats.max_render_seconds = stats.max_render_seconds.max(*render_seconds);
+    }
+
+    if let Some(missing) = found.iter().position(|&found| !found) {
+        bail!("Missing tile with index {} (of {} tiles)", missing, count);
+    }
+
+    // The format of the full image is that of the first tile, which is only decoded once
+    let tiles: Vec<(&Path, &Partition)> = tiles
+        .iter()
+        .map(|(tile_path, partition, _)| (tile_path.as_path(), partition))
+        .collect();
+    let first_tile = open_tile(tiles[0].0, tiles[0].1)?;
+    let image = match first_tile.color() {
+        ColorType::Rgb8 => {
+            DynamicImage::ImageRgb8(stitch(&tiles, first_tile, |tile| match tile {
+                DynamicImage::ImageRgb8(tile) => Some(tile),
+                _ => None,
+            })?)
+        }
+        ColorType::Rgba8 => {
+            DynamicImage::ImageRgba8(stitch(&tiles, first_tile, |tile| match tile {
+                DynamicImage::ImageRgba8(tile) => Some(tile),
+                _ => None,
+            })?)
+        }
+        ColorType::Rgb16 => {
+            DynamicImage::ImageRgb16(stitch(&tiles, first_tile, |tile| match tile {
+                DynamicImage::ImageRgb16(tile) => Some(tile),
+                _ => None,
+            })?)
+        }
+        ColorType::Rgba16 => {
+            DynamicImage::ImageRgba16(stitch(&tiles, first_tile, |tile| match tile {
+                DynamicImage::ImageRgba16(tile) => Some(tile),
+                _ => None,
+            })?)
+        }
+        ColorType::Rgb32F => {
+            DynamicImage::ImageRgb32F(stitch(&tiles, first_tile, |tile| match tile {
+                DynamicImage::ImageRgb32F(tile) => Some(tile),
+                _ => None,
+            })?)
+        }
+        ColorType::Rgba32F => {
+            DynamicImage::ImageRgba32F(stitch(&tiles, first_tile, |tile| match tile {
+                DynamicImage::ImageRgba32F(tile) => Some(tile),
+                _ => None,
+            })?)
+        }
+        // Other formats (e.g. grayscale) are never written by the renderer
+        other => bail!("Tiles with pixels of type {:?} can't be assembled", other),
+    };
+
+    Ok((image, stats))
+}
+
+/// Opens the image of the given tile, checking that it has the size its sidecar indicates.
+fn open_tile(tile_path: &Path, partition: &Partition) -> Result<DynamicImage> {
+    let tile = image::open(tile_path)
+        .with_context(|| format!("Failed to open tile: {}", tile_path.display()))?;
+
+    if tile.dimensions() != (partition.image_width, partition.rendered_height()) {
+        bail!(
+            "Tile {} is {}x{}, but its sidecar indicates it should be {}x{}",
+            tile_path.display(),
+            tile.width(),
+            tile.height(),
+            partition.image_width,
+            partition.rendered_height()
+        );
+    }
+
+    Ok(tile)
+}
+
+/// Copies the rows of the given tiles (the first of which is already open) into a full image
+/// with the pixels that `pixels` takes from the tiles' images, which gives `None` for tiles
+/// whose pixels are of a different type.
+fn stitch<P: Pixel>(
+    tiles: &[(&Path, &Partition)],
+    first_tile: DynamicImage,
+    pixels: impl Fn(DynamicImage) -> Option<ImageBuffer<P, Vec<P::Subpixel>>>,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+    let (_, first_partition) = tiles[0];
+    let mut image = ImageBuffer::new(first_partition.image_width, first_partition.image_height);
+
+    let mut first_tile = Some(first_tile);
+    for &(tile_path, partition) in tiles {
+        let tile = match first_tile.take() {
+            Some(tile) => tile,
+            None => open_tile(tile_path, partition)?,
+        };
+        let tile = pixels(tile).ok_or_else(|| {
+            anyhow!(
+                "Tile {} has a different pixel format than the other tiles",
+                tile_path.display()
+            )
+        })?;
+
+        // Copy the partition's own rows, skipping the overscan rows above them
+        let overscan_above = partition.row_start() - partition.rendered_row_start();
+        let rows = tile.view(
+            0,
+            overscan_above,
+            partition.image_width,
+            partition.row_end() - partition.row_start(),
+        );
+        image.copy_from(&*rows, 0, partition.row_start())?;
+    }
+
+    Ok(image)
+}
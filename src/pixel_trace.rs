@@ -0,0 +1,87 @@
+//! Verbose traces of how the color of a single pixel is computed (with `--debug-pixel`),
+//! listing every ray traced for it, what each one hit, and how each light contributed.
+
+use anyhow::{anyhow, Context, Error, Result};
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+/// Position of a pixel in the full image, given on the command line as `x,y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelPosition {
+    pub col: u32,
+    pub row: u32,
+}
+
+impl FromStr for PixelPosition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (col, row) = s
+            .split_once(',')
+            .ok_or_else(|| anyhow!("Pixel position must be given as x,y: {}", s))?;
+        let parse = |coordinate: &str| {
+            coordinate
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid pixel coordinate: {}", coordinate))
+        };
+
+        Ok(Self {
+            col: parse(col)?,
+            row: parse(row)?,
+        })
+    }
+}
+
+/// A record of the events in tracing a pixel, as lines which are indented to show which ray
+/// each event belongs to (rays spawned by a ray are nested under it).
+#[derive(Debug, Default)]
+pub struct PixelTrace {
+    state: RefCell<TraceState>,
+}
+
+#[derive(Debug, Default)]
+struct TraceState {
+    lines: Vec<String>,
+    /// Number of rays whose events are currently being recorded (within each other).
+    depth: usize,
+}
+
+impl PixelTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an event of the ray currently being traced.
+    pub fn record(&self, message: String) {
+        let mut state = self.state.borrow_mut();
+        let line = format!("{}{}", "  ".repeat(state.depth), message);
+        state.lines.push(line);
+    }
+
+    /// Records the start of a new ray, whose events are nested under it until [`Self::end`].
+    pub fn begin(&self, message: String) {
+        self.record(message);
+        self.state.borrow_mut().depth += 1;
+    }
+
+    /// Records the end of the ray most recently begun, along with its outcome.
+    pub fn end(&self, message: String) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.depth = state.depth.saturating_sub(1);
+        }
+        self.record(message);
+    }
+}
+
+impl fmt::Display for PixelTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.state.borrow().lines {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! Point clouds (e.g. from LiDAR or photogrammetry scans) loaded from XYZ files, whose points
+//! are rendered as small splats and intersected using a bounding volume hierarchy.
+
+use crate::bounding_box::BoundingBox;
+use crate::intersection::ComponentIntersection;
+use crate::math::{vec3, Vec3};
+use crate::primitive::PrimitiveComponent;
+use crate::raytracer::Ray;
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::Path;
+
+/// Maximum number of points stored in a single leaf of a point cloud's BVH.
+const MAX_POINTS_PER_LEAF: usize = 4;
+
+/// The shape drawn at each point of a point cloud.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Splat {
+    /// A flat disk that always faces the incoming ray (so it faces the camera for camera rays).
+    Disk,
+    Sphere,
+}
+
+/// A node of a point cloud's bounding volume hierarchy. Leaves refer to a contiguous range of
+/// the cloud's points, which are ordered during construction so that each leaf's points are
+/// adjacent.
+#[derive(Debug)]
+enum BvhNode {
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &BoundingBox {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A primitive component made up of a splat of the same radius at each of a set of points, in
+/// object space.
+#[derive(Debug)]
+pub struct PointCloud {
+    points: Vec<Vec3>,
+    radius: f32,
+    splat: Splat,
+    /// Nodes of the BVH over the points, where the first node is the root.
+    bvh: Vec<BvhNode>,
+}
+
+impl PointCloud {
+    /// Constructs a point cloud with splats of the given radius at the given points, building
+    /// its BVH.
+    pub fn new(mut points: Vec<Vec3>, radius: f32, splat: Splat) -> Self {
+        let mut bvh = Vec::new();
+
+        if !points.is_empty() {
+            PointCloud::build_bvh_node(&mut bvh, &mut points, 0, radius);
+        }
+
+        Self {
+            points,
+            radius,
+            splat,
+            bvh,
+        }
+    }
+
+    /// Loads the points of a point cloud from an XYZ file, which has a point on each line as
+    /// whitespace-separated x, y, and z coordinates. Any further values on a line (such as a
+    /// color or intensity) are ignored, as are blank lines and lines starting with `#`.
+    pub fn load_xyz(path: &Path) -> Result<Vec<Vec3>> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read point file: {}", path.display()))?;
+
+        PointCloud::parse_xyz(&contents)
+            .with_context(|| format!("Failed to parse point file: {}", path.display()))
+    }
+
+    fn parse_xyz(contents: &str) -> Result<Vec<Vec3>> {
+        let mut points = Vec::new();
+
+        for (line_index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let coordinates = line
+                .split_whitespace()
+                .take(3)
+                .map(|token| {
+                    token.parse::<f32>().map_err(|_| {
+                        anyhow!("Invalid number \"{}\" on line {}", token, line_index + 1)
+                    })
+                })
+                .collect::<Result<Vec<f32>>>()?;
+
+            if coordinates.len() < 3 {
+                bail!("Point on line {} must have 3 coordinates", line_index + 1);
+            }
+
+            points.push(vec3(coordinates[0], coordinates[1], coordinates[2]));
+        }
+
+        Ok(points)
+    }
+
+    /// Recursively builds the BVH node over the given points (which start at index `first`
+    /// in the cloud), splitting at the median along the longest axis. Returns the index of
+    /// the constructed node.
+    fn build_bvh_node(
+        nodes: &mut Vec<BvhNode>,
+        points: &mut [Vec3],
+        first: usize,
+        radius: f32,
+    ) -> usize {
+        let center_bounds = BoundingBox::from_points(points.iter());
+        let padding = vec3(radius, radius, radius);
+        let bounds = BoundingBox {
+            min: center_bounds.min - padding,
+            max: center_bounds.max + padding,
+        };
+
+        let index = nodes.len();
+        nodes.push(BvhNode::Leaf {
+            bounds,
+            first,
+            count: points.len(),
+        });
+
+        if points.len() <= MAX_POINTS_PER_LEAF {
+            return index;
+        }
+
+        let axis = center_bounds.longest_axis();
+        let middle = points.len() / 2;
+        points.select_nth_unstable_by(middle, |a, b| {
+            a[axis].partial_cmp(&b[axis]).unwrap_or(Ordering::Equal)
+        });
+
+        let (left_points, right_points) = points.split_at_mut(middle);
+        let left = PointCloud::build_bvh_node(nodes, left_points, first, radius);
+        let right = PointCloud::build_bvh_node(nodes, right_points, first + middle, radius);
+
+        nodes[index] = BvhNode::Interior {
+            bounds,
+            left,
+            right,
+        };
+
+        index
+    }
+
+    /// Intersects the ray with the splat at a single point.
+    fn intersect_splat(&self, center: Vec3, ray: &Ray) -> Option<ComponentIntersection> {
+        let origin = ray.position.truncate();
+        let direction = ray.direction.truncate();
+        let center_to_origin = origin - center;
+
+        let a = direction.dot(direction);
+        let b = direction.dot(center_to_origin);
+        let c = center_to_origin.dot(center_to_origin) - self.radius * self.radius;
+
+        let (t, normal) = match self.splat {
+            Splat::Disk => {
+                // Rays leaving a splat (e.g. shadow rays from a point on it) can't hit it
+                if c <= 0.0 {
+                    return None;
+                }
+
+                // The disk is perpendicular to the ray, through the splat's center
+                let t = -b / a;
+                let point = origin + direction * t;
+                if (point - center).length() > self.radius {
+                    return None;
+                }
+                (t, -direction)
+            }
+            Splat::Sphere => {
+                let discriminant = b * b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+
+                // Use the far side of the sphere if the ray starts inside it
+                let near = (-b - discriminant.sqrt()) / a;
+                let t = if near >= ray.t_min {
+                    near
+                } else {
+                    (-b + discriminant.sqrt()) / a
+                };
+                (t, origin + direction * t - center)
+            }
+        };
+
+        if !ray.contains(t) {
+            return None;
+        }
+
+        // Splats are too small for texture coordinates to be meaningful, so they have no
+        // derivatives either, and their tangent frame is an arbitrary one around the normal
+        let zero = vec3(0.0, 0.0, 0.0);
+        Some(ComponentIntersection::new(
+            t,
+            normal.normalize().extend(0.0),
+            (0.5, 0.5),
+            zero,
+            zero,
+        ))
+    }
+}
+
+impl PrimitiveComponent for PointCloud {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        let mut closest: Option<ComponentIntersection> = None;
+
+        if self.bvh.is_empty() {
+            return None;
+        }
+
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.bvh[node_index];
+
+            // Skip nodes that the ray misses, or which are entirely behind the closest hit so far
+            match node.bounds().intersect(ray) {
+                Some((t_enter, _)) if closest.as_ref().map_or(true, |c| t_enter <= c.t) => {}
+                _ => continue,
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &point in &self.points[first..first + count] {
+                        if let Some(intersection) = self.intersect_splat(point, ray) {
+                            if closest.as_ref().map_or(true, |c| intersection.t < c.t) {
+                                closest = Some(intersection);
+                            }
+                        }
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+
+        closest
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.bvh
+            .first()
+            .map_or(BoundingBox::empty(), |root| *root.bounds())
+    }
+}
@@ -1,9 +1,13 @@
 //! Lower-level representation of objects in scenes.
 
+use crate::bezier::{BezierPatch, ControlPoints};
+use crate::bounding_box::BoundingBox;
 use crate::intersection::ComponentIntersection;
+use crate::math::{onb, vec3, vec4, Vec3, Vec4};
 use crate::raytracer::Ray;
 use std::f32::consts::PI;
 use std::slice::Iter;
+use std::sync::Arc;
 
 /// A Primitive is a object-space version of a Shape, which represents the
 /// geometry of that shape. Primitives are composed of components (for instance
@@ -15,6 +19,219 @@ pub struct Primitive {
 }
 
 impl Primitive {
+    /// Constructs a unit cube centered at the origin, composed of six squares.
+    pub fn cube() -> Self {
+        let mut components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> = Vec::new();
+        for &normal_axis in Axis::iterator() {
+            for elevation in [-0.5, 0.5] {
+                components.push(Box::new(Square {
+                    plane: Plane {
+                        normal_axis,
+                        elevation,
+                    },
+                }))
+            }
+        }
+
+        Primitive { components }
+    }
+
+    /// Constructs a sphere of diameter 1 centered at the origin, which spans the given sweep
+    /// around the y axis.
+    pub fn sphere(sweep: Sweep) -> Self {
+        Primitive {
+            components: vec![Box::new(Sphere { sweep })],
+        }
+    }
+
+    /// Constructs a cylinder of diameter 1 and height 1 centered at the origin, which spans
+    /// the given sweep around the y axis, and has circular caps if `capped` is set.
+    pub fn cylinder(capped: bool, sweep: Sweep) -> Self {
+        let mut components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> =
+            vec![Box::new(CylinderBody { sweep })];
+
+        if capped {
+            components.push(Box::new(Circle::cap(0.5, sweep)));
+            components.push(Box::new(Circle::cap(-0.5, sweep)));
+        }
+
+        Primitive { components }
+    }
+
+    /// Constructs a cone with a base of diameter 1 and height 1 centered at the origin, which
+    /// spans the given sweep around the y axis, and has a circular base if `capped` is set.
+    pub fn cone(capped: bool, sweep: Sweep) -> Self {
+        let mut components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> =
+            vec![Box::new(ConeBody { sweep })];
+
+        if capped {
+            components.push(Box::new(Circle::cap(-0.5, sweep)));
+        }
+
+        Primitive { components }
+    }
+
+    /// Constructs a flat ring of diameter 1 in the xz-plane facing +y, with a hole of the
+    /// given radius in its middle (which is a full disk if the inner radius is 0).
+    pub fn ring(inner_radius: f32) -> Self {
+        Primitive {
+            components: vec![Box::new(Circle {
+                inner_radius,
+                ..Circle::cap(0.0, Sweep::FULL)
+            })],
+        }
+    }
+
+    /// Constructs a hollow tube of outer diameter 1 and height 1 centered at the origin,
+    /// whose bore has the given radius, with flat annular caps at each end.
+    pub fn tube(inner_radius: f32) -> Self {
+        let mut components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> = vec![
+            Box::new(CylinderBody { sweep: Sweep::FULL }),
+            Box::new(TubeBore {
+                radius: inner_radius,
+            }),
+        ];
+
+        for elevation in [0.5, -0.5] {
+            components.push(Box::new(Circle {
+                inner_radius,
+                ..Circle::cap(elevation, Sweep::FULL)
+            }));
+        }
+
+        Primitive { components }
+    }
+
+    /// Constructs a surface of revolution by revolving the given profile curve, a sequence
+    /// of (radius, y) points, around the y axis. Each segment of the profile becomes a
+    /// band of a cone (or a flat ring, if the segment is horizontal).
+    pub fn lathe(profile: &[(f32, f32)]) -> Self {
+        let segment_lengths: Vec<f32> = profile
+            .windows(2)
+            .map(|segment| {
+                let [(r0, y0), (r1, y1)] = [segment[0], segment[1]];
+                ((r1 - r0).powi(2) + (y1 - y0).powi(2)).sqrt()
+            })
+            .collect();
+        let total_length: f32 = segment_lengths.iter().sum();
+
+        let mut components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> = Vec::new();
+        let mut length_so_far = 0.0;
+
+        for (segment, length) in profile.windows(2).zip(segment_lengths) {
+            let [start, end] = [segment[0], segment[1]];
+            let v_range = (
+                length_so_far / total_length,
+                (length_so_far + length) / total_length,
+            );
+            length_so_far += length;
+
+            if start == end {
+                continue;
+            }
+
+            if start.1 == end.1 {
+                components.push(Box::new(LatheCap {
+                    start,
+                    end,
+                    v_range,
+                }));
+            } else {
+                components.push(Box::new(LatheBand {
+                    start,
+                    end,
+                    v_range,
+                }));
+            }
+        }
+
+        Primitive { components }
+    }
+
+    /// Constructs a prism by extruding the given polygon, a sequence of (x, z) points,
+    /// along the y axis from y = -0.5 to y = 0.5.
+    pub fn extrusion(polygon: &[(f32, f32)]) -> Self {
+        let polygon = Arc::new(polygon.to_vec());
+
+        // With a positive signed area, the polygon winds counter-clockwise in the xz-plane
+        let signed_area: f32 = polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .map(|(&(x0, z0), &(x1, z1))| x0 * z1 - x1 * z0)
+            .sum();
+        let winding = if signed_area >= 0.0 { 1.0 } else { -1.0 };
+
+        let perimeter: f32 = polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .map(|(&(x0, z0), &(x1, z1))| ((x1 - x0).powi(2) + (z1 - z0).powi(2)).sqrt())
+            .sum();
+
+        let mut components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> = Vec::new();
+        let mut length_so_far = 0.0;
+
+        for (&start, &end) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+            let length = ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt();
+            let u_range = (
+                length_so_far / perimeter,
+                (length_so_far + length) / perimeter,
+            );
+            length_so_far += length;
+
+            if length > 0.0 {
+                components.push(Box::new(ExtrusionSide {
+                    start,
+                    end,
+                    outward_normal: vec4(
+                        winding * (end.1 - start.1) / length,
+                        0.0,
+                        winding * -(end.0 - start.0) / length,
+                        0.0,
+                    ),
+                    u_range,
+                }));
+            }
+        }
+
+        for elevation in [-0.5, 0.5] {
+            components.push(Box::new(ExtrusionCap {
+                plane: Plane {
+                    normal_axis: Axis::Y,
+                    elevation,
+                },
+                polygon: Arc::clone(&polygon),
+            }));
+        }
+
+        Primitive { components }
+    }
+
+    /// Constructs a prism of height 1 centered at the origin, whose cross-section is a regular
+    /// polygon with the given number of sides, inscribed in a circle of diameter 1 in the xz-plane.
+    pub fn prism(sides: u32) -> Self {
+        let polygon: Vec<(f32, f32)> = (0..sides)
+            .map(|side| {
+                let angle = 2.0 * PI * side as f32 / sides as f32;
+                (0.5 * angle.cos(), 0.5 * angle.sin())
+            })
+            .collect();
+
+        Primitive::extrusion(&polygon)
+    }
+
+    /// Constructs a surface made up of the given bicubic Bezier patches.
+    pub fn bezier(patches: &[ControlPoints]) -> Self {
+        Primitive {
+            components: patches
+                .iter()
+                .map(|&control_points| {
+                    Box::new(BezierPatch::new(control_points))
+                        as Box<dyn PrimitiveComponent + Send + Sync>
+                })
+                .collect(),
+        }
+    }
+
     pub fn intersect(&self, object_space_ray: &Ray) -> Option<ComponentIntersection> {
         let mut intersections: Vec<ComponentIntersection> = Vec::new();
 
@@ -29,10 +246,57 @@ impl Primitive {
 
         intersections.into_iter().min()
     }
+
+    /// The object-space bounding box of all of the primitive's components.
+    pub fn bounds(&self) -> BoundingBox {
+        self.components
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, component| {
+                bounds.union(&component.bounds())
+            })
+    }
 }
 
 pub trait PrimitiveComponent: std::fmt::Debug {
     fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection>;
+
+    /// An object-space bounding box containing the whole component.
+    fn bounds(&self) -> BoundingBox;
+}
+
+/// The bounds of the unit cube centered at the origin, which contains all of the built-in
+/// primitives.
+fn unit_cube_bounds() -> BoundingBox {
+    BoundingBox {
+        min: vec3(-0.5, -0.5, -0.5),
+        max: vec3(0.5, 0.5, 0.5),
+    }
+}
+
+/// A range of angles around the y axis that a component spans, in radians. Angles are
+/// measured from the +x axis in the direction of a positive rotation about the +y axis.
+#[derive(Copy, Clone, Debug)]
+pub struct Sweep {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Sweep {
+    /// A sweep that goes all the way around the y axis.
+    pub const FULL: Sweep = Sweep {
+        start: 0.0,
+        end: 2.0 * PI,
+    };
+
+    /// Determines whether the given point lies within the range of angles of this sweep.
+    fn contains(&self, point: &Vec4) -> bool {
+        if self.end - self.start >= 2.0 * PI {
+            return true;
+        }
+
+        let angle = (-point.z).atan2(point.x);
+        (angle - self.start).rem_euclid(2.0 * PI) <= self.end - self.start
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -57,47 +321,57 @@ pub struct Plane {
 
 impl Plane {
     fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
-        let ray_position_on_plane = ray.position.as_array()[self.normal_axis as usize];
-        let ray_direction_on_plane = ray.direction.as_array()[self.normal_axis as usize];
-
-        if ray_direction_on_plane == 0.0 {
+        let ray_position_on_plane = ray.position[self.normal_axis as usize];
+        let ray_direction_on_plane = ray.direction[self.normal_axis as usize];
+
+        // The threshold applies to unit directions, which object space directions needn't be
+        if ray_direction_on_plane == 0.0
+            || (ray.parallel_threshold > 0.0
+                && ray_direction_on_plane.abs()
+                    <= ray.parallel_threshold * ray.direction.truncate().length())
+        {
             return None;
         }
 
         let t = (self.elevation - ray_position_on_plane) / ray_direction_on_plane;
 
-        // Reject negative t-values which represent aiming in the opposite direction of the ray
-        if t < 0.0 {
+        // Reject t-values outside of the ray's interval, such as negative ones which represent
+        // aiming in the opposite direction of the ray
+        if !ray.contains(t) {
             return None;
         }
 
         let uv = self.uv_map(&ray.at(t));
+        let (dp_du, dp_dv) = self.uv_derivatives();
 
-        Some(ComponentIntersection {
+        Some(ComponentIntersection::new(
             t,
-            normal: self.normal(),
+            self.normal(),
             uv,
-        })
+            dp_du,
+            dp_dv,
+        ))
     }
 
-    fn uv_map(&self, point: &glm::Vec4) -> (f32, f32) {
+    fn uv_map(&self, point: &Vec4) -> (f32, f32) {
+        let facing_positive = self.elevation >= 0.0;
         let prescaled = match self.normal_axis {
             Axis::X => {
-                if self.elevation > 0.0 {
+                if facing_positive {
                     (-point.z, point.y)
                 } else {
                     (point.z, point.y)
                 }
             }
             Axis::Y => {
-                if self.elevation > 0.0 {
+                if facing_positive {
                     (point.x, -point.z)
                 } else {
                     (point.x, point.z)
                 }
             }
             Axis::Z => {
-                if self.elevation > 0.0 {
+                if facing_positive {
                     (point.x, point.y)
                 } else {
                     (-point.x, point.y)
@@ -108,14 +382,37 @@ impl Plane {
         (prescaled.0 + 0.5, prescaled.1 + 0.5)
     }
 
-    fn normal(&self) -> glm::Vec4 {
-        let mut normal = glm::vec4(0.0, 0.0, 0.0, 0.0);
-        normal[self.normal_axis as usize] = if self.elevation > 0.0 { 1.0 } else { -1.0 };
+    /// The directions in which the u and v coordinates of `uv_map` increase.
+    fn uv_derivatives(&self) -> (Vec3, Vec3) {
+        let facing = if self.elevation >= 0.0 { 1.0 } else { -1.0 };
+        match self.normal_axis {
+            Axis::X => (vec3(0.0, 0.0, -facing), vec3(0.0, 1.0, 0.0)),
+            Axis::Y => (vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, -facing)),
+            Axis::Z => (vec3(facing, 0.0, 0.0), vec3(0.0, 1.0, 0.0)),
+        }
+    }
+
+    /// The normal of the plane, which faces away from the origin (and in the positive
+    /// direction along its axis, for planes through the origin).
+    fn normal(&self) -> Vec4 {
+        let mut normal = vec4(0.0, 0.0, 0.0, 0.0);
+        normal[self.normal_axis as usize] = if self.elevation >= 0.0 { 1.0 } else { -1.0 };
         normal
     }
 
+    /// The bounds of the square of the plane that extends the given distance from its axis in
+    /// each direction.
+    fn bounds(&self, half_width: f32) -> BoundingBox {
+        let mut min = vec3(-half_width, -half_width, -half_width);
+        let mut max = vec3(half_width, half_width, half_width);
+        min[self.normal_axis as usize] = self.elevation;
+        max[self.normal_axis as usize] = self.elevation;
+
+        BoundingBox { min, max }
+    }
+
     /// Flattens a point in 3D space onto this plane, returning a 2D point.
-    fn flatten_onto(&self, point: &glm::Vec4) -> [f32; 2] {
+    fn flatten_onto(&self, point: &Vec4) -> [f32; 2] {
         match self.normal_axis {
             Axis::X => [point.y, point.z],
             Axis::Y => [point.x, point.z],
@@ -145,11 +442,38 @@ impl PrimitiveComponent for Square {
             None
         }
     }
+
+    fn bounds(&self) -> BoundingBox {
+        self.plane.bounds(0.5)
+    }
 }
 
+/// A flat disk on a plane, which is an annulus (ring) if it has a nonzero inner radius.
 #[derive(Debug)]
 pub struct Circle {
     pub plane: Plane,
+    /// Radius of the outer edge of the circle.
+    pub radius: f32,
+    /// Radius of the hole in the middle of the circle, which is 0 for a full disk.
+    pub inner_radius: f32,
+    /// The part of the circle (around the y axis) that is included, for circles normal to the
+    /// y axis.
+    pub sweep: Sweep,
+}
+
+impl Circle {
+    /// Constructs a circular cap normal to the y axis at the given elevation.
+    fn cap(elevation: f32, sweep: Sweep) -> Self {
+        Circle {
+            plane: Plane {
+                normal_axis: Axis::Y,
+                elevation,
+            },
+            radius: 0.5,
+            inner_radius: 0.0,
+            sweep,
+        }
+    }
 }
 
 impl PrimitiveComponent for Circle {
@@ -157,13 +481,21 @@ impl PrimitiveComponent for Circle {
         let intersection = self.plane.intersect(ray)?;
         let intersection_point = ray.at(intersection.t);
         let [horizontal, vertical] = self.plane.flatten_onto(&intersection_point);
+        let squared_distance = horizontal.powi(2) + vertical.powi(2);
 
-        if horizontal.powi(2) + vertical.powi(2) <= 0.5f32.powi(2) {
+        if squared_distance <= self.radius.powi(2)
+            && squared_distance >= self.inner_radius.powi(2)
+            && self.sweep.contains(&intersection_point)
+        {
             Some(intersection)
         } else {
             None
         }
     }
+
+    fn bounds(&self) -> BoundingBox {
+        self.plane.bounds(self.radius)
+    }
 }
 
 impl<T: QuadraticBody + std::fmt::Debug> PrimitiveComponent for T {
@@ -172,16 +504,282 @@ impl<T: QuadraticBody + std::fmt::Debug> PrimitiveComponent for T {
 
         let solution = solve_quadratic(a, b, c)
             .into_iter()
-            .filter(|&t| t >= 0.0 && self.check_constraint(&ray.at(t)))
+            .filter(|&t| ray.contains(t) && self.check_constraint(&ray.at(t)))
             .reduce(f32::min)?;
 
         let intersection_point = ray.at(solution);
+        let (dp_du, dp_dv) = self.uv_derivatives_at_intersection(&intersection_point);
+
+        Some(ComponentIntersection::new(
+            solution,
+            self.normal_at_intersection(&intersection_point),
+            self.uv_at_intersection(&intersection_point),
+            dp_du,
+            dp_dv,
+        ))
+    }
 
-        Some(ComponentIntersection {
-            normal: self.normal_at_intersection(&intersection_point),
-            uv: self.uv_at_intersection(&intersection_point),
-            t: solution,
-        })
+    fn bounds(&self) -> BoundingBox {
+        QuadraticBody::bounds(self)
+    }
+}
+
+/// One of the flat ends of an extruded polygon.
+#[derive(Debug)]
+pub struct ExtrusionCap {
+    plane: Plane,
+    /// The (x, z) points of the polygon, shared with the opposite cap.
+    polygon: Arc<Vec<(f32, f32)>>,
+}
+
+impl PrimitiveComponent for ExtrusionCap {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        let intersection = self.plane.intersect(ray)?;
+        let point = ray.at(intersection.t);
+
+        // Even-odd rule: the point is inside if a ray cast from it along +x crosses
+        // the polygon's edges an odd number of times
+        let crossings = self
+            .polygon
+            .iter()
+            .zip(self.polygon.iter().cycle().skip(1))
+            .filter(|&(&(x0, z0), &(x1, z1))| {
+                (z0 > point.z) != (z1 > point.z)
+                    && point.x < x0 + (point.z - z0) / (z1 - z0) * (x1 - x0)
+            })
+            .count();
+
+        if crossings % 2 == 1 {
+            Some(intersection)
+        } else {
+            None
+        }
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        let points: Vec<Vec3> = self
+            .polygon
+            .iter()
+            .map(|&(x, z)| vec3(x, self.plane.elevation, z))
+            .collect();
+        BoundingBox::from_points(&points)
+    }
+}
+
+/// One of the rectangular sides of an extruded polygon, formed by extruding a single edge.
+#[derive(Debug)]
+pub struct ExtrusionSide {
+    /// The (x, z) point where the edge begins.
+    start: (f32, f32),
+    /// The (x, z) point where the edge ends.
+    end: (f32, f32),
+    outward_normal: Vec4,
+    /// Range of u texture coordinates that this side spans around the perimeter.
+    u_range: (f32, f32),
+}
+
+impl PrimitiveComponent for ExtrusionSide {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        let normal = self.outward_normal;
+        let denominator = normal.x * ray.direction.x + normal.z * ray.direction.z;
+
+        if denominator == 0.0 {
+            return None;
+        }
+
+        let t = (normal.x * (self.start.0 - ray.position.x)
+            + normal.z * (self.start.1 - ray.position.z))
+            / denominator;
+
+        if !ray.contains(t) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        if !(-0.5..=0.5).contains(&point.y) {
+            return None;
+        }
+
+        // Fraction of the way along the edge from its start to its end
+        let edge = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let fraction = ((point.x - self.start.0) * edge.0 + (point.z - self.start.1) * edge.1)
+            / (edge.0.powi(2) + edge.1.powi(2));
+
+        if !(0.0..=1.0).contains(&fraction) {
+            return None;
+        }
+
+        let du_dfraction = self.u_range.1 - self.u_range.0;
+
+        Some(ComponentIntersection::new(
+            t,
+            normal,
+            (self.u_range.0 + fraction * du_dfraction, point.y + 0.5),
+            vec3(edge.0, 0.0, edge.1) * du_dfraction.signum(),
+            vec3(0.0, 1.0, 0.0),
+        ))
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        BoundingBox::from_points(&[
+            vec3(self.start.0, -0.5, self.start.1),
+            vec3(self.end.0, 0.5, self.end.1),
+        ])
+    }
+}
+
+/// Computes the u texture coordinate of a point on a surface of revolution around the y axis.
+pub(crate) fn revolution_u(point: &Vec4) -> f32 {
+    let theta = point.z.atan2(point.x);
+    if theta < 0.0 {
+        -theta / (2.0 * PI)
+    } else {
+        1.0 - (theta / (2.0 * PI))
+    }
+}
+
+/// A horizontal segment of a lathe profile, revolved into a flat ring.
+#[derive(Debug)]
+pub struct LatheCap {
+    /// The (radius, y) point where this segment of the profile begins.
+    start: (f32, f32),
+    /// The (radius, y) point where this segment of the profile ends.
+    end: (f32, f32),
+    /// Range of v texture coordinates that this segment spans along the profile.
+    v_range: (f32, f32),
+}
+
+impl PrimitiveComponent for LatheCap {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        let plane = Plane {
+            normal_axis: Axis::Y,
+            elevation: self.start.1,
+        };
+        let intersection = plane.intersect(ray)?;
+        let point = ray.at(intersection.t);
+        let radius = (point.x.powi(2) + point.z.powi(2)).sqrt();
+
+        let (inner, outer) = if self.start.0 < self.end.0 {
+            (self.start.0, self.end.0)
+        } else {
+            (self.end.0, self.start.0)
+        };
+
+        if radius < inner || radius > outer {
+            return None;
+        }
+
+        // Following the profile outward faces down, and following it inward faces up
+        let facing = if self.end.0 > self.start.0 { -1.0 } else { 1.0 };
+        let fraction = (radius - self.start.0) / (self.end.0 - self.start.0);
+
+        // v runs along the profile, from the start of the segment to its end
+        let dv_dfraction = self.v_range.1 - self.v_range.0;
+        let outward = vec3(point.x, 0.0, point.z);
+
+        Some(ComponentIntersection::new(
+            intersection.t,
+            vec4(0.0, facing, 0.0, 0.0),
+            (
+                revolution_u(&point),
+                self.v_range.0 + fraction * dv_dfraction,
+            ),
+            onb::revolution_dp_du(&point),
+            outward * (self.end.0 - self.start.0) * dv_dfraction,
+        ))
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        revolution_bounds(self.start, self.end)
+    }
+}
+
+/// A non-horizontal segment of a lathe profile, revolved into a band of a cone
+/// (which is a band of a cylinder if the segment is vertical).
+#[derive(Debug)]
+pub struct LatheBand {
+    /// The (radius, y) point where this segment of the profile begins.
+    start: (f32, f32),
+    /// The (radius, y) point where this segment of the profile ends.
+    end: (f32, f32),
+    /// Range of v texture coordinates that this segment spans along the profile.
+    v_range: (f32, f32),
+}
+
+impl LatheBand {
+    /// The radius of the band as a function of y is `offset + slope * y`, which this returns as
+    /// `(offset, slope)`.
+    fn radius_function(&self) -> (f32, f32) {
+        let slope = (self.end.0 - self.start.0) / (self.end.1 - self.start.1);
+        (self.start.0 - slope * self.start.1, slope)
+    }
+}
+
+/// The bounds of the surface formed by revolving the segment between the given (radius, y)
+/// points around the y axis.
+fn revolution_bounds(start: (f32, f32), end: (f32, f32)) -> BoundingBox {
+    let radius = start.0.max(end.0);
+    BoundingBox {
+        min: vec3(-radius, start.1.min(end.1), -radius),
+        max: vec3(radius, start.1.max(end.1), radius),
+    }
+}
+
+impl QuadraticBody for LatheBand {
+    fn calculate_quadratic_coefficients(&self, ray: &Ray) -> (f32, f32, f32) {
+        let (offset, slope) = self.radius_function();
+        let radius_at_origin = offset + slope * ray.position.y;
+
+        let a = ray.direction.x.powi(2) + ray.direction.z.powi(2)
+            - slope.powi(2) * ray.direction.y.powi(2);
+        let b = 2.0 * (ray.position.x * ray.direction.x + ray.position.z * ray.direction.z)
+            - 2.0 * slope * ray.direction.y * radius_at_origin;
+        let c = ray.position.x.powi(2) + ray.position.z.powi(2) - radius_at_origin.powi(2);
+
+        (a, b, c)
+    }
+
+    fn check_constraint(&self, point: &Vec4) -> bool {
+        let (low, high) = if self.start.1 < self.end.1 {
+            (self.start.1, self.end.1)
+        } else {
+            (self.end.1, self.start.1)
+        };
+
+        low <= point.y && point.y <= high
+    }
+
+    fn normal_at_intersection(&self, point: &Vec4) -> Vec4 {
+        let (offset, slope) = self.radius_function();
+
+        // Segments that follow the profile downward have their outside facing the y axis
+        let facing = if self.end.1 > self.start.1 { 1.0 } else { -1.0 };
+
+        vec4(
+            2.0 * point.x,
+            -2.0 * slope * (offset + slope * point.y),
+            2.0 * point.z,
+            0.0,
+        ) * facing
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        revolution_bounds(self.start, self.end)
+    }
+
+    fn uv_derivatives_at_intersection(&self, point: &Vec4) -> (Vec3, Vec3) {
+        // v runs along the profile from the start of the segment to its end
+        let dv_dy = (self.v_range.1 - self.v_range.0) / (self.end.1 - self.start.1);
+        (onb::revolution_dp_du(point), vec3(0.0, dv_dy, 0.0))
+    }
+
+    fn uv_at_intersection(&self, point: &Vec4) -> (f32, f32) {
+        let fraction = (point.y - self.start.1) / (self.end.1 - self.start.1);
+
+        (
+            revolution_u(point),
+            self.v_range.0 + fraction * (self.v_range.1 - self.v_range.0),
+        )
     }
 }
 
@@ -214,23 +812,45 @@ trait QuadraticBody {
     /// solutions represent intersections with the shape component.
     fn calculate_quadratic_coefficients(&self, ray: &Ray) -> (f32, f32, f32);
 
+    /// The range of angles around the y axis that the shape component spans.
+    fn sweep(&self) -> Sweep {
+        Sweep::FULL
+    }
+
     /// Determines whether or not a given point of intersection actually lies
     /// within the bounds of the shape component.
-    fn check_constraint(&self, point: &glm::Vec4) -> bool {
-        -0.5 <= point.y && point.y <= 0.5
+    fn check_constraint(&self, point: &Vec4) -> bool {
+        -0.5 <= point.y && point.y <= 0.5 && self.sweep().contains(point)
     }
 
     /// Finds the normal vector to the shape component at a given point on the shape component.
-    fn normal_at_intersection(&self, point: &glm::Vec4) -> glm::Vec4;
+    fn normal_at_intersection(&self, point: &Vec4) -> Vec4;
 
     /// Finds the UV coordinate at a given point on the shape component.
-    fn uv_at_intersection(&self, point: &glm::Vec4) -> (f32, f32);
+    fn uv_at_intersection(&self, point: &Vec4) -> (f32, f32);
+
+    /// Finds the directions in which the u and v coordinates increase at a given point on the
+    /// shape component, which by default are around and up the y axis.
+    fn uv_derivatives_at_intersection(&self, point: &Vec4) -> (Vec3, Vec3) {
+        (onb::revolution_dp_du(point), vec3(0.0, 1.0, 0.0))
+    }
+
+    /// A bounding box containing the whole shape component, which by default is the unit cube.
+    fn bounds(&self) -> BoundingBox {
+        unit_cube_bounds()
+    }
 }
 
 #[derive(Debug)]
-pub struct ConeBody;
+pub struct ConeBody {
+    sweep: Sweep,
+}
 
 impl QuadraticBody for ConeBody {
+    fn sweep(&self) -> Sweep {
+        self.sweep
+    }
+
     fn calculate_quadratic_coefficients(&self, ray: &Ray) -> (f32, f32, f32) {
         let a = ray.direction.x.powi(2) + ray.direction.z.powi(2)
             - (1.0 / 4.0) * ray.direction.y.powi(2);
@@ -245,15 +865,15 @@ impl QuadraticBody for ConeBody {
         (a, b, c)
     }
 
-    fn normal_at_intersection(&self, point: &glm::Vec4) -> glm::Vec4 {
+    fn normal_at_intersection(&self, point: &Vec4) -> Vec4 {
         let x_norm = 2.0 * point.x;
         let y_norm = -(1.0 / 4.0) * (2.0 * point.y - 1.0);
         let z_norm = 2.0 * point.z;
 
-        glm::vec4(x_norm, y_norm, z_norm, 0.0)
+        vec4(x_norm, y_norm, z_norm, 0.0)
     }
 
-    fn uv_at_intersection(&self, point: &glm::Vec4) -> (f32, f32) {
+    fn uv_at_intersection(&self, point: &Vec4) -> (f32, f32) {
         let theta = point.z.atan2(point.x);
         let u = if theta < 0.0 {
             -theta / (2.0 * PI)
@@ -266,9 +886,15 @@ impl QuadraticBody for ConeBody {
 }
 
 #[derive(Debug)]
-pub struct CylinderBody;
+pub struct CylinderBody {
+    sweep: Sweep,
+}
 
 impl QuadraticBody for CylinderBody {
+    fn sweep(&self) -> Sweep {
+        self.sweep
+    }
+
     fn calculate_quadratic_coefficients(&self, ray: &Ray) -> (f32, f32, f32) {
         let a = ray.direction.x.powi(2) + ray.direction.z.powi(2);
         let b = 2.0 * (ray.position.x * ray.direction.x + ray.position.z * ray.direction.z);
@@ -277,11 +903,11 @@ impl QuadraticBody for CylinderBody {
         (a, b, c)
     }
 
-    fn normal_at_intersection(&self, point: &glm::Vec4) -> glm::Vec4 {
-        glm::vec4(2.0 * point.x, 0.0, 2.0 * point.z, 0.0)
+    fn normal_at_intersection(&self, point: &Vec4) -> Vec4 {
+        vec4(2.0 * point.x, 0.0, 2.0 * point.z, 0.0)
     }
 
-    fn uv_at_intersection(&self, point: &glm::Vec4) -> (f32, f32) {
+    fn uv_at_intersection(&self, point: &Vec4) -> (f32, f32) {
         let theta = point.z.atan2(point.x);
         let u = if theta < 0.0 {
             -theta / (2.0 * PI)
@@ -293,10 +919,41 @@ impl QuadraticBody for CylinderBody {
     }
 }
 
+/// The inside surface of a hollow tube, which is a cylinder of the given radius (and
+/// height 1) whose normals face inward, toward the y axis.
+#[derive(Debug)]
+pub struct TubeBore {
+    radius: f32,
+}
+
+impl QuadraticBody for TubeBore {
+    fn calculate_quadratic_coefficients(&self, ray: &Ray) -> (f32, f32, f32) {
+        let a = ray.direction.x.powi(2) + ray.direction.z.powi(2);
+        let b = 2.0 * (ray.position.x * ray.direction.x + ray.position.z * ray.direction.z);
+        let c = ray.position.x.powi(2) + ray.position.z.powi(2) - self.radius.powi(2);
+
+        (a, b, c)
+    }
+
+    fn normal_at_intersection(&self, point: &Vec4) -> Vec4 {
+        vec4(-2.0 * point.x, 0.0, -2.0 * point.z, 0.0)
+    }
+
+    fn uv_at_intersection(&self, point: &Vec4) -> (f32, f32) {
+        (revolution_u(point), point.y + 0.5)
+    }
+}
+
 #[derive(Debug)]
-pub struct Sphere;
+pub struct Sphere {
+    sweep: Sweep,
+}
 
 impl QuadraticBody for Sphere {
+    fn sweep(&self) -> Sweep {
+        self.sweep
+    }
+
     fn calculate_quadratic_coefficients(&self, ray: &Ray) -> (f32, f32, f32) {
         let a = ray.direction.x.powi(2) + ray.direction.y.powi(2) + ray.direction.z.powi(2);
         let b = 2.0
@@ -309,11 +966,11 @@ impl QuadraticBody for Sphere {
         (a, b, c)
     }
 
-    fn normal_at_intersection(&self, point: &glm::Vec4) -> glm::Vec4 {
-        glm::vec4(2.0 * point.x, 2.0 * point.y, 2.0 * point.z, 0.0)
+    fn normal_at_intersection(&self, point: &Vec4) -> Vec4 {
+        vec4(2.0 * point.x, 2.0 * point.y, 2.0 * point.z, 0.0)
     }
 
-    fn uv_at_intersection(&self, point: &glm::Vec4) -> (f32, f32) {
+    fn uv_at_intersection(&self, point: &Vec4) -> (f32, f32) {
         let v = (point.y / 0.5).asin() / PI + 0.5;
 
         let u = if v == 0.0 || v == 1.0 {
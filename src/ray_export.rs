@@ -0,0 +1,81 @@
+//! Recording of the paths taken by the rays of a sampled subset of pixels during a render,
+//! which are exported as a set of line segments (in an OBJ file) to be inspected in a 3D
+//! viewer, e.g. to see how deep reflections go or which way shadow rays are cast.
+
+use crate::cost::RayKind;
+use crate::math::{Vec3, Vec4};
+use crate::scene::Scene;
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// A straight part of the path of a ray, from where it was cast to where it ended (at the
+/// surface it hit, or at the light it was cast toward).
+#[derive(Debug, Clone)]
+pub struct RaySegment {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub kind: RayKind,
+}
+
+/// The segments of the rays traced for a single camera ray, as they are recorded.
+#[derive(Debug, Default)]
+pub struct RayPath {
+    segments: RefCell<Vec<RaySegment>>,
+}
+
+impl RayPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a segment of a ray between the given points.
+    pub fn add(&self, start: &Vec4, end: &Vec4, kind: RayKind) {
+        self.segments.borrow_mut().push(RaySegment {
+            start: start.truncate(),
+            end: end.truncate(),
+            kind,
+        });
+    }
+
+    pub fn into_segments(self) -> Vec<RaySegment> {
+        self.segments.into_inner()
+    }
+}
+
+/// Length of the segment drawn for a ray that escapes the scene (or is cast toward a light
+/// that is infinitely far away), which reaches well outside the scene.
+pub fn escape_length(scene: &Scene) -> f32 {
+    if scene.size.is_normal() {
+        scene.size
+    } else {
+        1.0
+    }
+}
+
+/// Writes the given segments to an OBJ file as lines, grouped by the kind of ray (so that
+/// the kinds can be shown or hidden separately in a viewer).
+pub fn write_obj(path: &Path, segments: &[RaySegment]) -> Result<()> {
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut vertex_count = 0;
+
+        for kind in RayKind::ALL {
+            writeln!(writer, "g {}", kind.name())?;
+
+            for segment in segments.iter().filter(|segment| segment.kind == kind) {
+                for point in [segment.start, segment.end] {
+                    writeln!(writer, "v {} {} {}", point.x, point.y, point.z)?;
+                }
+                writeln!(writer, "l {} {}", vertex_count + 1, vertex_count + 2)?;
+                vertex_count += 2;
+            }
+        }
+
+        writer.flush()
+    };
+
+    write().with_context(|| format!("Failed to write ray paths: {}", path.display()))
+}
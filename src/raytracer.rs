@@ -1,204 +1,845 @@
 //! Core raytracing functionality.
 
+use crate::accumulation::{Accumulation, PixelSamples};
+use crate::aov::{Aov, CenterHit};
+use crate::cost::{CostReport, RayKind};
+use crate::integrator::{Flat, Integrator, TraceContext, Whitted};
+use crate::interrupt::{self, PartialRender, StopReason, Tile};
 use crate::lights;
-use crate::scene::Scene;
+use crate::math::{Mat4, Vec4};
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::partition::Partition;
+use crate::pixel_trace::{PixelPosition, PixelTrace};
+use crate::ray_export::{RayPath, RaySegment};
+use crate::rerender::Rerender;
+use crate::sampling::{self, BlueNoiseMask, SamplePattern, Sampler};
+use crate::scene::{Camera, Scene, SceneStats};
+use crate::terminal_preview::TerminalPreview;
 use crate::Config;
-use image::RgbImage;
-use num_traits::Zero;
+use anyhow::{anyhow, Context, Result};
+use image::{Rgb, Rgb32FImage, RgbImage};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use std::sync::mpsc::channel;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-/// Total number of rays that will be traced (including camera ray) when
-/// computing illumination for reflective materials.
-const MAX_REFLECTION_DEPTH: u8 = 4;
+/// Color of pixels whose rendering was aborted for exceeding the maximum time per pixel.
+const ABORTED_PIXEL_COLOR: Rgb<u8> = Rgb([255, 0, 255]);
+
+/// Width and height (in pixels) of the buckets that images are divided into for rendering.
+const BUCKET_SIZE: u32 = 32;
 
 /// A ray is like a beam that originates from a point and travels through the scene,
 /// in a direction, possibly intersecting with an object(s) along its path.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ray {
-    pub position: glm::Vec4,
-    pub direction: glm::Vec4,
+    pub position: Vec4,
+    pub direction: Vec4,
+    /// The interval of t-values along the ray at which intersections are valid, so that
+    /// e.g. shadow rays end at their light, and secondary rays skip the surface they leave.
+    pub t_min: f32,
+    pub t_max: f32,
+    /// Largest component (along a plane's normal) of the ray's unit direction at which the
+    /// ray is considered parallel to the plane.
+    pub parallel_threshold: f32,
 }
 
 impl Ray {
-    /// Constructs a new Ray from the given components.
-    pub fn new(position: glm::Vec4, direction: glm::Vec4) -> Self {
+    /// Constructs a new Ray from the given components, which is valid for all non-negative
+    /// t-values.
+    pub fn new(position: Vec4, direction: Vec4) -> Self {
         Self {
             position,
             direction,
+            t_min: 0.0,
+            t_max: f32::INFINITY,
+            parallel_threshold: 0.0,
+        }
+    }
+
+    /// Restricts the ray to intersections between the given t-values.
+    pub fn with_t_range(self, t_min: f32, t_max: f32) -> Self {
+        Self {
+            t_min,
+            t_max,
+            ..self
+        }
+    }
+
+    /// Sets the tolerance at which the ray is considered parallel to planes.
+    pub fn with_parallel_threshold(self, parallel_threshold: f32) -> Self {
+        Self {
+            parallel_threshold,
+            ..self
         }
     }
 
+    /// Determines whether the given t-value is within the ray's valid interval.
+    pub fn contains(&self, t: f32) -> bool {
+        t >= self.t_min && t <= self.t_max
+    }
+
     /// Transform the ray by the given transformation matrix. If `normalize_direction`
     /// is set, the new ray's `direction` will be guaranteed to be a unit vector.
-    pub fn transform(&self, transformation: &glm::Mat4, normalize_direction: bool) -> Ray {
-        let position = transformation.mul_v(&self.position);
-        let mut direction = transformation.mul_v(&self.direction);
+    pub fn transform(&self, transformation: &Mat4, normalize_direction: bool) -> Ray {
+        let position = *transformation * self.position;
+        let mut direction = *transformation * self.direction;
+        let (mut t_min, mut t_max) = (self.t_min, self.t_max);
 
+        // Normalizing stretches the t-values of points along the ray by the direction's length
         if normalize_direction {
-            direction = glm::normalize(direction);
+            let length = direction.length();
+            direction /= length;
+            t_min *= length;
+            t_max *= length;
         }
 
         Ray {
             position,
             direction,
+            t_min,
+            t_max,
+            parallel_threshold: self.parallel_threshold,
         }
     }
 
     /// Convert a ray to object space by applying the given matrix and not normalizing the ray direction.
-    pub fn to_object_space(&self, transformation: &glm::Mat4) -> Ray {
+    pub fn to_object_space(&self, transformation: &Mat4) -> Ray {
         self.transform(transformation, false)
     }
 
     /// Evaluate the ray at a given t-value, which indicates a point on the ray
     /// by acting as a scalar on the ray's direction vector.
-    pub fn at(&self, t: f32) -> glm::Vec4 {
+    pub fn at(&self, t: f32) -> Vec4 {
         self.position + self.direction * t
     }
 }
 
-/// A raytracer renders a given scene under a configuration.
-pub struct RayTracer {
-    scene: Scene,
+/// The output of rendering (part of) an image.
+pub struct Render {
+    pub image: RgbImage,
+    /// Images of the AOVs that were requested in the configuration.
+    pub aovs: Vec<(Aov, Rgb32FImage)>,
+    /// Costs of intersecting rays with each shape, if requested in the configuration.
+    pub cost_report: Option<CostReport>,
+    /// Column/row positions (in the full image) of the pixels that were aborted for exceeding
+    /// the maximum time per pixel, which are filled with a debug color.
+    pub aborted_pixels: Vec<(u32, u32)>,
+    /// Segments of the paths of the rays that were recorded, if requested in the configuration.
+    pub ray_segments: Vec<RaySegment>,
+    /// Counts of the contents of the rendered scene.
+    pub scene_stats: SceneStats,
+    /// Time taken to build the scene before rendering it, if it was built for this render
+    /// (see [`render_config`](crate::render_config)).
+    pub scene_build_time: Duration,
+}
+
+/// Measurements of a render, as returned by [`RayTracer::render_timed`] (e.g. for
+/// benchmarking the raytracer).
+#[derive(Debug, Clone, Copy)]
+pub struct RenderStats {
+    /// Wall-clock time taken by the render.
+    pub elapsed: Duration,
+    /// Number of pixels that were rendered.
+    pub pixels: u64,
+    /// Number of camera rays that were traced (the number of samples taken of all pixels).
+    pub samples: u64,
+}
+
+impl RenderStats {
+    /// Number of camera rays (including the rays they spawned) traced per second.
+    pub fn samples_per_second(&self) -> f64 {
+        self.samples as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// A rectangular block of pixels of the output image, which is rendered as a single unit
+/// of work (by a single thread, so that neighbouring rays hit the same parts of the scene).
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    col_start: u32,
+    row_start: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Bucket {
+    /// Divides an image of the given size into buckets, row by row of buckets. The buckets
+    /// at the right and bottom edges are smaller if the image doesn't divide evenly.
+    fn divide(width: u32, height: u32) -> Vec<Bucket> {
+        (0..height)
+            .step_by(BUCKET_SIZE as usize)
+            .flat_map(|row_start| {
+                (0..width)
+                    .step_by(BUCKET_SIZE as usize)
+                    .map(move |col_start| Bucket {
+                        col_start,
+                        row_start,
+                        width: BUCKET_SIZE.min(width - col_start),
+                        height: BUCKET_SIZE.min(height - row_start),
+                    })
+            })
+            .collect()
+    }
+
+    /// The column/row positions of the bucket's pixels, row by row.
+    fn tile(self) -> Tile {
+        Tile {
+            x: self.col_start,
+            y: self.row_start,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn pixels(self) -> impl Iterator<Item = (u32, u32)> {
+        (self.row_start..self.row_start + self.height).flat_map(move |row| {
+            (self.col_start..self.col_start + self.width).map(move |col| (col, row))
+        })
+    }
+}
+
+/// The eye point and (world space) view plane axes of the camera, from which the rays
+/// through each pixel are generated without transforming them out of camera space.
+#[derive(Debug, Clone, Copy)]
+struct CameraBasis {
+    eye: Vec4,
+    /// Spans the width of the view plane, pointing to the right of the image.
+    right: Vec4,
+    /// Spans the height of the view plane, pointing to the top of the image.
+    up: Vec4,
+    /// From the eye to the center of the view plane (at depth 1).
+    forward: Vec4,
+}
+
+impl CameraBasis {
+    /// Computes the basis of the given camera, for an image of the given size.
+    fn new(camera: &Camera, width: u32, height: u32) -> Self {
+        let viewplane_height = 2.0 * (camera.height_angle / 2.0).tan(); // depth = 1
+        let viewplane_width = viewplane_height * (width as f32 / height as f32);
+
+        let inverse_view = &camera.inverse_view_matrix;
+        Self {
+            eye: inverse_view.w_axis,
+            right: inverse_view.x_axis * viewplane_width,
+            up: inverse_view.y_axis * viewplane_height,
+            forward: -inverse_view.z_axis,
+        }
+    }
+
+    /// The (unit) direction from the eye through the given continuous view plane
+    /// coordinates, each between -0.5 and 0.5.
+    fn direction(&self, x: f32, y: f32) -> Vec4 {
+        (self.forward + self.right * x + self.up * y).normalize()
+    }
+}
+
+/// A raytracer renders a given scene under a configuration. The scene is borrowed, so that
+/// one built scene can be rendered by several raytracers (e.g. a preview and a final render
+/// at different sizes).
+pub struct RayTracer<'a> {
+    scene: &'a Scene,
     config: Config,
+    /// Basis of the scene's camera, for generating camera rays.
+    camera_basis: CameraBasis,
+    /// Mask used to choose the offsets of samples within pixels, if using blue noise sampling.
+    blue_noise_mask: Option<BlueNoiseMask>,
+    /// Computes the light arriving along each camera ray.
+    integrator: Box<dyn Integrator>,
+    /// Segments of the paths of the rays recorded so far, if ray paths are being exported.
+    ray_segments: Mutex<Vec<RaySegment>>,
+    /// The previous render to composite the render over, if only some pixels are re-rendered.
+    rerender: Option<Rerender>,
+    /// Pool of threads to render in parallel with, if not the global pool.
+    thread_pool: Option<Arc<ThreadPool>>,
 }
 
-impl RayTracer {
-    /// Constructs a new `RayTracer`.
-    pub fn new(scene: Scene, config: Config) -> Self {
-        Self { scene, config }
+impl<'a> RayTracer<'a> {
+    /// Constructs a new `RayTracer`, which fails if the threads to render with (with
+    /// `--threads`) can't be started. The cost of each shape is only reported (with
+    /// `--cost-report`) if the scene tracks costs, as scenes made by [`crate::build_scene`] do.
+    pub fn new(scene: &'a Scene, config: Config) -> Result<Self> {
+        let blue_noise_mask = match config.sample_pattern {
+            SamplePattern::BlueNoise => Some(BlueNoiseMask::generate()),
+            SamplePattern::Random => None,
+        };
+
+        let camera_basis = CameraBasis::new(&scene.camera, config.width, config.height);
+        let integrator: Box<dyn Integrator> = if config.flat {
+            Box::new(Flat)
+        } else {
+            Box::new(Whitted)
+        };
+
+        // A pool of its own keeps the render to the given number of threads, however many the
+        // global pool has (and is reused by every pass of a progressive render)
+        let thread_pool = match config.threads.filter(|_| config.enable_parallelism) {
+            Some(threads) => Some(Arc::new(
+                ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .context("Failed to start the threads to render with")?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            scene,
+            config,
+            camera_basis,
+            blue_noise_mask,
+            integrator,
+            ray_segments: Mutex::new(Vec::new()),
+            rerender: None,
+            thread_pool,
+        })
+    }
+
+    /// Uses the given integrator to shade the render, instead of the default [`Whitted`] one
+    /// (or [`Flat`] one, with `--flat`).
+    pub fn with_integrator(mut self, integrator: impl Integrator + 'static) -> Self {
+        self.integrator = Box::new(integrator);
+        self
+    }
+
+    /// Only renders the pixels selected by the given re-render (with [`Self::render`]), and
+    /// takes the rest from its previous render.
+    pub fn with_rerender(mut self, rerender: Rerender) -> Self {
+        self.rerender = Some(rerender);
+        self
+    }
+
+    /// Renders in parallel (with `--enable-parallelism`) on the given pool of threads, instead
+    /// of the global pool (or a pool of `--threads` threads). An application rendering many
+    /// frames can share one pool between its renders, and control the priority of its threads.
+    pub fn with_thread_pool(mut self, thread_pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(thread_pool);
+        self
+    }
+
+    /// Traces a ray from the camera through the pixel at the given row/column of the full
+    /// image, offset within the pixel by the given (x, y) amounts (each between 0-1).
+    /// Returns `None` if the context's deadline (if any) passes before the ray has been traced.
+    fn trace_camera_ray(
+        &self,
+        row: u32,
+        col: u32,
+        offset: (f32, f32),
+        context: &TraceContext,
+    ) -> Option<Vec4> {
+        self.integrator.radiance(
+            &self.camera_ray(row, col, offset),
+            self.scene,
+            &mut Sampler::new(),
+            context,
+        )
+    }
+
+    /// Constructs the (world space) ray from the camera through the pixel at the given
+    /// row/column of the full image, offset within the pixel by the given (x, y) amounts.
+    fn camera_ray(&self, row: u32, col: u32, (offset_x, offset_y): (f32, f32)) -> Ray {
+        // Convert the image coordinates to continuous view plane coordinates
+        let y =
+            ((self.config.height - 1 - row) as f32 + offset_y) / self.config.height as f32 - 0.5;
+        let x = (col as f32 + offset_x) / self.config.width as f32 - 0.5;
+
+        self.scene.ray(
+            self.camera_basis.eye,
+            self.camera_basis.direction(x, y),
+            self.scene.tolerances.min_t,
+            f32::INFINITY,
+        )
     }
 
-    /// Trace the given ray into the raytracer's scene by determining if it intersects
-    /// any objects, and if so, calculating what intensity contribution this ray makes.
-    /// This may involve tracing further rays out from the point of intersection.
-    fn trace_ray(&self, ray: &Ray, depth: u8) -> glm::Vec4 {
-        // Look for the shape intersection with the minimum t-value (indicates closeness to the ray origin)
-        let closest_intersection = &self
-            .scene
-            .shapes
+    /// Traces a packet of camera rays through the pixel at the given row/column of the full
+    /// image, one for each of the given offsets within the pixel (of which there may be up to
+    /// [`PACKET_SIZE`]). Returns the intensity of each ray in the lane of its offset.
+    fn trace_packet(
+        &self,
+        row: u32,
+        col: u32,
+        offsets: &[(f32, f32)],
+        deadline: Option<Instant>,
+    ) -> [Option<Vec4>; PACKET_SIZE] {
+        let rays: Vec<Ray> = offsets
             .iter()
-            .flat_map(|shape| shape.intersect(ray))
-            .min();
-
-        match closest_intersection {
-            Some(intersection) => {
-                let color = lights::phong(&self.scene, &self.config, intersection, ray);
-
-                if !self.config.enable_reflections
-                    || glm::Vec4::zero() == intersection.material.reflective
-                    || depth == MAX_REFLECTION_DEPTH
-                {
-                    // If there are no reflections enabled, the material isn't at all reflective,
-                    // or we are at the maximum depth for recursively tracing rays, stop recurring.
-                    color
+            .map(|&offset| self.camera_ray(row, col, offset))
+            .collect();
+
+        self.integrator.radiance_packet(
+            &RayPacket::new(&rays),
+            self.scene,
+            &mut Sampler::new(),
+            &TraceContext::new(&self.config, deadline),
+        )
+    }
+
+    /// Whether the rays of the pixel at the given row/column of the full image are recorded,
+    /// for exporting ray paths.
+    fn records_path(&self, row: u32, col: u32) -> bool {
+        let stride = self.config.ray_export_stride.max(1);
+        self.config.ray_export.is_some() && col % stride == 0 && row % stride == 0
+    }
+
+    /// Traces a camera ray (see [`Self::trace_camera_ray`]) for the given sample of the pixel
+    /// at the given row/column of the full image, which must be finished by the deadline (if
+    /// any). The rays of the first sample of a subset of pixels are recorded, if ray paths are
+    /// being exported.
+    fn trace_sample(
+        &self,
+        row: u32,
+        col: u32,
+        sample: u32,
+        offset: (f32, f32),
+        deadline: Option<Instant>,
+    ) -> Option<Vec4> {
+        let context = TraceContext::new(&self.config, deadline);
+        if sample != 0 || !self.records_path(row, col) {
+            return self.trace_camera_ray(row, col, offset, &context);
+        }
+
+        let path = RayPath::new();
+        let intensity = self.trace_camera_ray(row, col, offset, &context.with_path(&path));
+        self.ray_segments
+            .lock()
+            .unwrap()
+            .extend(path.into_segments());
+
+        intensity
+    }
+
+    /// Traces a single ray through the center of the pixel at the given position of the full
+    /// image, recording everything that happens along the way (for debugging the pixel).
+    pub fn trace_pixel(&self, position: PixelPosition) -> Result<PixelTrace> {
+        if position.col >= self.config.width || position.row >= self.config.height {
+            return Err(anyhow!(
+                "Pixel ({}, {}) is outside the {}x{} image",
+                position.col,
+                position.row,
+                self.config.width,
+                self.config.height
+            ));
+        }
+
+        let trace = PixelTrace::new();
+        trace.record(format!("pixel ({}, {})", position.col, position.row));
+        let context = TraceContext::new(&self.config, self.pixel_deadline()).with_trace(&trace);
+        if let Some(intensity) =
+            self.trace_camera_ray(position.row, position.col, (0.5, 0.5), &context)
+        {
+            let Rgb([r, g, b]) = lights::to_rgb(&intensity);
+            trace.record(format!("color ({}, {}, {})", r, g, b));
+        }
+
+        Ok(trace)
+    }
+
+    /// The time by which a pixel whose rendering starts now must be finished, if there is a
+    /// maximum time per pixel.
+    fn pixel_deadline(&self) -> Option<Instant> {
+        self.config
+            .max_pixel_time
+            .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds))
+    }
+
+    /// Produces an image by rendering the given partition of the raytracer's scene. The
+    /// image contains only the rows of the partition (including its overscan rows).
+    ///
+    /// The `pixels_finished` parameter is a callback that is invoked every time a bucket of
+    /// pixels completes rendering, with the number of pixels that were just finished.
+    ///
+    /// If the render stops before every bucket is rendered (see [`interrupt`]), the error is
+    /// a [`PartialRender`] of the buckets that were.
+    pub fn render<F: Fn(u64) + Sync>(
+        &self,
+        partition: &Partition,
+        pixels_finished: F,
+    ) -> Result<Render> {
+        let mut accumulation = Accumulation::new(partition);
+        let row_offset = accumulation.row_offset();
+
+        // Renders a single pixel at the given column/row of the output image, returning the
+        // samples taken for it and whether it was aborted.
+        let render_pixel = |col: u32, output_row: u32| {
+            // Row of the full image that this pixel is in
+            let row = partition.rendered_row_start() + output_row;
+
+            let mut samples = PixelSamples::default();
+            let deadline = self.pixel_deadline();
+            let mut aborted = false;
+
+            // Pixels that aren't re-rendered are taken from the previous render when finishing
+            if let Some(ref rerender) = self.rerender {
+                if !rerender.selects(col, output_row) {
+                    return (samples, aborted);
+                }
+            }
+
+            let offsets: Vec<(f32, f32)> = (0..self.config.samples)
+                .map(|sample| match &self.blue_noise_mask {
+                    _ if self.config.analytic => {
+                        sampling::grid_offset(sample as u32, self.config.samples as u32)
+                    }
+                    Some(mask) => mask.offset(col, row, sample as u32),
+                    None => {
+                        // Choose an offset between 0-1 for stochastic super sampling,
+                        // ensuring that 1 sample goes through the center of the pixel.
+                        let random_offset = || {
+                            if sample == self.config.samples - 1 {
+                                0.5f32
+                            } else {
+                                rand::random()
+                            }
+                        };
+
+                        let offset_y = random_offset();
+                        let offset_x = random_offset();
+                        (offset_x, offset_y)
+                    }
+                })
+                .collect();
+
+            // With packet tracing, the pixel's samples are traced in packets of coherent rays
+            // (unless its rays are being recorded, which is only done one ray at a time)
+            let intensities: Box<dyn Iterator<Item = Option<Vec4>>> =
+                if self.config.packets && !self.records_path(row, col) {
+                    Box::new(offsets.chunks(PACKET_SIZE).flat_map(|chunk| {
+                        self.trace_packet(row, col, chunk, deadline)
+                            .into_iter()
+                            .take(chunk.len())
+                    }))
                 } else {
-                    let reflected_direction = lights::reflect_around(
-                        &ray.direction,
-                        &intersection.component_intersection.normal,
-                    );
-                    let reflected_ray = Ray::new(
-                        ray.at(intersection.component_intersection.t)
-                            + (reflected_direction * lights::SELF_INTERSECT_OFFSET),
-                        reflected_direction,
-                    );
-                    let reflected_light = intersection.material.reflective
-                        * self.scene.global_lighting_coefficients.ks
-                        * self.trace_ray(&reflected_ray, depth + 1);
-
-                    // Use the color from the original ray, but add the contribution of a
-                    // ray that has been reflected off the intersected surface
-                    color + reflected_light
+                    Box::new(offsets.iter().enumerate().map(|(sample, &offset)| {
+                        self.trace_sample(row, col, sample as u32, offset, deadline)
+                    }))
+                };
+
+            for intensity in intensities {
+                match intensity {
+                    Some(intensity) => samples.add(&intensity),
+                    None => {
+                        aborted = true;
+                        break;
+                    }
+                }
+            }
+
+            (samples, aborted)
+        };
+
+        let mut aborted_pixels = Vec::new();
+
+        let preview = self
+            .config
+            .preview_term
+            .then(|| TerminalPreview::new(accumulation.width(), accumulation.height()));
+        let (buckets, stop_reason) = self.render_buckets(
+            accumulation.width(),
+            accumulation.height(),
+            render_pixel,
+            &pixels_finished,
+            preview.as_ref(),
+            |(samples, _)| lights::to_rgb(&samples.mean()),
+        );
+        if let Some(ref preview) = preview {
+            preview.finish();
+        }
+
+        let mut valid_tiles = Vec::new();
+        for (bucket, pixels) in buckets {
+            for ((col, output_row), (samples, aborted)) in bucket.pixels().zip(pixels) {
+                *accumulation.pixel_mut(col, output_row) = samples;
+                if aborted {
+                    aborted_pixels.push((col, output_row + row_offset));
                 }
             }
-            // There is no intersection, so there is no illumination from this ray
-            None => glm::vec4(0.0, 0.0, 0.0, 1.0),
+            valid_tiles.push(bucket.tile());
+        }
+
+        let render = self.finish(&accumulation, aborted_pixels);
+        match stop_reason {
+            Some(reason) => Err(PartialRender {
+                image: render.image,
+                valid_tiles,
+                tile_count: Bucket::divide(accumulation.width(), accumulation.height()).len(),
+                reason,
+            }
+            .into()),
+            None => Ok(render),
         }
     }
 
-    /// Produces an image by rendering the raytracer's scene.
+    /// Like [`Self::render`] (without a callback for finished pixels), but also measures the
+    /// render.
+    pub fn render_timed(&self, partition: &Partition) -> Result<(Render, RenderStats)> {
+        let start = Instant::now();
+        let render = self.render(partition, |_| {})?;
+        let elapsed = start.elapsed();
+
+        let pixels = partition.image_width as u64 * partition.rendered_height() as u64;
+        let stats = RenderStats {
+            elapsed,
+            pixels,
+            samples: pixels * self.config.samples as u64,
+        };
+
+        Ok((render, stats))
+    }
+
+    /// Progressively renders the given partition into the accumulation buffer, in passes
+    /// which each add one sample to every pixel, until `--samples` passes have been made.
+    /// With random sampling, the first sample of each pixel goes through its center, and
+    /// later samples are jittered within it.
     ///
-    /// The `pixel_finished` parameter is a callback that is invoked every time a pixel completes rendering.
-    pub fn render<F: Fn() + Sync>(&self, pixel_finished: F) -> RgbImage {
-        let viewplane_height = 2.0 * (self.scene.camera.height_angle / 2.0).tan(); // depth = 1
-        let viewplane_width =
-            viewplane_height * (self.config.width as f32 / self.config.height as f32);
-
-        let mut output_image = RgbImage::new(self.config.width, self.config.height);
-        let output_width = output_image.width();
-
-        // Renders a single pixel at the given 1-dimensional index in the image,
-        // returning its row/column position as well as the computed pixel color.
-        let render_pixel = |pixel_index| {
-            // Convert pixel index to 2D discrete image coordinates
-            let row = pixel_index / output_width;
-            let col = pixel_index % output_width;
-
-            let mut accumulated_intensity = glm::vec4(0.0, 0.0, 0.0, 0.0);
-
-            for sample in 0..self.config.samples {
-                // Choose an offset between 0-1 for stochastic super sampling,
-                // ensuring that 1 sample goes through the center of the pixel.
-                let random_offset = || {
-                    if sample == self.config.samples - 1 {
-                        0.5f32
-                    } else {
-                        rand::random()
+    /// The `pixels_finished` callback is invoked every time a bucket of pixels completes a
+    /// pass (with the number of pixels in it), and `pass_finished` is invoked with the
+    /// updated accumulation after every pass, so that it can be saved (which allows an
+    /// interrupted render to be resumed).
+    ///
+    /// If a pass stops before every bucket is rendered (see [`interrupt`]), the samples of
+    /// the buckets that were are still accumulated (and passed to `pass_finished`), and the
+    /// error is a [`PartialRender`] of the buckets that have samples for every pixel.
+    pub fn render_progressive<F, P>(
+        &self,
+        accumulation: &mut Accumulation,
+        pixels_finished: F,
+        mut pass_finished: P,
+    ) -> Result<Render>
+    where
+        F: Fn(u64) + Sync,
+        P: FnMut(&Accumulation) -> Result<()>,
+    {
+        let width = accumulation.width();
+        let row_offset = accumulation.row_offset();
+        let mut aborted_pixels = Vec::new();
+
+        // Each pass is previewed in place of the previous one, showing only its new samples
+        let preview = self
+            .config
+            .preview_term
+            .then(|| TerminalPreview::new(width, accumulation.height()));
+
+        for _ in 0..self.config.samples {
+            let accumulated: &Accumulation = accumulation;
+
+            // Traces a single new sample for the pixel at the given column/row of the output image
+            let sample_pixel = |col: u32, output_row: u32| {
+                let row = row_offset + output_row;
+                let sample = accumulated.pixel(col, output_row).count;
+
+                let offset = match &self.blue_noise_mask {
+                    _ if self.config.analytic => {
+                        sampling::grid_offset(sample, self.config.samples as u32)
                     }
+                    Some(mask) => mask.offset(col, row, sample),
+                    None if sample == 0 => (0.5, 0.5),
+                    None => (rand::random(), rand::random()),
                 };
 
-                // Convert the image coordinates to continuous view plane coordinates
-                let y = ((self.config.height - 1 - row) as f32 + random_offset())
-                    / self.config.height as f32
-                    - 0.5;
-                let x = (col as f32 + random_offset()) / self.config.width as f32 - 0.5;
-
-                // Determine the direction from the camera to the pixel
-                let eye = glm::vec4(0.0, 0.0, 0.0, 1.0);
-                let direction = glm::normalize(glm::vec4(
-                    viewplane_width * x,
-                    viewplane_height * y,
-                    -1.0,
-                    0.0,
-                ));
-
-                // Construct a ray from the camera through this pixel, and trace it into the scene
-                let camera_ray = Ray::new(eye, direction);
-                let world_ray = camera_ray.transform(&self.scene.camera.inverse_view_matrix, false);
-
-                accumulated_intensity = accumulated_intensity + self.trace_ray(&world_ray, 0);
+                self.trace_sample(row, col, sample, offset, self.pixel_deadline())
+            };
+
+            let (buckets, stop_reason) = self.render_buckets(
+                width,
+                accumulation.height(),
+                sample_pixel,
+                &pixels_finished,
+                preview.as_ref(),
+                |intensity| {
+                    intensity.map_or(ABORTED_PIXEL_COLOR, |intensity| lights::to_rgb(&intensity))
+                },
+            );
+            if let Some(ref preview) = preview {
+                preview.finish();
             }
 
-            let average_intensity = accumulated_intensity / self.config.samples as f32;
-            let pixel_color = lights::to_rgb(&average_intensity);
+            // Aborted samples are left out of the accumulation, so that resuming doesn't keep them
+            for (bucket, intensities) in buckets {
+                for ((col, output_row), intensity) in bucket.pixels().zip(intensities) {
+                    match intensity {
+                        Some(intensity) => accumulation.pixel_mut(col, output_row).add(&intensity),
+                        None => aborted_pixels.push((col, row_offset + output_row)),
+                    }
+                }
+            }
 
-            pixel_finished();
+            pass_finished(accumulation)?;
+
+            if let Some(reason) = stop_reason {
+                let tiles = Bucket::divide(width, accumulation.height());
+                let tile_count = tiles.len();
+                let valid_tiles = tiles
+                    .into_iter()
+                    .filter(|bucket| {
+                        bucket
+                            .pixels()
+                            .all(|(col, row)| accumulation.pixel(col, row).count > 0)
+                    })
+                    .map(Bucket::tile)
+                    .collect();
+
+                return Err(PartialRender {
+                    image: self.finish(accumulation, aborted_pixels).image,
+                    valid_tiles,
+                    tile_count,
+                    reason,
+                }
+                .into());
+            }
+        }
 
-            (col, row, pixel_color)
-        };
+        Ok(self.finish(accumulation, aborted_pixels))
+    }
 
-        let all_pixel_indices = 0..(output_image.width() * output_image.height());
+    /// Renders an output image of the given size bucket by bucket (in parallel, if enabled),
+    /// using the given function of the column/row of each pixel. Returns each finished bucket
+    /// along with the values of its pixels, row by row.
+    ///
+    /// If the render is interrupted, or rendering a bucket fails (panics), no more buckets are
+    /// started, and the reason that the render stopped is returned with the finished buckets.
+    ///
+    /// The number of pixels in each bucket is passed to `pixels_finished` once the bucket
+    /// finishes (reporting progress per pixel would slow large renders down). If a terminal
+    /// preview is given, each bucket is also shown in it, in the color given by `preview_color`.
+    fn render_buckets<T, R, F, C>(
+        &self,
+        width: u32,
+        height: u32,
+        render_pixel: R,
+        pixels_finished: &F,
+        preview: Option<&TerminalPreview>,
+        preview_color: C,
+    ) -> (Vec<(Bucket, Vec<T>)>, Option<StopReason>)
+    where
+        T: Send,
+        R: Fn(u32, u32) -> T + Sync,
+        F: Fn(u64) + Sync,
+        C: Fn(&T) -> Rgb<u8> + Sync,
+    {
+        let failure = Mutex::new(None);
+
+        let render_bucket = |bucket: Bucket| {
+            if interrupt::is_interrupted() || failure.lock().unwrap().is_some() {
+                return None;
+            }
 
-        if self.config.enable_parallelism {
-            let (sender, receiver) = channel();
+            let pixels = panic::catch_unwind(AssertUnwindSafe(|| {
+                bucket
+                    .pixels()
+                    .map(|(col, row)| render_pixel(col, row))
+                    .collect::<Vec<T>>()
+            }));
+            let pixels = match pixels {
+                Ok(pixels) => pixels,
+                Err(payload) => {
+                    failure
+                        .lock()
+                        .unwrap()
+                        .get_or_insert(StopReason::Failed(panic_message(payload)));
+                    return None;
+                }
+            };
 
-            // Render all pixels in parallel, sending output to the image writer
-            all_pixel_indices
-                .into_par_iter()
-                .for_each_with(sender, |pixel_writer, pixel_index| {
-                    pixel_writer.send(render_pixel(pixel_index)).unwrap();
-                });
+            pixels_finished(pixels.len() as u64);
 
-            // Receive the pixel data and write it to the image buffer
-            for (x, y, color) in receiver.iter() {
-                output_image.put_pixel(x, y, color);
+            if let Some(preview) = preview {
+                preview.update(
+                    bucket
+                        .pixels()
+                        .zip(&pixels)
+                        .map(|(position, pixel)| (position, preview_color(pixel))),
+                );
             }
+
+            Some((bucket, pixels))
+        };
+
+        let buckets = Bucket::divide(width, height);
+        let bucket_count = buckets.len();
+
+        let rendered: Vec<(Bucket, Vec<T>)> = if !self.config.enable_parallelism {
+            buckets.into_iter().filter_map(render_bucket).collect()
         } else {
-            for (x, y, color) in all_pixel_indices.map(render_pixel) {
-                output_image.put_pixel(x, y, color);
+            match self.thread_pool {
+                Some(ref thread_pool) => thread_pool
+                    .install(|| buckets.into_par_iter().filter_map(render_bucket).collect()),
+                None => buckets.into_par_iter().filter_map(render_bucket).collect(),
             }
         };
 
-        output_image
+        let stop_reason = failure
+            .into_inner()
+            .unwrap()
+            .or_else(|| (rendered.len() < bucket_count).then_some(StopReason::Interrupted));
+
+        (rendered, stop_reason)
+    }
+
+    /// Produces the image (and requested AOVs) from the samples taken for each pixel, filling
+    /// the given aborted pixels (with positions in the full image) with a debug color.
+    fn finish(&self, accumulation: &Accumulation, mut aborted_pixels: Vec<(u32, u32)>) -> Render {
+        // Pixels may be aborted in multiple passes of a progressive render, or out of order
+        aborted_pixels.sort_unstable_by_key(|&(col, row)| (row, col));
+        aborted_pixels.dedup();
+
+        let mut image = accumulation.to_image();
+        if let Some(ref rerender) = self.rerender {
+            rerender.composite(&mut image);
+        }
+        if let Some(palette) = self.config.palette {
+            palette.quantize(&mut image);
+        }
+        for &(col, row) in &aborted_pixels {
+            image.put_pixel(col, row - accumulation.row_offset(), ABORTED_PIXEL_COLOR);
+        }
+
+        // What is seen through the center of a pixel of the accumulation
+        let normal_transform = self
+            .config
+            .normal_space
+            .from_world(&self.scene.camera.inverse_view_matrix);
+        let center_hit = |col: u32, row: u32| {
+            let ray = self.camera_ray(row + accumulation.row_offset(), col, (0.5, 0.5));
+            self.scene
+                .intersect(&ray, RayKind::Primary)
+                .map(|intersection| CenterHit {
+                    shape_id: intersection.shape_id,
+                    normal: (normal_transform * intersection.component_intersection.normal)
+                        .truncate()
+                        .normalize_or_zero()
+                        .extend(0.0),
+                })
+        };
+
+        Render {
+            image,
+            aovs: self
+                .config
+                .aovs
+                .iter()
+                .map(|aov| {
+                    let encoding = aov.encoding(self.config.normal_encoding);
+                    (*aov, aov.image(accumulation, encoding, center_hit))
+                })
+                .collect(),
+            cost_report: self.config.cost_report.then(|| self.scene.cost_report()),
+            aborted_pixels,
+            ray_segments: std::mem::take(&mut self.ray_segments.lock().unwrap()),
+            scene_stats: self.scene.stats(),
+            scene_build_time: Duration::ZERO,
+        }
+    }
+}
+
+/// The message of a panic, given its payload.
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
     }
 }
@@ -0,0 +1,231 @@
+//! Single-file HTML reports of a render, which embed the image along with its AOVs, the
+//! settings it was rendered with, and where the time went, so that results can be shared
+//! (e.g. for course submissions) as a single file.
+
+use crate::cost::RayKind;
+use crate::raytracer::Render;
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageOutputFormat, Rgb32FImage, RgbImage};
+use std::fmt::Write;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+
+/// Largest width or height (in pixels) of the thumbnails of AOVs.
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Number of shapes listed in the table of the most expensive shapes.
+const REPORTED_SHAPE_COUNT: usize = 10;
+
+/// Everything about a finished render that is described in its report.
+pub struct RenderReport<'a> {
+    /// Path of the scenefile that was rendered.
+    pub scene: &'a Path,
+    /// Description of the configuration that the scene was rendered with.
+    pub settings: String,
+    pub render: &'a Render,
+    /// Time taken by the whole render, including building the scene.
+    pub total_time: Duration,
+}
+
+impl RenderReport<'_> {
+    /// Saves the report as an HTML file at the given path.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_html()?)
+            .with_context(|| format!("Failed to write render report: {}", path.display()))
+    }
+
+    /// Produces the HTML of the report, with its images embedded as data URLs.
+    pub fn to_html(&self) -> Result<String> {
+        let render = self.render;
+        let title = format!("Render of {}", escape(&self.scene.display().to_string()));
+        let mut html = String::new();
+
+        writeln!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>\nbody {{ font-family: sans-serif; margin: 2em; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             td, th {{ border: 1px solid #ccc; padding: 0.25em 0.75em; text-align: left; }}\n\
+             img {{ image-rendering: pixelated; }}\n\
+             figure {{ display: inline-block; margin: 0 1em 1em 0; }}\n</style>\n\
+             </head>\n<body>\n<h1>{}</h1>",
+            title, title
+        )?;
+
+        writeln!(
+            html,
+            "<img src=\"{}\" alt=\"Rendered image\">",
+            png_data_url(&render.image)?
+        )?;
+
+        if !render.aovs.is_empty() {
+            writeln!(html, "<h2>AOVs</h2>")?;
+            for (aov, aov_image) in &render.aovs {
+                writeln!(
+                    html,
+                    "<figure><img src=\"{}\" alt=\"{}\"><figcaption>{}</figcaption></figure>",
+                    png_data_url(&thumbnail(aov_image))?,
+                    aov.name(),
+                    aov.name()
+                )?;
+            }
+        }
+
+        let stats = &render.scene_stats;
+        let (width, height) = render.image.dimensions();
+        writeln!(html, "<h2>Scene</h2>\n<table>")?;
+        for (name, value) in [
+            ("Image size", format!("{} x {} pixels", width, height)),
+            ("Shapes", stats.shapes.to_string()),
+            ("Lights", stats.lights.to_string()),
+            (
+                "Scene size (bounding box diagonal)",
+                format!("{:.3}", stats.size),
+            ),
+            ("Aborted pixels", render.aborted_pixels.len().to_string()),
+        ] {
+            writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", name, value)?;
+        }
+        writeln!(html, "</table>")?;
+
+        let render_time = self.total_time.saturating_sub(render.scene_build_time);
+        writeln!(html, "<h2>Timing</h2>\n<table>")?;
+        for (name, time) in [
+            ("Building the scene", render.scene_build_time),
+            ("Rendering", render_time),
+            ("Total", self.total_time),
+        ] {
+            writeln!(
+                html,
+                "<tr><th>{}</th><td>{:.3}s</td></tr>",
+                name,
+                time.as_secs_f64()
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        if let Some(ref cost_report) = render.cost_report {
+            let total_seconds: f64 = cost_report.shapes.iter().map(|shape| shape.seconds).sum();
+
+            write!(
+                html,
+                "<h2>Most expensive shapes</h2>\n<table>\n<tr><th>Shape</th><th>Time</th>\
+                 <th>Share</th>"
+            )?;
+            for kind in RayKind::ALL {
+                write!(html, "<th>{} hits/tests</th>", kind.name())?;
+            }
+            writeln!(html, "</tr>")?;
+            for shape in cost_report.shapes.iter().take(REPORTED_SHAPE_COUNT) {
+                let share = if total_seconds > 0.0 {
+                    100.0 * shape.seconds / total_seconds
+                } else {
+                    0.0
+                };
+                write!(
+                    html,
+                    "<tr><td>{}</td><td>{:.3}s</td><td>{:.1}%</td>",
+                    escape(&shape.label),
+                    shape.seconds,
+                    share
+                )?;
+                for (hits, tests) in shape.hits.iter().zip(&shape.tests) {
+                    write!(html, "<td>{}/{}</td>", hits, tests)?;
+                }
+                writeln!(html, "</tr>")?;
+            }
+            writeln!(html, "</table>")?;
+        }
+
+        writeln!(
+            html,
+            "<h2>Settings</h2>\n<pre>{}</pre>\n</body>\n</html>",
+            escape(&self.settings)
+        )?;
+
+        Ok(html)
+    }
+}
+
+/// Shrinks an AOV to a thumbnail, scaling its values so that the largest is white (the
+/// values of AOVs are often far outside of 0-1).
+fn thumbnail(aov_image: &Rgb32FImage) -> RgbImage {
+    let max_value = aov_image
+        .pixels()
+        .flat_map(|pixel| pixel.0)
+        .fold(0f32, f32::max);
+    let scale = if max_value > 0.0 {
+        1.0 / max_value
+    } else {
+        1.0
+    };
+
+    let mut scaled = aov_image.clone();
+    for pixel in scaled.pixels_mut() {
+        pixel.0 = pixel.0.map(|value| value * scale);
+    }
+
+    let (width, height) = scaled.dimensions();
+    let image = DynamicImage::ImageRgb32F(scaled).into_rgb8();
+    if width.max(height) <= THUMBNAIL_SIZE {
+        image
+    } else {
+        let (thumbnail_width, thumbnail_height) = if width >= height {
+            let height = height as u64 * THUMBNAIL_SIZE as u64 / width as u64;
+            (THUMBNAIL_SIZE, (height as u32).max(1))
+        } else {
+            let width = width as u64 * THUMBNAIL_SIZE as u64 / height as u64;
+            ((width as u32).max(1), THUMBNAIL_SIZE)
+        };
+        imageops::resize(
+            &image,
+            thumbnail_width,
+            thumbnail_height,
+            FilterType::Triangle,
+        )
+    }
+}
+
+/// Encodes the image as a PNG data URL, which can be used as the source of an `<img>`.
+fn png_data_url(image: &RgbImage) -> Result<String> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+
+    Ok(format!("data:image/png;base64,{}", base64(&png)))
+}
+
+/// Encodes bytes in (standard, padded) base64.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (index, &byte)| {
+                group | (byte as u32) << (16 - 8 * index)
+            });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Escapes the characters of text that have special meanings in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
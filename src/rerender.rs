@@ -0,0 +1,93 @@
+//! Re-rendering only the pixels of selected objects of a previous render (e.g. after tweaking
+//! one of their materials), which are composited over the previous image.
+
+use crate::aov::{Aov, AovEncoding};
+use crate::partition::Partition;
+use anyhow::{bail, Context, Result};
+use image::{Rgb32FImage, RgbImage};
+use std::path::Path;
+
+/// A previous render, along with which of its pixels are re-rendered.
+#[derive(Debug)]
+pub struct Rerender {
+    previous: RgbImage,
+    /// Whether each pixel is re-rendered, row by row.
+    selected: Vec<bool>,
+}
+
+impl Rerender {
+    /// Loads the previous render of the given partition at the given path, along with its
+    /// object ID AOV (saved next to it), to re-render the pixels that show any of the given
+    /// objects (by their IDs in the AOV).
+    ///
+    /// The neighbours of those pixels are also re-rendered, as the samples of pixels at the
+    /// edges of an object may hit it even when the ray through their center doesn't.
+    pub fn load(path: &Path, objects: &[u32], partition: &Partition) -> Result<Self> {
+        let previous = image::open(path)
+            .with_context(|| format!("Failed to load previous render: {}", path.display()))?
+            .into_rgb8();
+
+        let object_ids_path = Aov::ObjectId.output_path(path, AovEncoding::Exr);
+        let object_ids: Rgb32FImage = image::open(&object_ids_path)
+            .with_context(|| {
+                format!(
+                    "Failed to load the object ID AOV of the previous render (rendered with \
+                     --aov object-id): {}",
+                    object_ids_path.display()
+                )
+            })?
+            .into_rgb32f();
+
+        let (width, height) = (partition.image_width, partition.rendered_height());
+        for (image_path, dimensions) in [
+            (path, previous.dimensions()),
+            (object_ids_path.as_path(), object_ids.dimensions()),
+        ] {
+            if dimensions != (width, height) {
+                bail!(
+                    "Previous render {} ({}x{}) does not match the image being rendered ({}x{})",
+                    image_path.display(),
+                    dimensions.0,
+                    dimensions.1,
+                    width,
+                    height
+                );
+            }
+        }
+
+        let shows_object = |col: u32, row: u32| {
+            let id = object_ids.get_pixel(col, row)[0];
+            objects.iter().any(|&object| id == object as f32)
+        };
+        // The positions within 1 pixel of the given one, along an axis of the given length
+        let neighbours =
+            |position: u32, length: u32| position.saturating_sub(1)..(position + 2).min(length);
+
+        let mut selected = Vec::with_capacity(width as usize * height as usize);
+        for row in 0..height {
+            for col in 0..width {
+                selected.push(
+                    neighbours(row, height)
+                        .any(|row| neighbours(col, width).any(|col| shows_object(col, row))),
+                );
+            }
+        }
+
+        Ok(Self { previous, selected })
+    }
+
+    /// Whether the pixel at the given column/row of the output image is re-rendered.
+    pub fn selects(&self, col: u32, row: u32) -> bool {
+        self.selected[(row * self.previous.width() + col) as usize]
+    }
+
+    /// Copies the pixels that aren't re-rendered from the previous render into the given
+    /// output image.
+    pub fn composite(&self, image: &mut RgbImage) {
+        for (col, row, pixel) in image.enumerate_pixels_mut() {
+            if !self.selects(col, row) {
+                *pixel = *self.previous.get_pixel(col, row);
+            }
+        }
+    }
+}
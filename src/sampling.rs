@@ -0,0 +1,237 @@
+//! Patterns for choosing where within each pixel the camera rays pass.
+
+use anyhow::{bail, Error, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+
+/// Width and height of the blue noise mask, which is tiled across the image.
+const BLUE_NOISE_MASK_SIZE: usize = 64;
+
+/// Standard deviation of the Gaussian used to measure how clustered the points of the
+/// mask are while it is being generated.
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Seed for the random initial pattern of the mask, so that the same mask is always used.
+const BLUE_NOISE_SEED: u64 = 0x5eed;
+
+/// Increments of the R2 low-discrepancy sequence, which spread successive samples of a
+/// pixel evenly over the pixel.
+const R2_INCREMENTS: (f32, f32) = (0.754_877_7, 0.569_840_3);
+
+/// How the offsets of the samples within each pixel are chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplePattern {
+    /// Independent uniformly random offsets (white noise).
+    Random,
+    /// Offsets from a tiled blue noise mask, so that neighbouring pixels have very different
+    /// offsets and the resulting noise has no low-frequency structure.
+    BlueNoise,
+}
+
+impl SamplePattern {
+    /// Names of all the sample patterns, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["random", "blue-noise"];
+}
+
+impl FromStr for SamplePattern {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "random" => Ok(SamplePattern::Random),
+            "blue-noise" => Ok(SamplePattern::BlueNoise),
+            other_name => bail!("Unknown sample pattern {}", other_name),
+        }
+    }
+}
+
+/// A square of values in [0, 1) whose distribution is blue noise: values that are close
+/// together (in value) are far apart in the mask.
+#[derive(Debug)]
+pub struct BlueNoiseMask {
+    values: Vec<f32>,
+}
+
+impl BlueNoiseMask {
+    /// Generates a mask using the void-and-cluster method (Ulichney, 1993), which ranks each
+    /// cell of the mask by repeatedly filling in the largest void in the pattern of cells
+    /// ranked so far (or removing the tightest cluster of them).
+    pub fn generate() -> Self {
+        let size = BLUE_NOISE_MASK_SIZE;
+        let cell_count = size * size;
+
+        // Gaussian weight between two cells, by their (wrapped) offset from each other
+        let kernel: Vec<f32> = (0..cell_count)
+            .map(|offset| {
+                let wrapped = |d: usize| d.min(size - d) as f32;
+                let (dx, dy) = (wrapped(offset % size), wrapped(offset / size));
+                (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+            })
+            .collect();
+
+        let mut pattern = Pattern {
+            size,
+            points: vec![false; cell_count],
+            energy: vec![0.0; cell_count],
+            kernel,
+        };
+
+        // Start with a random tenth of the cells, then move points from the tightest cluster
+        // to the largest void until that no longer changes anything (or is evidently cycling)
+        let mut rng = StdRng::seed_from_u64(BLUE_NOISE_SEED);
+        let initial_count = cell_count / 10;
+        while pattern.points.iter().filter(|&&point| point).count() < initial_count {
+            pattern.toggle(rng.gen_range(0..cell_count));
+        }
+
+        for _ in 0..cell_count {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+
+            if void == cluster {
+                pattern.toggle(cluster);
+                break;
+            }
+            pattern.toggle(void);
+        }
+
+        let mut ranks = vec![0; cell_count];
+
+        // Rank the initial points by removing them from the tightest clusters first
+        let initial_points = pattern.points.clone();
+        let initial_energy = pattern.energy.clone();
+        for rank in (0..initial_count).rev() {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            ranks[cluster] = rank;
+        }
+
+        // Rank the remaining cells by filling in the largest voids first
+        pattern.points = initial_points;
+        pattern.energy = initial_energy;
+        for rank in initial_count..cell_count {
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            ranks[void] = rank;
+        }
+
+        Self {
+            values: ranks
+                .into_iter()
+                .map(|rank| (rank as f32 + 0.5) / cell_count as f32)
+                .collect(),
+        }
+    }
+
+    /// The offset (between 0-1 in each direction) within the pixel at the given column/row
+    /// of the given sample of that pixel.
+    pub fn offset(&self, col: u32, row: u32, sample: u32) -> (f32, f32) {
+        let size = BLUE_NOISE_MASK_SIZE as u32;
+        let value = |col: u32, row: u32| self.values[((row % size) * size + col % size) as usize];
+
+        // Use a shifted copy of the mask for the second dimension, so that it is decorrelated
+        // from the first, and step successive samples along the R2 sequence
+        let x = value(col, row) + sample as f32 * R2_INCREMENTS.0;
+        let y = value(col + size / 2, row + size / 2) + sample as f32 * R2_INCREMENTS.1;
+
+        (x.fract(), y.fract())
+    }
+}
+
+/// The offset within a pixel of one of its samples on a regular grid, which has enough cells
+/// for all of the pixel's samples, filled row by row. The same offsets are used for every
+/// pixel, so that renders are deterministic (a single sample goes through the center).
+pub fn grid_offset(sample: u32, samples: u32) -> (f32, f32) {
+    let cells_per_side = (samples as f32).sqrt().ceil().max(1.0) as u32;
+    let cell = sample % (cells_per_side * cells_per_side);
+
+    (
+        ((cell % cells_per_side) as f32 + 0.5) / cells_per_side as f32,
+        ((cell / cells_per_side) as f32 + 0.5) / cells_per_side as f32,
+    )
+}
+
+/// A binary pattern of points over the cells of a (wrapping) square, which tracks the energy
+/// (the sum of the Gaussian weights of all points) at every cell.
+struct Pattern {
+    size: usize,
+    points: Vec<bool>,
+    energy: Vec<f32>,
+    kernel: Vec<f32>,
+}
+
+impl Pattern {
+    /// Adds or removes the point at the given cell.
+    fn toggle(&mut self, cell: usize) {
+        let size = self.size;
+        self.points[cell] = !self.points[cell];
+        let sign = if self.points[cell] { 1.0 } else { -1.0 };
+
+        let (x, y) = (cell % size, cell / size);
+        for (other, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (other % size + size - x) % size;
+            let dy = (other / size + size - y) % size;
+            *energy += sign * self.kernel[dy * size + dx];
+        }
+    }
+
+    /// The point with the highest energy.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme_cell(true, |a, b| a > b)
+    }
+
+    /// The empty cell with the lowest energy.
+    fn largest_void(&self) -> usize {
+        self.extreme_cell(false, |a, b| a < b)
+    }
+
+    /// Finds the cell with (or without) a point whose energy is most extreme by the given
+    /// comparison.
+    fn extreme_cell(&self, has_point: bool, is_better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best: Option<usize> = None;
+
+        for (cell, &energy) in self.energy.iter().enumerate() {
+            if self.points[cell] == has_point
+                && best.map_or(true, |b| is_better(energy, self.energy[b]))
+            {
+                best = Some(cell);
+            }
+        }
+
+        best.expect("pattern must have a cell of the requested kind")
+    }
+}
+
+/// Source of the random numbers used while tracing a single camera ray (e.g. to decide
+/// whether a ray passes through a partially transparent surface).
+#[derive(Debug)]
+pub struct Sampler {
+    rng: rand::rngs::ThreadRng,
+}
+
+impl Sampler {
+    /// Constructs a sampler which draws from the current thread's random number generator.
+    pub fn new() -> Self {
+        Self {
+            rng: rand::thread_rng(),
+        }
+    }
+
+    /// Draws a value uniformly distributed in [0, 1).
+    pub fn next_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    /// Draws a pair of independent values, each uniformly distributed in [0, 1).
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,85 @@
+//! Automatic level of detail: shapes that appear tiny in the image are replaced by cheaper
+//! proxies, or left out of the scene entirely, when it is flattened.
+
+use super::{Camera, ParsedShape, PrimitiveType};
+use crate::bounding_box::BoundingBox;
+use crate::math::{Mat4, Vec3};
+use std::f32::consts::PI;
+
+/// Settings for simplifying shapes by their size in the image, in pixels (measured as the
+/// projected diameter of their bounding spheres).
+#[derive(Debug, Clone, Copy)]
+pub struct LevelOfDetail {
+    /// Shapes smaller than this are replaced by sphere proxies.
+    pub proxy_size: f32,
+    /// Shapes smaller than this (if given) are left out entirely.
+    pub cull_size: Option<f32>,
+    /// Height of the image, in pixels.
+    pub image_height: u32,
+}
+
+/// How a shape is added to a flattened scene.
+pub(super) enum Detail {
+    Full,
+    /// The shape is replaced by the given shape (a sphere), with the given CTM.
+    Proxy(ParsedShape, Mat4),
+    Culled,
+}
+
+impl LevelOfDetail {
+    /// Chooses how to add the given shape, whose world space bounding box is given, to a
+    /// scene seen from the given camera.
+    pub(super) fn choose(
+        &self,
+        shape: &ParsedShape,
+        bounds: &BoundingBox,
+        camera: &Camera,
+    ) -> Detail {
+        if bounds.is_empty() {
+            return Detail::Full;
+        }
+
+        let center = bounds.centroid();
+        let radius = bounds.diagonal_length() / 2.0;
+        let distance = (center - camera.inverse_view_matrix.w_axis.truncate()).length();
+        if distance <= radius {
+            // The camera is within the shape's bounding sphere, so the shape may fill the image
+            return Detail::Full;
+        }
+
+        // Height of a pixel at the distance of the shape
+        let pixel_size =
+            distance * 2.0 * (camera.height_angle / 2.0).tan() / self.image_height as f32;
+        let projected_size = 2.0 * radius / pixel_size;
+
+        if self
+            .cull_size
+            .map_or(false, |cull_size| projected_size < cull_size)
+        {
+            Detail::Culled
+        } else if projected_size < self.proxy_size {
+            Detail::Proxy(
+                ParsedShape {
+                    label: format!("{} (proxy)", shape.label),
+                    primitive_type: PrimitiveType::Sphere,
+                    ..shape.clone()
+                },
+                proxy_ctm(bounds, center, radius),
+            )
+        } else {
+            Detail::Full
+        }
+    }
+}
+
+/// The CTM of the unit-diameter sphere that stands in for a shape with the given bounds. The
+/// sphere covers the same area of the image as the bounding box would on average (a convex
+/// shape's average projected area is a quarter of its surface area), so that the proxies of
+/// tiny shapes don't make them look bigger than they are.
+fn proxy_ctm(bounds: &BoundingBox, center: Vec3, bounding_radius: f32) -> Mat4 {
+    let size = bounds.max - bounds.min;
+    let surface_area = 2.0 * (size.x * size.y + size.y * size.z + size.z * size.x);
+    let radius = (surface_area / (4.0 * PI)).sqrt().min(bounding_radius);
+
+    Mat4::from_translation(center) * Mat4::from_scale(Vec3::splat(2.0 * radius))
+}
@@ -1,38 +1,75 @@
 //! Module for representation of scenes, as well as the parser that converts XML into this representation.
 
-use crate::lights::Light;
-use crate::primitive::{
-    Axis, Circle, ConeBody, CylinderBody, Plane, Primitive, PrimitiveComponent, Sphere, Square,
+use crate::accelerator::{Accelerator, AcceleratorKind, Bvh};
+use crate::bounding_box::BoundingBox;
+use crate::cost::{CostReport, RayKind};
+use crate::environment::Environment;
+use crate::intersection::Intersection;
+use crate::lights::{self, Light};
+use crate::math::{vec4, Mat4, Quat, Vec3, Vec4};
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::primitive::{self, Primitive, Sweep};
+use crate::raytracer::Ray;
+use crate::shape::Shapes;
+use crate::texture::{
+    AtlasTexture, ColorSpace, ImageTexture, TextureCache, TextureSource, WrapMode,
+    MIN_ATLAS_TEXTURES,
 };
-use crate::shape::Shape;
-use image::RgbImage;
-use num_traits::identities::One;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use crate::user_primitive::{UserPrimitive, UserPrimitiveFactory};
+use anyhow::bail;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
 use std::path::PathBuf;
-use std::rc::Rc;
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
+mod lod;
+mod mtl;
+mod overrides;
 mod parser;
+mod patch;
+mod query;
 
-#[derive(Debug)]
+use lod::Detail;
+pub use lod::LevelOfDetail;
+pub use overrides::{MaterialOverride, MaterialOverrides};
+pub use patch::ScenePatch;
+pub use query::ShapeQuery;
+
+#[derive(Debug, Clone)]
 pub struct GlobalLightingCoefficients {
     pub ka: f32,
     pub kd: f32,
     pub ks: f32,
 }
 
-#[derive(Debug)]
+/// Tolerances of the intersection tests of a scene, which can be tuned (with tags in the
+/// `<globaldata>` of its scenefile) to fix precision artifacts such as shadow acne.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tolerances {
+    /// Smallest t-value at which camera rays can hit a surface.
+    pub min_t: f32,
+    /// Distance along secondary (shadow, reflected, and transmitted) rays from the surface
+    /// that they leave, within which they can't hit anything. If not given, this is derived
+    /// from the size of the scene.
+    pub self_intersect_offset: Option<f32>,
+    /// Largest component (along a plane's normal) of a unit ray direction at which the ray
+    /// is considered parallel to the plane, and so never hits it.
+    pub parallel_threshold: f32,
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
-    position: glm::Vector4<f32>,
-    look: glm::Vector4<f32>,
-    up: glm::Vector4<f32>,
+    position: Vec4,
+    look: Vec4,
+    up: Vec4,
     pub height_angle: f32,
-    pub inverse_view_matrix: glm::Mat4,
+    pub inverse_view_matrix: Mat4,
 }
 
 impl Camera {
-    pub fn new(position: glm::Vec4, look: glm::Vec4, up: glm::Vec4, height_angle: f32) -> Self {
+    pub fn new(position: Vec4, look: Vec4, up: Vec4, height_angle: f32) -> Self {
         Self {
             position,
             look,
@@ -42,165 +79,1070 @@ impl Camera {
         }
     }
 
-    fn calculate_inverse_view_matrix(
-        position: glm::Vec4,
-        look: glm::Vec4,
-        up: glm::Vec4,
-    ) -> glm::Mat4 {
-        let w = glm::normalize(-look).truncate(3);
-        let v = glm::normalize(up.truncate(3) - (w * glm::dot(up.truncate(3), w)));
-        let u = glm::cross(v, w);
+    fn calculate_inverse_view_matrix(position: Vec4, look: Vec4, up: Vec4) -> Mat4 {
+        let w = (-look).normalize().truncate();
+        let v = (up.truncate() - (w * up.truncate().dot(w))).normalize();
+        let u = v.cross(w);
 
-        let rotation_matrix = glm::Mat4::new(
-            glm::vec4(u.x, v.x, w.x, 0.0),
-            glm::vec4(u.y, v.y, w.y, 0.0),
-            glm::vec4(u.z, v.z, w.z, 0.0),
-            glm::vec4(0.0, 0.0, 0.0, 1.0),
+        let rotation_matrix = Mat4::from_cols(
+            vec4(u.x, v.x, w.x, 0.0),
+            vec4(u.y, v.y, w.y, 0.0),
+            vec4(u.z, v.z, w.z, 0.0),
+            vec4(0.0, 0.0, 0.0, 1.0),
         );
 
-        let translation_matrix = glm::Mat4::new(
-            glm::vec4(1.0, 0.0, 0.0, 0.0),
-            glm::vec4(0.0, 1.0, 0.0, 0.0),
-            glm::vec4(0.0, 0.0, 1.0, 0.0),
-            glm::vec4(-position.x, -position.y, -position.z, 1.0),
+        let translation_matrix = Mat4::from_cols(
+            vec4(1.0, 0.0, 0.0, 0.0),
+            vec4(0.0, 1.0, 0.0, 0.0),
+            vec4(0.0, 0.0, 1.0, 0.0),
+            vec4(-position.x, -position.y, -position.z, 1.0),
         );
 
         let rotate_and_translate_matrix = rotation_matrix * translation_matrix;
 
-        glm::inverse(&rotate_and_translate_matrix)
+        rotate_and_translate_matrix.inverse()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Texture {
-    pub filename: PathBuf,
+    /// Path of the texture's image file, unless it uses a texture source registered with
+    /// the scene builder.
+    pub filename: Option<PathBuf>,
+    /// Where the texture's values come from. Image files are loaded when the scene is built
+    /// (and shared by all textures with the same filename).
+    pub source: OnceLock<Arc<dyn TextureSource>>,
     pub repeat_u: f32,
     pub repeat_v: f32,
+    /// Color space of the texture's image file, or `None` to use the scene's default.
+    pub color_space: Option<ColorSpace>,
+    /// How the texture is extended beyond its edges when it repeats.
+    pub wrap: WrapMode,
     pub blend: f32,
 }
 
+impl Texture {
+    /// The value of the texture at a point with the given UV coordinate (wrapped according to
+    /// how many times the texture repeats) and object space position.
+    pub fn value(&self, (u, v): (f32, f32), object_point: Vec3) -> Vec4 {
+        let source = self.source.get().expect("Tried to access unloaded texture");
+
+        source.value_at(
+            (
+                self.wrap.wrap(u * self.repeat_u),
+                self.wrap.wrap(v * self.repeat_v),
+            ),
+            object_point,
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Material {
-    pub ambient: glm::Vector4<f32>,
-    pub diffuse: glm::Vector4<f32>,
-    pub specular: glm::Vector4<f32>,
+    pub ambient: Vec4,
+    pub diffuse: Vec4,
+    pub specular: Vec4,
     pub shininess: f32,
-    pub reflective: glm::Vector4<f32>,
+    pub reflective: Vec4,
+    /// Color that the surface glows with, which is added to its illumination whatever the
+    /// lights (though the surface doesn't light anything else).
+    pub emissive: Vec4,
     pub texture: Option<Texture>,
+    /// Texture whose color multiplies the specular color at each point, so that parts of the
+    /// surface can be dull and others polished.
+    pub specular_map: Option<Texture>,
+    /// Texture whose brightness (the average of its color channels, from 0 to 1) multiplies
+    /// the shininess at each point.
+    pub shininess_map: Option<Texture>,
+    /// How much the material blocks light passing through it, from 0 (fully transparent) to 1
+    /// (fully opaque).
+    pub opacity: f32,
+    /// Name by which the material can be referred to by material overrides.
+    pub name: Option<String>,
+    /// Index of refraction, from which the surface's reflectance is found with
+    /// `--reflection-model dielectric`.
+    pub ior: Option<f32>,
+    /// How rough the surface is, from 0 (a mirror) to 1, which blurs its reflection of the
+    /// scene's environment.
+    pub roughness: f32,
+    /// Another material mixed over this one (e.g. rust over metal), which may itself have a
+    /// layer, so that a material is a chain of mixes rather than a single set of properties.
+    pub layer: Option<Box<MaterialLayer>>,
 }
 
-#[derive(Debug)]
+impl Material {
+    /// All of the material's textures (its color texture, any maps, and those of its layers).
+    pub fn textures(&self) -> Box<dyn Iterator<Item = &Texture> + '_> {
+        let own = [&self.texture, &self.specular_map, &self.shininess_map]
+            .into_iter()
+            .flatten();
+
+        match self.layer {
+            Some(ref layer) => Box::new(own.chain(&layer.mask).chain(layer.material.textures())),
+            None => Box::new(own),
+        }
+    }
+}
+
+/// A material mixed over another, covering it by a constant factor and (optionally) a mask
+/// texture.
+#[derive(Debug, Clone)]
+pub struct MaterialLayer {
+    pub material: Material,
+    /// How much of the material beneath it the layer covers, from 0 (none) to 1 (all of it).
+    pub factor: f32,
+    /// Texture whose brightness (the average of its color channels, from 0 to 1) multiplies
+    /// the factor at each point.
+    pub mask: Option<Texture>,
+}
+
+#[derive(Debug, Clone)]
 pub enum PrimitiveType {
-    Cone,
+    /// A cone, which is open-ended (has no base) unless `capped` is set.
+    Cone {
+        capped: bool,
+    },
     Cube,
-    Cylinder,
+    /// A cylinder, which is open-ended (has no top or bottom) unless `capped` is set.
+    Cylinder {
+        capped: bool,
+    },
     Sphere,
+    /// A primitive whose geometry is specific to the shape (e.g. a mesh loaded from
+    /// a file, or a lathe with a given profile), which is constructed during parsing.
+    Custom(Arc<Primitive>),
 }
 
-#[derive(Debug)]
+/// How a shape treats hits on the back of its surface (where the ray travels along its
+/// normal), e.g. on the inside of a sphere that the camera is within.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backfaces {
+    /// Backfaces are shaded with the normal facing away from the ray (so they are usually
+    /// dark, as lights in front of them are behind the surface).
+    #[default]
+    Single,
+    /// Backfaces are shaded like front faces, with the normal flipped toward the ray.
+    Double,
+    /// Backfaces are invisible, so rays pass through them (including shadow rays).
+    Cull,
+}
+
+impl Backfaces {
+    /// Names of all the modes, as accepted by the `backfaces` attribute of a primitive.
+    pub const NAMES: &'static [&'static str] = &["single", "double", "cull"];
+}
+
+impl FromStr for Backfaces {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "single" => Ok(Backfaces::Single),
+            "double" => Ok(Backfaces::Double),
+            "cull" => Ok(Backfaces::Cull),
+            other_name => bail!("Unknown backfaces mode {}", other_name),
+        }
+    }
+}
+
+/// A mapping of UV coordinates projected from the object space position of each point on a
+/// shape, which replaces the shape's natural mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UvProjection {
+    /// Projected along the z axis onto the xy-plane, so that the unit square centered on the
+    /// origin covers the texture once.
+    Planar,
+    /// Projected from the origin onto a sphere, with u around the y axis and v from the
+    /// bottom to the top (as on the sphere primitive).
+    Spherical,
+    /// Projected from the y axis onto a cylinder, with u around the y axis and v from
+    /// y = -0.5 to y = 0.5 (as on the cylinder primitive).
+    Cylindrical,
+}
+
+impl UvProjection {
+    /// Names of all the projections, as accepted by the `projection` attribute of a primitive.
+    pub const NAMES: &'static [&'static str] = &["planar", "spherical", "cylindrical"];
+
+    /// The UV coordinate of the point with the given object space position.
+    pub fn uv(&self, point: Vec3) -> (f32, f32) {
+        match self {
+            UvProjection::Planar => (point.x + 0.5, point.y + 0.5),
+            UvProjection::Spherical => {
+                let direction = point.normalize_or_zero();
+                (
+                    primitive::revolution_u(&point.extend(1.0)),
+                    direction.y.clamp(-1.0, 1.0).asin() / PI + 0.5,
+                )
+            }
+            UvProjection::Cylindrical => {
+                (primitive::revolution_u(&point.extend(1.0)), point.y + 0.5)
+            }
+        }
+    }
+}
+
+impl FromStr for UvProjection {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "planar" => Ok(UvProjection::Planar),
+            "spherical" => Ok(UvProjection::Spherical),
+            "cylindrical" => Ok(UvProjection::Cylindrical),
+            other_name => bail!("Unknown UV projection {}", other_name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ParsedShape {
-    pub material: Material,
+    pub material: Arc<Material>,
+    /// Description of the shape, e.g. "cone #2 in object table".
+    pub label: String,
+    /// Name of the object whose definition the shape is in, e.g. "table".
+    pub object: Arc<str>,
+    /// Name of the shape's primitive in the scenefile, e.g. "cone" or "mesh".
+    pub primitive_name: Arc<str>,
     pub primitive_type: PrimitiveType,
+    /// Position of the shape among all of the shapes in the scenefile, which decides which of
+    /// two shapes is hit when a ray hits both at exactly the same distance.
+    pub id: usize,
+    /// Distance by which hits on the shape are brought toward the origin of the ray, so that
+    /// it is drawn over a coplanar surface (e.g. a decal on the face of a cube).
+    pub depth_offset: f32,
+    pub backfaces: Backfaces,
+    /// Projection that replaces the natural UV mapping of the shape's primitive, if any.
+    pub uv_projection: Option<UvProjection>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Transformation {
-    Translate(glm::Vector3<f32>),
-    Scale(glm::Vector3<f32>),
-    Rotate(glm::Vector3<f32>, f32),
-    Matrix(glm::Mat4),
+    Translate(Vec3),
+    Scale(Vec3),
+    Rotate(Vec3, f32),
+    /// A rotation described by a unit quaternion.
+    RotateQuaternion(Quat),
+    /// Places an object at `eye`, oriented (like the camera) so that its -z axis points
+    /// toward `target` and its +y axis is as close to `up` as possible.
+    LookAt {
+        eye: Vec3,
+        target: Vec3,
+        up: Vec3,
+    },
+    Matrix(Mat4),
 }
 
 impl Transformation {
-    fn apply_matrix(&self, ctm: &glm::Mat4) -> glm::Mat4 {
+    fn apply_matrix(&self, ctm: &Mat4) -> Mat4 {
         match self {
-            Transformation::Translate(translation) => glm::ext::translate(ctm, *translation),
-            Transformation::Rotate(axis, angle) => glm::ext::rotate(ctm, *angle, *axis),
-            Transformation::Scale(scale_factors) => glm::ext::scale(ctm, *scale_factors),
+            Transformation::Translate(translation) => *ctm * Mat4::from_translation(*translation),
+            Transformation::Rotate(axis, angle) => {
+                *ctm * Mat4::from_axis_angle(axis.normalize(), *angle)
+            }
+            Transformation::RotateQuaternion(quaternion) => *ctm * Mat4::from_quat(*quaternion),
+            Transformation::LookAt { eye, target, up } => {
+                *ctm * Camera::calculate_inverse_view_matrix(
+                    eye.extend(1.0),
+                    (*target - *eye).extend(0.0),
+                    up.extend(0.0),
+                )
+            }
+            Transformation::Scale(scale_factors) => *ctm * Mat4::from_scale(*scale_factors),
             Transformation::Matrix(matrix) => *matrix,
         }
     }
 }
 
-#[derive(Debug, Default)]
+/// A node of the scene graph as it is parsed. Nodes are immutable once they have been
+/// parsed, so that those of a master object can be shared by every instance of it.
+#[derive(Debug, Default, Clone)]
 struct Node {
+    /// Name of the object that this node is the top of, if any.
+    name: Option<String>,
     transformations: Vec<Transformation>,
     shapes: Vec<ParsedShape>,
-    children: Vec<Rc<RefCell<Node>>>,
+    children: Vec<Arc<Node>>,
 }
 
-#[derive(Debug)]
+impl Node {
+    /// Computes the transformation that this node applies to its shapes and children,
+    /// relative to the coordinate space of its parent.
+    fn local_transformation(&self) -> Mat4 {
+        self.transformations
+            .iter()
+            .fold(Mat4::IDENTITY, |ctm, transformation| {
+                transformation.apply_matrix(&ctm)
+            })
+    }
+}
+
+/// Parses scenefiles which may use primitives defined outside of this crate, as well as the
+/// built-in ones.
+#[derive(Default)]
+pub struct SceneBuilder {
+    /// Factories for the user-defined primitives, by the name used for them in scenefiles.
+    user_primitives: HashMap<String, Arc<UserPrimitiveFactory>>,
+    /// User-defined texture sources, by the name used for them in scenefiles.
+    texture_sources: HashMap<String, Arc<dyn TextureSource>>,
+}
+
+impl SceneBuilder {
+    /// Constructs a builder which only knows about the built-in primitives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a kind of user-defined primitive, which `<primitive>` tags with the given
+    /// name are then constructed with (passing the factory the tag's attributes). The names
+    /// of the built-in primitives always refer to the built-in ones.
+    pub fn register_primitive<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: Fn(&HashMap<String, String>) -> anyhow::Result<Arc<dyn UserPrimitive>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.user_primitives
+            .insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    /// Registers a texture source (e.g. a procedural texture), which `<texture>` tags can
+    /// then use instead of an image file by giving its name as their "source" attribute.
+    pub fn register_texture(mut self, name: &str, source: impl TextureSource + 'static) -> Self {
+        self.texture_sources
+            .insert(name.to_string(), Arc::new(source));
+        self
+    }
+}
+
+impl std::fmt::Debug for SceneBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SceneBuilder")
+            .field("user_primitives", &self.user_primitives.keys())
+            .field("texture_sources", &self.texture_sources)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TreeScene {
     global_lighting_coefficients: GlobalLightingCoefficients,
+    tolerances: Tolerances,
     camera: Camera,
     lights: Vec<Light>,
     root_node: Node,
+    /// Color space of the image textures that aren't given one in the scenefile.
+    texture_color_space: ColorSpace,
+    /// Cache that image textures are lazily loaded into, if they aren't all loaded up front.
+    texture_cache: Option<Arc<TextureCache>>,
+    environment: Option<Arc<Environment>>,
+}
+
+/// A named object of a scene, along with the named objects within it.
+#[derive(Debug)]
+pub struct ObjectOutline {
+    pub name: String,
+    /// Number of shapes directly within the object (not within other named objects).
+    pub shape_count: usize,
+    /// Material of the first shape directly within the object, if it has any shapes.
+    pub material: Option<Arc<Material>>,
+    /// Named objects within this one, which are left empty for objects that are instanced
+    /// more than once after their first appearance.
+    pub children: Vec<ObjectOutline>,
+}
+
+impl TreeScene {
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut [Light] {
+        &mut self.lights
+    }
+
+    pub fn tolerances_mut(&mut self) -> &mut Tolerances {
+        &mut self.tolerances
+    }
+
+    /// Sets the color space of the image textures that aren't given one in the scenefile
+    /// (linear by default).
+    pub fn set_texture_color_space(&mut self, color_space: ColorSpace) {
+        self.texture_color_space = color_space;
+    }
+
+    /// Makes image textures load when they are first sampled, into a cache that evicts the
+    /// least recently used images once they take up more than the given memory (in bytes),
+    /// rather than all being loaded when the scene is built.
+    pub fn set_texture_cache_capacity(&mut self, capacity: usize) {
+        self.texture_cache = Some(Arc::new(TextureCache::new(capacity)));
+    }
+
+    /// Scales the light of the scene's environment (or sky) by the given factor, and turns it
+    /// by the given angle (in radians) around the y axis, on top of the intensity and rotation
+    /// given in the scenefile. Scenes without an environment are unaffected.
+    pub fn adjust_environment(&mut self, intensity: f32, rotation: f32) {
+        if let Some(ref mut environment) = self.environment {
+            Arc::make_mut(environment).adjust(intensity, rotation);
+        }
+    }
+
+    /// Outlines the hierarchy of named objects in the scene, starting from the root object.
+    pub fn outline(&self) -> ObjectOutline {
+        let mut outline = ObjectOutline {
+            name: self.root_node.name.clone().unwrap_or_default(),
+            shape_count: 0,
+            material: None,
+            children: Vec::new(),
+        };
+        Self::outline_node(&self.root_node, &mut outline, &mut HashSet::new());
+
+        outline
+    }
+
+    /// Adds the contents of the given node to the outline of the object it belongs to.
+    fn outline_node(node: &Node, outline: &mut ObjectOutline, seen: &mut HashSet<*const Node>) {
+        outline.shape_count += node.shapes.len();
+        if outline.material.is_none() {
+            outline.material = node.shapes.first().map(|shape| Arc::clone(&shape.material));
+        }
+
+        for child in &node.children {
+            let first_appearance = seen.insert(Arc::as_ptr(child));
+
+            match child.name {
+                Some(ref name) => {
+                    let mut child_outline = ObjectOutline {
+                        name: name.clone(),
+                        shape_count: 0,
+                        material: None,
+                        children: Vec::new(),
+                    };
+                    if first_appearance {
+                        Self::outline_node(child, &mut child_outline, seen);
+                    }
+                    outline.children.push(child_outline);
+                }
+                None => Self::outline_node(child, outline, seen),
+            }
+        }
+    }
+}
+
+/// A node of the scene graph as it is kept at render time when the transform
+/// hierarchy is preserved. Rays are transformed down the tree into each node's
+/// coordinate space, and nodes instanced from the same master object are shared.
+#[derive(Debug)]
+pub struct HierarchyNode {
+    /// Transformation from this node's coordinate space into its parent's.
+    transformation: Mat4,
+    /// Inverse of the transformation, cached here for performance reasons.
+    inverse_transformation: Mat4,
+    /// Shapes at this node, whose CTMs are relative to this node's coordinate space.
+    shapes: Shapes,
+    children: Vec<Arc<HierarchyNode>>,
+}
+
+impl HierarchyNode {
+    /// Converts a node of the tree scene into a hierarchy node, reusing the already-converted
+    /// node for any tree node that has been seen before (i.e. an instanced master object).
+    fn from_node(
+        node: &Node,
+        primitives: &Primitives,
+        converted: &mut HashMap<*const Node, Arc<HierarchyNode>>,
+    ) -> Self {
+        let transformation = node.local_transformation();
+
+        let mut shapes = Shapes::default();
+        for parsed_shape in &node.shapes {
+            shapes.push(parsed_shape, primitives, Mat4::IDENTITY);
+        }
+
+        let children = node
+            .children
+            .iter()
+            .map(|child| {
+                if let Some(existing) = converted.get(&Arc::as_ptr(child)) {
+                    return Arc::clone(existing);
+                }
+
+                let child_node = Arc::new(HierarchyNode::from_node(child, primitives, converted));
+                converted.insert(Arc::as_ptr(child), Arc::clone(&child_node));
+                child_node
+            })
+            .collect();
+
+        Self {
+            transformation,
+            inverse_transformation: transformation.inverse(),
+            shapes,
+            children,
+        }
+    }
+
+    /// Finds the closest intersection between the given ray (in the parent's coordinate
+    /// space) and any shape in this subtree, with the normal in the parent's coordinate space.
+    /// The cost of the tests is recorded against the kind of the ray, if one is given.
+    fn intersect(&self, ray: &Ray, kind: Option<RayKind>) -> Option<Intersection> {
+        let local_ray = ray.to_object_space(&self.inverse_transformation);
+
+        let mut closest_intersection = (0..self.shapes.len())
+            .flat_map(|index| self.shapes.intersect_tracked(index, &local_ray, kind))
+            .chain(
+                self.children
+                    .iter()
+                    .flat_map(|child| child.intersect(&local_ray, kind)),
+            )
+            .min()?;
+
+        closest_intersection
+            .component_intersection
+            .transform(&self.transformation);
+
+        Some(closest_intersection)
+    }
+
+    /// The bounding box of all shapes in this subtree, in the parent's coordinate space.
+    fn bounds(&self) -> BoundingBox {
+        self.shapes
+            .all_bounds()
+            .into_iter()
+            .chain(self.children.iter().map(|child| child.bounds()))
+            .fold(BoundingBox::empty(), |bounds, child_bounds| {
+                bounds.union(&child_bounds)
+            })
+            .transformed(&self.transformation)
+    }
+
+    /// Collects the shapes of the nodes in this subtree, including the shapes of shared
+    /// subtrees only once.
+    fn collect_unique_shapes<'a>(
+        &'a self,
+        shapes: &mut Vec<&'a Shapes>,
+        visited: &mut HashSet<*const HierarchyNode>,
+    ) {
+        shapes.push(&self.shapes);
+
+        for child in &self.children {
+            if visited.insert(Arc::as_ptr(child)) {
+                child.collect_unique_shapes(shapes, visited);
+            }
+        }
+    }
+}
+
+/// Counts of the contents of a built scene.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneStats {
+    /// Number of shapes, where the instances of a shared object are only counted once if
+    /// the hierarchy is preserved.
+    pub shapes: usize,
+    pub lights: usize,
+    /// Length of the diagonal of the scene's bounding box.
+    pub size: f32,
 }
 
 #[derive(Debug)]
 pub struct Scene {
     pub global_lighting_coefficients: GlobalLightingCoefficients,
+    pub tolerances: Tolerances,
     pub camera: Camera,
     pub lights: Vec<Light>,
-    pub shapes: Vec<Shape>,
-    pub textures: HashMap<PathBuf, RgbImage>,
+    /// All shapes in the scene with flattened CTMs. Empty if the hierarchy is preserved.
+    pub shapes: Shapes,
+    /// Acceleration structure over `shapes`, which all rays are intersected through.
+    accelerator: Box<dyn Accelerator>,
+    /// Kind of the acceleration structure, with which it is rebuilt if it can't be refit.
+    accelerator_kind: AcceleratorKind,
+    /// The transform hierarchy of the scene, if it has been preserved rather than flattened.
+    pub hierarchy: Option<HierarchyNode>,
+    /// Whether to record the cost of intersecting rays with each shape. The costs accumulate
+    /// over every render of the scene.
+    pub track_costs: bool,
+    /// Offset from a point of intersection that recursive rays are fired from, scaled to
+    /// the size of the scene.
+    pub self_intersect_offset: f32,
+    /// Length of the diagonal of the scene's bounding box.
+    pub size: f32,
+    /// Color space of the image textures that aren't given one in the scenefile.
+    texture_color_space: ColorSpace,
+    /// Cache that image textures are lazily loaded into, if any.
+    texture_cache: Option<Arc<TextureCache>>,
+    /// Light arriving from far away in every direction, which rays that miss the scene see
+    /// (rather than black), and which lights surfaces in place of their ambient color.
+    pub environment: Option<Arc<Environment>>,
 }
 
 impl Scene {
-    fn traverse_tree_scene<N>(
-        node: N,
+    /// Adds the shapes of the given node and its descendants to the list of shapes, with the
+    /// given CTM of the node's parent, simplifying them by the given level of detail (if any)
+    /// as seen from the camera.
+    fn traverse_tree_scene(
+        node: &Node,
         primitives: &Primitives,
-        shapes: &mut Vec<Shape>,
-        mut ctm: glm::Mat4,
-    ) where
-        N: std::ops::Deref<Target = Node>,
-    {
+        shapes: &mut Shapes,
+        mut ctm: Mat4,
+        lod: Option<(&LevelOfDetail, &Camera)>,
+    ) {
         for transformation in &node.transformations {
             ctm = transformation.apply_matrix(&ctm);
         }
 
         for parsed_shape in &node.shapes {
-            shapes.push(Shape::from_parsed_shape(parsed_shape, primitives, ctm));
+            let detail = match lod {
+                Some((lod, camera)) => {
+                    let bounds = primitives
+                        .get(&parsed_shape.primitive_type)
+                        .bounds()
+                        .transformed(&ctm);
+                    lod.choose(parsed_shape, &bounds, camera)
+                }
+                None => Detail::Full,
+            };
+
+            match detail {
+                Detail::Full => shapes.push(parsed_shape, primitives, ctm),
+                Detail::Proxy(proxy, proxy_ctm) => shapes.push(&proxy, primitives, proxy_ctm),
+                Detail::Culled => {}
+            }
         }
 
         for child in &node.children {
-            Scene::traverse_tree_scene(child.borrow(), primitives, shapes, ctm);
+            Scene::traverse_tree_scene(child, primitives, shapes, ctm, lod);
+        }
+    }
+
+    /// Constructs a scene that keeps the transform hierarchy of the given tree scene at
+    /// render time, instead of flattening it into a CTM per shape. Instances of the same
+    /// master object share a single subtree.
+    pub fn with_hierarchy(tree_scene: TreeScene) -> anyhow::Result<Self> {
+        let primitives = Primitives::new();
+        let hierarchy =
+            HierarchyNode::from_node(&tree_scene.root_node, &primitives, &mut HashMap::new());
+
+        let mut unique_shapes = Vec::new();
+        hierarchy.collect_unique_shapes(&mut unique_shapes, &mut HashSet::new());
+
+        Scene::load_textures(
+            unique_shapes
+                .into_iter()
+                .flat_map(Shapes::materials)
+                .flat_map(|material| material.textures())
+                .chain(
+                    tree_scene
+                        .lights
+                        .iter()
+                        .filter_map(|light| light.kind.gobo()),
+                ),
+            tree_scene.texture_color_space,
+            tree_scene.texture_cache.as_ref(),
+        )?;
+
+        let bounds = hierarchy.bounds();
+
+        Ok(Scene {
+            global_lighting_coefficients: tree_scene.global_lighting_coefficients,
+            tolerances: tree_scene.tolerances,
+            camera: tree_scene.camera,
+            lights: tree_scene.lights,
+            shapes: Shapes::default(),
+            accelerator: Box::<Bvh>::default(),
+            accelerator_kind: AcceleratorKind::Bvh,
+            hierarchy: Some(hierarchy),
+            track_costs: false,
+            texture_color_space: tree_scene.texture_color_space,
+            texture_cache: tree_scene.texture_cache,
+            environment: tree_scene.environment,
+            self_intersect_offset: tree_scene
+                .tolerances
+                .self_intersect_offset
+                .unwrap_or_else(|| lights::self_intersect_offset(&bounds)),
+            size: bounds.diagonal_length(),
+        })
+    }
+
+    /// Resolves the sources of the given textures (of materials and lights), loading each
+    /// distinct image file once per color space (in parallel, as decoding large images is
+    /// slow), with the given color space for textures without one. If there are many small
+    /// images, they are packed into a single atlas. With a texture cache, the images are
+    /// instead loaded into the cache when they are first sampled.
+    fn load_textures<'a>(
+        textures: impl IntoIterator<Item = &'a Texture>,
+        default_color_space: ColorSpace,
+        cache: Option<&Arc<TextureCache>>,
+    ) -> anyhow::Result<()> {
+        // Textures which are loaded from files, and haven't been resolved yet (a texture may
+        // be shared by many materials), along with the files and color spaces to load them
+        let unresolved: Vec<(&Texture, (&PathBuf, ColorSpace))> = textures
+            .into_iter()
+            .filter_map(|texture| {
+                let filename = texture.filename.as_ref()?;
+                let color_space = texture.color_space.unwrap_or(default_color_space);
+                texture
+                    .source
+                    .get()
+                    .is_none()
+                    .then_some((texture, (filename, color_space)))
+            })
+            .collect();
+
+        let files: HashSet<(&PathBuf, ColorSpace)> =
+            unresolved.iter().map(|&(_, file)| file).collect();
+
+        if let Some(cache) = cache {
+            let sources = files
+                .into_iter()
+                .map(|file @ (filename, color_space)| {
+                    let source = cache.add(filename, color_space)?;
+                    Ok((file, Arc::new(source) as Arc<dyn TextureSource>))
+                })
+                .collect::<anyhow::Result<HashMap<_, _>>>()?;
+            for (texture, file) in unresolved {
+                let _ = texture.source.set(Arc::clone(&sources[&file]));
+            }
+
+            return Ok(());
+        }
+        let images: Vec<((&PathBuf, ColorSpace), ImageTexture)> = files
+            .into_par_iter()
+            .map(|file @ (filename, color_space)| {
+                Ok((file, ImageTexture::open(filename, color_space)?))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        // Many small textures are packed into an atlas, so that shading them is cache-friendly
+        let small_count = images
+            .iter()
+            .filter(|(_, image)| AtlasTexture::fits(image))
+            .count();
+        let (small, large): (Vec<_>, Vec<_>) = if small_count >= MIN_ATLAS_TEXTURES {
+            images
+                .into_iter()
+                .partition(|(_, image)| AtlasTexture::fits(image))
+        } else {
+            (Vec::new(), images)
+        };
+
+        let (small_files, small_images): (Vec<_>, Vec<_>) = small.into_iter().unzip();
+        let mut sources: HashMap<(&PathBuf, ColorSpace), Arc<dyn TextureSource>> = small_files
+            .into_iter()
+            .zip(AtlasTexture::pack(small_images))
+            .map(|(file, source)| (file, Arc::new(source) as Arc<dyn TextureSource>))
+            .collect();
+        sources.extend(
+            large
+                .into_iter()
+                .map(|(file, source)| (file, Arc::new(source) as Arc<dyn TextureSource>)),
+        );
+
+        for (texture, file) in unresolved {
+            let _ = texture.source.set(Arc::clone(&sources[&file]));
+        }
+
+        Ok(())
+    }
+
+    /// Adds a light for each shape whose material glows (has an emissive color), so that it
+    /// lights its surroundings (see [`Light::emissive`]), casting the given number of shadow
+    /// rays toward it from each shaded point. Returns the number of lights added.
+    ///
+    /// Lights can only be added for the shapes of scenes whose transform hierarchy has been
+    /// flattened.
+    pub fn add_emissive_lights(&mut self, shadow_samples: u32) -> anyhow::Result<usize> {
+        if self.hierarchy.is_some() {
+            bail!("Emissive shapes can't light a scene whose transform hierarchy is preserved");
+        }
+        if shadow_samples == 0 {
+            bail!("Emissive lights must cast at least 1 shadow ray");
+        }
+
+        let lights: Vec<Light> = (0..self.shapes.len())
+            .filter(|&index| {
+                self.shapes
+                    .material(index)
+                    .emissive
+                    .truncate()
+                    .max_element()
+                    > 0.0
+            })
+            .map(|index| {
+                Light::emissive(
+                    index,
+                    self.shapes.material(index).emissive,
+                    &self.shapes.bounds(index),
+                    shadow_samples,
+                )
+            })
+            .collect();
+        let count = lights.len();
+        self.lights.extend(lights);
+
+        Ok(count)
+    }
+
+    /// The light seen along a ray (traveling in the given direction) that misses every shape:
+    /// that of the scene's environment, or black if it has none.
+    pub fn background(&self, direction: Vec4) -> Vec4 {
+        match self.environment {
+            Some(ref environment) => environment.radiance(direction),
+            None => vec4(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// The light seen along a ray reflected off a surface of the given roughness that misses
+    /// every shape: that of the scene's environment, blurred by the roughness (see
+    /// [`Environment::glossy_radiance`]), or black if it has none.
+    pub fn glossy_background(&self, direction: Vec4, roughness: f32) -> Vec4 {
+        match self.environment {
+            Some(ref environment) => environment.glossy_radiance(direction, roughness),
+            None => vec4(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Constructs a (world space) ray to trace through the scene, which is valid between
+    /// the given t-values, and has the scene's tolerance for rays parallel to planes.
+    pub fn ray(&self, position: Vec4, direction: Vec4, t_min: f32, t_max: f32) -> Ray {
+        Ray::new(position, direction)
+            .with_t_range(t_min, t_max)
+            .with_parallel_threshold(self.tolerances.parallel_threshold)
+    }
+
+    /// Finds the closest intersection between the given (world space) ray, which is traced
+    /// for the given purpose, and any shape in the scene.
+    pub fn intersect(&self, ray: &Ray, kind: RayKind) -> Option<Intersection> {
+        let kind = self.track_costs.then_some(kind);
+
+        match self.hierarchy {
+            Some(ref hierarchy) => hierarchy.intersect(ray, kind),
+            None => self.accelerator.closest_intersection(ray, &|index| {
+                self.shapes.intersect_tracked(index, ray, kind)
+            }),
+        }
+    }
+
+    /// Finds the closest intersection of each active ray of the packet (see
+    /// [`Self::intersect`]), tracing the rays together through the acceleration structure.
+    pub fn intersect_packet(
+        &self,
+        packet: &RayPacket,
+        kind: RayKind,
+    ) -> [Option<Intersection>; PACKET_SIZE] {
+        let kind = self.track_costs.then_some(kind);
+
+        match self.hierarchy {
+            Some(ref hierarchy) => std::array::from_fn(|lane| {
+                if packet.is_active(lane) {
+                    hierarchy.intersect(&packet.rays[lane], kind)
+                } else {
+                    None
+                }
+            }),
+            None => self
+                .accelerator
+                .closest_intersections(packet, &|lane, index| {
+                    self.shapes
+                        .intersect_tracked(index, &packet.rays[lane], kind)
+                }),
+        }
+    }
+
+    /// Moves the shape at the given index (in `shapes`) by replacing its CTM, then updates
+    /// the scene's acceleration structure to match (see [`Self::update_shape_transforms`]).
+    pub fn update_shape_transform(&mut self, index: usize, ctm: Mat4) -> anyhow::Result<()> {
+        self.update_shape_transforms([(index, ctm)])
+    }
+
+    /// Moves the shapes at the given indices (in `shapes`) by replacing their CTMs, then
+    /// updates the scene's acceleration structure to match. The structure is refit to the
+    /// new bounds of the shapes where possible (see [`Accelerator::refit`]), which is much
+    /// quicker than building the scene again, and is otherwise rebuilt. The lights of moved
+    /// glowing shapes (see [`Self::add_emissive_lights`]) move along with them.
+    ///
+    /// If any of the indices is invalid, nothing is moved. Shapes can only be moved in scenes
+    /// whose transform hierarchy has been flattened.
+    pub fn update_shape_transforms(
+        &mut self,
+        updates: impl IntoIterator<Item = (usize, Mat4)>,
+    ) -> anyhow::Result<()> {
+        if self.hierarchy.is_some() {
+            bail!("Shapes can't be moved in a scene whose transform hierarchy is preserved");
+        }
+
+        let updates: Vec<(usize, Mat4)> = updates.into_iter().collect();
+        if let Some(&(index, _)) = updates
+            .iter()
+            .find(|&&(index, _)| index >= self.shapes.len())
+        {
+            bail!(
+                "No shape at index {} (the scene has {} shapes)",
+                index,
+                self.shapes.len()
+            );
+        }
+        for &(index, ctm) in &updates {
+            self.shapes.set_ctm(index, ctm);
+        }
+
+        let shape_bounds = self.shapes.all_bounds();
+        if !self.accelerator.refit(&shape_bounds) {
+            self.accelerator = self.accelerator_kind.build(&shape_bounds);
+        }
+
+        for light in &mut self.lights {
+            let Some(index) = light.emitter else {
+                continue;
+            };
+            if updates.iter().any(|&(moved, _)| moved == index) {
+                // Only the light's position and size change
+                let color = *light.kind.color_mut();
+                light.kind =
+                    Light::emissive(index, color, &shape_bounds[index], light.shadow_samples).kind;
+            }
+        }
+
+        let scene_bounds = shape_bounds
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, shape_bounds| {
+                bounds.union(shape_bounds)
+            });
+        self.self_intersect_offset = self
+            .tolerances
+            .self_intersect_offset
+            .unwrap_or_else(|| lights::self_intersect_offset(&scene_bounds));
+        self.size = scene_bounds.diagonal_length();
+
+        Ok(())
+    }
+
+    /// Counts the contents of the scene.
+    pub fn stats(&self) -> SceneStats {
+        let shapes = match self.hierarchy {
+            Some(ref hierarchy) => {
+                let mut unique_shapes = Vec::new();
+                hierarchy.collect_unique_shapes(&mut unique_shapes, &mut HashSet::new());
+                unique_shapes.iter().map(|shapes| shapes.len()).sum()
+            }
+            None => self.shapes.len(),
+        };
+
+        SceneStats {
+            shapes,
+            lights: self.lights.len(),
+            size: self.size,
+        }
+    }
+
+    /// Summarizes the costs recorded for each shape (which are all zero unless costs are tracked).
+    pub fn cost_report(&self) -> CostReport {
+        match self.hierarchy {
+            Some(ref hierarchy) => {
+                let mut unique_shapes = Vec::new();
+                hierarchy.collect_unique_shapes(&mut unique_shapes, &mut HashSet::new());
+                CostReport::new(unique_shapes.into_iter().flat_map(Shapes::costs))
+            }
+            None => CostReport::new(self.shapes.costs()),
         }
     }
 }
 
+// Scenes are shared by the threads that render them, and may be built on a different thread
+// than the one that renders them, so both kinds of scene must be thread-safe. This fails to
+// compile if either isn't.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<TreeScene>();
+    assert_send_sync::<Scene>();
+};
+
 impl TryFrom<TreeScene> for Scene {
     type Error = anyhow::Error;
 
     fn try_from(tree_scene: TreeScene) -> std::result::Result<Self, Self::Error> {
+        Scene::with_accelerator(tree_scene, AcceleratorKind::Bvh)
+    }
+}
+
+impl Scene {
+    /// Constructs a scene by flattening the transform hierarchy of the given tree scene into
+    /// a CTM per shape, and building the given kind of acceleration structure over the shapes.
+    pub fn with_accelerator(
+        tree_scene: TreeScene,
+        accelerator_kind: AcceleratorKind,
+    ) -> anyhow::Result<Self> {
+        Scene::flatten(tree_scene, accelerator_kind, None)
+    }
+
+    /// Like [`Self::with_accelerator`], but replaces the shapes that appear tiny in the image
+    /// with cheaper proxies (or leaves them out), as given by the level of detail.
+    pub fn with_level_of_detail(
+        tree_scene: TreeScene,
+        accelerator_kind: AcceleratorKind,
+        lod: &LevelOfDetail,
+    ) -> anyhow::Result<Self> {
+        Scene::flatten(tree_scene, accelerator_kind, Some(lod))
+    }
+
+    fn flatten(
+        tree_scene: TreeScene,
+        accelerator_kind: AcceleratorKind,
+        lod: Option<&LevelOfDetail>,
+    ) -> anyhow::Result<Self> {
         let primitives = Primitives::new();
 
         // Traverse the scene's node tree and construct shapes from it, using
         // the transformations at each node to add CTMs to the shapes.
-        let mut shapes = Vec::new();
+        let mut shapes = Shapes::default();
         Scene::traverse_tree_scene(
             &tree_scene.root_node,
             &primitives,
             &mut shapes,
-            glm::Mat4::one(),
+            Mat4::IDENTITY,
+            lod.map(|lod| (lod, &tree_scene.camera)),
         );
 
-        let mut textures = HashMap::new();
-        for shape in &shapes {
-            if let Some(ref texture) = shape.material.texture {
-                if !textures.contains_key(&texture.filename) {
-                    let texture_image = image::open(&texture.filename)?.to_rgb8();
-                    textures.insert(texture.filename.clone(), texture_image);
-                }
-            }
-        }
+        Scene::load_textures(
+            shapes
+                .materials()
+                .iter()
+                .flat_map(|material| material.textures())
+                .chain(
+                    tree_scene
+                        .lights
+                        .iter()
+                        .filter_map(|light| light.kind.gobo()),
+                ),
+            tree_scene.texture_color_space,
+            tree_scene.texture_cache.as_ref(),
+        )?;
+
+        let shape_bounds = shapes.all_bounds();
+        let accelerator = accelerator_kind.build(&shape_bounds);
+        let scene_bounds = shape_bounds
+            .iter()
+            .fold(BoundingBox::empty(), |bounds, shape_bounds| {
+                bounds.union(shape_bounds)
+            });
 
         Ok(Scene {
             global_lighting_coefficients: tree_scene.global_lighting_coefficients,
+            tolerances: tree_scene.tolerances,
             camera: tree_scene.camera,
             lights: tree_scene.lights,
             shapes,
-            textures,
+            accelerator,
+            accelerator_kind,
+            hierarchy: None,
+            track_costs: false,
+            texture_color_space: tree_scene.texture_color_space,
+            texture_cache: tree_scene.texture_cache,
+            environment: tree_scene.environment,
+            self_intersect_offset: tree_scene
+                .tolerances
+                .self_intersect_offset
+                .unwrap_or_else(|| lights::self_intersect_offset(&scene_bounds)),
+            size: scene_bounds.diagonal_length(),
         })
     }
 }
@@ -211,57 +1153,32 @@ pub struct Primitives {
     pub sphere: Arc<Primitive>,
     pub cylinder: Arc<Primitive>,
     pub cone: Arc<Primitive>,
+    pub open_cylinder: Arc<Primitive>,
+    pub open_cone: Arc<Primitive>,
 }
 
 impl Primitives {
     fn new() -> Self {
-        let mut cube_components: Vec<Box<dyn PrimitiveComponent + Send + Sync>> = Vec::new();
-        for &normal_axis in Axis::iterator() {
-            for elevation in [-0.5, 0.5] {
-                cube_components.push(Box::new(Square {
-                    plane: Plane {
-                        normal_axis,
-                        elevation,
-                    },
-                }))
-            }
+        Self {
+            cube: Arc::new(Primitive::cube()),
+            sphere: Arc::new(Primitive::sphere(Sweep::FULL)),
+            cylinder: Arc::new(Primitive::cylinder(true, Sweep::FULL)),
+            cone: Arc::new(Primitive::cone(true, Sweep::FULL)),
+            open_cylinder: Arc::new(Primitive::cylinder(false, Sweep::FULL)),
+            open_cone: Arc::new(Primitive::cone(false, Sweep::FULL)),
         }
+    }
 
-        Self {
-            cube: Arc::new(Primitive {
-                components: cube_components,
-            }),
-            sphere: Arc::new(Primitive {
-                components: vec![Box::new(Sphere {})],
-            }),
-            cylinder: Arc::new(Primitive {
-                components: vec![
-                    Box::new(CylinderBody {}),
-                    Box::new(Circle {
-                        plane: Plane {
-                            normal_axis: Axis::Y,
-                            elevation: 0.5,
-                        },
-                    }),
-                    Box::new(Circle {
-                        plane: Plane {
-                            normal_axis: Axis::Y,
-                            elevation: -0.5,
-                        },
-                    }),
-                ],
-            }),
-            cone: Arc::new(Primitive {
-                components: vec![
-                    Box::new(ConeBody {}),
-                    Box::new(Circle {
-                        plane: Plane {
-                            normal_axis: Axis::Y,
-                            elevation: -0.5,
-                        },
-                    }),
-                ],
-            }),
+    /// The primitive of the given type.
+    pub fn get<'a>(&'a self, primitive_type: &'a PrimitiveType) -> &'a Arc<Primitive> {
+        match primitive_type {
+            PrimitiveType::Cone { capped: true } => &self.cone,
+            PrimitiveType::Cone { capped: false } => &self.open_cone,
+            PrimitiveType::Cube => &self.cube,
+            PrimitiveType::Sphere => &self.sphere,
+            PrimitiveType::Cylinder { capped: true } => &self.cylinder,
+            PrimitiveType::Cylinder { capped: false } => &self.open_cylinder,
+            PrimitiveType::Custom(ref primitive) => primitive,
         }
     }
 }
@@ -0,0 +1,136 @@
+//! Wavefront MTL material libraries, which define the materials assigned to the faces of OBJ
+//! meshes.
+
+use super::{Material, Texture};
+use crate::math::{vec4, Vec4};
+use crate::texture::WrapMode;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Loads the materials of an MTL file, by name. Colors (`Ka`, `Kd`, `Ks`, `Ke`), the specular
+/// exponent (`Ns`), dissolve (`d`, or its inverse `Tr`), index of refraction (`Ni`), and
+/// roughness (`Pr`, of the PBR extension) are used, along with the `map_Kd` (which replaces the diffuse color, as a texture with a blend
+/// of 1), `map_Ks`, and `map_Ns` textures, whose paths are relative to the MTL file.
+pub(super) fn load_mtl(path: &Path) -> Result<HashMap<String, Material>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read material library: {}", path.display()))?;
+
+    parse_mtl(&contents, path.parent().unwrap_or_else(|| Path::new("")))
+        .with_context(|| format!("Failed to parse material library: {}", path.display()))
+}
+
+fn parse_mtl(contents: &str, directory: &Path) -> Result<HashMap<String, Material>> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+
+    for (line_index, line) in contents.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut tokens = line.split_whitespace();
+        let Some(statement) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if statement == "newmtl" {
+            if arguments.is_empty() {
+                bail!("Material on line {} must have a name", line_number);
+            }
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+
+            let name = arguments.join(" ");
+            current = Some((name.clone(), default_material(name)));
+            continue;
+        }
+
+        // Ignore comments and statements outside of any material
+        let Some((_, ref mut material)) = current else {
+            continue;
+        };
+
+        let number = |index: usize| -> Result<f32> {
+            arguments
+                .get(index)
+                .ok_or_else(|| anyhow!("Missing value on line {}", line_number))?
+                .parse()
+                .map_err(|_| anyhow!("Invalid number on line {}", line_number))
+        };
+        let color = || -> Result<Vec4> {
+            let r = number(0)?;
+            // A single value is a gray
+            match arguments.len() {
+                1 => Ok(vec4(r, r, r, 1.0)),
+                _ => Ok(vec4(r, number(1)?, number(2)?, 1.0)),
+            }
+        };
+        // Texture statements may have options before the file (e.g. `-s 2 2 1 file.png`),
+        // which aren't supported, so only the last argument is used
+        let texture = |blend: f32| -> Result<Texture> {
+            let file = arguments
+                .last()
+                .ok_or_else(|| anyhow!("Missing texture file on line {}", line_number))?;
+
+            Ok(Texture {
+                filename: Some(directory.join(file)),
+                source: OnceLock::new(),
+                repeat_u: 1.0,
+                repeat_v: 1.0,
+                color_space: None,
+                wrap: WrapMode::default(),
+                blend,
+            })
+        };
+
+        match statement {
+            "Ka" => material.ambient = color()?,
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ke" => material.emissive = color()?,
+            "Ns" => material.shininess = number(0)?,
+            "d" => material.opacity = number(0)?.clamp(0.0, 1.0),
+            "Tr" => material.opacity = (1.0 - number(0)?).clamp(0.0, 1.0),
+            "Ni" => {
+                let ior = number(0)?;
+                material.ior = (ior > 1.0).then_some(ior);
+            }
+            "Pr" => material.roughness = number(0)?.clamp(0.0, 1.0),
+            "map_Kd" => material.texture = Some(texture(1.0)?),
+            "map_Ks" => material.specular_map = Some(texture(0.0)?),
+            "map_Ns" => material.shininess_map = Some(texture(0.0)?),
+            // Ignore unsupported statements (e.g. illumination models and bump maps)
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+/// The material with the given name, before any of its statements are applied.
+fn default_material(name: String) -> Material {
+    let zero = vec4(0.0, 0.0, 0.0, 0.0);
+
+    Material {
+        ambient: zero,
+        diffuse: vec4(1.0, 1.0, 1.0, 0.0),
+        specular: zero,
+        shininess: 0.0,
+        reflective: zero,
+        emissive: zero,
+        texture: None,
+        specular_map: None,
+        shininess_map: None,
+        opacity: 1.0,
+        name: Some(name),
+        ior: None,
+        roughness: 0.0,
+        layer: None,
+    }
+}
@@ -0,0 +1,351 @@
+//! Material overrides, which replace the material parameters of shapes after a scenefile is
+//! parsed (e.g. so that materials can be tweaked for look-dev without editing the scenefile).
+
+use super::{Material, Node, TreeScene};
+use crate::math::vec4;
+use anyhow::{anyhow, bail, Context, Error, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Diffuse color of the neutral material that replaces every material of a clay render.
+const CLAY_DIFFUSE: f32 = 0.7;
+
+/// Replacements for some of the parameters of a material. Colors are given as [r, g, b].
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialOverride {
+    pub ambient: Option<[f32; 3]>,
+    pub diffuse: Option<[f32; 3]>,
+    pub specular: Option<[f32; 3]>,
+    pub reflective: Option<[f32; 3]>,
+    pub emissive: Option<[f32; 3]>,
+    pub shininess: Option<f32>,
+    pub opacity: Option<f32>,
+    pub roughness: Option<f32>,
+    /// Blend of the material's texture, which only has an effect if it has a texture.
+    pub blend: Option<f32>,
+}
+
+impl MaterialOverride {
+    fn apply(&self, material: &mut Material) {
+        let color = |[r, g, b]: [f32; 3]| vec4(r, g, b, 0.0);
+
+        if let Some(ambient) = self.ambient {
+            material.ambient = color(ambient);
+        }
+        if let Some(diffuse) = self.diffuse {
+            material.diffuse = color(diffuse);
+        }
+        if let Some(specular) = self.specular {
+            material.specular = color(specular);
+        }
+        if let Some(reflective) = self.reflective {
+            material.reflective = color(reflective);
+        }
+        if let Some(emissive) = self.emissive {
+            material.emissive = color(emissive);
+        }
+        if let Some(shininess) = self.shininess {
+            material.shininess = shininess;
+        }
+        if let Some(opacity) = self.opacity {
+            material.opacity = opacity;
+        }
+        if let Some(roughness) = self.roughness {
+            material.roughness = roughness;
+        }
+        if let (Some(blend), Some(texture)) = (self.blend, material.texture.as_mut()) {
+            texture.blend = blend;
+        }
+    }
+
+    /// A neutral material (matte light gray, without any textures or layers) with the
+    /// overridden parameters, which replaces every material of a scene for a clay render.
+    pub fn clay_material(&self) -> Material {
+        let zero = vec4(0.0, 0.0, 0.0, 0.0);
+        let mut material = Material {
+            ambient: zero,
+            diffuse: vec4(CLAY_DIFFUSE, CLAY_DIFFUSE, CLAY_DIFFUSE, 0.0),
+            specular: zero,
+            shininess: 0.0,
+            reflective: zero,
+            emissive: zero,
+            texture: None,
+            specular_map: None,
+            shininess_map: None,
+            opacity: 1.0,
+            name: None,
+            ior: None,
+            roughness: 0.0,
+            layer: None,
+        };
+        self.apply(&mut material);
+
+        material
+    }
+}
+
+/// Parses an override from the command line, as a list of parameters separated by
+/// semicolons, each given as `name:value` (with colors as `r,g,b`), e.g.
+/// `diffuse:0.7,0.7,0.7;shininess:20`.
+impl FromStr for MaterialOverride {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let mut material_override = MaterialOverride::default();
+
+        for parameter in string
+            .split(';')
+            .filter(|parameter| !parameter.trim().is_empty())
+        {
+            let (name, value) = parameter.split_once(':').ok_or_else(|| {
+                anyhow!("Material parameter must be name:value, got {}", parameter)
+            })?;
+            let number = |value: &str| {
+                value
+                    .trim()
+                    .parse::<f32>()
+                    .with_context(|| format!("Invalid value for material parameter {}", name))
+            };
+            let color = || -> Result<[f32; 3]> {
+                let components = value.split(',').map(number).collect::<Result<Vec<f32>>>()?;
+                match components[..] {
+                    [r, g, b] => Ok([r, g, b]),
+                    _ => bail!("Color of material parameter {} must be r,g,b", name),
+                }
+            };
+
+            match name.trim() {
+                "ambient" => material_override.ambient = Some(color()?),
+                "diffuse" => material_override.diffuse = Some(color()?),
+                "specular" => material_override.specular = Some(color()?),
+                "reflective" => material_override.reflective = Some(color()?),
+                "emissive" => material_override.emissive = Some(color()?),
+                "shininess" => material_override.shininess = Some(number(value)?),
+                "opacity" => {
+                    let opacity = number(value)?;
+                    if !(0.0..=1.0).contains(&opacity) {
+                        bail!("Opacity must be between 0 and 1");
+                    }
+                    material_override.opacity = Some(opacity);
+                }
+                "roughness" => {
+                    let roughness = number(value)?;
+                    if !(0.0..=1.0).contains(&roughness) {
+                        bail!("Roughness must be between 0 and 1");
+                    }
+                    material_override.roughness = Some(roughness);
+                }
+                "blend" => material_override.blend = Some(number(value)?),
+                other_name => bail!("Unknown material parameter {}", other_name),
+            }
+        }
+
+        Ok(material_override)
+    }
+}
+
+/// A set of material overrides, loaded from a TOML file with a table for each overridden
+/// object or material, e.g. `[objects.teapot]` or `[materials.brass]`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialOverrides {
+    /// Overrides for all shapes directly within the object (not within other objects that it
+    /// instances) with the given name.
+    #[serde(default)]
+    objects: HashMap<String, MaterialOverride>,
+    /// Overrides for all shapes whose material has the given name (given in the scenefile by
+    /// the `material` attribute of a primitive).
+    #[serde(default)]
+    materials: HashMap<String, MaterialOverride>,
+}
+
+impl MaterialOverrides {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read material overrides: {}", path.display()))?;
+
+        let overrides: Self = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse material overrides: {}", path.display()))?;
+
+        for material_override in overrides
+            .objects
+            .values()
+            .chain(overrides.materials.values())
+        {
+            if material_override
+                .opacity
+                .map_or(false, |opacity| !(0.0..=1.0).contains(&opacity))
+            {
+                bail!("Opacity must be between 0 and 1");
+            }
+            if material_override
+                .roughness
+                .map_or(false, |roughness| !(0.0..=1.0).contains(&roughness))
+            {
+                bail!("Roughness must be between 0 and 1");
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Mutable access to the override for the object with the given name, which is added
+    /// (overriding nothing) if there isn't one yet.
+    pub fn object_mut(&mut self, name: &str) -> &mut MaterialOverride {
+        self.objects.entry(name.to_string()).or_default()
+    }
+
+    /// Mutable access to the override for the material with the given name, which is added
+    /// (overriding nothing) if there isn't one yet.
+    pub fn material_mut(&mut self, name: &str) -> &mut MaterialOverride {
+        self.materials.entry(name.to_string()).or_default()
+    }
+}
+
+/// Names of the objects and materials seen while applying overrides, used to detect overrides
+/// that don't match anything in the scene (e.g. because of a typo).
+#[derive(Default)]
+struct SeenNames {
+    objects: HashSet<String>,
+    materials: HashSet<String>,
+}
+
+impl TreeScene {
+    /// Replaces the parameters of the materials matched by the given overrides. Material
+    /// overrides are applied first, so object overrides take precedence over them.
+    pub fn apply_material_overrides(&mut self, overrides: &MaterialOverrides) -> Result<()> {
+        let mut seen = SeenNames::default();
+        self.root_node = apply_to_node(
+            &self.root_node,
+            overrides,
+            None,
+            &mut seen,
+            &mut HashMap::new(),
+        );
+
+        if let Some(name) = overrides
+            .objects
+            .keys()
+            .find(|&name| !seen.objects.contains(name))
+        {
+            bail!("Material override for unknown object: {}", name);
+        }
+        if let Some(name) = overrides
+            .materials
+            .keys()
+            .find(|&name| !seen.materials.contains(name))
+        {
+            bail!("Material override for unknown material: {}", name);
+        }
+
+        Ok(())
+    }
+}
+
+impl TreeScene {
+    /// Replaces the material of every shape in the scene with the given one (e.g. for a clay
+    /// render, which shows the lighting of a scene independently of its materials).
+    pub fn replace_all_materials(&mut self, material: Material) {
+        self.root_node = replace_in_node(&self.root_node, &Arc::new(material), &mut HashMap::new());
+    }
+}
+
+/// Constructs a copy of the given node and its descendants with the given material in place
+/// of those of their shapes, copying nodes shared between multiple parents only once.
+fn replace_in_node(
+    node: &Node,
+    material: &Arc<Material>,
+    replaced: &mut HashMap<*const Node, Arc<Node>>,
+) -> Node {
+    let mut shapes = node.shapes.clone();
+    for shape in &mut shapes {
+        shape.material = Arc::clone(material);
+    }
+
+    let children = node
+        .children
+        .iter()
+        .map(|child| {
+            if let Some(existing) = replaced.get(&Arc::as_ptr(child)) {
+                return Arc::clone(existing);
+            }
+
+            let replaced_child = Arc::new(replace_in_node(child, material, replaced));
+            replaced.insert(Arc::as_ptr(child), Arc::clone(&replaced_child));
+            replaced_child
+        })
+        .collect();
+
+    Node {
+        name: node.name.clone(),
+        transformations: node.transformations.clone(),
+        shapes,
+        children,
+    }
+}
+
+/// Constructs a copy of the given node and its descendants with the overrides applied to
+/// their shapes. Nodes that start a named object use the override for that object, while
+/// other nodes use the override of the object they belong to. Nodes shared between multiple
+/// parents (instanced objects) are only copied once, and the copy is shared in their place.
+fn apply_to_node(
+    node: &Node,
+    overrides: &MaterialOverrides,
+    parent_override: Option<&MaterialOverride>,
+    seen: &mut SeenNames,
+    overridden: &mut HashMap<*const Node, Arc<Node>>,
+) -> Node {
+    let object_override = match node.name {
+        Some(ref name) => {
+            seen.objects.insert(name.clone());
+            overrides.objects.get(name)
+        }
+        None => parent_override,
+    };
+
+    let mut shapes = node.shapes.clone();
+    for shape in &mut shapes {
+        if let Some(name) = shape.material.name.clone() {
+            if let Some(material_override) = overrides.materials.get(&name) {
+                material_override.apply(Arc::make_mut(&mut shape.material));
+            }
+            seen.materials.insert(name);
+        }
+
+        if let Some(object_override) = object_override {
+            object_override.apply(Arc::make_mut(&mut shape.material));
+        }
+    }
+
+    let children = node
+        .children
+        .iter()
+        .map(|child| {
+            if let Some(existing) = overridden.get(&Arc::as_ptr(child)) {
+                return Arc::clone(existing);
+            }
+
+            let overridden_child = Arc::new(apply_to_node(
+                child,
+                overrides,
+                object_override,
+                seen,
+                overridden,
+            ));
+            overridden.insert(Arc::as_ptr(child), Arc::clone(&overridden_child));
+            overridden_child
+        })
+        .collect();
+
+    Node {
+        name: node.name.clone(),
+        transformations: node.transformations.clone(),
+        shapes,
+        children,
+    }
+}
@@ -1,19 +1,113 @@
 //! Parser for XML scenefiles.
 
-use super::{GlobalLightingCoefficients, Material, Node, ParsedShape, PrimitiveType, Texture};
-use crate::lights::Light;
-use crate::scene::{Camera, Transformation, TreeScene};
+use super::mtl;
+use super::{
+    Backfaces, GlobalLightingCoefficients, Material, MaterialLayer, Node, ParsedShape,
+    PrimitiveType, Texture, Tolerances,
+};
+use crate::bezier::{self, ControlPoints};
+use crate::curve::Curve;
+use crate::environment::Environment;
+use crate::lights::{Light, LightKind};
+use crate::math::{vec3, vec4, Mat4, Quat, Vec3, Vec4};
+use crate::mesh::{Displacement, Mesh};
+use crate::point_cloud::{PointCloud, Splat};
+use crate::primitive::{Primitive, Sweep};
+use crate::scene::{Camera, MaterialOverride, SceneBuilder, ScenePatch, Transformation, TreeScene};
+use crate::sky::Sky;
+use crate::texture::{
+    CheckerTexture, ColorSpace, ImageTexture, NoisePattern, NoiseTexture, TextureSource, WrapMode,
+};
 use anyhow::{anyhow, bail};
 use anyhow::{Context, Result};
-use num_traits::Zero;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::path::Path;
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 use xmltree::Element;
 
+/// Part of a mesh: the faces of an OBJ file assigned a material, or those without one (which
+/// have the material of the primitive).
+#[derive(Clone)]
+struct MeshPart {
+    material: Option<Arc<Material>>,
+    primitive: Arc<Primitive>,
+}
+
+/// State shared across the parsing of a single scenefile.
+struct ParseContext<'a> {
+    /// Directory that texture images in the scenefile are relative to.
+    textures: &'a Path,
+    /// Directory that mesh and patch files in the scenefile are relative to (that of the
+    /// scenefile itself).
+    meshes: &'a Path,
+    /// Primitives that have already been loaded from mesh and patch files, so that each file
+    /// is only loaded once.
+    loaded_meshes: HashMap<PathBuf, Arc<Primitive>>,
+    /// Parts of the meshes that have already been loaded from OBJ files, by their paths and
+    /// whether they were split by the materials of their faces.
+    loaded_mesh_parts: HashMap<(PathBuf, bool), Vec<MeshPart>>,
+    /// Name of the top-level object currently being parsed, and how many shapes it has so far.
+    object_name: Arc<str>,
+    object_shape_count: usize,
+    /// Number of shapes parsed so far in the whole scenefile.
+    shape_count: usize,
+    /// Primitives defined outside of this crate which the scenefile may use.
+    builder: &'a SceneBuilder,
+    /// Descriptions of the tags that the element currently being parsed is within.
+    path: Vec<String>,
+    /// Errors found so far, each along with the tags it was found in.
+    errors: Vec<String>,
+    /// Names of the top-level objects that failed to parse, which other objects may still
+    /// instance (without reporting another error for each instance).
+    failed_objects: HashSet<String>,
+}
+
+impl ParseContext<'_> {
+    /// Parses an element (described by the given description) with the given function. If it
+    /// fails, the error is recorded along with the tags it was found in, and parsing carries
+    /// on without the element, so that every error in the scenefile can be reported at once.
+    fn recover<T>(
+        &mut self,
+        description: String,
+        parse: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Option<T> {
+        self.path.push(description);
+        let result = parse(self);
+        let path = self.path.join(" > ");
+        self.path.pop();
+
+        match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.errors.push(format!("{}: {:#}", path, error));
+                None
+            }
+        }
+    }
+}
+
+/// Describes an element for the location of an error, by its tag and the attributes that
+/// identify it (e.g. `<object type="primitive" name="sphere">`).
+fn describe(element: &Element) -> String {
+    let mut description = format!("<{}", element.name);
+    for attribute in ["type", "name"] {
+        if let Some(value) = element.attributes.get(attribute) {
+            description.push_str(&format!(" {}=\"{}\"", attribute, value));
+        }
+    }
+    description.push('>');
+
+    description
+}
+
+/// Number of squares along each of u and v of a checker texture without a `scale`.
+const DEFAULT_CHECKER_SCALE: f32 = 8.0;
+
+/// Number of features per unit of object space of a noise texture without a `scale`.
+const DEFAULT_NOISE_SCALE: f32 = 4.0;
+
 fn parse_attribute<T: FromStr>(element: &Element, attribute_name: &str) -> Result<T> {
     element
         .attributes
@@ -35,19 +129,16 @@ fn parse_attribute<T: FromStr>(element: &Element, attribute_name: &str) -> Resul
         })
 }
 
-fn parse_vec3(element: &Element, (x, y, z): (&str, &str, &str)) -> Result<glm::Vector3<f32>> {
-    Ok(glm::vec3(
+fn parse_vec3(element: &Element, (x, y, z): (&str, &str, &str)) -> Result<Vec3> {
+    Ok(vec3(
         parse_attribute(element, x)?,
         parse_attribute(element, y)?,
         parse_attribute(element, z)?,
     ))
 }
 
-fn parse_vec4(
-    element: &Element,
-    (x, y, z, w): (&str, &str, &str, &str),
-) -> Result<glm::Vector4<f32>> {
-    Ok(glm::vec4(
+fn parse_vec4(element: &Element, (x, y, z, w): (&str, &str, &str, &str)) -> Result<Vec4> {
+    Ok(vec4(
         parse_attribute(element, x)?,
         parse_attribute(element, y)?,
         parse_attribute(element, z)?,
@@ -55,12 +146,19 @@ fn parse_vec4(
     ))
 }
 
-fn parse_global_lighting_coefficients(element: &Element) -> Result<GlobalLightingCoefficients> {
+/// Parses the global lighting coefficients of a `<globaldata>` tag, along with any tolerances
+/// of intersection tests that it tunes.
+fn parse_global_data(
+    element: &Element,
+    context: &ParseContext,
+) -> Result<(GlobalLightingCoefficients, Tolerances, Option<Environment>)> {
     let mut global_lighting_coefficients = GlobalLightingCoefficients {
         ka: 0.5,
         kd: 0.5,
         ks: 0.5,
     };
+    let mut tolerances = Tolerances::default();
+    let mut environment = None;
 
     for child in child_elements(element) {
         match child.name.as_str() {
@@ -73,14 +171,87 @@ fn parse_global_lighting_coefficients(element: &Element) -> Result<GlobalLightin
             "specularcoeff" => {
                 global_lighting_coefficients.ks = parse_attribute(child, "v")?;
             }
-            other_name => bail!(
-                "Unknown global lighting coefficient tagname: <{}>",
-                other_name
-            ),
+            "mint" => tolerances.min_t = parse_tolerance(child)?,
+            "selfintersectoffset" => {
+                tolerances.self_intersect_offset = Some(parse_tolerance(child)?);
+            }
+            "parallelthreshold" => tolerances.parallel_threshold = parse_tolerance(child)?,
+            "environment" | "sky" if environment.is_some() => {
+                bail!("Scenes can only have one <environment> or <sky>")
+            }
+            "environment" => environment = Some(parse_environment(child, context)?),
+            "sky" => environment = Some(parse_sky(child)?),
+            other_name => bail!("Unknown global data tagname: <{}>", other_name),
         }
     }
 
-    Ok(global_lighting_coefficients)
+    Ok((global_lighting_coefficients, tolerances, environment))
+}
+
+/// Parses an `<environment>` tag of the global data: an equirectangular image (whose path is
+/// relative to the textures directory) of the light arriving from every direction, scaled by
+/// an optional `intensity` and rotated around the y axis by an optional `rotation` (in
+/// degrees).
+fn parse_environment(element: &Element, context: &ParseContext) -> Result<Environment> {
+    let filename = Path::join(
+        context.textures,
+        Path::new(&parse_attribute::<String>(element, "file")?),
+    );
+    let intensity = if element.attributes.contains_key("intensity") {
+        parse_attribute::<f32>(element, "intensity")?
+    } else {
+        1.0
+    };
+    if intensity.is_nan() || intensity < 0.0 {
+        bail!("Environment intensity must not be negative");
+    }
+    let rotation = if element.attributes.contains_key("rotation") {
+        parse_attribute::<f32>(element, "rotation")?
+    } else {
+        0.0
+    };
+    let color_space = match element.attributes.get("colorspace") {
+        Some(name) => name.parse()?,
+        None => ColorSpace::Linear,
+    };
+
+    Ok(Environment::new(
+        ImageTexture::open(&filename, color_space)?,
+        intensity,
+        rotation.to_radians(),
+    ))
+}
+
+/// Parses a `<sky>` tag of the global data: a procedural daylight sky with the sun in the
+/// direction given by its `x`, `y`, and `z` attributes, through air of an optional
+/// `turbidity` (2 by default), whose light is scaled by an optional `intensity`.
+fn parse_sky(element: &Element) -> Result<Environment> {
+    let sun = parse_vec3(element, ("x", "y", "z"))?;
+    let turbidity = if element.attributes.contains_key("turbidity") {
+        parse_attribute::<f32>(element, "turbidity")?
+    } else {
+        2.0
+    };
+    let intensity = if element.attributes.contains_key("intensity") {
+        parse_attribute::<f32>(element, "intensity")?
+    } else {
+        1.0
+    };
+    if intensity.is_nan() || intensity < 0.0 {
+        bail!("Sky intensity must not be negative");
+    }
+
+    Ok(Sky::new(sun, turbidity)?.environment(intensity))
+}
+
+/// Parses the value of a tolerance tag, which must be a non-negative number.
+fn parse_tolerance(element: &Element) -> Result<f32> {
+    let tolerance: f32 = parse_attribute(element, "v")?;
+    if tolerance.is_nan() || tolerance < 0.0 {
+        bail!("<{}> must not be negative", element.name);
+    }
+
+    Ok(tolerance)
 }
 
 fn child_elements(element: &Element) -> impl Iterator<Item = &Element> {
@@ -92,10 +263,10 @@ fn child_elements(element: &Element) -> impl Iterator<Item = &Element> {
 
 fn parse_camera(element: &Element) -> Result<Camera> {
     let mut camera = Camera::new(
-        glm::vec4(5.0, 5.0, 5.0, 1.0),
-        glm::vec4(0.0, 1.0, 0.0, 0.0),
-        glm::vec4(-1.0, -1.0, -1.0, 0.0),
-        glm::radians(45.0),
+        vec4(5.0, 5.0, 5.0, 1.0),
+        vec4(0.0, 1.0, 0.0, 0.0),
+        vec4(-1.0, -1.0, -1.0, 0.0),
+        45f32.to_radians(),
     );
 
     let mut look_found = false;
@@ -110,7 +281,7 @@ fn parse_camera(element: &Element) -> Result<Camera> {
                 camera.up = parse_vec3(child, ("x", "y", "z"))?.extend(0.0);
             }
             "heightangle" => {
-                camera.height_angle = glm::radians(parse_attribute(child, "v")?);
+                camera.height_angle = parse_attribute::<f32>(child, "v")?.to_radians();
             }
             "look" => {
                 camera.look = parse_vec3(child, ("x", "y", "z"))?.extend(0.0);
@@ -135,7 +306,7 @@ fn parse_camera(element: &Element) -> Result<Camera> {
     }
 
     if focus_found {
-        camera.look = camera.look - camera.position;
+        camera.look -= camera.position;
     }
 
     // Ensure that the inverse view matrix has been calculated using the most up-to-date position/look/up
@@ -145,24 +316,35 @@ fn parse_camera(element: &Element) -> Result<Camera> {
     Ok(camera)
 }
 
-fn parse_color(element: &Element) -> Result<glm::Vec4> {
+fn parse_color(element: &Element) -> Result<Vec4> {
     Ok(parse_vec3(element, ("x", "y", "z"))
         .or_else(|_| parse_vec3(element, ("r", "g", "b")))?
         .extend(1.0))
 }
 
-fn parse_light(element: &Element) -> Result<Light> {
+/// Parses a `<lightdata>` tag. Lights can only project textures (with a `<texture>` tag) when
+/// there is a context to load them in, i.e. in scenefiles rather than patches.
+fn parse_light(element: &Element, context: Option<&ParseContext>) -> Result<Light> {
     let mut color = None;
     let mut direction = None;
     let mut position = None;
     let mut attenuation = None;
     let mut penumbra = None;
     let mut angle = None;
+    let mut radius = None;
+    let mut angular_radius = None;
     let mut light_type = None;
+    let mut shadow_samples = 1;
+    let mut shadow_only = false;
+    let mut max_distance = None;
+    let mut gobo = None;
+    let mut id = None;
 
     for child in child_elements(element) {
         match child.name.as_str() {
-            "id" => {}
+            "id" => {
+                id = Some(parse_attribute::<String>(child, "v")?);
+            }
             "type" => {
                 light_type = Some(parse_attribute::<String>(child, "v")?);
             }
@@ -183,10 +365,38 @@ fn parse_light(element: &Element) -> Result<Light> {
                 direction = Some(parse_vec3(child, ("x", "y", "z"))?.extend(0.0));
             }
             "angle" => {
-                angle = Some(glm::radians(parse_attribute::<f32>(child, "v")?));
+                angle = Some(parse_attribute::<f32>(child, "v")?.to_radians());
             }
             "penumbra" => {
-                penumbra = Some(glm::radians(parse_attribute::<f32>(child, "v")?));
+                penumbra = Some(parse_attribute::<f32>(child, "v")?.to_radians());
+            }
+            "radius" => {
+                radius = Some(parse_attribute::<f32>(child, "v")?);
+            }
+            "angularradius" => {
+                let degrees = parse_attribute::<f32>(child, "v")?;
+                if !(0.0..90.0).contains(&degrees) {
+                    bail!("Light angular radius must be at least 0 and less than 90 degrees");
+                }
+                angular_radius = Some(degrees.to_radians());
+            }
+            "samples" => {
+                shadow_samples = parse_attribute(child, "v")?;
+                if shadow_samples == 0 {
+                    bail!("Light must have at least 1 shadow sample");
+                }
+            }
+            "shadowonly" => {
+                shadow_only = parse_attribute(child, "v")?;
+            }
+            "maxdistance" => {
+                max_distance = Some(parse_attribute::<f32>(child, "v")?);
+            }
+            "texture" => {
+                let Some(context) = context else {
+                    bail!("Lights of scene patches cannot have a <texture>");
+                };
+                gobo = Some(Arc::new(parse_texture_map(child, context)?));
             }
             other_name => {
                 bail!("Unknown light tagname: <{}>", other_name)
@@ -194,12 +404,12 @@ fn parse_light(element: &Element) -> Result<Light> {
         }
     }
 
-    let default_color = glm::vec4(1.0, 1.0, 1.0, 1.0);
-    let default_position = glm::vec4(3.0, 3.0, 3.0, 1.0);
-    let default_attenuation = glm::vec3(1.0, 0.0, 0.0);
-    let default_direction = glm::vec4(0.0, 0.0, 0.0, 0.0);
+    let default_color = vec4(1.0, 1.0, 1.0, 1.0);
+    let default_position = vec4(3.0, 3.0, 3.0, 1.0);
+    let default_attenuation = vec3(1.0, 0.0, 0.0);
+    let default_direction = vec4(0.0, 0.0, 0.0, 0.0);
 
-    match light_type.as_deref() {
+    let kind = match light_type.as_deref() {
         Some("directional") => {
             if position.is_some() {
                 bail!("Directional light cannot have position");
@@ -210,12 +420,22 @@ fn parse_light(element: &Element) -> Result<Light> {
             if angle.is_some() {
                 bail!("Directional light cannot have angle");
             }
+            if radius.is_some() {
+                bail!("Directional light cannot have radius");
+            }
+            if max_distance.is_some() {
+                bail!("Directional light cannot have maxdistance");
+            }
+            if gobo.is_some() {
+                bail!("Directional light cannot have texture");
+            }
 
-            Ok(Light::Directional {
+            LightKind::Directional {
                 color: color.unwrap_or(default_color),
                 direction: direction.unwrap_or(default_direction),
                 attenuation: attenuation.unwrap_or(default_attenuation),
-            })
+                angular_radius: angular_radius.unwrap_or(0.0),
+            }
         }
         Some("point") | None => {
             if direction.is_some() {
@@ -227,55 +447,80 @@ fn parse_light(element: &Element) -> Result<Light> {
             if angle.is_some() {
                 bail!("Point light cannot have angle");
             }
+            if gobo.is_some() {
+                bail!("Point light cannot have texture");
+            }
+            if angular_radius.is_some() {
+                bail!("Point light cannot have angularradius");
+            }
 
-            Ok(Light::Point {
+            LightKind::Point {
                 color: color.unwrap_or(default_color),
                 position: position.unwrap_or(default_position),
                 attenuation: attenuation.unwrap_or(default_attenuation),
-            })
+                radius: radius.unwrap_or(0.0),
+            }
+        }
+        Some("spot") if angular_radius.is_some() => {
+            bail!("Spot light cannot have angularradius")
         }
-        Some("spot") => Ok(Light::Spot {
+        Some("spot") => LightKind::Spot {
             color: color.unwrap_or(default_color),
             position: position.unwrap_or(default_position),
             direction: direction.unwrap_or(default_direction),
             attenuation: attenuation.unwrap_or(default_attenuation),
             penumbra: penumbra.unwrap_or(0.0),
             angle: angle.unwrap_or(0.0),
-        }),
+            radius: radius.unwrap_or(0.0),
+            gobo,
+        },
         Some(t) => bail!("Unknown light type: \"{}\"", t),
-    }
+    };
+
+    Ok(Light {
+        id,
+        kind,
+        shadow_samples,
+        shadow_only,
+        max_distance,
+        emitter: None,
+    })
 }
 
-/// Map from object names to the node for that object
-type ObjectMap = HashMap<String, Rc<RefCell<Node>>>;
+/// Map from object names to the node for that object (for the objects that have been
+/// fully parsed so far)
+type ObjectMap = HashMap<String, Arc<Node>>;
 
 fn parse_object_body(
     element: &Element,
-    parent_node: &Rc<RefCell<Node>>,
+    parent_node: &mut Node,
     objects: &ObjectMap,
-    textures: &Path,
+    context: &mut ParseContext,
 ) -> Result<()> {
-    for child in child_elements(element) {
-        match child.name.as_str() {
-            "transblock" => {
-                let child_node: Rc<RefCell<Node>> = Default::default();
-
-                // Add child to parent's children list
-                parent_node
-                    .borrow_mut()
-                    .children
-                    .push(Rc::clone(&child_node));
+    for (index, child) in child_elements(element).enumerate() {
+        context.recover(format!("{} #{}", describe(child), index + 1), |context| {
+            match child.name.as_str() {
+                "transblock" => {
+                    let mut child_node = Node::default();
+                    parse_transblock(child, &mut child_node, objects, context);
 
-                parse_transblock(child, child_node, objects, textures)?;
+                    // Add child to parent's children list
+                    parent_node.children.push(Arc::new(child_node));
+                    Ok(())
+                }
+                other_name => bail!("Cannot have tag <{}> in <object>", other_name),
             }
-            other_name => bail!("Cannot have tag <{}> in <object>", other_name),
-        }
+        });
     }
 
     Ok(())
 }
 
-fn parse_object(element: &Element, objects: &mut ObjectMap, textures: &Path) -> Result<()> {
+fn parse_object(
+    element: &Element,
+    objects: &mut ObjectMap,
+    context: &mut ParseContext,
+) -> Result<()> {
     let object_name = parse_attribute::<String>(element, "name")?;
     let object_type = parse_attribute::<String>(element, "type")?;
 
@@ -286,83 +531,121 @@ fn parse_object(element: &Element, objects: &mut ObjectMap, textures: &Path) ->
         )
     }
 
-    let current_node = Default::default();
-
-    if objects
-        .insert(object_name.clone(), Rc::clone(&current_node))
-        .is_some()
-    {
+    if objects.contains_key(&object_name) {
         bail!(
             "Cannot have two objects with the same name: {}",
             object_name
         );
     }
 
-    parse_object_body(element, &current_node, objects, textures)?;
+    let mut current_node = Node {
+        name: Some(object_name.clone()),
+        ..Default::default()
+    };
+
+    context.object_name = Arc::from(object_name.as_str());
+    context.object_shape_count = 0;
+    parse_object_body(element, &mut current_node, objects, context)?;
+
+    // The object can only be instanced (by later objects) once it is complete
+    objects.insert(object_name, Arc::new(current_node));
 
     Ok(())
 }
 
+/// Parses the contents of a `<transblock>` into the given node, recording the errors in any
+/// of them.
 fn parse_transblock(
     element: &Element,
-    node: Rc<RefCell<Node>>,
+    node: &mut Node,
     objects: &ObjectMap,
-    textures: &Path,
-) -> Result<()> {
+    context: &mut ParseContext,
+) {
     for child in child_elements(element) {
+        context.recover(describe(child), |context| {
+            parse_transblock_child(child, node, objects, context)
+        });
+    }
+}
+
+/// Parses a transformation or object within a `<transblock>` into the given node.
+fn parse_transblock_child(
+    child: &Element,
+    node: &mut Node,
+    objects: &ObjectMap,
+    context: &mut ParseContext,
+) -> Result<()> {
+    {
         match child.name.as_str() {
             "translate" => {
-                node.borrow_mut()
-                    .transformations
+                node.transformations
                     .push(Transformation::Translate(parse_vec3(
                         child,
                         ("x", "y", "z"),
                     )?));
             }
             "rotate" => {
-                node.borrow_mut()
-                    .transformations
-                    .push(Transformation::Rotate(
-                        parse_vec3(child, ("x", "y", "z"))?,
-                        glm::radians(parse_attribute(child, "angle")?),
-                    ));
+                node.transformations.push(Transformation::Rotate(
+                    parse_vec3(child, ("x", "y", "z"))?,
+                    parse_attribute::<f32>(child, "angle")?.to_radians(),
+                ));
+            }
+            "rotateq" => {
+                let quaternion = parse_vec4(child, ("x", "y", "z", "w"))?;
+                let length = quaternion.length();
+
+                if length == 0.0 {
+                    bail!("<rotateq> quaternion must have nonzero length");
+                }
+
+                node.transformations
+                    .push(Transformation::RotateQuaternion(Quat::from_vec4(
+                        quaternion / length,
+                    )));
+            }
+            "lookat" => {
+                node.transformations.push(parse_look_at(child)?);
             }
             "scale" => {
-                node.borrow_mut()
-                    .transformations
+                node.transformations
                     .push(Transformation::Scale(parse_vec3(child, ("x", "y", "z"))?));
             }
             "matrix" => {
-                let mut matrix = glm::Mat4::zero();
+                let mut rows = [[0.0; 4]; 4];
 
                 for (row_index, row) in child_elements(child).enumerate() {
                     match row.name.as_str() {
                         "row0" | "row1" | "row2" | "row3" => {
-                            let row_vector = parse_vec4(row, ("v1", "v2", "v3", "v4"))?;
-
-                            for (col_index, &value) in row_vector.as_array().iter().enumerate() {
-                                // NOTE: glm::Mat4 uses .as_array() for indexing, so it indexes column-first
-                                matrix[col_index][row_index] = value;
-                            }
+                            rows[row_index] = parse_vec4(row, ("v1", "v2", "v3", "v4"))?.to_array();
                         }
                         other_name => bail!("Cannot have tag <{}> in <matrix>", other_name),
                     }
                 }
 
-                node.borrow_mut()
-                    .transformations
-                    .push(Transformation::Matrix(matrix));
+                // Matrices are constructed from columns, so the rows are transposed into place
+                let matrix = Mat4::from_cols_array_2d(&rows).transpose();
+
+                node.transformations.push(Transformation::Matrix(matrix));
             }
             "object" => match parse_attribute::<String>(child, "type")?.as_str() {
                 "master" => {
-                    let master_object = objects
-                        .get(&parse_attribute::<String>(child, "name")?)
-                        .ok_or_else(|| anyhow!("Master object must have name"))?;
+                    // Objects can only instance objects defined before them, which also
+                    // keeps an object from containing itself
+                    let master_name = parse_attribute::<String>(child, "name")?;
+                    if context.failed_objects.contains(&master_name) {
+                        return Ok(());
+                    }
+                    let master_object = objects.get(&master_name).ok_or_else(|| {
+                        anyhow!(
+                            "Master object must be defined before it is used: {}",
+                            master_name
+                        )
+                    })?;
 
-                    node.borrow_mut().children.push(Rc::clone(master_object));
+                    node.children.push(Arc::clone(master_object));
                 }
-                "tree" => parse_object_body(child, &node, objects, textures)?,
-                "primitive" => parse_primitive(child, &node, textures)?,
+                "tree" => parse_object_body(child, node, objects, context)?,
+                "primitive" => parse_primitive(child, node, context)?,
                 other_name => bail!("Cannot have tag<{}> in <object>", other_name),
             },
             other_name => bail!("Cannot have tag <{}> in <transblock>", other_name),
@@ -372,22 +655,564 @@ fn parse_transblock(
     Ok(())
 }
 
-fn parse_primitive(element: &Element, node: &Rc<RefCell<Node>>, textures: &Path) -> Result<()> {
-    let primitive_type = match parse_attribute::<String>(element, "name")?.as_str() {
-        "sphere" => PrimitiveType::Sphere,
-        "cube" => PrimitiveType::Cube,
-        "cylinder" => PrimitiveType::Cylinder,
-        "cone" => PrimitiveType::Cone,
-        other_name => bail!("Unsupported primitive type {}", other_name),
+fn parse_look_at(element: &Element) -> Result<Transformation> {
+    let mut eye = None;
+    let mut target = None;
+    let mut up = vec3(0.0, 1.0, 0.0);
+
+    for child in child_elements(element) {
+        match child.name.as_str() {
+            "eye" => eye = Some(parse_vec3(child, ("x", "y", "z"))?),
+            "target" => target = Some(parse_vec3(child, ("x", "y", "z"))?),
+            "up" => up = parse_vec3(child, ("x", "y", "z"))?,
+            other_name => bail!("Cannot have tag <{}> in <lookat>", other_name),
+        }
+    }
+
+    let eye = eye.ok_or_else(|| anyhow!("<lookat> must have an <eye> tag"))?;
+    let target = target.ok_or_else(|| anyhow!("<lookat> must have a <target> tag"))?;
+
+    if eye == target {
+        bail!("<lookat> eye and target must be different points");
+    }
+    if ((target - eye).cross(up)).length() == 0.0 {
+        bail!("<lookat> up vector cannot be parallel to the direction from eye to target");
+    }
+
+    Ok(Transformation::LookAt { eye, target, up })
+}
+
+fn parse_primitive(element: &Element, node: &mut Node, context: &mut ParseContext) -> Result<()> {
+    let primitive_name = parse_attribute::<String>(element, "name")?;
+
+    // Cylinders and cones can be made open-ended by disabling their caps
+    let capped = if element.attributes.contains_key("capped") {
+        if !matches!(primitive_name.as_str(), "cylinder" | "cone") {
+            bail!("Only cylinder and cone primitives can have a \"capped\" attribute");
+        }
+        parse_attribute(element, "capped")?
+    } else {
+        true
+    };
+
+    // Spheres, cylinders, and cones can span only part of the way around the y axis
+    let sweep = if ["startangle", "endangle"]
+        .iter()
+        .any(|&attribute| element.attributes.contains_key(attribute))
+    {
+        if !matches!(primitive_name.as_str(), "sphere" | "cylinder" | "cone") {
+            bail!("Only sphere, cylinder, and cone primitives can have start/end angles");
+        }
+        Some(parse_sweep(element)?)
+    } else {
+        None
+    };
+
+    // Meshes are split into parts by the materials of their faces, each of which is a shape
+    let mesh_parts = if primitive_name == "mesh" {
+        parse_mesh(element, context)?
+    } else {
+        Vec::new()
+    };
+
+    let primitive_type = match (primitive_name.as_str(), sweep) {
+        ("sphere", Some(sweep)) => PrimitiveType::Custom(Arc::new(Primitive::sphere(sweep))),
+        ("cylinder", Some(sweep)) => {
+            PrimitiveType::Custom(Arc::new(Primitive::cylinder(capped, sweep)))
+        }
+        ("cone", Some(sweep)) => PrimitiveType::Custom(Arc::new(Primitive::cone(capped, sweep))),
+        ("sphere", None) => PrimitiveType::Sphere,
+        ("cube", _) => PrimitiveType::Cube,
+        ("cylinder", None) => PrimitiveType::Cylinder { capped },
+        ("cone", None) => PrimitiveType::Cone { capped },
+        ("mesh", _) => PrimitiveType::Custom(Arc::clone(&mesh_parts[0].primitive)),
+        ("lathe", _) => PrimitiveType::Custom(parse_lathe(element)?),
+        ("extrusion", _) => PrimitiveType::Custom(parse_extrusion(element)?),
+        ("prism", _) => PrimitiveType::Custom(parse_prism(element)?),
+        ("ring", _) => PrimitiveType::Custom(parse_ring(element)?),
+        ("tube", _) => PrimitiveType::Custom(parse_tube(element)?),
+        ("bezier", _) => PrimitiveType::Custom(parse_bezier(element, context)?),
+        ("curve", _) => PrimitiveType::Custom(parse_curve(element)?),
+        ("pointcloud", _) => PrimitiveType::Custom(parse_point_cloud(element, context)?),
+        (other_name, _) => match context.builder.user_primitives.get(other_name) {
+            Some(factory) => PrimitiveType::Custom(Arc::new(Primitive::user(
+                factory(&element.attributes)
+                    .with_context(|| format!("Failed to construct {} primitive", other_name))?,
+            ))),
+            None => bail!("Unsupported primitive type {}", other_name),
+        },
     };
 
+    // Whether the primitive's geometry is described by a list of <point> tags
+    let has_points = matches!(
+        primitive_name.as_str(),
+        "lathe" | "extrusion" | "bezier" | "curve"
+    );
+
+    let mut ignored_tags = Vec::new();
+    if has_points {
+        ignored_tags.push("point");
+    }
+    if primitive_name == "mesh" {
+        ignored_tags.push("displacement");
+    }
+    let material = Material {
+        name: element.attributes.get("material").cloned(),
+        ..parse_material(element, context, "primitive object", &ignored_tags)?
+    };
+
+    let depth_offset: f32 = if element.attributes.contains_key("depthoffset") {
+        parse_attribute(element, "depthoffset")?
+    } else {
+        0.0
+    };
+    if depth_offset.is_nan() || depth_offset < 0.0 {
+        bail!("Primitive's depthoffset must not be negative");
+    }
+
+    let backfaces = match element.attributes.get("backfaces") {
+        Some(mode) => mode.parse()?,
+        None => Backfaces::default(),
+    };
+    let uv_projection = match element.attributes.get("projection") {
+        Some(projection) => Some(projection.parse()?),
+        None => None,
+    };
+
+    let material = Arc::new(material);
+    let parts = if mesh_parts.is_empty() {
+        vec![(primitive_type, Arc::clone(&material))]
+    } else {
+        mesh_parts
+            .into_iter()
+            .map(|part| {
+                (
+                    PrimitiveType::Custom(part.primitive),
+                    part.material.unwrap_or_else(|| Arc::clone(&material)),
+                )
+            })
+            .collect()
+    };
+
+    for (primitive_type, material) in parts {
+        context.object_shape_count += 1;
+        let label = format!(
+            "{} #{} in object {}",
+            primitive_name, context.object_shape_count, context.object_name
+        );
+
+        let shape = ParsedShape {
+            primitive_type,
+            material,
+            label,
+            object: Arc::clone(&context.object_name),
+            primitive_name: Arc::from(primitive_name.as_str()),
+            id: context.shape_count,
+            depth_offset,
+            backfaces,
+            uv_projection,
+        };
+        context.shape_count += 1;
+
+        // Add shape to node's list of shapes
+        node.shapes.push(shape);
+    }
+
+    Ok(())
+}
+
+/// Parses a mesh primitive, loading it from its OBJ file. Unless it has a
+/// `facematerials="false"` attribute, the mesh is split into a part for each of the
+/// materials that its faces are assigned in the file (see [`load_mesh_parts`]).
+fn parse_mesh(element: &Element, context: &mut ParseContext) -> Result<Vec<MeshPart>> {
+    let filename = Path::join(
+        context.meshes,
+        Path::new(&parse_attribute::<String>(element, "meshfile")?),
+    );
+    let face_materials = if element.attributes.contains_key("facematerials") {
+        parse_attribute::<bool>(element, "facematerials")?
+    } else {
+        true
+    };
+    let displacement = match child_elements(element).find(|child| child.name == "displacement") {
+        Some(displacement) => Some(parse_displacement(displacement, context)?),
+        None => None,
+    };
+
+    // Displaced meshes are tessellated for each primitive, rather than shared
+    let key = (filename, face_materials);
+    if displacement.is_none() {
+        if let Some(parts) = context.loaded_mesh_parts.get(&key) {
+            return Ok(parts.clone());
+        }
+    }
+
+    // The whole mesh is displaced before it is split, so that its parts meet without cracks
+    let meshes = if face_materials {
+        load_mesh_parts(&key.0, displacement.as_ref())?
+    } else {
+        vec![(None, Mesh::load_obj(&key.0, displacement.as_ref())?)]
+    };
+    let parts: Vec<MeshPart> = meshes
+        .into_iter()
+        .map(|(material, mesh)| MeshPart {
+            material,
+            primitive: Arc::new(Primitive {
+                components: vec![Box::new(mesh)],
+            }),
+        })
+        .collect();
+
+    if displacement.is_none() {
+        context.loaded_mesh_parts.insert(key, parts.clone());
+    }
+
+    Ok(parts)
+}
+
+/// Loads the mesh in the given OBJ file, split into a mesh for each material that its faces
+/// are assigned, along with that material (loaded from the material libraries that the file
+/// refers to). Faces without a material are in a part without one. The mesh is displaced by
+/// the given displacement (if any) before it is split.
+fn load_mesh_parts(
+    filename: &Path,
+    displacement: Option<&Displacement>,
+) -> Result<Vec<(Option<Arc<Material>>, Mesh)>> {
+    let obj = Mesh::load_obj_parts(filename, displacement)?;
+    let directory = filename.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials = HashMap::new();
+    for library in &obj.material_libraries {
+        materials.extend(mtl::load_mtl(&directory.join(library))?);
+    }
+
+    obj.parts
+        .into_iter()
+        .map(|(name, mesh)| {
+            let material = match name {
+                Some(name) => Some(Arc::new(materials.remove(&name).ok_or_else(|| {
+                    anyhow!(
+                        "Mesh file {} uses unknown material {} (give the primitive \
+                         facematerials=\"false\" to ignore the materials of its faces)",
+                        filename.display(),
+                        name
+                    )
+                })?)),
+                None => None,
+            };
+
+            Ok((material, mesh))
+        })
+        .collect()
+}
+
+/// Parses a `<displacement>` tag of a mesh, whose height texture is relative to the textures
+/// directory.
+fn parse_displacement(element: &Element, context: &ParseContext) -> Result<Displacement> {
+    let filename = Path::join(
+        context.textures,
+        Path::new(&parse_attribute::<String>(element, "file")?),
+    );
+    let scale = parse_attribute::<f32>(element, "scale")?;
+    let segments = if element.attributes.contains_key("subdivisions") {
+        let segments = parse_attribute::<u32>(element, "subdivisions")?;
+        if segments == 0 {
+            bail!("Displacement subdivisions must be at least 1");
+        }
+        Some(segments)
+    } else {
+        None
+    };
+
+    Ok(Displacement {
+        height: ImageTexture::open(&filename, ColorSpace::Linear)?,
+        scale,
+        segments,
+    })
+}
+
+fn parse_sweep(element: &Element) -> Result<Sweep> {
+    let start: f32 = if element.attributes.contains_key("startangle") {
+        parse_attribute(element, "startangle")?
+    } else {
+        0.0
+    };
+    let end: f32 = if element.attributes.contains_key("endangle") {
+        parse_attribute(element, "endangle")?
+    } else {
+        360.0
+    };
+
+    if end <= start {
+        bail!("Primitive's endangle must be greater than its startangle");
+    }
+
+    Ok(Sweep {
+        start: start.to_radians(),
+        end: end.to_radians(),
+    })
+}
+
+fn parse_ring(element: &Element) -> Result<Arc<Primitive>> {
+    let inner_radius: f32 = if element.attributes.contains_key("innerradius") {
+        parse_attribute(element, "innerradius")?
+    } else {
+        0.0
+    };
+
+    if !(0.0..0.5).contains(&inner_radius) {
+        bail!("Ring's innerradius must be at least 0 and less than its outer radius (0.5)");
+    }
+
+    Ok(Arc::new(Primitive::ring(inner_radius)))
+}
+
+fn parse_tube(element: &Element) -> Result<Arc<Primitive>> {
+    let inner_radius: f32 = parse_attribute(element, "innerradius")?;
+
+    if !(inner_radius > 0.0 && inner_radius < 0.5) {
+        bail!("Tube's innerradius must be greater than 0 and less than its outer radius (0.5)");
+    }
+
+    Ok(Arc::new(Primitive::tube(inner_radius)))
+}
+
+fn parse_lathe(element: &Element) -> Result<Arc<Primitive>> {
+    let profile = child_elements(element)
+        .filter(|child| child.name == "point")
+        .map(|point| Ok((parse_attribute(point, "r")?, parse_attribute(point, "y")?)))
+        .collect::<Result<Vec<(f32, f32)>>>()?;
+
+    if profile.len() < 2 {
+        bail!("Lathe profile must have at least 2 <point> tags");
+    }
+    if profile.iter().any(|&(radius, _)| radius < 0.0) {
+        bail!("Lathe profile cannot have negative radii");
+    }
+
+    Ok(Arc::new(Primitive::lathe(&profile)))
+}
+
+fn parse_extrusion(element: &Element) -> Result<Arc<Primitive>> {
+    let polygon = child_elements(element)
+        .filter(|child| child.name == "point")
+        .map(|point| Ok((parse_attribute(point, "x")?, parse_attribute(point, "z")?)))
+        .collect::<Result<Vec<(f32, f32)>>>()?;
+
+    if polygon.len() < 3 {
+        bail!("Extrusion polygon must have at least 3 <point> tags");
+    }
+
+    Ok(Arc::new(Primitive::extrusion(&polygon)))
+}
+
+fn parse_prism(element: &Element) -> Result<Arc<Primitive>> {
+    let sides: u32 = parse_attribute(element, "sides")?;
+
+    if sides < 3 {
+        bail!("Prism must have at least 3 sides");
+    }
+
+    Ok(Arc::new(Primitive::prism(sides)))
+}
+
+fn parse_bezier(element: &Element, context: &mut ParseContext) -> Result<Arc<Primitive>> {
+    // Patches are either loaded from a file, or given as control points inside the tag
+    if element.attributes.contains_key("patchfile") {
+        let filename = Path::join(
+            context.meshes,
+            Path::new(&parse_attribute::<String>(element, "patchfile")?),
+        );
+
+        if let Some(patches) = context.loaded_meshes.get(&filename) {
+            return Ok(Arc::clone(patches));
+        }
+
+        let patches = Arc::new(Primitive::bezier(&bezier::load_patches(&filename)?));
+        context.loaded_meshes.insert(filename, Arc::clone(&patches));
+
+        return Ok(patches);
+    }
+
+    let points = child_elements(element)
+        .filter(|child| child.name == "point")
+        .map(|point| {
+            Ok(vec3(
+                parse_attribute(point, "x")?,
+                parse_attribute(point, "y")?,
+                parse_attribute(point, "z")?,
+            ))
+        })
+        .collect::<Result<Vec<Vec3>>>()?;
+
+    if points.is_empty() || points.len() % 16 != 0 {
+        bail!("Bezier patches must have 16 <point> tags each (4 rows of 4 control points)");
+    }
+
+    let patches: Vec<ControlPoints> = points
+        .chunks(16)
+        .map(|patch| [0, 1, 2, 3].map(|row| [0, 1, 2, 3].map(|col| patch[row * 4 + col])))
+        .collect();
+
+    Ok(Arc::new(Primitive::bezier(&patches)))
+}
+
+fn parse_curve(element: &Element) -> Result<Arc<Primitive>> {
+    let points = child_elements(element)
+        .filter(|child| child.name == "point")
+        .map(|point| {
+            Ok(vec3(
+                parse_attribute(point, "x")?,
+                parse_attribute(point, "y")?,
+                parse_attribute(point, "z")?,
+            ))
+        })
+        .collect::<Result<Vec<Vec3>>>()?;
+
+    let radius: f32 = parse_attribute(element, "radius")?;
+    if radius <= 0.0 {
+        bail!("Curve's radius must be positive");
+    }
+
+    // Curves are polylines through their points, unless they are smoothed as Bezier curves
+    let smooth = if element.attributes.contains_key("bezier") {
+        parse_attribute(element, "bezier")?
+    } else {
+        false
+    };
+
+    if points.windows(2).all(|segment| segment[0] == segment[1]) {
+        bail!("Curve must have at least 2 distinct <point> tags");
+    }
+
+    let curve = if smooth {
+        if points.len() % 3 != 1 {
+            bail!(
+                "Bezier curve must have 3n + 1 <point> tags (a start point, then 3 for each span)"
+            );
+        }
+        Curve::bezier(&points, radius)
+    } else {
+        Curve::polyline(&points, radius)
+    };
+
+    Ok(Arc::new(Primitive {
+        components: vec![Box::new(curve)],
+    }))
+}
+
+fn parse_point_cloud(element: &Element, context: &ParseContext) -> Result<Arc<Primitive>> {
+    let filename = Path::join(
+        context.meshes,
+        Path::new(&parse_attribute::<String>(element, "pointfile")?),
+    );
+
+    let radius: f32 = parse_attribute(element, "radius")?;
+    if radius <= 0.0 {
+        bail!("Point cloud's radius must be positive");
+    }
+
+    let splat = match element.attributes.get("splat").map(String::as_str) {
+        None | Some("disk") => Splat::Disk,
+        Some("sphere") => Splat::Sphere,
+        Some(other) => bail!("Unknown splat type \"{}\"", other),
+    };
+
+    let points = PointCloud::load_xyz(&filename)?;
+
+    Ok(Arc::new(Primitive {
+        components: vec![Box::new(PointCloud::new(points, radius, splat))],
+    }))
+}
+
+/// Parses a color given by an attribute as three numbers separated by whitespace, e.g.
+/// `color1="1 0.5 0"`.
+fn parse_color_attribute(element: &Element, attribute_name: &str) -> Result<Vec4> {
+    let value = parse_attribute::<String>(element, attribute_name)?;
+    let components: Option<Vec<f32>> = value
+        .split_whitespace()
+        .map(|component| component.parse().ok())
+        .collect();
+
+    match components.as_deref() {
+        Some(&[r, g, b]) => Ok(vec4(r, g, b, 1.0)),
+        _ => bail!(
+            "Attribute \"{}\" of tag <{}> must be a color of the form \"r g b\"",
+            attribute_name,
+            element.name
+        ),
+    }
+}
+
+/// Parses the built-in procedural texture of the given type.
+fn parse_procedural_texture(
+    element: &Element,
+    texture_type: &str,
+) -> Result<Arc<dyn TextureSource>> {
+    let optional_color = |attribute_name, default| {
+        if element.attributes.contains_key(attribute_name) {
+            parse_color_attribute(element, attribute_name)
+        } else {
+            Ok(default)
+        }
+    };
+    let color1 = optional_color("color1", vec4(1.0, 1.0, 1.0, 1.0))?;
+    let color2 = optional_color("color2", vec4(0.0, 0.0, 0.0, 1.0))?;
+    let scale = |default| -> Result<f32> {
+        let scale = if element.attributes.contains_key("scale") {
+            parse_attribute::<f32>(element, "scale")?
+        } else {
+            default
+        };
+        if scale.is_nan() || scale <= 0.0 {
+            bail!("Texture scale must be positive");
+        }
+        Ok(scale)
+    };
+
+    match texture_type {
+        "checker" => Ok(Arc::new(CheckerTexture {
+            color1,
+            color2,
+            scale: scale(DEFAULT_CHECKER_SCALE)?,
+        })),
+        other_type => {
+            let pattern: NoisePattern = other_type
+                .parse()
+                .map_err(|_| anyhow!("Unknown texture type: {}", other_type))?;
+
+            Ok(Arc::new(NoiseTexture {
+                pattern,
+                color1,
+                color2,
+                scale: scale(DEFAULT_NOISE_SCALE)?,
+            }))
+        }
+    }
+}
+
+/// Parses the material of a primitive or material layer from the tags within the given
+/// element, skipping the given tags (which the caller parses itself). The material is unnamed.
+fn parse_material(
+    element: &Element,
+    context: &ParseContext,
+    owner: &str,
+    ignored_tags: &[&str],
+) -> Result<Material> {
     let mut diffuse = None;
     let mut ambient = None;
     let mut specular = None;
     let mut reflective = None;
+    let mut emissive = None;
     let mut shininess = None;
     let mut texture = None;
+    let mut specular_map = None;
+    let mut shininess_map = None;
     let mut blend = None;
+    let mut opacity = None;
+    let mut ior = None;
+    let mut roughness = None;
+    let mut layer = None;
 
     for child in child_elements(element) {
         match child.name.as_str() {
@@ -395,10 +1220,38 @@ fn parse_primitive(element: &Element, node: &Rc<RefCell<Node>>, textures: &Path)
             "ambient" => ambient = Some(parse_color(child)?),
             "specular" => specular = Some(parse_color(child)?),
             "reflective" => reflective = Some(parse_color(child)?),
+            "emissive" => emissive = Some(parse_color(child)?),
             "shininess" => shininess = Some(parse_attribute::<f32>(child, "v")?),
-            "texture" => texture = Some(parse_texture_map(child, textures)?),
+            "texture" => texture = Some(parse_texture_map(child, context)?),
+            "specularmap" => specular_map = Some(parse_texture_map(child, context)?),
+            "shininessmap" => shininess_map = Some(parse_texture_map(child, context)?),
             "blend" => blend = Some(parse_attribute::<f32>(child, "v")?),
-            other_name => bail!("Cannot have <{}> tag in primitive object", other_name),
+            "opacity" => {
+                let value = parse_attribute::<f32>(child, "v")?;
+                if !(0.0..=1.0).contains(&value) {
+                    bail!("Opacity must be between 0 and 1");
+                }
+                opacity = Some(value);
+            }
+            "ior" => {
+                let value = parse_attribute::<f32>(child, "v")?;
+                if value.is_nan() || value < 1.0 {
+                    bail!("Index of refraction must be at least 1");
+                }
+                ior = Some(value);
+            }
+            "roughness" => {
+                let value = parse_attribute::<f32>(child, "v")?;
+                if !(0.0..=1.0).contains(&value) {
+                    bail!("Roughness must be between 0 and 1");
+                }
+                roughness = Some(value);
+            }
+            "layer" => layer = Some(Box::new(parse_material_layer(child, context)?)),
+            // Tags that aren't part of the material (e.g. the points of a primitive's
+            // geometry) have already been parsed by the caller
+            other_name if ignored_tags.contains(&other_name) => {}
+            other_name => bail!("Cannot have <{}> tag in {}", other_name, owner),
         }
     }
 
@@ -407,41 +1260,96 @@ fn parse_primitive(element: &Element, node: &Rc<RefCell<Node>>, textures: &Path)
         texture.blend = blend.unwrap_or(0.0);
     }
 
-    let zero = glm::vec4(0.0, 0.0, 0.0, 0.0);
+    let zero = vec4(0.0, 0.0, 0.0, 0.0);
 
-    let material = Material {
+    Ok(Material {
         ambient: ambient.unwrap_or(zero),
-        diffuse: diffuse.unwrap_or(glm::vec4(1.0, 1.0, 1.0, 0.0)),
+        diffuse: diffuse.unwrap_or(vec4(1.0, 1.0, 1.0, 0.0)),
         specular: specular.unwrap_or(zero),
         shininess: shininess.unwrap_or(0.0),
         reflective: reflective.unwrap_or(zero),
+        emissive: emissive.unwrap_or(zero),
         texture,
-    };
+        specular_map,
+        shininess_map,
+        opacity: opacity.unwrap_or(1.0),
+        name: None,
+        ior,
+        roughness: roughness.unwrap_or(0.0),
+        layer,
+    })
+}
 
-    let shape = ParsedShape {
-        primitive_type,
-        material,
+/// Parses a `<layer>` tag: a material (given by the same tags as that of a primitive) mixed
+/// over the material containing it, by a constant `factor` attribute and an optional `<mask>`
+/// texture.
+fn parse_material_layer(element: &Element, context: &ParseContext) -> Result<MaterialLayer> {
+    let factor = if element.attributes.contains_key("factor") {
+        parse_attribute::<f32>(element, "factor")?
+    } else {
+        1.0
     };
+    if !(0.0..=1.0).contains(&factor) {
+        bail!("Layer factor must be between 0 and 1");
+    }
 
-    // Add shape to node's list of shapes
-    node.borrow_mut().shapes.push(shape);
+    let mask = match element.get_child("mask") {
+        Some(mask) => Some(parse_texture_map(mask, context)?),
+        None => None,
+    };
 
-    Ok(())
+    Ok(MaterialLayer {
+        material: parse_material(element, context, "material layer", &["mask"])?,
+        factor,
+        mask,
+    })
 }
 
-fn parse_texture_map(element: &Element, textures: &Path) -> Result<Texture> {
-    let filename = Path::join(
-        textures,
-        Path::new(&parse_attribute::<String>(element, "file")?),
-    );
+fn parse_texture_map(element: &Element, context: &ParseContext) -> Result<Texture> {
+    let (filename, source) = match (
+        element.attributes.get("type"),
+        element.attributes.get("source"),
+    ) {
+        (Some(texture_type), _) => (
+            None,
+            OnceLock::from(parse_procedural_texture(element, texture_type)?),
+        ),
+        (None, Some(name)) => {
+            let source = context
+                .builder
+                .texture_sources
+                .get(name)
+                .ok_or_else(|| anyhow!("Unknown texture source: {}", name))?;
+            (None, OnceLock::from(Arc::clone(source)))
+        }
+        (None, None) => {
+            let filename = Path::join(
+                context.textures,
+                Path::new(&parse_attribute::<String>(element, "file")?),
+            );
+            (Some(filename), OnceLock::new())
+        }
+    };
 
     let repeat_u = parse_attribute(element, "u").unwrap_or(1.0);
     let repeat_v = parse_attribute(element, "v").unwrap_or(1.0);
+    let color_space = match element.attributes.get("colorspace") {
+        Some(name) => Some(name.parse()?),
+        None => None,
+    };
+    let wrap = if element.attributes.contains_key("wrap") {
+        parse_attribute::<String>(element, "wrap")?.parse()?
+    } else {
+        WrapMode::default()
+    };
 
     Ok(Texture {
         filename,
+        source,
         repeat_u,
         repeat_v,
+        color_space,
+        wrap,
         blend: 0.0,
     })
 }
@@ -450,6 +1358,14 @@ impl TreeScene {
     /// Parses a `Scene` from the given scenefile path and a path that all
     /// texture images are relative to.
     pub fn parse(scenefile: &Path, textures: &Path) -> Result<Self> {
+        SceneBuilder::new().parse(scenefile, textures)
+    }
+}
+
+impl SceneBuilder {
+    /// Parses a `Scene` from the given scenefile path and a path that all texture images
+    /// are relative to, constructing any user-defined primitives that it uses.
+    pub fn parse(&self, scenefile: &Path, textures: &Path) -> Result<TreeScene> {
         let root = Element::parse(
             File::open(scenefile)
                 .with_context(|| format!("Failed to open scenefile: {}", scenefile.display()))?,
@@ -460,39 +1376,154 @@ impl TreeScene {
             bail!("Missing <scenefile> tag");
         }
 
-        let mut global_lighting_coefficients = None;
+        let mut global_data = None;
         let mut camera = None;
         let mut lights = Vec::new();
 
         let mut objects = HashMap::new();
+        let mut context = ParseContext {
+            textures,
+            meshes: scenefile.parent().unwrap_or_else(|| Path::new("")),
+            loaded_meshes: HashMap::new(),
+            loaded_mesh_parts: HashMap::new(),
+            object_name: Arc::from(""),
+            object_shape_count: 0,
+            shape_count: 0,
+            builder: self,
+            path: Vec::new(),
+            errors: Vec::new(),
+            failed_objects: HashSet::new(),
+        };
 
-        for child in child_elements(&root) {
-            match child.name.as_str() {
-                "cameradata" => camera = Some(parse_camera(child)?),
-                "lightdata" => lights.push(parse_light(child)?),
-                "globaldata" => {
-                    global_lighting_coefficients = Some(parse_global_lighting_coefficients(child)?);
+        for (index, child) in child_elements(&root).enumerate() {
+            context.recover(format!("{} #{}", describe(child), index + 1), |context| {
+                match child.name.as_str() {
+                    "cameradata" => camera = Some(parse_camera(child)?),
+                    "lightdata" => lights.push(parse_light(child, Some(context))?),
+                    "globaldata" => global_data = Some(parse_global_data(child, context)?),
+                    "object" => {
+                        if let Err(error) = parse_object(child, &mut objects, context) {
+                            if let Some(name) = child.attributes.get("name") {
+                                context.failed_objects.insert(name.clone());
+                            }
+                            return Err(error);
+                        }
+                    }
+                    other_name => bail!("Unknown tagname <{}>", other_name),
+                }
+                Ok(())
+            });
+        }
+
+        // Tags that are missing are only reported if they weren't given at all (rather than
+        // given with errors, which have been reported already)
+        let has_tag = |name: &str| child_elements(&root).any(|child| child.name == name);
+        let mut errors = context.errors;
+        if camera.is_none() && !has_tag("cameradata") {
+            errors.push(String::from("Must have <cameradata> tag"));
+        }
+        if global_data.is_none() && !has_tag("globaldata") {
+            errors.push(String::from("Must have <globaldata> tag"));
+        }
+        let root_node = objects.remove("root");
+        if root_node.is_none() && !context.failed_objects.contains("root") {
+            errors.push(String::from("Scene must have a root object"));
+        }
+
+        match (camera, global_data, root_node) {
+            (
+                Some(camera),
+                Some((global_lighting_coefficients, tolerances, environment)),
+                Some(root_node),
+            ) if errors.is_empty() => {
+                // Take ownership of the root node, which is only shared if another object
+                // instances it (in which case it is copied)
+                let root_node =
+                    Arc::try_unwrap(root_node).unwrap_or_else(|root_node| (*root_node).clone());
+
+                Ok(TreeScene {
+                    global_lighting_coefficients,
+                    tolerances,
+                    camera,
+                    lights,
+                    root_node,
+                    texture_color_space: ColorSpace::default(),
+                    texture_cache: None,
+                    environment: environment.map(Arc::new),
+                })
+            }
+            _ => bail!(
+                "Found {} error{} in scenefile {}:\n  {}",
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" },
+                scenefile.display(),
+                errors.join("\n  ")
+            ),
+        }
+    }
+}
+
+/// Parses the replacement parameters of a `<material>` tag of a patch.
+fn parse_material_override(element: &Element) -> Result<MaterialOverride> {
+    let color = |child: &Element| -> Result<[f32; 3]> { Ok(parse_color(child)?.truncate().into()) };
+    let mut material_override = MaterialOverride::default();
+
+    for child in child_elements(element) {
+        match child.name.as_str() {
+            "ambient" => material_override.ambient = Some(color(child)?),
+            "diffuse" => material_override.diffuse = Some(color(child)?),
+            "specular" => material_override.specular = Some(color(child)?),
+            "reflective" => material_override.reflective = Some(color(child)?),
+            "emissive" => material_override.emissive = Some(color(child)?),
+            "shininess" => material_override.shininess = Some(parse_attribute(child, "v")?),
+            "blend" => material_override.blend = Some(parse_attribute(child, "v")?),
+            "opacity" => {
+                let value = parse_attribute::<f32>(child, "v")?;
+                if !(0.0..=1.0).contains(&value) {
+                    bail!("Opacity must be between 0 and 1");
+                }
+                material_override.opacity = Some(value);
+            }
+            "roughness" => {
+                let value = parse_attribute::<f32>(child, "v")?;
+                if !(0.0..=1.0).contains(&value) {
+                    bail!("Roughness must be between 0 and 1");
                 }
-                "object" => parse_object(child, &mut objects, textures)?,
-                other_name => bail!("Unknown tagname <{}>", other_name),
+                material_override.roughness = Some(value);
             }
+            other_name => bail!("Cannot have <{}> tag in patch material", other_name),
         }
+    }
+
+    Ok(material_override)
+}
+
+impl ScenePatch {
+    /// Parses a patch from the given path, which contains `<lightdata>` tags (like those of
+    /// scenefiles) and `<material name="...">` tags of material parameters.
+    pub fn load(path: &Path) -> Result<Self> {
+        let root = Element::parse(
+            File::open(path)
+                .with_context(|| format!("Failed to open scene patch: {}", path.display()))?,
+        )
+        .with_context(|| format!("Failed to parse scene patch as XML: {}", path.display()))?;
 
-        let root_node = objects
-            .remove("root")
-            .ok_or_else(|| anyhow!("Scene must have a root object"))?;
+        if root.name != "scenefile" {
+            bail!("Missing <scenefile> tag in scene patch");
+        }
 
-        // Extract ownership of the root node by unwrapping its RefCell and Rc containers.
-        // This works because we have a single Rc<RefCell<Node>> to the root node (it
-        // has no parents), which we've just moved out of the objects map.
-        let root_node = Rc::try_unwrap(root_node).unwrap().into_inner();
+        let mut patch = ScenePatch::default();
+        for child in child_elements(&root) {
+            match child.name.as_str() {
+                "lightdata" => patch.lights.push(parse_light(child, None)?),
+                "material" => {
+                    let name = parse_attribute::<String>(child, "name")?;
+                    *patch.materials.material_mut(&name) = parse_material_override(child)?;
+                }
+                other_name => bail!("Unknown scene patch tagname <{}>", other_name),
+            }
+        }
 
-        Ok(TreeScene {
-            global_lighting_coefficients: global_lighting_coefficients
-                .ok_or_else(|| anyhow!("Must have <globaldata> tag"))?,
-            camera: camera.ok_or_else(|| anyhow!("Must have <cameradata> tag"))?,
-            lights,
-            root_node,
-        })
+        Ok(patch)
     }
 }
@@ -0,0 +1,36 @@
+//! Scene patches, which replace or add lights and replace material parameters of a parsed
+//! scene (e.g. so that day and night versions of a scene can share its geometry).
+
+use super::{MaterialOverrides, TreeScene};
+use crate::lights::Light;
+use anyhow::Result;
+
+/// Changes to a scene, parsed from an XML file with the same `<scenefile>` root as scenefiles.
+#[derive(Debug, Default, Clone)]
+pub struct ScenePatch {
+    /// Lights which replace the light of the scene with the same id, or are added to the scene
+    /// if it has no such light (or they have no id).
+    pub lights: Vec<Light>,
+    /// Replacement parameters for the materials with each name.
+    pub materials: MaterialOverrides,
+}
+
+impl TreeScene {
+    /// Replaces or adds the lights of the patch, and applies its material parameters.
+    pub fn apply_patch(&mut self, patch: &ScenePatch) -> Result<()> {
+        for light in &patch.lights {
+            let existing = light.id.as_ref().and_then(|id| {
+                self.lights
+                    .iter_mut()
+                    .find(|existing| existing.id.as_ref() == Some(id))
+            });
+
+            match existing {
+                Some(existing) => *existing = light.clone(),
+                None => self.lights.push(light.clone()),
+            }
+        }
+
+        self.apply_material_overrides(&patch.materials)
+    }
+}
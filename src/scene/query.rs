@@ -0,0 +1,198 @@
+//! Queries that find the shapes of a scene by their names and materials, so that scenes can
+//! be manipulated from Rust (e.g. moving every shape of an object, or restyling every
+//! reflective shape) without knowing the layout of the scenefile.
+
+use super::{Material, Node, ParsedShape, Scene, TreeScene};
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// A condition on the material of a shape.
+type MaterialFilter = Box<dyn Fn(&Material) -> bool + Send + Sync>;
+
+/// Conditions on shapes, all of which a shape must meet to match the query. A query without
+/// any conditions matches every shape.
+#[derive(Default)]
+pub struct ShapeQuery {
+    object: Option<String>,
+    primitive: Option<String>,
+    material_name: Option<String>,
+    material_filters: Vec<MaterialFilter>,
+}
+
+impl ShapeQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches shapes in the definition of the object with the given name (not shapes
+    /// of other objects that it instances).
+    pub fn in_object(mut self, name: &str) -> Self {
+        self.object = Some(name.to_string());
+        self
+    }
+
+    /// Only matches shapes whose primitive has the given name in the scenefile, e.g. "sphere".
+    pub fn with_primitive(mut self, name: &str) -> Self {
+        self.primitive = Some(name.to_string());
+        self
+    }
+
+    /// Only matches shapes whose material has the given name (given in the scenefile by the
+    /// `material` attribute of a primitive).
+    pub fn with_material_name(mut self, name: &str) -> Self {
+        self.material_name = Some(name.to_string());
+        self
+    }
+
+    /// Only matches shapes whose material meets the given condition.
+    pub fn with_material<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Material) -> bool + Send + Sync + 'static,
+    {
+        self.material_filters.push(Box::new(filter));
+        self
+    }
+
+    /// Only matches shapes whose material reflects some light.
+    pub fn reflective(self) -> Self {
+        self.with_material(|material| material.reflective.truncate().max_element() > 0.0)
+    }
+
+    /// Only matches shapes whose material lets some light through.
+    pub fn transparent(self) -> Self {
+        self.with_material(|material| material.opacity < 1.0)
+    }
+
+    /// Whether a shape of the given object, primitive, and material matches the query.
+    fn matches(&self, object: &str, primitive: &str, material: &Material) -> bool {
+        self.object.as_deref().map_or(true, |name| name == object)
+            && self
+                .primitive
+                .as_deref()
+                .map_or(true, |name| name == primitive)
+            && self
+                .material_name
+                .as_ref()
+                .map_or(true, |name| material.name.as_ref() == Some(name))
+            && self.material_filters.iter().all(|filter| filter(material))
+    }
+
+    fn matches_parsed(&self, shape: &ParsedShape) -> bool {
+        self.matches(&shape.object, &shape.primitive_name, &shape.material)
+    }
+}
+
+impl fmt::Debug for ShapeQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShapeQuery")
+            .field("object", &self.object)
+            .field("primitive", &self.primitive)
+            .field("material_name", &self.material_name)
+            .field("material_filters", &self.material_filters.len())
+            .finish()
+    }
+}
+
+impl TreeScene {
+    /// Names of the objects whose definitions contain shapes matching the query, in the
+    /// order they appear in the scene. These are the names used by the object overrides of
+    /// [`super::MaterialOverrides`] (which apply to every shape of the object, not only the
+    /// matching ones).
+    pub fn find_objects(&self, query: &ShapeQuery) -> Vec<String> {
+        let mut names = Vec::new();
+        find_objects_in_node(
+            &self.root_node,
+            query,
+            &mut names,
+            &mut HashSet::new(),
+            &mut HashSet::new(),
+        );
+
+        names
+    }
+}
+
+/// Adds the names of the objects with shapes matching the query in the given node and its
+/// descendants (visiting nodes shared between instances only once) to the list of names.
+fn find_objects_in_node(
+    node: &Node,
+    query: &ShapeQuery,
+    names: &mut Vec<String>,
+    seen_names: &mut HashSet<String>,
+    visited: &mut HashSet<*const Node>,
+) {
+    for shape in node
+        .shapes
+        .iter()
+        .filter(|shape| query.matches_parsed(shape))
+    {
+        if seen_names.insert(shape.object.to_string()) {
+            names.push(shape.object.to_string());
+        }
+    }
+
+    for child in &node.children {
+        if visited.insert(Arc::as_ptr(child)) {
+            find_objects_in_node(child, query, names, seen_names, visited);
+        }
+    }
+}
+
+impl Scene {
+    /// Indices (in `shapes`) of the shapes matching the query, in order. These are the
+    /// indices used by [`Self::update_shape_transform`] and [`Self::replace_material`].
+    ///
+    /// Shapes can only be found in scenes whose transform hierarchy has been flattened.
+    pub fn find_shapes(&self, query: &ShapeQuery) -> Result<Vec<usize>> {
+        if self.hierarchy.is_some() {
+            bail!("Shapes can't be found in a scene whose transform hierarchy is preserved");
+        }
+
+        Ok((0..self.shapes.len())
+            .filter(|&index| {
+                query.matches(
+                    self.shapes.object(index),
+                    self.shapes.primitive_name(index),
+                    self.shapes.material(index),
+                )
+            })
+            .collect())
+    }
+
+    /// Replaces the material of the shapes at the given indices (in `shapes`), loading its
+    /// texture if it has one.
+    ///
+    /// Materials can only be replaced in scenes whose transform hierarchy has been flattened.
+    pub fn replace_material(
+        &mut self,
+        indices: impl IntoIterator<Item = usize>,
+        material: Material,
+    ) -> Result<()> {
+        if self.hierarchy.is_some() {
+            bail!("Materials can't be replaced in a scene whose transform hierarchy is preserved");
+        }
+
+        let indices: Vec<usize> = indices.into_iter().collect();
+        if let Some(&index) = indices.iter().find(|&&index| index >= self.shapes.len()) {
+            bail!(
+                "No shape at index {} (the scene has {} shapes)",
+                index,
+                self.shapes.len()
+            );
+        }
+
+        let material = Arc::new(material);
+        Scene::load_textures(
+            material.textures(),
+            self.texture_color_space,
+            self.texture_cache.as_ref(),
+        )?;
+        for index in indices {
+            self.shapes.set_material(index, &material);
+        }
+
+        Ok(())
+    }
+}
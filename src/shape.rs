@@ -1,72 +1,224 @@
-//! Provides the [`Shape`] type, which is a high-level representation of objects in scenes.
+//! Provides the [`Shapes`] type, which is a high-level representation of the objects in scenes.
 
+use crate::bounding_box::BoundingBox;
+use crate::cost::{RayKind, ShapeCost};
 use crate::intersection::Intersection;
+use crate::math::Mat4;
 use crate::primitive::Primitive;
 use crate::raytracer::Ray;
-use crate::scene::{Material, ParsedShape, PrimitiveType, Primitives};
+use crate::scene::{Backfaces, Material, ParsedShape, Primitives, UvProjection};
+use std::collections::HashMap;
 use std::sync::Arc;
 
-/// A Shape represents a particular instance of a Primitive, which has been
+/// Distance (relative to the t-value, in object space) past a culled backface at which to look
+/// for the next hit on a shape.
+const CULLED_HIT_STEP: f32 = 1e-5;
+
+/// A list of shapes, each of which is a particular instance of a Primitive, which has been
 /// transformed and has a material (which affects lighting).
-#[derive(Debug)]
-pub struct Shape {
-    /// Reference to the primitive shape that this is an instance of.
-    primitive: Arc<Primitive>,
-    /// Material of this particular shape.
-    pub material: Material,
-    /// The cumulative transformation matrix for this shape.
-    ctm: glm::Mat4,
-    /// Inverse of the CTM, cached here for performance reasons.
-    inverse_ctm: glm::Mat4,
+///
+/// The properties of the shapes are kept in separate arrays (indexed by shape), so that the
+/// ones needed to intersect rays with many shapes (such as their transformations) are packed
+/// together in memory, rather than interleaved with ones that are only needed for hits.
+#[derive(Debug, Default)]
+pub struct Shapes {
+    /// The cumulative transformation matrix of each shape.
+    ctms: Vec<Mat4>,
+    /// Inverse of each CTM, cached here for performance reasons.
+    inverse_ctms: Vec<Mat4>,
+    /// Index (in `primitives`) of the primitive that each shape is an instance of.
+    primitive_ids: Vec<u32>,
+    /// Index (in `materials`) of the material of each shape.
+    material_ids: Vec<u32>,
+    /// Distance by which hits on each shape are brought toward the origin of the ray.
+    depth_offsets: Vec<f32>,
+    /// How each shape treats hits on the back of its surface.
+    backfaces: Vec<Backfaces>,
+    /// Projection that replaces the natural UV mapping of each shape, if any.
+    uv_projections: Vec<Option<UvProjection>>,
+    /// Position of each shape in the scenefile, which breaks ties between equally distant hits.
+    ids: Vec<usize>,
+    /// Description of each shape, used when reporting its cost.
+    labels: Vec<String>,
+    /// Name of the object whose definition each shape is in, and of its primitive in the
+    /// scenefile, by which shapes are found (see [`crate::scene::ShapeQuery`]).
+    objects: Vec<Arc<str>>,
+    primitive_names: Vec<Arc<str>>,
+    /// Work done intersecting rays with each shape, if costs are being tracked.
+    costs: Vec<ShapeCost>,
+    /// The distinct primitives that the shapes are instances of.
+    primitives: Vec<Arc<Primitive>>,
+    /// The distinct materials of the shapes, which are shared with the other instances of the
+    /// same shape in the scenefile.
+    materials: Vec<Arc<Material>>,
+    /// Indices of the distinct primitives and materials, by their addresses.
+    primitive_indices: HashMap<usize, u32>,
+    material_indices: HashMap<usize, u32>,
 }
 
-impl Shape {
-    /// Convert information about a shape that has been parsed from the scenefile into a [`Shape`].
-    pub fn from_parsed_shape(
-        parsed_shape: &ParsedShape,
-        primitives: &Primitives,
-        ctm: glm::Mat4,
-    ) -> Self {
-        let primitive = Arc::clone(match parsed_shape.primitive_type {
-            PrimitiveType::Cone => &primitives.cone,
-            PrimitiveType::Cube => &primitives.cube,
-            PrimitiveType::Sphere => &primitives.sphere,
-            PrimitiveType::Cylinder => &primitives.cylinder,
-        });
-
-        // TODO: Instead of cloning the material here, we could have it be multiply-owned (Rc)
-        let material = parsed_shape.material.clone();
-        let inverse_ctm = glm::inverse(&ctm);
-
-        Self {
-            primitive,
-            material,
-            ctm,
-            inverse_ctm,
-        }
+impl Shapes {
+    /// Adds a shape from information about it that has been parsed from the scenefile, with
+    /// the given CTM.
+    pub fn push(&mut self, parsed_shape: &ParsedShape, primitives: &Primitives, ctm: Mat4) {
+        let primitive = primitives.get(&parsed_shape.primitive_type);
+        let primitive_id = intern(&mut self.primitives, &mut self.primitive_indices, primitive);
+        let material_id = intern(
+            &mut self.materials,
+            &mut self.material_indices,
+            &parsed_shape.material,
+        );
+
+        self.ctms.push(ctm);
+        self.inverse_ctms.push(ctm.inverse());
+        self.primitive_ids.push(primitive_id);
+        self.material_ids.push(material_id);
+        self.depth_offsets.push(parsed_shape.depth_offset);
+        self.backfaces.push(parsed_shape.backfaces);
+        self.uv_projections.push(parsed_shape.uv_projection);
+        self.ids.push(parsed_shape.id);
+        self.labels.push(parsed_shape.label.clone());
+        self.objects.push(Arc::clone(&parsed_shape.object));
+        self.primitive_names
+            .push(Arc::clone(&parsed_shape.primitive_name));
+        self.costs.push(ShapeCost::default());
+    }
+
+    pub fn len(&self) -> usize {
+        self.ctms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ctms.is_empty()
+    }
+
+    /// Description of the shape at the given index.
+    pub fn label(&self, index: usize) -> &str {
+        &self.labels[index]
+    }
+
+    /// Name of the object whose definition the shape at the given index is in.
+    pub fn object(&self, index: usize) -> &str {
+        &self.objects[index]
+    }
+
+    /// Name of the primitive of the shape at the given index, as given in the scenefile.
+    pub fn primitive_name(&self, index: usize) -> &str {
+        &self.primitive_names[index]
+    }
+
+    /// The material of the shape at the given index.
+    pub fn material(&self, index: usize) -> &Material {
+        &self.materials[self.material_ids[index] as usize]
+    }
+
+    /// Replaces the material of the shape at the given index.
+    pub fn set_material(&mut self, index: usize, material: &Arc<Material>) {
+        self.material_ids[index] =
+            intern(&mut self.materials, &mut self.material_indices, material);
+    }
+
+    /// The cumulative transformation matrix of the shape at the given index.
+    pub fn ctm(&self, index: usize) -> Mat4 {
+        self.ctms[index]
     }
 
-    /// Determine if the given ray intersects with this shape, returning information about
-    /// where the intersection occurs and what kind of material properties are implicated if so.
-    pub fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-        let object_space_ray = ray.to_object_space(&self.inverse_ctm);
+    /// Moves the shape at the given index by replacing its CTM.
+    pub fn set_ctm(&mut self, index: usize, ctm: Mat4) {
+        self.ctms[index] = ctm;
+        self.inverse_ctms[index] = ctm.inverse();
+    }
 
-        let mut component_intersection = self.primitive.intersect(&object_space_ray)?;
+    /// The distinct materials of the shapes.
+    pub fn materials(&self) -> &[Arc<Material>] {
+        &self.materials
+    }
+
+    /// The label and cost of each shape.
+    pub fn costs(&self) -> impl Iterator<Item = (&str, &ShapeCost)> {
+        self.labels.iter().map(String::as_str).zip(&self.costs)
+    }
 
-        let four_ctm_vec3s = self.ctm.as_array().map(|v| v.truncate(3));
-        let three_ctm_vec3s = [four_ctm_vec3s[0], four_ctm_vec3s[1], four_ctm_vec3s[2]];
-        let ctm_mat3 = glm::Mat3::from_array(&three_ctm_vec3s);
-        let ctm_mat3_transpose = glm::transpose(ctm_mat3);
-        let normal_transform = glm::inverse(&ctm_mat3_transpose);
-        let world_normal =
-            glm::normalize(normal_transform * component_intersection.normal.truncate(3))
-                .extend(0.0);
+    /// Determine if the given ray intersects with the shape at the given index, returning
+    /// information about where the intersection occurs and what kind of material properties
+    /// are implicated if so.
+    pub fn intersect(&self, index: usize, ray: &Ray) -> Option<Intersection> {
+        let mut object_space_ray = ray.to_object_space(&self.inverse_ctms[index]);
 
-        component_intersection.normal = world_normal;
+        let primitive = &self.primitives[self.primitive_ids[index] as usize];
+        let mut component_intersection = loop {
+            let mut component_intersection = primitive.intersect(&object_space_ray)?;
+            component_intersection.transform(&self.ctms[index]);
+
+            let backface = component_intersection.normal.dot(ray.direction) > 0.0;
+            match self.backfaces[index] {
+                // Look for a hit on a front face past the culled one
+                Backfaces::Cull if backface => {
+                    let t = component_intersection.t;
+                    object_space_ray.t_min = t + t.abs().max(1.0) * CULLED_HIT_STEP;
+                }
+                Backfaces::Double if backface => {
+                    component_intersection.normal = -component_intersection.normal;
+                    component_intersection.bitangent = -component_intersection.bitangent;
+                    break component_intersection;
+                }
+                _ => break component_intersection,
+            }
+        };
+        let object_point = object_space_ray.at(component_intersection.t).truncate();
+        if let Some(projection) = self.uv_projections[index] {
+            component_intersection.uv = projection.uv(object_point);
+        }
+
+        // The hit is moved toward the ray's origin (rather than only being preferred over
+        // nearby hits), so that it stays consistent with the bounds used to skip shapes
+        component_intersection.t = (component_intersection.t - self.depth_offsets[index]).max(0.0);
 
         Some(Intersection {
             component_intersection,
-            material: &self.material,
+            material: &self.materials[self.material_ids[index] as usize],
+            object_point,
+            label: &self.labels[index],
+            shape_id: self.ids[index],
+            shape_index: index,
         })
     }
+
+    /// Like [`Shapes::intersect`], but records the cost of the test against the given kind
+    /// of ray, if one is given.
+    pub fn intersect_tracked(
+        &self,
+        index: usize,
+        ray: &Ray,
+        kind: Option<RayKind>,
+    ) -> Option<Intersection> {
+        match kind {
+            Some(kind) => self.costs[index].measure(kind, || self.intersect(index, ray)),
+            None => self.intersect(index, ray),
+        }
+    }
+
+    /// The world-space bounding box of the shape at the given index, grown by its depth
+    /// offset so that every (offset) hit on the shape is within it.
+    pub fn bounds(&self, index: usize) -> BoundingBox {
+        self.primitives[self.primitive_ids[index] as usize]
+            .bounds()
+            .transformed(&self.ctms[index])
+            .expanded(self.depth_offsets[index])
+    }
+
+    /// The bounding boxes of all of the shapes, in order.
+    pub fn all_bounds(&self) -> Vec<BoundingBox> {
+        (0..self.len()).map(|index| self.bounds(index)).collect()
+    }
+}
+
+/// The index of the given shared value in the list of distinct values, adding it if it isn't
+/// there already.
+fn intern<T>(values: &mut Vec<Arc<T>>, indices: &mut HashMap<usize, u32>, value: &Arc<T>) -> u32 {
+    *indices
+        .entry(Arc::as_ptr(value) as usize)
+        .or_insert_with(|| {
+            values.push(Arc::clone(value));
+            (values.len() - 1) as u32
+        })
 }
@@ -0,0 +1,155 @@
+//! A procedural daylight sky (the analytic model of Preetham, Shirley, and Smits, "A Practical
+//! Analytic Model for Daylight"), which lights outdoor scenes like an environment map of the
+//! sky, without needing an HDR photo of one.
+
+use crate::environment::{direction, Environment};
+use crate::math::{vec4, Vec3, Vec4};
+use crate::texture::ImageTexture;
+use anyhow::{bail, Result};
+use image::{Rgba, Rgba32FImage};
+
+/// Size of the equirectangular image of the sky that lights the scene, which is smooth enough
+/// to be stored at a modest resolution.
+const IMAGE_WIDTH: u32 = 1024;
+const IMAGE_HEIGHT: u32 = 512;
+
+/// Range of turbidities (how hazy the air is, from a clear sky to a hazy one) over which the
+/// model was fitted.
+const MIN_TURBIDITY: f32 = 2.0;
+const MAX_TURBIDITY: f32 = 10.0;
+
+/// Luminance of the sky at the zenith (before it is scaled by its intensity), which leaves
+/// room for the brighter sky toward the horizon and the sun before colors saturate.
+const ZENITH_LUMINANCE: f32 = 0.25;
+
+/// Smallest cosine of the angle between a direction and the zenith at which the sky is
+/// evaluated. Directions at or below the horizon see the sky at the horizon.
+const MIN_ZENITH_COSINE: f32 = 1e-3;
+
+/// Coefficients of the distribution of a quantity (luminance or a chromaticity coordinate)
+/// over the sky, relative to its value at the zenith.
+#[derive(Debug, Clone, Copy)]
+struct Distribution([f32; 5]);
+
+impl Distribution {
+    /// The (unnormalized) distribution toward a direction at the given angle from the
+    /// zenith and at the given angle from the sun.
+    fn value(&self, zenith_angle: f32, sun_angle: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let zenith_cosine = zenith_angle.cos().max(MIN_ZENITH_COSINE);
+
+        (1.0 + a * (b / zenith_cosine).exp())
+            * (1.0 + c * (d * sun_angle).exp() + e * sun_angle.cos().powi(2))
+    }
+}
+
+/// A clear daylight sky, lit by the sun from a given direction.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    /// Unit direction toward the sun.
+    sun: Vec3,
+    /// Angle between the sun and the zenith.
+    sun_zenith_angle: f32,
+    /// Luminance and chromaticity (as CIE xyY) of the sky at the zenith.
+    zenith: Vec3,
+    /// Distributions of luminance and of the two chromaticity coordinates over the sky.
+    distributions: [Distribution; 3],
+}
+
+impl Sky {
+    /// Constructs the sky with the sun in the given (world space) direction, which must be
+    /// above the horizon (+y being up), through air of the given turbidity.
+    pub fn new(sun: Vec3, turbidity: f32) -> Result<Self> {
+        if !(MIN_TURBIDITY..=MAX_TURBIDITY).contains(&turbidity) {
+            bail!(
+                "Sky turbidity must be from {} to {}",
+                MIN_TURBIDITY,
+                MAX_TURBIDITY
+            );
+        }
+        let sun = sun.normalize_or_zero();
+        if sun.y <= 0.0 {
+            bail!("The sun must be above the horizon");
+        }
+
+        let t = turbidity;
+        let theta = sun.y.clamp(-1.0, 1.0).acos();
+        let chromaticity = |coefficients: [[f32; 4]; 3]| {
+            let [t2, t1, t0] = coefficients
+                .map(|[c3, c2, c1, c0]| c3 * theta.powi(3) + c2 * theta.powi(2) + c1 * theta + c0);
+            t2 * t * t + t1 * t + t0
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        Ok(Self {
+            sun,
+            sun_zenith_angle: theta,
+            zenith: Vec3::new(ZENITH_LUMINANCE, zenith_x, zenith_y),
+            distributions: [
+                Distribution([
+                    0.1787 * t - 1.4630,
+                    -0.3554 * t + 0.4275,
+                    -0.0227 * t + 5.3251,
+                    0.1206 * t - 2.5771,
+                    -0.0670 * t + 0.3703,
+                ]),
+                Distribution([
+                    -0.0193 * t - 0.2592,
+                    -0.0665 * t + 0.0008,
+                    -0.0004 * t + 0.2125,
+                    -0.0641 * t - 0.8989,
+                    -0.0033 * t + 0.0452,
+                ]),
+                Distribution([
+                    -0.0167 * t - 0.2608,
+                    -0.0950 * t + 0.0092,
+                    -0.0079 * t + 0.2102,
+                    -0.0441 * t - 1.6537,
+                    -0.0109 * t + 0.0529,
+                ]),
+            ],
+        })
+    }
+
+    /// The (linear RGB) light of the sky arriving from the given unit direction.
+    pub fn radiance(&self, direction: Vec3) -> Vec4 {
+        let zenith_angle = direction.y.clamp(MIN_ZENITH_COSINE, 1.0).acos();
+        let sun_angle = direction.dot(self.sun).clamp(-1.0, 1.0).acos();
+
+        let [luminance, x, y] = [0, 1, 2].map(|index| {
+            self.zenith[index] * self.distributions[index].value(zenith_angle, sun_angle)
+                / self.distributions[index].value(0.0, self.sun_zenith_angle)
+        });
+
+        // Convert from CIE xyY to XYZ, and then to linear sRGB
+        let (cx, cy, cz) = (x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        vec4(
+            (3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0),
+            (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0),
+            (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0),
+            1.0,
+        )
+    }
+
+    /// An environment of the sky, whose light is scaled by the given intensity.
+    pub fn environment(&self, intensity: f32) -> Environment {
+        let image = Rgba32FImage::from_fn(IMAGE_WIDTH, IMAGE_HEIGHT, |column, row| {
+            let uv = (
+                (column as f32 + 0.5) / IMAGE_WIDTH as f32,
+                1.0 - (row as f32 + 0.5) / IMAGE_HEIGHT as f32,
+            );
+            Rgba(self.radiance(direction(uv)).to_array())
+        });
+
+        Environment::new(ImageTexture::new(image), intensity, 0.0)
+    }
+}
@@ -0,0 +1,165 @@
+//! Stylized versions of the final image: quantizing it to a small fixed palette of colors
+//! (with dithering), or drawing it as ASCII art text (optionally colored with ANSI escape
+//! codes).
+
+use anyhow::{bail, Error, Result};
+use image::{Rgb, RgbImage};
+use std::str::FromStr;
+
+/// Characters of ASCII art, from the darkest to the brightest.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// How many times taller than they are wide the characters of a terminal are, so that ASCII
+/// art has roughly the proportions of the image.
+const CHARACTER_ASPECT_RATIO: f32 = 2.0;
+
+/// A fixed set of colors that an image can be quantized to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Palette {
+    /// Black and white.
+    Monochrome,
+    /// The four shades of green of the original Game Boy.
+    GameBoy,
+    /// The black, cyan, magenta, and white of CGA graphics.
+    Cga,
+    /// The 16 colors of the PICO-8 fantasy console.
+    Pico8,
+}
+
+impl Palette {
+    /// Names of all the palettes, as accepted on the command line.
+    pub const NAMES: &'static [&'static str] = &["monochrome", "gameboy", "cga", "pico8"];
+
+    /// The colors of the palette.
+    pub fn colors(&self) -> &'static [[u8; 3]] {
+        match self {
+            Palette::Monochrome => &[[0, 0, 0], [255, 255, 255]],
+            Palette::GameBoy => &[[15, 56, 15], [48, 98, 48], [139, 172, 15], [155, 188, 15]],
+            Palette::Cga => &[[0, 0, 0], [85, 255, 255], [255, 85, 255], [255, 255, 255]],
+            Palette::Pico8 => &[
+                [0, 0, 0],
+                [29, 43, 83],
+                [126, 37, 83],
+                [0, 135, 81],
+                [171, 82, 54],
+                [95, 87, 79],
+                [194, 195, 199],
+                [255, 241, 232],
+                [255, 0, 77],
+                [255, 163, 0],
+                [255, 236, 39],
+                [0, 228, 54],
+                [41, 173, 255],
+                [131, 118, 156],
+                [255, 119, 168],
+                [255, 204, 170],
+            ],
+        }
+    }
+
+    /// The color of the palette closest to the given color.
+    fn closest(&self, color: [f32; 3]) -> [u8; 3] {
+        let squared_distance = |candidate: &[u8; 3]| -> f32 {
+            (0..3)
+                .map(|channel| (candidate[channel] as f32 - color[channel]).powi(2))
+                .sum()
+        };
+
+        *self
+            .colors()
+            .iter()
+            .min_by(|a, b| squared_distance(a).total_cmp(&squared_distance(b)))
+            .unwrap()
+    }
+
+    /// Quantizes the image to the colors of the palette, using Floyd-Steinberg dithering
+    /// (which spreads the error of each pixel over its unvisited neighbours) so that
+    /// gradients are approximated by mixtures of the colors.
+    pub fn quantize(&self, image: &mut RgbImage) {
+        let (width, height) = image.dimensions();
+
+        // The colors of the pixels, including the error spread to them so far
+        let mut colors: Vec<[f32; 3]> =
+            image.pixels().map(|pixel| pixel.0.map(f32::from)).collect();
+
+        for row in 0..height {
+            for col in 0..width {
+                let index = row as usize * width as usize + col as usize;
+                let color = colors[index];
+                let quantized = self.closest(color);
+                image.put_pixel(col, row, Rgb(quantized));
+
+                let error: [f32; 3] =
+                    std::array::from_fn(|channel| color[channel] - quantized[channel] as f32);
+                for (offset_col, offset_row, weight) in [
+                    (1, 0, 7.0 / 16.0),
+                    (-1, 1, 3.0 / 16.0),
+                    (0, 1, 5.0 / 16.0),
+                    (1, 1, 1.0 / 16.0),
+                ] {
+                    let neighbour_col = col as i64 + offset_col;
+                    let neighbour_row = row as i64 + offset_row;
+                    if (0..width as i64).contains(&neighbour_col)
+                        && (0..height as i64).contains(&neighbour_row)
+                    {
+                        let neighbour =
+                            &mut colors[(neighbour_row * width as i64 + neighbour_col) as usize];
+                        for channel in 0..3 {
+                            neighbour[channel] += error[channel] * weight;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "monochrome" => Ok(Palette::Monochrome),
+            "gameboy" => Ok(Palette::GameBoy),
+            "cga" => Ok(Palette::Cga),
+            "pico8" => Ok(Palette::Pico8),
+            other_name => bail!("Unknown palette {}", other_name),
+        }
+    }
+}
+
+/// Draws the image as ASCII art with the given number of columns, choosing each character by
+/// the brightness of the pixel at its center. If `color` is set, each character is also
+/// given the color of that pixel, with truecolor ANSI escape codes.
+pub fn ascii_art(image: &RgbImage, columns: u32, color: bool) -> String {
+    let (width, height) = image.dimensions();
+    let columns = columns.clamp(1, width.max(1));
+    let rows = ((height as f32 * columns as f32 / width as f32 / CHARACTER_ASPECT_RATIO).round()
+        as u32)
+        .max(1);
+
+    let mut art = String::new();
+    for row in 0..rows {
+        for col in 0..columns {
+            let pixel = image.get_pixel(
+                ((col as f32 + 0.5) * width as f32 / columns as f32) as u32,
+                ((row as f32 + 0.5) * height as f32 / rows as f32) as u32,
+            );
+            let [r, g, b] = pixel.0;
+            let luminance = (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) / 255.0;
+            let character = ASCII_RAMP
+                [((luminance * ASCII_RAMP.len() as f32) as usize).min(ASCII_RAMP.len() - 1)];
+
+            if color {
+                art.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+            }
+            art.push(character as char);
+        }
+        if color {
+            art.push_str("\x1b[0m");
+        }
+        art.push('\n');
+    }
+
+    art
+}
@@ -0,0 +1,130 @@
+//! A downscaled preview of a render, drawn in the terminal with truecolor ANSI escape codes
+//! and redrawn as parts of the image finish (e.g. for feedback over SSH, without a GUI).
+
+use image::{Rgb, RgbImage};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Width (in characters) of the preview when the width of the terminal is unknown.
+const DEFAULT_COLUMNS: u32 = 80;
+
+/// Minimum time between redraws of the preview, so that drawing doesn't slow the render.
+const MIN_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Color of the parts of the preview that haven't been rendered yet.
+const UNRENDERED_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// A preview of an image in the terminal. Each character is an upper half block whose
+/// foreground and background colors are two vertically adjacent pixels of the preview, so
+/// that the preview's pixels are roughly square.
+#[derive(Debug)]
+pub struct TerminalPreview {
+    /// Size of the full image that is being previewed.
+    image_width: u32,
+    image_height: u32,
+    state: Mutex<PreviewState>,
+}
+
+#[derive(Debug)]
+struct PreviewState {
+    /// The preview's pixels, each taken from the pixel of the full image at its center.
+    pixels: RgbImage,
+    /// When the preview was last drawn, if it has been.
+    last_drawn: Option<Instant>,
+}
+
+impl TerminalPreview {
+    /// Constructs a preview of an image of the given size, which fits the width of the
+    /// terminal (as given by the `COLUMNS` environment variable).
+    pub fn new(image_width: u32, image_height: u32) -> Self {
+        let columns = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(DEFAULT_COLUMNS);
+
+        let width = columns.min(image_width).max(1);
+        let height =
+            ((image_height as f32 * width as f32 / image_width as f32).round() as u32).max(1);
+
+        Self {
+            image_width,
+            image_height,
+            state: Mutex::new(PreviewState {
+                pixels: RgbImage::from_pixel(width, height, UNRENDERED_COLOR),
+                last_drawn: None,
+            }),
+        }
+    }
+
+    /// Updates the preview with the given pixels (at column/row positions in the full image),
+    /// and redraws it unless it was drawn very recently.
+    pub fn update(&self, pixels: impl IntoIterator<Item = ((u32, u32), Rgb<u8>)>) {
+        let mut state = self.state.lock().unwrap();
+        let (width, height) = state.pixels.dimensions();
+
+        for ((col, row), color) in pixels {
+            let preview_col = (col as u64 * width as u64 / self.image_width as u64) as u32;
+            let preview_row = (row as u64 * height as u64 / self.image_height as u64) as u32;
+
+            // Only the pixel at the center of each preview pixel is shown
+            if source_position(preview_col, width, self.image_width) == col
+                && source_position(preview_row, height, self.image_height) == row
+            {
+                state.pixels.put_pixel(preview_col, preview_row, color);
+            }
+        }
+
+        if state.last_drawn.map_or(true, |last_drawn| {
+            last_drawn.elapsed() >= MIN_REDRAW_INTERVAL
+        }) {
+            Self::draw(&mut state);
+        }
+    }
+
+    /// Draws the preview as it currently is (e.g. once the render has finished).
+    pub fn finish(&self) {
+        Self::draw(&mut self.state.lock().unwrap());
+    }
+
+    /// Draws the preview, over the top of the previous drawing if there was one.
+    fn draw(state: &mut PreviewState) {
+        let (width, height) = state.pixels.dimensions();
+        let lines = (height + 1) / 2;
+
+        let mut output = String::new();
+        if state.last_drawn.is_some() {
+            output.push_str(&format!("\x1b[{}A", lines));
+        }
+
+        for line in 0..lines {
+            for col in 0..width {
+                let Rgb([r, g, b]) = *state.pixels.get_pixel(col, line * 2);
+                let Rgb([br, bg, bb]) = if line * 2 + 1 < height {
+                    *state.pixels.get_pixel(col, line * 2 + 1)
+                } else {
+                    UNRENDERED_COLOR
+                };
+                output.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    r, g, b, br, bg, bb
+                ));
+            }
+            output.push_str("\x1b[0m\n");
+        }
+
+        // The preview is only feedback, so failing to draw it shouldn't stop the render
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(output.as_bytes());
+        let _ = stdout.flush();
+
+        state.last_drawn = Some(Instant::now());
+    }
+}
+
+/// The position (along one axis) in the full image that the pixel at the given position in
+/// the preview shows.
+fn source_position(preview_position: u32, preview_size: u32, image_size: u32) -> u32 {
+    (((preview_position as f32 + 0.5) * image_size as f32 / preview_size as f32) as u32)
+        .min(image_size - 1)
+}
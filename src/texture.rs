@@ -0,0 +1,586 @@
+//! Sources of the values of textures, which may be images loaded from files, or computed
+//! (procedural) textures registered by user code with a
+//! [`SceneBuilder`](crate::scene::SceneBuilder).
+
+use crate::math::{vec3, Vec3, Vec4};
+use crate::noise;
+use anyhow::{bail, Context, Error, Result};
+use image::{DynamicImage, GenericImage, Rgba32FImage};
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+/// Something that gives the value of a texture at any UV coordinate.
+pub trait TextureSource: std::fmt::Debug + Send + Sync {
+    /// The value of the texture at the given UV coordinate (each from 0 to 1, with v
+    /// increasing upward), as an RGBA color whose alpha is used to cut out parts of surfaces.
+    /// Repeated textures are tiled before their sources are sampled.
+    fn value(&self, uv: (f32, f32)) -> Vec4;
+
+    /// The value of the texture at a point of a surface with the given UV coordinate and
+    /// position in the object space of its shape. Solid textures (which are carved out of a
+    /// 3D pattern, like marble) use the position, and other textures only the UV coordinate.
+    fn value_at(&self, uv: (f32, f32), _object_point: Vec3) -> Vec4 {
+        self.value(uv)
+    }
+}
+
+/// A texture whose values are the pixels of an image. The pixels are stored as floats
+/// (from 0 to 1), so that they don't have to be converted every time they are sampled.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    image: Rgba32FImage,
+}
+
+impl ImageTexture {
+    pub fn new(image: Rgba32FImage) -> Self {
+        Self { image }
+    }
+
+    /// Loads the texture from the image file at the given path, whose colors are in the
+    /// given color space. High dynamic range images (e.g. `.hdr` and `.exr` files) keep
+    /// their values above 1, and as their values are stored linearly, they are never decoded
+    /// from sRGB.
+    pub fn open(path: &Path, color_space: ColorSpace) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to load texture image: {}", path.display()))?;
+        let high_dynamic_range = matches!(
+            image,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+        );
+        let mut image = image.to_rgba32f();
+
+        if color_space == ColorSpace::Srgb && !high_dynamic_range {
+            for pixel in image.pixels_mut() {
+                // Alpha is always linear
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+        }
+
+        Ok(Self::new(image))
+    }
+
+    /// The width and height of the image, in pixels.
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    /// The image shrunk to the given size (which is at most its own), where each pixel is
+    /// the average of the pixels of the image that fall within it. The pixels are given row
+    /// by row, from the top.
+    pub fn downsampled(&self, width: u32, height: u32) -> Vec<Vec4> {
+        let (image_width, image_height) = self.image.dimensions();
+        let mut sums = vec![Vec4::ZERO; (width * height) as usize];
+        let mut counts = vec![0u32; (width * height) as usize];
+
+        for (x, y, pixel) in self.image.enumerate_pixels() {
+            let column = (x as u64 * width as u64 / image_width as u64) as u32;
+            let row = (y as u64 * height as u64 / image_height as u64) as u32;
+            let index = (row * width + column) as usize;
+            sums[index] += Vec4::from_array(pixel.0);
+            counts[index] += 1;
+        }
+
+        sums.into_iter()
+            .zip(counts)
+            .map(|(sum, count)| sum / count.max(1) as f32)
+            .collect()
+    }
+
+    /// Memory taken by the image's pixels, in bytes.
+    fn size(&self) -> usize {
+        self.image.as_raw().len() * std::mem::size_of::<f32>()
+    }
+}
+
+/// How the colors of an image texture are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColorSpace {
+    /// The values are used as they are (e.g. for data such as shininess maps).
+    #[default]
+    Linear,
+    /// The colors are gamma-encoded sRGB (as most PNG and JPEG images are), and are decoded
+    /// into linear intensities before shading.
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Names of all the color spaces, as accepted by the `colorspace` attribute of a
+    /// `<texture>`.
+    pub const NAMES: &'static [&'static str] = &["linear", "srgb"];
+}
+
+impl FromStr for ColorSpace {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "linear" => Ok(ColorSpace::Linear),
+            "srgb" => Ok(ColorSpace::Srgb),
+            other_name => bail!("Unknown color space {}", other_name),
+        }
+    }
+}
+
+/// Decodes an sRGB-encoded value (from 0 to 1) into a linear intensity.
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl TextureSource for ImageTexture {
+    fn value(&self, uv: (f32, f32)) -> Vec4 {
+        let (width, height) = self.image.dimensions();
+        image_value(&self.image, 0, 0, width, height, uv)
+    }
+}
+
+/// How a texture is extended beyond the unit square of UV coordinates (e.g. when it repeats a
+/// fractional number of times).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    /// The texture is tiled, which leaves a seam where a partial tile meets the first one.
+    #[default]
+    Repeat,
+    /// The edges of the texture are stretched outward.
+    Clamp,
+    /// The texture is tiled with every other tile flipped, so that neighbouring tiles always
+    /// meet seamlessly.
+    Mirror,
+}
+
+impl WrapMode {
+    /// Names of all the wrap modes, as accepted by the `wrap` attribute of a `<texture>`.
+    pub const NAMES: &'static [&'static str] = &["repeat", "clamp", "mirror"];
+
+    /// Wraps a texture coordinate (scaled by the number of repeats) into the range from 0
+    /// to 1.
+    pub fn wrap(&self, coordinate: f32) -> f32 {
+        match self {
+            WrapMode::Repeat => coordinate.rem_euclid(1.0),
+            WrapMode::Clamp => coordinate.clamp(0.0, 1.0),
+            WrapMode::Mirror => {
+                let coordinate = coordinate.rem_euclid(2.0);
+                if coordinate > 1.0 {
+                    2.0 - coordinate
+                } else {
+                    coordinate
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for WrapMode {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "repeat" => Ok(WrapMode::Repeat),
+            "clamp" => Ok(WrapMode::Clamp),
+            "mirror" => Ok(WrapMode::Mirror),
+            other_name => bail!("Unknown wrap mode {}", other_name),
+        }
+    }
+}
+
+/// The value at the given UV coordinate of the region of an image with the given position
+/// and size. Coordinates of exactly 1 (e.g. from clamping) are in the last column or row.
+fn image_value(
+    image: &Rgba32FImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    (u, v): (f32, f32),
+) -> Vec4 {
+    let column = ((u * width as f32).floor() as u32).min(width - 1);
+    let row = (((1.0 - v) * height as f32).floor() as u32).min(height - 1);
+
+    Vec4::from_array(image.get_pixel(x + column, y + row).0)
+}
+
+/// Minimum number of small image textures in a scene for them to be packed into an atlas
+/// (with only a few, they fit in the cache anyway).
+pub const MIN_ATLAS_TEXTURES: usize = 8;
+
+/// Largest width or height (in pixels) of an image texture that is packed into an atlas.
+pub const MAX_ATLAS_TEXTURE_SIZE: u32 = 256;
+
+/// A texture whose values are a region of an atlas image, which holds many small textures
+/// packed together (so that they are close together in memory).
+#[derive(Debug)]
+pub struct AtlasTexture {
+    atlas: Arc<Rgba32FImage>,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl AtlasTexture {
+    /// Packs the given textures into a single atlas image, returning a texture of the
+    /// atlas for each of them (in the same order).
+    ///
+    /// The textures are placed on shelves: each row of the atlas holds textures side by side,
+    /// tallest first, up to a width at which the atlas is roughly square.
+    pub fn pack(textures: Vec<ImageTexture>) -> Vec<AtlasTexture> {
+        let area: u64 = textures
+            .iter()
+            .map(|texture| texture.image.width() as u64 * texture.image.height() as u64)
+            .sum();
+        let atlas_width = textures
+            .iter()
+            .map(|texture| texture.image.width())
+            .max()
+            .unwrap_or(0)
+            .max((area as f64).sqrt().ceil() as u32);
+
+        let mut order: Vec<usize> = (0..textures.len()).collect();
+        order.sort_by_key(|&index| std::cmp::Reverse(textures[index].image.height()));
+
+        // Position of each texture in the atlas
+        let mut positions = vec![(0, 0); textures.len()];
+        let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+        for &index in &order {
+            let (width, height) = textures[index].image.dimensions();
+            if shelf_x + width > atlas_width {
+                shelf_y += shelf_height;
+                (shelf_x, shelf_height) = (0, 0);
+            }
+            positions[index] = (shelf_x, shelf_y);
+            shelf_x += width;
+            shelf_height = shelf_height.max(height);
+        }
+
+        let mut atlas = Rgba32FImage::new(atlas_width, shelf_y + shelf_height);
+        for (texture, &(x, y)) in textures.iter().zip(&positions) {
+            atlas
+                .copy_from(&texture.image, x, y)
+                .expect("Texture should fit in the atlas");
+        }
+
+        let atlas = Arc::new(atlas);
+        textures
+            .iter()
+            .zip(positions)
+            .map(|(texture, (x, y))| AtlasTexture {
+                atlas: Arc::clone(&atlas),
+                x,
+                y,
+                width: texture.image.width(),
+                height: texture.image.height(),
+            })
+            .collect()
+    }
+
+    /// Whether the given texture is small enough to be packed into an atlas.
+    pub fn fits(texture: &ImageTexture) -> bool {
+        let (width, height) = texture.image.dimensions();
+        width <= MAX_ATLAS_TEXTURE_SIZE && height <= MAX_ATLAS_TEXTURE_SIZE
+    }
+}
+
+impl TextureSource for AtlasTexture {
+    fn value(&self, uv: (f32, f32)) -> Vec4 {
+        image_value(&self.atlas, self.x, self.y, self.width, self.height, uv)
+    }
+}
+
+/// Decoded images of lazily loaded textures (see [`LazyImageTexture`]), which are evicted
+/// (least recently used first) whenever they take up more memory than the cache's capacity.
+#[derive(Debug)]
+pub struct TextureCache {
+    /// Most memory (in bytes) that the images can take up. The most recently used image is
+    /// kept even if it alone is larger.
+    capacity: usize,
+    /// Number of images loaded so far, which textures are stamped with when they are
+    /// sampled, to order their uses. It only changes when an image is loaded, so sampling
+    /// a loaded image reads it without contending with other threads.
+    clock: AtomicU64,
+    /// Images that are currently loaded, and the memory they take up.
+    loaded: Mutex<LoadedImages>,
+    /// Whether images have been loaded again after being evicted, which has been reported.
+    thrashing_reported: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct LoadedImages {
+    images: Vec<Arc<CachedImage>>,
+    /// Memory taken up by the images, in bytes.
+    size: usize,
+}
+
+/// The image of a lazily loaded texture, if it is in the cache.
+#[derive(Debug)]
+struct CachedImage {
+    path: PathBuf,
+    color_space: ColorSpace,
+    image: RwLock<Option<Arc<ImageTexture>>>,
+    /// Value of the cache's clock when the image was last sampled.
+    last_used: AtomicU64,
+    /// Held while the image is loaded, so that threads sampling it meanwhile wait for it
+    /// rather than decoding it again.
+    loading: Mutex<()>,
+    /// Whether the image has been loaded to be sampled before.
+    sampled: AtomicBool,
+    /// Why loading the image failed, after which sampling it fails the same way rather than
+    /// trying to load it again.
+    error: OnceLock<String>,
+}
+
+impl CachedImage {
+    /// The image, if it is in the cache, which is marked as used now.
+    fn get(&self, clock: &AtomicU64) -> Option<Arc<ImageTexture>> {
+        let now = clock.load(Ordering::Relaxed);
+        if self.last_used.load(Ordering::Relaxed) != now {
+            self.last_used.store(now, Ordering::Relaxed);
+        }
+        self.image.read().unwrap().clone()
+    }
+}
+
+impl TextureCache {
+    /// Constructs an empty cache holding at most the given amount of memory (in bytes).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: AtomicU64::new(0),
+            loaded: Mutex::new(LoadedImages::default()),
+            thrashing_reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Adds a texture whose image is loaded from the given file into the cache when it is
+    /// first sampled. Only the header of the file is read now, so that missing files and
+    /// unsupported formats are reported before rendering.
+    pub fn add(self: &Arc<Self>, path: &Path, color_space: ColorSpace) -> Result<LazyImageTexture> {
+        image::image_dimensions(path)
+            .with_context(|| format!("Failed to load texture image: {}", path.display()))?;
+
+        let cached = Arc::new(CachedImage {
+            path: path.to_path_buf(),
+            color_space,
+            image: RwLock::new(None),
+            last_used: AtomicU64::new(0),
+            loading: Mutex::new(()),
+            sampled: AtomicBool::new(false),
+            error: OnceLock::new(),
+        });
+
+        Ok(LazyImageTexture {
+            cached,
+            cache: Arc::clone(self),
+        })
+    }
+
+    /// The image of the given texture, loading it if it isn't in the cache (and evicting the
+    /// least recently used images to make room for it).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image can't be loaded (e.g. the file is corrupt past its header, or was
+    /// deleted during the render), which fails the render (see
+    /// [`StopReason::Failed`](crate::interrupt::StopReason::Failed)) rather than rendering the
+    /// texture wrong.
+    fn image(&self, cached: &Arc<CachedImage>) -> Arc<ImageTexture> {
+        if let Some(image) = cached.get(&self.clock) {
+            return image;
+        }
+
+        let _loading = cached.loading.lock().unwrap();
+        if let Some(image) = cached.get(&self.clock) {
+            return image;
+        }
+        if let Some(error) = cached.error.get() {
+            panic!("{}", error);
+        }
+        if cached.sampled.swap(true, Ordering::Relaxed)
+            && !self.thrashing_reported.swap(true, Ordering::Relaxed)
+        {
+            eprintln!(
+                "Texture cache of {} MB is too small for the textures being sampled, so evicted \
+                 images are loaded again (which is slow): {}",
+                self.capacity / (1024 * 1024),
+                cached.path.display()
+            );
+        }
+
+        match self.load(cached) {
+            Ok(image) => image,
+            Err(error) => panic!("{}", cached.error.get_or_init(|| format!("{:#}", error))),
+        }
+    }
+
+    /// Decodes the given image into the cache, evicting the least recently used images to
+    /// make room for it. Other images can still be sampled while it is decoded.
+    fn load(&self, cached: &Arc<CachedImage>) -> Result<Arc<ImageTexture>> {
+        let image = Arc::new(ImageTexture::open(&cached.path, cached.color_space)?);
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        cached.last_used.store(now, Ordering::Relaxed);
+        *cached.image.write().unwrap() = Some(Arc::clone(&image));
+
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.size += image.size();
+        loaded.images.push(Arc::clone(cached));
+        while loaded.size > self.capacity && loaded.images.len() > 1 {
+            let least_recent = loaded
+                .images
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| !Arc::ptr_eq(other, cached))
+                .min_by_key(|(_, other)| other.last_used.load(Ordering::Relaxed))
+                .map(|(index, _)| index)
+                .expect("Cache should have other images");
+            let evicted = loaded
+                .images
+                .swap_remove(least_recent)
+                .image
+                .write()
+                .unwrap()
+                .take();
+            if let Some(evicted) = evicted {
+                loaded.size -= evicted.size();
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// An image texture that is loaded into a [`TextureCache`] when it is first sampled, which
+/// may evict it, in which case it is loaded again when next sampled.
+#[derive(Debug)]
+pub struct LazyImageTexture {
+    cached: Arc<CachedImage>,
+    cache: Arc<TextureCache>,
+}
+
+impl TextureSource for LazyImageTexture {
+    fn value(&self, uv: (f32, f32)) -> Vec4 {
+        self.cache.image(&self.cached).value(uv)
+    }
+}
+
+/// A texture whose values are computed by the given function of the UV coordinate.
+pub struct ProceduralTexture<F>(pub F);
+
+impl<F> std::fmt::Debug for ProceduralTexture<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProceduralTexture")
+    }
+}
+
+impl<F> TextureSource for ProceduralTexture<F>
+where
+    F: Fn((f32, f32)) -> Vec4 + Send + Sync,
+{
+    fn value(&self, uv: (f32, f32)) -> Vec4 {
+        (self.0)(uv)
+    }
+}
+
+/// A checkerboard of two colors, with the given number of squares along each of u and v.
+#[derive(Debug)]
+pub struct CheckerTexture {
+    pub color1: Vec4,
+    pub color2: Vec4,
+    pub scale: f32,
+}
+
+impl TextureSource for CheckerTexture {
+    fn value(&self, (u, v): (f32, f32)) -> Vec4 {
+        let square = (u * self.scale).floor() + (v * self.scale).floor();
+
+        if square.rem_euclid(2.0) < 1.0 {
+            self.color1
+        } else {
+            self.color2
+        }
+    }
+}
+
+/// Number of octaves of noise summed by the noise patterns, beyond which the details are too
+/// fine to see.
+const NOISE_OCTAVES: u32 = 6;
+
+/// A solid pattern made from noise, which blends between two colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Veins of the second color through the first, along the x axis.
+    Marble,
+    /// Rings of the second color around the y axis, like the grain of a trunk.
+    Wood,
+    /// Soft billows of fractal noise.
+    Clouds,
+    /// Cells (from Worley noise) of the first color, with borders of the second.
+    Cells,
+}
+
+impl NoisePattern {
+    /// Names of all the patterns, as accepted by the `type` attribute of a `<texture>`.
+    pub const NAMES: &'static [&'static str] = &["marble", "wood", "clouds", "cells"];
+
+    /// How much of the second color is at the given (scaled) point, from 0 to 1.
+    fn blend(&self, point: Vec3) -> f32 {
+        let blend = match self {
+            NoisePattern::Marble => {
+                0.5 + 0.5 * (PI * (point.x + 4.0 * noise::turbulence(point, NOISE_OCTAVES))).sin()
+            }
+            NoisePattern::Wood => {
+                let radius = vec3(point.x, 0.0, point.z).length()
+                    + 0.3 * noise::fractal(point * 0.5, NOISE_OCTAVES);
+                // Each ring fades in gradually and ends sharply
+                (radius * 2.0).rem_euclid(1.0).powi(3)
+            }
+            NoisePattern::Clouds => 0.5 + noise::fractal(point, NOISE_OCTAVES),
+            NoisePattern::Cells => noise::worley(point),
+        };
+
+        blend.clamp(0.0, 1.0)
+    }
+}
+
+impl FromStr for NoisePattern {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "marble" => Ok(NoisePattern::Marble),
+            "wood" => Ok(NoisePattern::Wood),
+            "clouds" => Ok(NoisePattern::Clouds),
+            "cells" => Ok(NoisePattern::Cells),
+            other_name => bail!("Unknown noise pattern {}", other_name),
+        }
+    }
+}
+
+/// A solid texture of a noise pattern, evaluated at the position of each point in its
+/// shape's object space (so that the pattern runs through the shape, rather than being
+/// wrapped around it), where `scale` is the number of features of the pattern per unit.
+#[derive(Debug)]
+pub struct NoiseTexture {
+    pub pattern: NoisePattern,
+    pub color1: Vec4,
+    pub color2: Vec4,
+    pub scale: f32,
+}
+
+impl TextureSource for NoiseTexture {
+    /// Without a position, the pattern is evaluated in the plane of the UV coordinates.
+    fn value(&self, (u, v): (f32, f32)) -> Vec4 {
+        self.value_at((u, v), vec3(u, v, 0.0))
+    }
+
+    fn value_at(&self, _uv: (f32, f32), object_point: Vec3) -> Vec4 {
+        let blend = self.pattern.blend(object_point * self.scale);
+        self.color1.lerp(self.color2, blend)
+    }
+}
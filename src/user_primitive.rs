@@ -0,0 +1,84 @@
+//! Primitives defined outside of this crate, which downstream crates can add to scenes (by
+//! registering them with a [`SceneBuilder`](crate::scene::SceneBuilder)) without having to
+//! know how the built-in primitives are represented.
+
+use crate::bounding_box::BoundingBox;
+use crate::intersection::ComponentIntersection;
+use crate::math::Vec3;
+use crate::primitive::{Primitive, PrimitiveComponent};
+use crate::raytracer::Ray;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// An analytic shape, described in its own object space. Like the built-in primitives,
+/// instances of it are placed in the scene by the transformations of the objects they
+/// belong to, so a primitive which fits within the unit cube centered at the origin will
+/// behave like the built-in ones.
+pub trait UserPrimitive: std::fmt::Debug + Send + Sync {
+    /// Finds the smallest t-value within the ray's valid interval (from `ray.t_min` to
+    /// `ray.t_max`) at which the given object-space ray hits the surface, if it does at all.
+    /// The ray's direction is not necessarily of unit length.
+    fn intersect(&self, ray: &Ray) -> Option<f32>;
+
+    /// An object-space bounding box containing the whole surface.
+    fn bounds(&self) -> BoundingBox;
+
+    /// The outward-facing unit normal of the surface at the given object-space point on it.
+    fn normal(&self, point: &Vec3) -> Vec3;
+
+    /// The texture coordinates of the given object-space point on the surface, each from 0 to 1.
+    fn uv(&self, point: &Vec3) -> (f32, f32);
+
+    /// The derivatives of position with respect to the u and v texture coordinates at the
+    /// given point on the surface, returned as `(dp_du, dp_dv)`, from which the surface's
+    /// tangent frame is built. By default these are zero, in which case an arbitrary (but
+    /// consistent) tangent frame around the normal is used.
+    fn uv_derivatives(&self, _point: &Vec3) -> (Vec3, Vec3) {
+        (Vec3::ZERO, Vec3::ZERO)
+    }
+}
+
+/// Constructs a user-defined primitive from the attributes of the `<primitive>` tag that
+/// refers to it by name (which include the "name" attribute itself).
+pub type UserPrimitiveFactory =
+    dyn Fn(&HashMap<String, String>) -> Result<Arc<dyn UserPrimitive>> + Send + Sync;
+
+/// Adapts a user-defined primitive to the components that primitives are built from.
+#[derive(Debug)]
+struct UserComponent {
+    primitive: Arc<dyn UserPrimitive>,
+}
+
+impl PrimitiveComponent for UserComponent {
+    fn intersect(&self, ray: &Ray) -> Option<ComponentIntersection> {
+        let t = self.primitive.intersect(ray)?;
+        if !ray.contains(t) {
+            return None;
+        }
+
+        let point = ray.at(t).truncate();
+        let (dp_du, dp_dv) = self.primitive.uv_derivatives(&point);
+
+        Some(ComponentIntersection::new(
+            t,
+            self.primitive.normal(&point).extend(0.0),
+            self.primitive.uv(&point),
+            dp_du,
+            dp_dv,
+        ))
+    }
+
+    fn bounds(&self) -> BoundingBox {
+        self.primitive.bounds()
+    }
+}
+
+impl Primitive {
+    /// Constructs a primitive whose geometry is given by a user-defined primitive.
+    pub fn user(primitive: Arc<dyn UserPrimitive>) -> Self {
+        Primitive {
+            components: vec![Box::new(UserComponent { primitive })],
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use anyhow::{Context, Result};
 use image::{Rgb, RgbImage};
+use rustracer::accelerator::AcceleratorKind;
+use rustracer::aov::{AovEncoding, NormalSpace};
+use rustracer::integrator::ReflectionModel;
+use rustracer::sampling::SamplePattern;
 use rustracer::{render_config, Config};
 use std::path::PathBuf;
 
@@ -15,6 +19,11 @@ const TEST_FAILURE_DIFF_THRESHOLD: f32 = 0.01;
 /// The interpretation of this value depends on how diffs are calculated - see `pixel_diff()`.
 const SIGNIFICANT_PIXEL_DIFF_THRESHOLD: f32 = 10.0;
 
+/// Scenefiles (by name) whose renders are random under the default configuration (e.g. with
+/// soft shadows), which are rendered in analytic mode so that they match their benchmarks.
+/// They are listed here rather than in the test cases, which are generated.
+const ANALYTIC_SCENES: &[&str] = &["emissive_instances"];
+
 /// Macro for generating a test case that renders a given scenefile with the rustracer
 /// and compares this output with the corresponding benchmark image, succeeding if any
 /// difference between the rendered images is acceptably negligible.
@@ -67,21 +76,77 @@ pub fn render_and_diff(
     benchmark_output: PathBuf,
 ) -> Result<()> {
     let diff_image_path = output.clone();
+    let analytic = scene
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| ANALYTIC_SCENES.contains(&name));
 
     let config = Config {
         width: BENCHMARK_IMG_WIDTH,
         height: BENCHMARK_IMG_HEIGHT,
+        max_megapixels: 100.0,
+        allow_huge: false,
         scene,
         output,
         textures,
         enable_shadows: true,
         enable_reflections: true,
         enable_texture: true,
+        // Glowing shapes light the scene (and only the scenes that have them are affected)
+        emissive_lights: Some(1),
+        environment_intensity: None,
+        environment_rotation: None,
+        environment_shadow_samples: 16,
+        texture_cache: None,
+        clay: false,
+        override_material: None,
+        srgb_textures: false,
         enable_parallelism: true,
+        threads: None,
+        reflection_model: ReflectionModel::Legacy,
+        reflection_threshold: None,
+        flat: false,
         samples: 1,
+        preserve_hierarchy: false,
+        lod: None,
+        lod_cull: None,
+        tile_index: 0,
+        tile_count: 1,
+        tile_overscan: 0,
+        frame_start: None,
+        frame_end: None,
+        frame_step: 1,
+        accumulation: None,
+        aovs: Vec::new(),
+        normal_space: NormalSpace::World,
+        normal_encoding: AovEncoding::Exr,
+        sample_pattern: SamplePattern::Random,
+        stochastic_transparency: false,
+        analytic,
+        min_t: None,
+        self_intersect_offset: None,
+        parallel_threshold: None,
+        material_overrides: None,
+        patch: None,
+        cost_report: false,
+        max_pixel_time: None,
+        accelerator: AcceleratorKind::Bvh,
+        preview_term: false,
+        debug_pixel: None,
+        ray_export: None,
+        ray_export_stride: 16,
+        packets: false,
+        rerender: None,
+        rerender_objects: Vec::new(),
+        report: None,
+        palette: None,
+        ascii: None,
+        ascii_columns: 80,
+        ascii_color: false,
+        save_partial: false,
     };
 
-    let image = render_config(config, || {})?;
+    let image = render_config(config, |_| {})?.image;
     let benchmark_image = image::open(&benchmark_output)
         .with_context(|| {
             format!(
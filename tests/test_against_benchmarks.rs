@@ -39,3 +39,15 @@ test_against_benchmark!(test_feature, shadow_test);
 test_against_benchmark!(test_feature, texture_cyl2);
 test_against_benchmark!(test_feature, shadow_special_case);
 test_against_benchmark!(test_feature, texture_cheese);
+test_against_benchmark!(test_feature, mesh_torus);
+test_against_benchmark!(test_feature, lathe);
+test_against_benchmark!(test_feature, extrusion);
+test_against_benchmark!(test_feature, bezier);
+test_against_benchmark!(test_feature, curve);
+test_against_benchmark!(test_feature, cutout);
+test_against_benchmark!(test_feature, tube);
+test_against_benchmark!(test_feature, prism);
+test_against_benchmark!(test_feature, pointcloud);
+test_against_benchmark!(test_feature, large_scale);
+test_against_benchmark!(test_feature, decal);
+test_against_benchmark!(test_feature, emissive_instances);
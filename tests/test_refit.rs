@@ -0,0 +1,143 @@
+//! Tests that moving shapes in a built scene (which refits its acceleration structure rather
+//! than building it again) renders the same as building the scene with the shapes moved.
+
+use image::RgbImage;
+use rustracer::math::{vec3, Mat4};
+use rustracer::raytracer::RayTracer;
+use rustracer::scene::{SceneBuilder, ShapeQuery};
+use rustracer::{build_scene, Config};
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Scenefile of a sphere (at the x coordinate that replaces `SPHERE_X`) among other shapes,
+/// some of which it overlaps when moved, lit by a light that it casts a shadow from.
+const SCENEFILE: &str = r#"<scenefile>
+    <globaldata>
+        <diffusecoeff v="0.5"/>
+        <specularcoeff v="0.5"/>
+        <ambientcoeff v="0.5"/>
+    </globaldata>
+    <cameradata>
+        <pos x="0" y="3" z="5"/>
+        <up x="0" y="1" z="0"/>
+        <focus x="0" y="0" z="0"/>
+        <heightangle v="45"/>
+    </cameradata>
+    <lightdata>
+        <type v="point"/>
+        <color r="1" g="1" b="1"/>
+        <position x="1" y="4" z="2"/>
+    </lightdata>
+    <object type="tree" name="root">
+        <transblock>
+            <translate x="SPHERE_X" y="0.5" z="0"/>
+            <object type="primitive" name="sphere">
+                <diffuse r="0.8" g="0.3" b="0.3"/>
+            </object>
+        </transblock>
+        <transblock>
+            <translate x="0.8" y="0.4" z="-0.5"/>
+            <scale x="0.8" y="0.8" z="0.8"/>
+            <object type="primitive" name="cube">
+                <diffuse r="0.3" g="0.3" b="0.8"/>
+            </object>
+        </transblock>
+        <transblock>
+            <translate x="-1" y="0.5" z="-1"/>
+            <object type="primitive" name="cone">
+                <diffuse r="0.3" g="0.8" b="0.3"/>
+            </object>
+        </transblock>
+        <transblock>
+            <scale x="6" y="0.1" z="6"/>
+            <object type="primitive" name="cube">
+                <diffuse r="0.6" g="0.6" b="0.6"/>
+            </object>
+        </transblock>
+    </object>
+</scenefile>
+"#;
+
+/// Largest difference in any channel at which pixels are considered the same, which allows
+/// for rounding in the composed transformations.
+const PIXEL_TOLERANCE: u8 = 2;
+
+/// The configuration to render the scenefile with the sphere at the given x coordinate, which
+/// is written to a file of the given name.
+fn config(name: &str, sphere_x: f32) -> Config {
+    let directory = std::env::temp_dir().join("rustracer_test_refit");
+    fs::create_dir_all(&directory).unwrap();
+    let scene = directory.join(format!("{}.xml", name));
+    fs::write(&scene, SCENEFILE.replace("SPHERE_X", &sphere_x.to_string())).unwrap();
+
+    let output: PathBuf = directory.join(format!("{}.png", name));
+    Config::from_iter_safe([
+        "rustracer",
+        "--scene",
+        scene.to_str().unwrap(),
+        "--textures",
+        directory.to_str().unwrap(),
+        "--output",
+        output.to_str().unwrap(),
+        "--width",
+        "128",
+        "--height",
+        "96",
+        "--enable-shadows",
+        "--analytic",
+    ])
+    .unwrap()
+}
+
+fn render(config: Config, update: impl FnOnce(&mut rustracer::scene::Scene)) -> RgbImage {
+    let mut scene = build_scene(&config, &SceneBuilder::new()).unwrap();
+    update(&mut scene);
+
+    let partition = config.partition().unwrap();
+    RayTracer::new(&scene, config)
+        .unwrap()
+        .render(&partition, |_| {})
+        .unwrap()
+        .image
+}
+
+#[test]
+fn refit_matches_rebuilt_scene() {
+    let refit = render(config("refit", -1.5), |scene| {
+        let sphere = scene
+            .find_shapes(&ShapeQuery::new().with_primitive("sphere"))
+            .unwrap()[0];
+        let ctm = Mat4::from_translation(vec3(3.0, 0.0, 0.0)) * scene.shapes.ctm(sphere);
+        scene.update_shape_transforms([(sphere, ctm)]).unwrap();
+    });
+    let rebuilt = render(config("rebuilt", 1.5), |_| {});
+
+    let different_pixels = refit
+        .pixels()
+        .zip(rebuilt.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(&a, b)| a.abs_diff(b) > PIXEL_TOLERANCE)
+        })
+        .count();
+    assert_eq!(different_pixels, 0);
+}
+
+#[test]
+fn invalid_update_moves_nothing() {
+    let config = config("invalid", -1.5);
+    let mut scene = build_scene(&config, &SceneBuilder::new()).unwrap();
+    let ctms: Vec<Mat4> = (0..scene.shapes.len())
+        .map(|index| scene.shapes.ctm(index))
+        .collect();
+
+    let moved = Mat4::from_translation(vec3(3.0, 0.0, 0.0));
+    let updates = [(0, moved * ctms[0]), (scene.shapes.len(), moved)];
+    assert!(scene.update_shape_transforms(updates).is_err());
+
+    for (index, ctm) in ctms.iter().enumerate() {
+        assert_eq!(scene.shapes.ctm(index), *ctm);
+    }
+}